@@ -0,0 +1,251 @@
+// nc_ingestor/src/avro_input.rs
+// Reads Avro object container files as records, typed from the writer schema.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use apache_avro::Schema;
+use apache_avro::types::Value as AvroValue;
+use chrono::{DateTime, NaiveDate, NaiveTime, SecondsFormat};
+use nc_schema::DataType;
+use serde_json::{Map, Value};
+
+use crate::error::{IngestorError, Result};
+use crate::ingestor::RecordSet;
+
+pub fn is_avro(path: &Path,) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str(),)
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("avro",),)
+}
+
+/// Reads every record of an Avro container file. The schema comes from the file's writer schema
+/// rather than inference: decimals become `Number` (NUMERIC in Postgres) and are written as exact
+/// decimal strings, dates, times and timestamps become ISO 8601 strings, bytes become hex.
+pub fn read_records(path: &Path,) -> Result<RecordSet,> {
+    let file = std::fs::File::open(path,)?;
+    let reader = apache_avro::Reader::new(std::io::BufReader::new(file,),).map_err(|e| {
+        IngestorError::IngestionError(format!("Failed to read Avro {}: {}", path.display(), e),)
+    },)?;
+    let schema = reader.writer_schema().clone();
+
+    let mut rows = Vec::new();
+    for value in reader {
+        let value = value.map_err(|e| {
+            IngestorError::IngestionError(format!("Failed to read Avro {}: {}", path.display(), e),)
+        },)?;
+        rows.push(to_json(value, Some(&schema,),)?,);
+    }
+
+    let columns = columns(&schema,);
+    let schema = match (&schema, data_type(&schema,),) {
+        (Schema::Record(_,), DataType::Object(fields,),) => Some(fields.into_iter().collect(),),
+        _ => None,
+    };
+    Ok(RecordSet { rows, schema, columns, },)
+}
+
+/// Field names of an Avro container file's writer schema, in schema order. Only the file's
+/// header is read.
+pub fn read_columns(path: &Path,) -> Result<Vec<String,>,> {
+    let file = std::fs::File::open(path,)?;
+    let reader = apache_avro::Reader::new(std::io::BufReader::new(file,),).map_err(|e| {
+        IngestorError::IngestionError(format!("Failed to read Avro {}: {}", path.display(), e),)
+    },)?;
+    Ok(columns(reader.writer_schema(),),)
+}
+
+fn columns(schema: &Schema,) -> Vec<String,> {
+    match schema {
+        Schema::Record(record,) => record.fields.iter().map(|field| field.name.clone(),).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Column type for an Avro schema. Nullable unions (`["null", T]`) become `Union([T, Null])`.
+pub fn data_type(schema: &Schema,) -> DataType {
+    match schema {
+        Schema::Null => DataType::Null,
+        Schema::Boolean => DataType::Boolean,
+        Schema::Int | Schema::Long => DataType::Integer,
+        Schema::Float | Schema::Double => DataType::Float,
+        Schema::Decimal(_,) | Schema::BigDecimal => DataType::Number,
+        Schema::Array(array,) => DataType::Array(Box::new(data_type(&array.items,),),),
+        Schema::Map(_,) | Schema::Duration => DataType::Object(Default::default(),),
+        Schema::Record(record,) => DataType::Object(
+            record
+                .fields
+                .iter()
+                .map(|field| (field.name.clone(), data_type(&field.schema,),),)
+                .collect(),
+        ),
+        Schema::Union(union,) => {
+            let mut variants: Vec<DataType,> = union
+                .variants()
+                .iter()
+                .filter(|variant| !matches!(variant, Schema::Null),)
+                .map(data_type,)
+                .collect();
+            if union.is_nullable() {
+                variants.push(DataType::Null,);
+            }
+            match variants.len() {
+                0 => DataType::Null,
+                1 => variants.remove(0,),
+                _ => DataType::Union(variants,),
+            }
+        },
+        // Strings, enums, fixed/bytes, UUIDs, dates, times and timestamps are stored as text
+        _ => DataType::String,
+    }
+}
+
+/// Converts one Avro value to JSON. `schema` is the value's own schema when known; it supplies
+/// the scale of decimals.
+fn to_json(value: AvroValue, schema: Option<&Schema,>,) -> Result<Value,> {
+    Ok(match value {
+        AvroValue::Null => Value::Null,
+        AvroValue::Boolean(b,) => Value::Bool(b,),
+        AvroValue::Int(i,) => Value::from(i,),
+        AvroValue::Long(i,) => Value::from(i,),
+        AvroValue::Float(f,) => Value::from(f as f64,),
+        AvroValue::Double(f,) => Value::from(f,),
+        AvroValue::String(s,) | AvroValue::Enum(_, s,) => Value::String(s,),
+        AvroValue::Bytes(bytes,) | AvroValue::Fixed(_, bytes,) => Value::String(hex(&bytes,),),
+        AvroValue::Uuid(uuid,) => Value::String(uuid.to_string(),),
+        AvroValue::Decimal(decimal,) => {
+            let scale = match schema {
+                Some(Schema::Decimal(decimal_schema,),) => decimal_schema.scale,
+                _ => 0,
+            };
+            let bytes: Vec<u8,> = (&decimal).try_into().map_err(|e| {
+                IngestorError::IngestionError(format!("Invalid Avro decimal: {}", e),)
+            },)?;
+            Value::String(decimal_string(&bytes, scale,)?,)
+        },
+        AvroValue::BigDecimal(decimal,) => Value::String(decimal.to_string(),),
+        AvroValue::Date(days,) => text(
+            NaiveDate::from_ymd_opt(1970, 1, 1,)
+                .and_then(|epoch| epoch.checked_add_signed(chrono::Duration::days(days as i64,),),)
+                .map(|date| date.to_string(),),
+        ),
+        AvroValue::TimeMillis(ms,) => text(time_of_day(ms as i64 * 1_000_000,),),
+        AvroValue::TimeMicros(us,) => text(time_of_day(us * 1_000,),),
+        AvroValue::TimestampMillis(ms,) => {
+            text(DateTime::from_timestamp_millis(ms,).map(rfc3339,),)
+        },
+        AvroValue::TimestampMicros(us,) => {
+            text(DateTime::from_timestamp_micros(us,).map(rfc3339,),)
+        },
+        AvroValue::TimestampNanos(ns,) => {
+            Value::String(rfc3339(DateTime::from_timestamp_nanos(ns,),),)
+        },
+        AvroValue::LocalTimestampMillis(ms,) => {
+            text(DateTime::from_timestamp_millis(ms,).map(|t| local(t.naive_utc(),),),)
+        },
+        AvroValue::LocalTimestampMicros(us,) => {
+            text(DateTime::from_timestamp_micros(us,).map(|t| local(t.naive_utc(),),),)
+        },
+        AvroValue::LocalTimestampNanos(ns,) => {
+            Value::String(local(DateTime::from_timestamp_nanos(ns,).naive_utc(),),)
+        },
+        AvroValue::Duration(duration,) => serde_json::json!({
+            "months": u32::from(duration.months()),
+            "days": u32::from(duration.days()),
+            "millis": u32::from(duration.millis()),
+        }),
+        AvroValue::Union(index, inner,) => {
+            let variant = match schema {
+                Some(Schema::Union(union,),) => union.variants().get(index as usize,),
+                _ => None,
+            };
+            to_json(*inner, variant,)?
+        },
+        AvroValue::Array(items,) => {
+            let item_schema = match schema {
+                Some(Schema::Array(array,),) => Some(&*array.items,),
+                _ => None,
+            };
+            Value::Array(
+                items
+                    .into_iter()
+                    .map(|item| to_json(item, item_schema,),)
+                    .collect::<Result<_,>>()?,
+            )
+        },
+        AvroValue::Map(entries,) => {
+            let value_schema = match schema {
+                Some(Schema::Map(map,),) => Some(&*map.types,),
+                _ => None,
+            };
+            let mut obj = Map::new();
+            for (key, value,) in entries {
+                obj.insert(key, to_json(value, value_schema,)?,);
+            }
+            Value::Object(obj,)
+        },
+        AvroValue::Record(fields,) => {
+            let field_schemas: HashMap<&str, &Schema,> = match schema {
+                Some(Schema::Record(record,),) => record
+                    .fields
+                    .iter()
+                    .map(|field| (field.name.as_str(), &field.schema,),)
+                    .collect(),
+                _ => HashMap::new(),
+            };
+            let mut obj = Map::new();
+            for (name, value,) in fields {
+                let field_schema = field_schemas.get(name.as_str(),).copied();
+                obj.insert(name, to_json(value, field_schema,)?,);
+            }
+            Value::Object(obj,)
+        },
+    },)
+}
+
+/// Formats a big-endian two's-complement unscaled decimal with `scale` fractional digits.
+fn decimal_string(bytes: &[u8], scale: usize,) -> Result<String,> {
+    if bytes.len() > 16 {
+        return Err(IngestorError::IngestionError(format!(
+            "Avro decimal of {} bytes exceeds the supported 38 digits",
+            bytes.len()
+        ),),);
+    }
+    let negative = bytes.first().is_some_and(|b| b & 0x80 != 0,);
+    let unscaled = bytes
+        .iter()
+        .fold(if negative { -1i128 } else { 0 }, |acc, &b| (acc << 8) | b as i128,);
+
+    let digits = unscaled.unsigned_abs().to_string();
+    let digits = format!("{:0>width$}", digits, width = scale + 1);
+    let (int_part, frac_part,) = digits.split_at(digits.len() - scale,);
+    let sign = if negative { "-" } else { "" };
+    Ok(if scale == 0 {
+        format!("{}{}", sign, int_part)
+    } else {
+        format!("{}{}.{}", sign, int_part, frac_part)
+    },)
+}
+
+fn hex(bytes: &[u8],) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b),).collect()
+}
+
+fn time_of_day(nanos: i64,) -> Option<String,> {
+    let secs = u32::try_from(nanos.div_euclid(1_000_000_000,),).ok()?;
+    let frac = nanos.rem_euclid(1_000_000_000,) as u32;
+    NaiveTime::from_num_seconds_from_midnight_opt(secs, frac,).map(|t| t.to_string(),)
+}
+
+fn rfc3339(timestamp: DateTime<chrono::Utc,>,) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true,)
+}
+
+fn local(timestamp: chrono::NaiveDateTime,) -> String {
+    timestamp.format("%Y-%m-%dT%H:%M:%S%.f",).to_string()
+}
+
+/// Out-of-range dates and times are stored as null.
+fn text(formatted: Option<String,>,) -> Value {
+    formatted.map_or(Value::Null, Value::String,)
+}
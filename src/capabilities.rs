@@ -0,0 +1,43 @@
+// nc_ingestor/src/capabilities.rs
+// `capabilities`: what this build can ingest and where, for tooling to check before running.
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::embeddings::EmbeddingProvider;
+use crate::pipeline::Backend;
+
+/// Everything compiled into this build, printed as JSON by the `capabilities` subcommand.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize,)]
+pub struct Capabilities {
+    pub version:       &'static str,
+    /// Backend names as accepted by `clean --backend` and pipeline targets
+    pub backends:      Vec<String,>,
+    /// File extensions with a dedicated reader; other files go through nc_reader
+    pub input_formats: Vec<&'static str,>,
+    /// URL schemes accepted by `--path` besides local paths
+    pub remote_inputs: Vec<&'static str,>,
+    /// URL schemes accepted by `--source`
+    pub sources:       Vec<&'static str,>,
+    /// Providers accepted by `--embedder`
+    pub embedders:     Vec<&'static str,>,
+    /// Optional capabilities built in. The crate has no Cargo features yet, so every build
+    /// lists all of them
+    pub features:      Vec<&'static str,>,
+}
+
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        version:       env!("CARGO_PKG_VERSION"),
+        backends:      Backend::value_variants()
+            .iter()
+            .filter_map(|backend| backend.to_possible_value(),)
+            .map(|value| value.get_name().to_string(),)
+            .collect(),
+        input_formats: vec!["csv", "json", "jsonl", "ndjson", "avro", "xlsx", "xlsm", "xls"],
+        remote_inputs: vec!["s3", "gs"],
+        sources:       vec!["postgres", "postgresql"],
+        embedders:     EmbeddingProvider::value_variants().iter().map(|p| p.name(),).collect(),
+        features:      vec!["mongo-tls", "otel", "remote-inputs", "scripts"],
+    }
+}
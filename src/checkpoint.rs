@@ -0,0 +1,101 @@
+// nc_ingestor/src/checkpoint.rs
+// Resumable runs: which files finished, and how many records of unfinished ones were committed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::{IngestorError, Result};
+
+/// How often `--checkpoint` progress is saved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize,)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckpointGranularity {
+    /// After each file finishes. An interrupted file is ingested again from the start.
+    #[default]
+    File,
+    /// After every committed batch. An interrupted file resumes after its last committed batch.
+    Batch,
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize,)]
+#[serde(default)]
+struct CheckpointState {
+    /// Files ingested completely
+    completed: BTreeSet<String,>,
+    /// Records committed so far for files that haven't finished (batch granularity)
+    committed: BTreeMap<String, usize,>,
+}
+
+/// Progress of a run, kept in a JSON file so a rerun with the same checkpoint skips finished
+/// files and, at batch granularity, the committed head of an interrupted file. Every update
+/// rewrites the file through a temporary file and a rename, so a crash never leaves it torn.
+#[derive(Debug,)]
+pub struct Checkpoint {
+    path:        PathBuf,
+    granularity: CheckpointGranularity,
+    state:       Mutex<CheckpointState,>,
+}
+
+impl Checkpoint {
+    /// Loads the checkpoint at `path`, or starts an empty one if the file doesn't exist yet.
+    pub fn open(path: &Path, granularity: CheckpointGranularity,) -> Result<Self,> {
+        let state = match std::fs::read_to_string(path,) {
+            Ok(text,) => serde_json::from_str(&text,).map_err(|e| {
+                IngestorError::ConfigurationError(format!(
+                    "Invalid checkpoint file {}: {}",
+                    path.display(),
+                    e
+                ),)
+            },)?,
+            Err(e,) if e.kind() == std::io::ErrorKind::NotFound => CheckpointState::default(),
+            Err(e,) => return Err(e.into(),),
+        };
+        Ok(Checkpoint { path: path.to_path_buf(), granularity, state: Mutex::new(state,), },)
+    }
+
+    pub fn granularity(&self,) -> CheckpointGranularity {
+        self.granularity
+    }
+
+    /// Whether `file` was ingested completely by an earlier run.
+    pub fn is_complete(&self, file: &str,) -> bool {
+        self.state.lock().unwrap().completed.contains(file,)
+    }
+
+    /// Records of `file` an earlier run already committed; always 0 at file granularity.
+    pub fn committed(&self, file: &str,) -> usize {
+        if self.granularity != CheckpointGranularity::Batch {
+            return 0;
+        }
+        self.state.lock().unwrap().committed.get(file,).copied().unwrap_or_default()
+    }
+
+    /// Saves that `records` records of `file` are committed (batch granularity only).
+    pub fn record_batch(&self, file: &str, records: usize,) -> Result<(),> {
+        if self.granularity != CheckpointGranularity::Batch {
+            return Ok((),);
+        }
+        let mut state = self.state.lock().unwrap();
+        state.committed.insert(file.to_string(), records,);
+        self.save(&state,)
+    }
+
+    /// Saves that `file` was ingested completely.
+    pub fn complete(&self, file: &str,) -> Result<(),> {
+        let mut state = self.state.lock().unwrap();
+        state.committed.remove(file,);
+        state.completed.insert(file.to_string(),);
+        self.save(&state,)
+    }
+
+    fn save(&self, state: &CheckpointState,) -> Result<(),> {
+        let json = serde_json::to_string_pretty(state,)
+            .map_err(|e| IngestorError::Other(e.to_string(),),)?;
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp",);
+        std::fs::write(&tmp, json,)?;
+        std::fs::rename(&tmp, &self.path,)?;
+        Ok((),)
+    }
+}
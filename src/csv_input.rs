@@ -0,0 +1,445 @@
+// nc_ingestor/src/csv_input.rs
+// Direct CSV reading for paths that don't go through nc_reader's inference.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+
+use csv::StringRecord;
+use nc_reader::error::DataReaderError;
+use nc_reader::nc_reader_result::{DataReaderResult, FileMetadata, RecordStream};
+use nc_schema::DataType;
+use serde_json::{Map, Value};
+
+use crate::error::{IngestorError, Result};
+use crate::ingestor::RecordSet;
+use crate::json_input::infer_schema;
+use crate::schema_builder::ColumnType;
+use crate::transform::coerce_value;
+
+/// Junk rows around the data region of a CSV file, as left by spreadsheet exports: `skip_rows`
+/// title rows before the header and `skip_footer_rows` summary rows after the last data row.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq,)]
+pub struct RowTrim {
+    pub skip_rows:        usize,
+    pub skip_footer_rows: usize,
+}
+
+impl RowTrim {
+    pub fn is_empty(&self,) -> bool {
+        self.skip_rows == 0 && self.skip_footer_rows == 0
+    }
+}
+
+/// What to do with a header name that repeats an earlier one in the same header row.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize,)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateColumns {
+    /// Rename repeats to `name_2`, `name_3`, ... (skipping names the header already has)
+    #[default]
+    Suffix,
+    /// Fail the file
+    Error,
+    /// Keep the first column of that name and drop the repeats
+    First,
+}
+
+/// How the ingestor reads CSV files itself: junk rows around the data region, repeated header
+/// names and how cells are typed.
+#[derive(Debug, Clone, Default, PartialEq, Eq,)]
+pub struct CsvLayout {
+    pub trim:           RowTrim,
+    pub on_duplicate:   DuplicateColumns,
+    /// Keep every non-empty cell as the string in the file, so identifiers such as ZIP codes
+    /// keep their leading zeros and every column is created as TEXT
+    pub all_text:       bool,
+    /// Columns typed as given instead of inferred, parsed from the raw cell (`--type-override`)
+    pub type_overrides: HashMap<String, ColumnType,>,
+    /// Records keep the header's column order (`--column-order source`), which nc_reader's rows
+    /// lose
+    pub header_order:   bool,
+}
+
+impl CsvLayout {
+    /// Type of the cells of `column`, if forced: the override, or a string with `all_text`.
+    fn forced_type(&self, column: &str,) -> Option<DataType,> {
+        match self.type_overrides.get(column,) {
+            Some(column_type,) => Some(column_type.data_type(),),
+            None => self.all_text.then_some(DataType::String,),
+        }
+    }
+
+    /// `schema` with the overridden columns it has set to their given type.
+    fn retyped(&self, mut schema: HashMap<String, DataType,>,) -> HashMap<String, DataType,> {
+        for (column, column_type,) in &self.type_overrides {
+            if let Some(data_type,) = schema.get_mut(column,) {
+                *data_type = column_type.data_type();
+            }
+        }
+        schema
+    }
+}
+
+pub fn is_csv(path: &Path,) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str(),)
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv",),)
+}
+
+/// Column names of a CSV file, with repeated header names resolved. Only the rows up to the
+/// header are parsed.
+pub fn read_headers(path: &Path, layout: CsvLayout,) -> Result<Vec<String,>,> {
+    header_names(&read_header_row(path, layout.trim,)?, layout.on_duplicate,)
+}
+
+/// The header row of a CSV file as written, below the `trim.skip_rows` rows above it. Only the
+/// rows up to the header are parsed, so callers read it once and derive the rest from it.
+pub fn read_header_row(path: &Path, trim: RowTrim,) -> Result<StringRecord,> {
+    Ok(raw_region(path, trim,)?.0,)
+}
+
+/// Column names of a header row, with repeated names resolved according to `policy`.
+pub fn header_names(headers: &StringRecord, policy: DuplicateColumns,) -> Result<Vec<String,>,> {
+    Ok(unique_headers(headers, policy,)?.into_iter().flatten().collect(),)
+}
+
+/// Whether a header row repeats a name. nc_reader would collapse such columns into one, so these
+/// files are read by the ingestor itself.
+pub fn has_duplicate_headers(headers: &StringRecord,) -> bool {
+    let mut seen = HashSet::new();
+    !headers.iter().all(|header| seen.insert(header,),)
+}
+
+/// Resolves repeated names in a header row according to `policy`. `None` marks a column dropped
+/// by [`DuplicateColumns::First`].
+pub fn unique_headers(
+    headers: &StringRecord,
+    policy: DuplicateColumns,
+) -> Result<Vec<Option<String,>,>,> {
+    unique_names(headers.iter(), policy,).map_err(|name| {
+        IngestorError::IngestionError(format!("CSV header repeats the column name '{}'", name),)
+    },)
+}
+
+/// Resolves repeated names in `names`, in order, according to `policy`. `None` marks a name
+/// dropped by [`DuplicateColumns::First`]; with [`DuplicateColumns::Error`] the first repeated
+/// name is the error.
+pub fn unique_names<'a,>(
+    names: impl IntoIterator<Item = &'a str,>,
+    policy: DuplicateColumns,
+) -> std::result::Result<Vec<Option<String,>,>, String,> {
+    let names: Vec<&str,> = names.into_iter().collect();
+    let all: HashSet<&str,> = names.iter().copied().collect();
+    let mut seen: HashSet<String,> = HashSet::new();
+    let mut unique = Vec::with_capacity(names.len(),);
+    for header in names {
+        if seen.insert(header.to_string(),) {
+            unique.push(Some(header.to_string(),),);
+            continue;
+        }
+        match policy {
+            DuplicateColumns::Error => return Err(header.to_string(),),
+            DuplicateColumns::First => unique.push(None,),
+            DuplicateColumns::Suffix => {
+                let renamed = (2..)
+                    .map(|n| format!("{}_{}", header, n),)
+                    .find(|name| !all.contains(name.as_str(),) && !seen.contains(name,),)
+                    .expect("an unused suffix exists",);
+                seen.insert(renamed.clone(),);
+                unique.push(Some(renamed,),);
+            },
+        }
+    }
+    Ok(unique,)
+}
+
+/// Reads every row of a CSV file as a JSON object, typing cells according to `schema` (and
+/// `layout.type_overrides`) rather than inferring. Columns missing from the schema are kept as
+/// strings, and so are columns with a cell that doesn't parse as their type (see
+/// [`widened_schema`]); the returned record set carries the schema the rows were typed against.
+pub fn read_typed_rows(
+    path: &Path,
+    schema: &HashMap<String, DataType,>,
+    layout: CsvLayout,
+) -> Result<RecordSet,> {
+    let (headers, records,) = region(path, layout.clone(),)?;
+    let records = records.collect::<csv::Result<Vec<_,>,>>().map_err(csv_error,)?;
+    let schema = widened_schema(&headers, &records, layout.retyped(schema.clone(),), &layout,);
+    let rows = records
+        .iter()
+        .map(|record| typed_row(&headers, record, &schema, &layout,),)
+        .collect::<Result<_,>>()?;
+    let columns = headers.into_iter().flatten().collect();
+    Ok(RecordSet { rows, schema: Some(schema,), columns, },)
+}
+
+/// Reads a CSV file with junk rows around its data region, which nc_reader can't skip. Column
+/// types are inferred from the first `sample` rows (all rows when unset), widened to strings for
+/// columns with a later cell that doesn't fit (see [`widened_schema`]), and every row is then
+/// typed against them. With `layout.all_text` every
+/// column is a string, and `layout.type_overrides` columns have their given type.
+pub fn read_region(
+    path: &Path,
+    layout: CsvLayout,
+    sample: Option<usize,>,
+) -> Result<RecordSet,> {
+    let (headers, records,) = region(path, layout.clone(),)?;
+    let records = records.collect::<csv::Result<Vec<_,>,>>().map_err(csv_error,)?;
+    let columns = headers.iter().flatten().cloned().collect();
+    if records.is_empty() {
+        return Ok(RecordSet { rows: Vec::new(), schema: None, columns, },);
+    }
+
+    let sampled = &records[..records.len().min(sample.unwrap_or(usize::MAX,),)];
+    let schema = sample_schema(&headers, sampled, &layout,);
+    let schema = widened_schema(&headers, &records, schema, &layout,);
+    let rows = records
+        .iter()
+        .map(|record| typed_row(&headers, record, &schema, &layout,),)
+        .collect::<Result<_,>>()?;
+    Ok(RecordSet { rows, schema: Some(schema,), columns, },)
+}
+
+/// Rows read ahead to type a streamed CSV or line-delimited JSON file when `--infer-sample`
+/// isn't set.
+pub const STREAM_SAMPLE_ROWS: usize = 1000;
+
+/// Reads a CSV file as a lazy record stream, so only the row being ingested (and the sampled
+/// head) is held in memory. Column types are inferred from the first `sample` rows
+/// ([`STREAM_SAMPLE_ROWS`] when unset) as in [`read_region`], and every row is typed against
+/// them, so a column has one type throughout instead of one per cell. Cells later in the file
+/// that don't parse as their column's type stay strings.
+pub fn stream_rows(
+    path: &Path,
+    layout: CsvLayout,
+    sample: Option<usize,>,
+) -> Result<DataReaderResult,> {
+    let metadata = FileMetadata {
+        size:       std::fs::metadata(path,)?.len(),
+        line_count: None,
+    };
+    let (headers, mut records,) = region(path, layout.clone(),)?;
+    let head = records
+        .by_ref()
+        .take(sample.unwrap_or(STREAM_SAMPLE_ROWS,),)
+        .collect::<csv::Result<Vec<_,>,>>()
+        .map_err(csv_error,)?;
+    let schema = sample_schema(&headers, &head, &layout,);
+
+    let stream: RecordStream = Box::new(head.into_iter().map(Ok,).chain(records,).map(
+        move |record| {
+            let record = record.map_err(|e| DataReaderError::Other(e.to_string(),),)?;
+            typed_row(&headers, &record, &schema, &layout,)
+                .map_err(|e| DataReaderError::Other(e.to_string(),),)
+        },
+    ),);
+    Ok(DataReaderResult::Stream(stream, metadata,),)
+}
+
+/// Column names and data rows of a CSV file, with `layout` applied.
+fn region(
+    path: &Path,
+    layout: CsvLayout,
+) -> Result<(Vec<Option<String,>,>, impl Iterator<Item = csv::Result<StringRecord,>,> + Send,),> {
+    let (headers, records,) = raw_region(path, layout.trim,)?;
+    Ok((unique_headers(&headers, layout.on_duplicate,)?, records,),)
+}
+
+/// Header and data rows of a CSV file after `trim`. With junk rows to skip, rows may have any
+/// width, since title and summary rows rarely match the header.
+fn raw_region(
+    path: &Path,
+    trim: RowTrim,
+) -> Result<(StringRecord, impl Iterator<Item = csv::Result<StringRecord,>,> + Send,),> {
+    let mut records = csv::ReaderBuilder::new()
+        .has_headers(false,)
+        .flexible(!trim.is_empty(),)
+        .from_path(path,)
+        .map_err(csv_error,)?
+        .into_records();
+    let headers = match records.nth(trim.skip_rows,) {
+        Some(headers,) => headers.map_err(csv_error,)?,
+        None => StringRecord::new(),
+    };
+    Ok((headers, DropLast { inner: records, held: VecDeque::new(), n: trim.skip_footer_rows, },),)
+}
+
+/// All but the last `n` items of `inner`. Only `n` items are held back at a time, so streaming
+/// stays lazy.
+struct DropLast<I: Iterator,> {
+    inner: I,
+    held:  VecDeque<I::Item,>,
+    n:     usize,
+}
+
+impl<I: Iterator,> Iterator for DropLast<I,> {
+    type Item = I::Item;
+
+    fn next(&mut self,) -> Option<I::Item,> {
+        while self.held.len() <= self.n {
+            self.held.push_back(self.inner.next()?,);
+        }
+        self.held.pop_front()
+    }
+}
+
+/// Cells of `record` under their column names, skipping columns dropped from `headers`.
+fn named_cells<'a,>(
+    headers: &'a [Option<String,>],
+    record: &'a StringRecord,
+) -> impl Iterator<Item = (&'a String, &'a str,),> {
+    headers
+        .iter()
+        .zip(record.iter(),)
+        .filter_map(|(header, cell,)| Some((header.as_ref()?, cell,),),)
+}
+
+/// Column types of `records`: each cell is typed on its own (integers, floats and booleans are
+/// parsed, numbers with a leading zero such as ZIP codes stay strings) and a column whose cells
+/// disagree becomes a string column. With `layout.all_text` every column is a string, and
+/// `layout.type_overrides` columns have their given type.
+fn sample_schema(
+    headers: &[Option<String,>],
+    records: &[StringRecord],
+    layout: &CsvLayout,
+) -> HashMap<String, DataType,> {
+    let sampled: Vec<Value,> =
+        records.iter().map(|record| inferred_row(headers, record, layout,),).collect();
+    layout.retyped(infer_schema(&sampled,),)
+}
+
+fn inferred_row(headers: &[Option<String,>], record: &StringRecord, layout: &CsvLayout,) -> Value {
+    let obj: Map<String, Value,> = named_cells(headers, record,)
+        .map(|(header, cell,)| {
+            let value = match layout.forced_type(header,) {
+                Some(data_type,) => typed_cell(cell, &data_type,),
+                None => inferred_cell(cell,),
+            };
+            (header.clone(), value,)
+        },)
+        .collect();
+    Value::Object(obj,)
+}
+
+/// `schema` with every column that has a cell in `records` not parsing as its type turned into a
+/// string column, so the rows typed against it never put a string into a typed SQL column (which
+/// Postgres `COPY` would reject). A sample can miss such cells further down the file. Columns of
+/// `layout.type_overrides` keep their type, and their cells must parse.
+fn widened_schema(
+    headers: &[Option<String,>],
+    records: &[StringRecord],
+    mut schema: HashMap<String, DataType,>,
+    layout: &CsvLayout,
+) -> HashMap<String, DataType,> {
+    for (header, cell,) in records.iter().flat_map(|record| named_cells(headers, record,),) {
+        if layout.type_overrides.contains_key(header,) {
+            continue;
+        }
+        if let Some(data_type,) = schema.get_mut(header,)
+            && parses(data_type,)
+            && typed_cell(cell, data_type,).is_string()
+        {
+            *data_type = match data_type {
+                DataType::Union(_,) => DataType::Union(vec![DataType::String, DataType::Null],),
+                _ => DataType::String,
+            };
+        }
+    }
+    schema
+}
+
+/// Whether [`typed_cell`] parses cells of `data_type`, rather than keeping them as strings.
+fn parses(data_type: &DataType,) -> bool {
+    match data_type {
+        DataType::Integer
+        | DataType::Float
+        | DataType::Number
+        | DataType::Boolean
+        | DataType::Array(_,)
+        | DataType::Object(_,) => true,
+        DataType::Union(variants,) => {
+            variants.iter().find(|t| !matches!(t, DataType::Null),).is_some_and(parses,)
+        },
+        _ => false,
+    }
+}
+
+/// `record` typed against `schema`. Cells of `layout.type_overrides` columns must parse as their
+/// given type; anything else that doesn't parse stays a string.
+fn typed_row(
+    headers: &[Option<String,>],
+    record: &StringRecord,
+    schema: &HashMap<String, DataType,>,
+    layout: &CsvLayout,
+) -> Result<Value,> {
+    let mut obj = Map::new();
+    for (header, cell,) in named_cells(headers, record,) {
+        let value = match (layout.type_overrides.get(header,), schema.get(header,),) {
+            (Some(column_type,), _,) => {
+                coerce_value(header, Value::String(cell.to_string(),), *column_type,)?
+            },
+            (None, Some(data_type,),) => typed_cell(cell, data_type,),
+            (None, None,) => Value::String(cell.to_string(),),
+        };
+        obj.insert(header.to_string(), value,);
+    }
+    Ok(Value::Object(obj,),)
+}
+
+fn inferred_cell(raw: &str,) -> Value {
+    if raw.is_empty() {
+        return Value::Null;
+    }
+    // Parsing would drop the zero, so identifiers like `02134` are kept as written
+    let digits = raw.strip_prefix('-',).unwrap_or(raw,);
+    if digits.len() > 1 && digits.starts_with('0',) && !digits.starts_with("0.",) {
+        return Value::String(raw.to_string(),);
+    }
+    if let Ok(i,) = raw.parse::<i64>() {
+        return Value::from(i,);
+    }
+    if let Some(n,) = raw.parse::<f64>().ok().and_then(serde_json::Number::from_f64,) {
+        return Value::Number(n,);
+    }
+    match raw.to_ascii_lowercase().as_str() {
+        "true" => Value::Bool(true,),
+        "false" => Value::Bool(false,),
+        _ => Value::String(raw.to_string(),),
+    }
+}
+
+/// Converts a raw cell to the JSON value for `data_type`. Empty cells are null and cells that
+/// don't parse as the expected type are kept as strings.
+pub fn typed_cell(raw: &str, data_type: &DataType,) -> Value {
+    if raw.is_empty() {
+        return Value::Null;
+    }
+    let fallback = || Value::String(raw.to_string(),);
+
+    match data_type {
+        DataType::Integer => raw.parse::<i64>().map(Value::from,).unwrap_or_else(|_| fallback(),),
+        DataType::Float | DataType::Number => raw
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64,)
+            .map(Value::Number,)
+            .unwrap_or_else(fallback,),
+        DataType::Boolean => match raw.to_ascii_lowercase().as_str() {
+            "true" => Value::Bool(true,),
+            "false" => Value::Bool(false,),
+            _ => fallback(),
+        },
+        DataType::Array(_,) | DataType::Object(_,) => {
+            serde_json::from_str(raw,).unwrap_or_else(|_| fallback(),)
+        },
+        DataType::Union(variants,) => variants
+            .iter()
+            .find(|t| !matches!(t, DataType::Null),)
+            .map(|t| typed_cell(raw, t,),)
+            .unwrap_or_else(fallback,),
+        _ => fallback(),
+    }
+}
+
+fn csv_error(e: csv::Error,) -> IngestorError {
+    IngestorError::IngestionError(format!("Failed to read CSV: {}", e),)
+}
@@ -0,0 +1,41 @@
+// nc_ingestor/src/defaults.rs
+// Process-wide fallbacks for target names and sizes left unset in an `IngestorConfig`.
+
+use std::sync::RwLock;
+
+use crate::{DEFAULT_COLLECTION_NAME, DEFAULT_SQL_TABLE_NAME, DEFAULT_VECTOR_SIZE};
+
+/// What the ingestors use when `collection_name` or `vector_size` is `None`. Library users set
+/// it once with [`set_defaults`] instead of filling those fields in every config; until then
+/// the crate constants apply.
+#[derive(Debug, Clone, PartialEq, Eq,)]
+pub struct Defaults {
+    /// Collection or class name for the document and vector backends
+    pub collection_name: String,
+    /// Table name for the SQL backends
+    pub sql_table_name:  String,
+    pub vector_size:     u64,
+}
+
+impl Default for Defaults {
+    fn default() -> Self {
+        Defaults {
+            collection_name: DEFAULT_COLLECTION_NAME.to_string(),
+            sql_table_name:  DEFAULT_SQL_TABLE_NAME.to_string(),
+            vector_size:     DEFAULT_VECTOR_SIZE,
+        }
+    }
+}
+
+static DEFAULTS: RwLock<Option<Defaults,>,> = RwLock::new(None,);
+
+/// Replaces the process-wide defaults. Ingestors created afterwards, and configs resolved
+/// afterwards, pick them up.
+pub fn set_defaults(defaults: Defaults,) {
+    *DEFAULTS.write().unwrap() = Some(defaults,);
+}
+
+/// The current defaults: the last ones set, or the crate constants.
+pub fn defaults() -> Defaults {
+    DEFAULTS.read().unwrap().clone().unwrap_or_default()
+}
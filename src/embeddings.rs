@@ -1,18 +1,253 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+
 use async_trait::async_trait;
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
 
 use crate::error::{IngestorError, Result};
+use crate::ingestor::IngestorConfig;
+use crate::retry::{execute_with_connection_retry, wrap_error};
+
+/// Embedding requests allowed in flight at once unless `--max-concurrent-embeddings` says
+/// otherwise.
+pub const DEFAULT_MAX_CONCURRENT_EMBEDDINGS: usize = 4;
+
+/// Retries of a transient failure of one provider before a fallback chain moves on to the next.
+const PROVIDER_RETRIES: u32 = 2;
+
+/// Batches served per provider by fallback chains, for the run report.
+static PROVIDER_BATCHES: LazyLock<Mutex<BTreeMap<String, u64,>,>,> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new(),),);
+
+/// Process-wide limit on embedding requests, shared by every ingestor and file task.
+static EMBEDDING_PERMITS: LazyLock<RwLock<Arc<Semaphore,>,>,> =
+    LazyLock::new(|| RwLock::new(Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_EMBEDDINGS,),),),);
+
+/// Replaces the embedding request limit (`--max-concurrent-embeddings`, at least 1). Requests
+/// already waiting keep the limit they started with.
+pub fn set_max_concurrent_embeddings(limit: usize,) {
+    *EMBEDDING_PERMITS.write().unwrap() = Arc::new(Semaphore::new(limit.max(1,),),);
+}
+
+/// Calls `embedder` once a slot under the embedding limit is free. Every backend embeds
+/// through here, so file and write concurrency can't multiply provider requests.
+pub async fn embed(embedder: &dyn Embedder, texts: &[String],) -> Result<Vec<Vec<f32,>,>,> {
+    if texts.is_empty() {
+        return Ok(vec![],);
+    }
+    let permits = Arc::clone(&EMBEDDING_PERMITS.read().unwrap(),);
+    let _permit = permits
+        .acquire_owned()
+        .await
+        .map_err(|e| IngestorError::Other(format!("Embedding limiter closed: {}", e),),)?;
+    embedder.generate_embeddings(texts,).await
+}
 
 #[async_trait]
 pub trait Embedder: Send + Sync {
     async fn generate_embeddings(&self, texts: &[String],) -> Result<Vec<Vec<f32,>,>,>;
 }
 
+/// An embedding service `--embedder` can list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize,)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingProvider {
+    /// OpenAI's embeddings API, with `--openai-api-key`
+    Openai,
+    /// A local or remote Ollama server (`--ollama-url`, `--ollama-model`)
+    Ollama,
+}
+
+impl EmbeddingProvider {
+    pub fn name(self,) -> &'static str {
+        match self {
+            EmbeddingProvider::Openai => "openai",
+            EmbeddingProvider::Ollama => "ollama",
+        }
+    }
+
+    fn embedder(self, config: &IngestorConfig,) -> Box<dyn Embedder,> {
+        match self {
+            EmbeddingProvider::Openai => {
+                let key = config.openai_api_key.clone().unwrap_or_default();
+                Box::new(
+                    OpenAIEmbedder::new(key, None,)
+                        .with_headers(config.openai_headers.clone().unwrap_or_default(),),
+                )
+            },
+            EmbeddingProvider::Ollama => Box::new(OllamaEmbedder::new(
+                config.ollama_url.clone(),
+                config.ollama_model.clone(),
+            ),),
+        }
+    }
+}
+
+/// The embedder of `config`: its [`IngestorConfig::embedding_providers`], chained with
+/// [`FallbackEmbedder`] when there is more than one. `None` when there is none. A chain expects
+/// the dimensions of the Postgres embedding column, or else `vector_size` when set.
+pub fn build_embedder(config: &IngestorConfig,) -> Result<Option<Box<dyn Embedder,>,>,> {
+    let mut chain: Vec<(String, Box<dyn Embedder,>,),> = config
+        .embedding_providers()?
+        .into_iter()
+        .map(|provider| (provider.name().to_string(), provider.embedder(config,),),)
+        .collect();
+    let dimensions = config
+        .postgres
+        .embedding_column
+        .as_ref()
+        .map(|column| column.dimensions,)
+        .or(config.vector_size,);
+    Ok(match chain.len() {
+        0 => None,
+        1 => chain.pop().map(|(_, embedder,)| embedder,),
+        _ => Some(Box::new(FallbackEmbedder::new(chain,).with_dimensions(dimensions,),),),
+    },)
+}
+
+/// Batches each provider of a fallback chain served so far in this process, by provider name.
+pub fn provider_usage() -> BTreeMap<String, u64,> {
+    PROVIDER_BATCHES.lock().unwrap_or_else(|e| e.into_inner(),).clone()
+}
+
+/// Embeds each batch with the first provider of its chain that succeeds. A provider's transient
+/// failures (timeouts, connection failures, 429 rate limits and server errors) are retried
+/// [`PROVIDER_RETRIES`] times before the batch falls through to the next one, as does a batch
+/// whose vectors don't have the expected dimensions; if every provider fails, the last error is
+/// returned.
+pub struct FallbackEmbedder {
+    chain:      Vec<(String, Box<dyn Embedder,>,),>,
+    dimensions: Option<u64,>,
+}
+
+impl FallbackEmbedder {
+    /// `chain` holds the providers in the order they are tried, each with the name it is
+    /// logged and counted under in [`provider_usage`].
+    pub fn new(chain: Vec<(String, Box<dyn Embedder,>,),>,) -> Self {
+        Self { chain, dimensions: None, }
+    }
+
+    /// Length every vector must have to be accepted from a provider.
+    pub fn with_dimensions(mut self, dimensions: Option<u64,>,) -> Self {
+        self.dimensions = dimensions;
+        self
+    }
+
+    /// Error for `embeddings` from `name` that don't fit `texts` or the expected dimensions.
+    fn mismatch(
+        &self,
+        name: &str,
+        texts: &[String],
+        embeddings: &[Vec<f32,>],
+    ) -> Option<IngestorError,> {
+        if embeddings.len() != texts.len() {
+            return Some(IngestorError::IngestionError(format!(
+                "Embedding provider {} returned {} vectors for {} texts",
+                name,
+                embeddings.len(),
+                texts.len()
+            ),),);
+        }
+        let dimensions = self.dimensions?;
+        let wrong = embeddings.iter().find(|e| e.len() as u64 != dimensions,)?;
+        Some(IngestorError::ConfigurationError(format!(
+            "Embedding provider {} returned {} dimensions but {} are expected",
+            name,
+            wrong.len(),
+            dimensions
+        ),),)
+    }
+}
+
+#[async_trait]
+impl Embedder for FallbackEmbedder {
+    async fn generate_embeddings(&self, texts: &[String],) -> Result<Vec<Vec<f32,>,>,> {
+        let mut last_error = None;
+        for (name, embedder,) in &self.chain {
+            let attempt = execute_with_connection_retry(PROVIDER_RETRIES, || async {
+                embedder.generate_embeddings(texts,).await.map_err(wrap_error,)
+            },)
+            .await;
+            let attempt = attempt.and_then(|embeddings| {
+                match self.mismatch(name, texts, &embeddings,) {
+                    Some(e,) => Err(e,),
+                    None => Ok(embeddings,),
+                }
+            },);
+            match attempt {
+                Ok(embeddings,) => {
+                    debug!("Embedded {} texts with {}", texts.len(), name);
+                    *PROVIDER_BATCHES
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner(),)
+                        .entry(name.clone(),)
+                        .or_default() += 1;
+                    return Ok(embeddings,);
+                },
+                Err(e,) => {
+                    warn!(
+                        "Embedding provider {} failed, trying the next one: {}",
+                        name,
+                        e
+                    );
+                    last_error = Some(e,);
+                },
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            IngestorError::ConfigurationError("No embedding provider configured".to_string(),)
+        },),)
+    }
+}
+
+/// The text to embed from `record`. An `embed_field` starting with `/` is a JSON Pointer into
+/// nested records (`/data/description/text`); anything else, or a pointer that resolves to
+/// nothing, is looked up as a top-level key.
+pub fn embed_text<'a,>(record: &'a Value, field: &str,) -> Option<&'a str,> {
+    let value = if field.starts_with('/',) {
+        record.pointer(field,).or_else(|| record.get(field,),)
+    } else {
+        record.get(field,)
+    };
+    value?.as_str()
+}
+
+/// Error for an embedding request that got no response. Timeouts and connection failures are
+/// `ConnectionError`s, so they are retried.
+fn request_error(provider: &str, e: reqwest::Error,) -> IngestorError {
+    let message = format!("{} API error: {}", provider, e);
+    if e.is_timeout() || e.is_connect() {
+        IngestorError::ConnectionError(message,)
+    } else {
+        IngestorError::Other(message,)
+    }
+}
+
+/// Error for an embedding request answered with `status`. Rate limits (429) and server errors
+/// are `ConnectionError`s, so they are retried; other statuses, such as a rejected key or an
+/// unknown model, aren't.
+fn status_error(provider: &str, status: StatusCode, body: &str,) -> IngestorError {
+    let message = format!("{} API error: {} - {}", provider, status, body);
+    if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        IngestorError::ConnectionError(message,)
+    } else {
+        IngestorError::Other(message,)
+    }
+}
+
+/// Base URL of OpenAI's API; `/embeddings` is appended.
+pub const DEFAULT_OPENAI_URL: &str = "https://api.openai.com/v1";
+
 pub struct OpenAIEmbedder {
-    client:  Client,
-    api_key: String,
-    model:   String,
+    client:   Client,
+    api_key:  String,
+    model:    String,
+    base_url: String,
+    headers:  Vec<(String, String,),>,
 }
 
 impl OpenAIEmbedder {
@@ -21,8 +256,39 @@ impl OpenAIEmbedder {
             client: Client::new(),
             api_key,
             model: model.unwrap_or_else(|| "text-embedding-3-small".to_string(),),
+            base_url: DEFAULT_OPENAI_URL.to_string(),
+            headers: Vec::new(),
         }
     }
+
+    /// Extra headers sent with every request, e.g. for gateways or `OpenAI-Organization`.
+    pub fn with_headers(mut self, headers: Vec<(String, String,),>,) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Sends requests to an OpenAI-compatible API other than [`DEFAULT_OPENAI_URL`].
+    pub fn with_base_url(mut self, base_url: &str,) -> Self {
+        self.base_url = base_url.trim_end_matches('/',).to_string();
+        self
+    }
+}
+
+/// The headers `--openai-header`, `--openai-organization` and `--openai-project` attach to
+/// OpenAI requests, in that order.
+pub fn openai_headers(
+    extra: &[(String, String,)],
+    organization: Option<&str,>,
+    project: Option<&str,>,
+) -> Vec<(String, String,),> {
+    let mut headers = extra.to_vec();
+    if let Some(organization,) = organization {
+        headers.push(("OpenAI-Organization".to_string(), organization.to_string(),),);
+    }
+    if let Some(project,) = project {
+        headers.push(("OpenAI-Project".to_string(), project.to_string(),),);
+    }
+    headers
 }
 
 #[derive(Serialize,)]
@@ -48,25 +314,27 @@ impl Embedder for OpenAIEmbedder {
             return Ok(vec![],);
         }
 
-        let response = self
+        let mut request = self
             .client
-            .post("https://api.openai.com/v1/embeddings",)
-            .header("Authorization", format!("Bearer {}", self.api_key),)
+            .post(format!("{}/embeddings", self.base_url),)
+            .header("Authorization", format!("Bearer {}", self.api_key),);
+        for (name, value,) in &self.headers {
+            request = request.header(name, value,);
+        }
+
+        let response = request
             .json(&OpenAIRequest {
                 input: texts.to_vec(),
                 model: self.model.clone(),
             },)
             .send()
             .await
-            .map_err(|e| IngestorError::Other(format!("OpenAI API error: {}", e),),)?;
+            .map_err(|e| request_error("OpenAI", e,),)?;
 
         if !response.status().is_success() {
             let status = response.status();
             let error_text = response.text().await.unwrap_or_default();
-            return Err(IngestorError::Other(format!(
-                "OpenAI API error: {} - {}",
-                status, error_text
-            ),),);
+            return Err(status_error("OpenAI", status, &error_text,),);
         }
 
         let result: OpenAIResponse = response.json().await.map_err(|e| {
@@ -76,3 +344,66 @@ impl Embedder for OpenAIEmbedder {
         Ok(result.data.into_iter().map(|d| d.embedding,).collect(),)
     }
 }
+
+/// Default `--ollama-url`.
+pub const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434";
+
+/// Embeddings from an Ollama server's `/api/embed` endpoint.
+pub struct OllamaEmbedder {
+    client:   Client,
+    base_url: String,
+    model:    String,
+}
+
+impl OllamaEmbedder {
+    pub fn new(base_url: Option<String,>, model: Option<String,>,) -> Self {
+        Self {
+            client:   Client::new(),
+            base_url: base_url
+                .unwrap_or_else(|| DEFAULT_OLLAMA_URL.to_string(),)
+                .trim_end_matches('/',)
+                .to_string(),
+            model:    model.unwrap_or_else(|| "nomic-embed-text".to_string(),),
+        }
+    }
+}
+
+#[derive(Serialize,)]
+struct OllamaRequest {
+    model: String,
+    input: Vec<String,>,
+}
+
+#[derive(Deserialize,)]
+struct OllamaResponse {
+    embeddings: Vec<Vec<f32,>,>,
+}
+
+#[async_trait]
+impl Embedder for OllamaEmbedder {
+    async fn generate_embeddings(&self, texts: &[String],) -> Result<Vec<Vec<f32,>,>,> {
+        if texts.is_empty() {
+            return Ok(vec![],);
+        }
+
+        let response = self
+            .client
+            .post(format!("{}/api/embed", self.base_url),)
+            .json(&OllamaRequest { model: self.model.clone(), input: texts.to_vec(), },)
+            .send()
+            .await
+            .map_err(|e| request_error("Ollama", e,),)?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(status_error("Ollama", status, &error_text,),);
+        }
+
+        let result: OllamaResponse = response.json().await.map_err(|e| {
+            IngestorError::Other(format!("Failed to parse Ollama response: {}", e),)
+        },)?;
+
+        Ok(result.embeddings,)
+    }
+}
@@ -0,0 +1,119 @@
+// nc_ingestor/src/excel_input.rs
+// Reads Excel workbooks as records, one per spreadsheet row below the header row.
+
+use std::path::Path;
+
+use calamine::{Data, Range, Reader, open_workbook_auto};
+use serde_json::{Map, Value};
+
+use crate::csv_input::RowTrim;
+use crate::error::{IngestorError, Result};
+use crate::ingestor::RecordSet;
+use crate::json_input::infer_schema;
+
+pub fn is_excel(path: &Path,) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str(),)
+        .is_some_and(|ext| ["xlsx", "xlsm", "xls"].iter().any(|e| ext.eq_ignore_ascii_case(e,),),)
+}
+
+/// Reads one sheet of a workbook: `sheet` by name, or the first sheet when unset. The first row
+/// after `trim.skip_rows` holds the column names (blank ones become `column_N`), and fully empty
+/// rows are skipped. Cells keep their spreadsheet types: whole numbers become integers, dates
+/// and times ISO 8601 strings, empty and error cells null. The schema is inferred from all rows.
+pub fn read_records(path: &Path, sheet: Option<&str,>, trim: RowTrim,) -> Result<RecordSet,> {
+    let range = sheet_range(path, sheet,)?;
+
+    let mut rows = range.rows().skip(trim.skip_rows,);
+    let headers = match rows.next() {
+        Some(header_row,) => header_names(header_row,),
+        None => return Ok(RecordSet::default(),),
+    };
+
+    let mut records: Vec<Value,> = rows
+        .filter(|row| row.iter().any(|cell| !matches!(cell, Data::Empty),),)
+        .map(|row| {
+            let obj: Map<String, Value,> =
+                headers.iter().cloned().zip(row.iter().map(cell_value,),).collect();
+            Value::Object(obj,)
+        },)
+        .collect();
+    records.truncate(records.len().saturating_sub(trim.skip_footer_rows,),);
+
+    if records.is_empty() {
+        return Ok(RecordSet { rows: records, schema: None, columns: headers, },);
+    }
+    let schema = infer_schema(&records,);
+    Ok(RecordSet { rows: records, schema: Some(schema,), columns: headers, },)
+}
+
+/// `sheet` of the workbook at `path`, or its first sheet when unset.
+fn sheet_range(path: &Path, sheet: Option<&str,>,) -> Result<Range<Data,>,> {
+    let mut workbook = open_workbook_auto(path,).map_err(|e| excel_error(path, e,),)?;
+    let name = match sheet {
+        Some(name,) => name.to_string(),
+        None => workbook.sheet_names().into_iter().next().ok_or_else(|| {
+            IngestorError::IngestionError(format!("{} has no sheets", path.display()),)
+        },)?,
+    };
+    if !workbook.sheet_names().contains(&name,) {
+        return Err(IngestorError::ConfigurationError(format!(
+            "{} has no sheet named '{}' (sheets: {})",
+            path.display(),
+            name,
+            workbook.sheet_names().join(", ")
+        ),),);
+    }
+    workbook.worksheet_range(&name,).map_err(|e| excel_error(path, e,),)
+}
+
+/// Column names of a sheet's header row, as [`read_records`] names them, without turning the
+/// rows below into records.
+pub fn read_headers(path: &Path, sheet: Option<&str,>, trim: RowTrim,) -> Result<Vec<String,>,> {
+    let range = sheet_range(path, sheet,)?;
+    Ok(range.rows().nth(trim.skip_rows,).map(header_names,).unwrap_or_default(),)
+}
+
+/// Header cells as column names; blank ones become `column_N`.
+fn header_names(header_row: &[Data],) -> Vec<String,> {
+    header_row
+        .iter()
+        .enumerate()
+        .map(|(i, cell,)| match cell.to_string().trim() {
+            "" => format!("column_{}", i + 1),
+            header => header.to_string(),
+        },)
+        .collect()
+}
+
+fn cell_value(cell: &Data,) -> Value {
+    match cell {
+        Data::Empty | Data::Error(_,) => Value::Null,
+        Data::Bool(b,) => Value::Bool(*b,),
+        Data::Int(i,) => Value::from(*i,),
+        // Excel stores every number as a double, so whole values are read back as integers
+        Data::Float(f,) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => {
+            Value::from(*f as i64,)
+        },
+        Data::Float(f,) => Value::from(*f,),
+        Data::String(s,) | Data::DateTimeIso(s,) | Data::DurationIso(s,) => {
+            Value::String(s.clone(),)
+        },
+        Data::DateTime(datetime,) if datetime.is_duration() => {
+            datetime.as_duration().map_or(Value::Null, |d| Value::String(d.to_string(),),)
+        },
+        Data::DateTime(datetime,) => datetime.as_datetime().map_or(Value::Null, |t| {
+            // Dates without a time of day are stored as plain dates
+            let formatted = if t.time() == chrono::NaiveTime::MIN {
+                t.date().to_string()
+            } else {
+                t.format("%Y-%m-%dT%H:%M:%S%.f",).to_string()
+            };
+            Value::String(formatted,)
+        },),
+    }
+}
+
+fn excel_error(path: &Path, e: impl std::fmt::Display,) -> IngestorError {
+    IngestorError::IngestionError(format!("Failed to read Excel {}: {}", path.display(), e),)
+}
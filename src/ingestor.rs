@@ -2,31 +2,434 @@
 // Core ingestion logic and traits.
 
 use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
-use nc_reader::nc_reader_result::DataReaderResult;
+use nc_reader::nc_reader_result::{DataReaderResult, FileMetadata, RecordStream};
+use nc_schema::DataType;
+use sha2::{Digest, Sha256};
 
-use crate::error::Result; // Assuming this path is correct
+use crate::defaults::defaults;
+use crate::embeddings::EmbeddingProvider;
+use crate::error::{IngestorError, Result}; // Assuming this path is correct
+use crate::mongo::MongoOptions;
+use crate::nested::NestedMode;
+use crate::postgres::PostgresOptions;
+use crate::qdrant::QdrantOptions;
+use crate::schema_builder::{
+    ColumnOrder, ColumnType, PartitionConfig, SchemaDiff, TableComments,
+};
+use crate::sqlite::SqliteOptions;
+use crate::transform::FieldFilter;
+use crate::weaviate::WeaviateOptions;
 
 /// Configuration for an ingestor.
-#[derive(Debug, Clone,)]
+#[derive(Debug, Clone, Default, serde::Deserialize,)]
+#[serde(default)]
 pub struct IngestorConfig {
     // Common configuration options for all ingestors
-    pub database_url:    String,
-    pub collection_name: Option<String,>,
-    pub vector_size:     Option<u64,>,
-    pub mappings:        Option<HashMap<String, String,>,>,
-    pub openai_api_key:  Option<String,>,
-    pub embed_field:     Option<String,>,
-    pub relationships:   Option<Vec<RelationshipConfig,>,>,
+    #[serde(alias = "uri")]
+    pub database_url:       String,
+    pub collection_name:    Option<String,>,
+    pub vector_size:        Option<u64,>,
+    pub mappings:           Option<HashMap<String, String,>,>,
+    pub openai_api_key:     Option<String,>,
+    pub openai_headers:     Option<Vec<(String, String,),>,>,
+    /// Embedding providers in fallback order (`--embedder`); empty means OpenAI alone
+    pub embedders:          Vec<EmbeddingProvider,>,
+    pub ollama_url:         Option<String,>,
+    pub ollama_model:       Option<String,>,
+    pub embed_field:        Option<String,>,
+    pub id_field:           Option<String,>,
+    pub label_field:        Option<String,>,
+    pub relationships:      Option<Vec<RelationshipConfig,>,>,
+    pub unwrap_variant:     bool,
+    /// Retries while connecting, separate from the per-operation backoff. `None` uses
+    /// [`DEFAULT_CONNECTION_RETRIES`].
+    pub connection_retries: Option<u32,>,
+    /// Consecutive transient failures of one write after which a single-client backend (Neo4j,
+    /// MongoDB) rebuilds its client. `None` never reconnects.
+    pub reconnect_after:    Option<u32,>,
+    /// Decimal places kept for embedding components and `Float`/`Number` columns. `None` or 0
+    /// stores values unrounded.
+    pub float_precision:    Option<u32,>,
+    /// When an existing SQL table's columns differ from the inferred schema, write to the next
+    /// free `<table>_N` instead.
+    pub rename_on_conflict: bool,
+    /// Compare the inferred schema with the existing SQL table and log the differences instead
+    /// of writing anything (`--dry-run-schema-diff`).
+    pub schema_diff:        bool,
+    /// Connections a pooled backend (SQLite) may hold open. The CLI sets it to `--concurrency`.
+    pub pool_size:          Option<u32,>,
+    /// SQL column types forced on fields instead of inferred (`--type-override`)
+    pub type_overrides:     HashMap<String, ColumnType,>,
+    /// Column order of created SQL tables
+    pub column_order:       ColumnOrder,
+    /// Collation of string columns in created SQL tables
+    pub collation:          Option<String,>,
+    /// How typed SQL tables store array and object columns (`--normalize-nested`)
+    pub normalize_nested:   NestedMode,
+    /// Table and column comments set on created SQL tables (Postgres only)
+    pub comments:           TableComments,
+    pub partition:          Option<PartitionConfig,>,
+    pub mongo:              MongoOptions,
+    pub postgres:           PostgresOptions,
+    pub qdrant:             QdrantOptions,
+    pub sqlite:             SqliteOptions,
+    pub weaviate:           WeaviateOptions,
+    pub batch:              BatchConfig,
+    /// `--include-fields` / `--exclude-fields`, applied by [`blob_value`] to blob fallbacks.
+    /// Structured records are filtered before they reach the ingestor. Pipeline targets take it
+    /// from their own field lists.
+    #[serde(skip)]
+    pub field_filter:       FieldFilter,
+    /// Library hook fired after each batch a backend commits. Not settable from config files.
+    #[serde(skip)]
+    pub on_batch_committed: Option<BatchCallback,>,
+}
+
+impl IngestorConfig {
+    /// Target collection (or class, or label), falling back to [`defaults`].
+    pub fn collection_name(&self,) -> String {
+        self.collection_name.clone().unwrap_or_else(|| defaults().collection_name,)
+    }
+
+    /// Target SQL table: `collection_name`, falling back to [`defaults`].
+    pub fn table_name(&self,) -> String {
+        self.collection_name.clone().unwrap_or_else(|| defaults().sql_table_name,)
+    }
+
+    /// Vector size of created collections, falling back to [`defaults`].
+    pub fn vector_size(&self,) -> u64 {
+        self.vector_size.unwrap_or_else(|| defaults().vector_size,)
+    }
+
+    /// `embedders` in fallback order. Without any, OpenAI is used when an API key is set; an
+    /// explicitly listed OpenAI without a key is an error.
+    pub fn embedding_providers(&self,) -> Result<Vec<EmbeddingProvider,>,> {
+        if self.embedders.is_empty() {
+            return Ok(self.openai_api_key.iter().map(|_| EmbeddingProvider::Openai,).collect(),);
+        }
+        if self.embedders.contains(&EmbeddingProvider::Openai,) && self.openai_api_key.is_none() {
+            return Err(IngestorError::ConfigurationError(
+                "--embedder openai needs an API key (--openai-api-key or OPENAI_API_KEY)"
+                    .to_string(),
+            ),);
+        }
+        Ok(self.embedders.clone(),)
+    }
+
+    /// Retries allowed while establishing the connection.
+    pub fn connection_retries(&self,) -> u32 {
+        self.connection_retries.unwrap_or(DEFAULT_CONNECTION_RETRIES,)
+    }
+
+    /// `float_precision` when rounding is enabled.
+    pub fn float_precision(&self,) -> Option<u32,> {
+        self.float_precision.filter(|&decimals| decimals > 0,)
+    }
+
+    /// Reports a committed batch of `records` records written to `target` to
+    /// `on_batch_committed`, if set, and to the running [`CommitTracker`].
+    pub fn batch_committed(&self, target: &str, records: usize,) {
+        if let Some(callback,) = &self.on_batch_committed {
+            callback.call(target, records,);
+        }
+        if let Some(tracker,) = CommitTracker::current() {
+            tracker.add(records,);
+        }
+    }
+}
+
+/// Progress of one committed batch, passed to [`IngestorConfig::on_batch_committed`], and what
+/// [`Ingestor::flush`] wrote to each target.
+#[derive(Debug, Clone, Default, PartialEq, Eq,)]
+pub struct IngestStats {
+    /// Table, collection or Neo4j label the batch was written to
+    pub target:  String,
+    /// Records in the batch
+    pub records: usize,
+}
+
+/// Callback invoked with [`IngestStats`] after every committed batch, e.g. to drive external
+/// progress tracking or metrics. It runs on the ingesting task (inside `spawn_blocking` for
+/// SQLite), so it should return quickly.
+#[derive(Clone,)]
+pub struct BatchCallback(pub Arc<dyn Fn(IngestStats,) + Send + Sync,>,);
+
+impl BatchCallback {
+    pub fn new(callback: impl Fn(IngestStats,) + Send + Sync + 'static,) -> Self {
+        BatchCallback(Arc::new(callback,),)
+    }
+
+    pub fn call(&self, target: &str, records: usize,) {
+        (self.0)(IngestStats {
+            target: target.to_string(),
+            records,
+        },);
+    }
+}
+
+impl std::fmt::Debug for BatchCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_,>,) -> std::fmt::Result {
+        f.write_str("BatchCallback",)
+    }
+}
+
+tokio::task_local! {
+    static COMMIT_TRACKER: CommitTracker;
+}
+
+/// Running count of one file's records that backends have committed, for batch-level
+/// checkpoints. Set for the file's ingestion with [`CommitTracker::scope`]; every
+/// [`IngestorConfig::batch_committed`] inside it adds to the count and reports the new total.
+/// Records a backend leaves out on purpose are counted apart, with [`CommitTracker::skip`].
+#[derive(Clone,)]
+pub struct CommitTracker {
+    committed: Arc<AtomicUsize,>,
+    skipped:   Arc<AtomicUsize,>,
+    on_commit: Arc<dyn Fn(usize,) + Send + Sync,>,
+}
+
+impl CommitTracker {
+    /// Starts counting at `committed`, the records already committed by an earlier run.
+    pub fn new(committed: usize, on_commit: impl Fn(usize,) + Send + Sync + 'static,) -> Self {
+        CommitTracker {
+            committed: Arc::new(AtomicUsize::new(committed,),),
+            skipped:   Arc::new(AtomicUsize::new(0,),),
+            on_commit: Arc::new(on_commit,),
+        }
+    }
+
+    /// Runs `ingest` with this tracker receiving the commits it reports.
+    pub async fn scope<T,>(self, ingest: impl Future<Output = T,>,) -> T {
+        COMMIT_TRACKER.scope(self, ingest,).await
+    }
+
+    /// The tracker of the running ingestion, if any. Backends that commit inside
+    /// `spawn_blocking` take it along, since the blocking thread can't see it.
+    pub fn current() -> Option<Self,> {
+        COMMIT_TRACKER.try_with(Clone::clone,).ok()
+    }
+
+    /// Records committed so far, counting those of the earlier run.
+    pub fn committed(&self,) -> usize {
+        self.committed.load(Ordering::SeqCst,)
+    }
+
+    pub fn add(&self, records: usize,) {
+        let total = self.committed.fetch_add(records, Ordering::SeqCst,) + records;
+        (self.on_commit)(total,);
+    }
+
+    /// Records skipped so far, e.g. by `--on-empty-embedding skip`.
+    pub fn skipped(&self,) -> usize {
+        self.skipped.load(Ordering::SeqCst,)
+    }
+
+    pub fn skip(&self, records: usize,) {
+        self.skipped.fetch_add(records, Ordering::SeqCst,);
+    }
+}
+
+impl std::fmt::Debug for CommitTracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_,>,) -> std::fmt::Result {
+        f.debug_struct("CommitTracker",)
+            .field("committed", &self.committed.load(Ordering::SeqCst,),)
+            .field("skipped", &self.skipped.load(Ordering::SeqCst,),)
+            .finish()
+    }
+}
+
+/// Files an ingestor that [`Ingestor::defers_writes`] accepted while their records may still be
+/// waiting for [`Ingestor::flush`]. They only count as ingested once the flush succeeds.
+#[derive(Debug, Default,)]
+pub struct DeferredFiles {
+    files: std::sync::Mutex<Vec<String,>,>,
+}
+
+impl DeferredFiles {
+    pub fn push(&self, file: &str,) {
+        self.files.lock().unwrap().push(file.to_string(),);
+    }
+
+    /// Flushes `ingestor` and hands back the files that were waiting on it with the outcome.
+    /// If the flush failed, their records may not have been written.
+    pub async fn flush(
+        &self,
+        ingestor: &dyn Ingestor,
+    ) -> (Vec<String,>, Result<Vec<IngestStats,>,>,) {
+        let result = ingestor.flush().await;
+        (std::mem::take(&mut *self.files.lock().unwrap(),), result,)
+    }
+}
+
+/// Default number of retries while connecting to a backend.
+pub const DEFAULT_CONNECTION_RETRIES: u32 = 3;
+
+/// Default number of records written per batch.
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Batching knobs shared by every backend that writes in batches (SQLite and Postgres commit
+/// each batch separately, Qdrant upserts batches of points, MongoDB uses `insert_many`, Neo4j
+/// merges each batch in one transaction).
+///
+/// Defaults: 1000 records, ordered, no timeout, no ordering guarantee.
+#[derive(Debug, Clone, serde::Deserialize,)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatchConfig {
+    /// Records per write batch
+    pub size:           usize,
+    /// Stop a batch at its first failed record. Unordered writes let the rest of the batch
+    /// through where the backend supports it (MongoDB).
+    pub ordered:        bool,
+    /// Fail a single batch write that takes longer than this. Timed-out batches are rolled back
+    /// and retried like other transient database errors, except on SQLite, where the file fails.
+    #[serde(rename = "timeout_ms", deserialize_with = "deserialize_millis")]
+    pub timeout:        Option<Duration,>,
+    /// Records must land in source order: batches are always ordered and backends that can
+    /// apply writes out of order (Qdrant replicas) are asked for strong ordering.
+    pub preserve_order: bool,
+    /// Let backends that support it (MongoDB) fill batches with records from several files and
+    /// write the remainder in [`Ingestor::flush`], instead of flushing every file on its own.
+    pub across_files:   bool,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            size:           DEFAULT_BATCH_SIZE,
+            ordered:        true,
+            timeout:        None,
+            preserve_order: false,
+            across_files:   false,
+        }
+    }
+}
+
+impl BatchConfig {
+    /// Batch size, never zero.
+    pub fn size(&self,) -> usize {
+        self.size.max(1,)
+    }
+
+    /// Whether a batch stops at its first failed record. Always true with `preserve_order`.
+    pub fn is_ordered(&self,) -> bool {
+        self.ordered || self.preserve_order
+    }
+
+    /// Runs one batch write under the configured timeout.
+    pub async fn run<T,>(&self, write: impl Future<Output = Result<T,>,>,) -> Result<T,> {
+        match self.timeout {
+            Some(timeout,) => {
+                tokio::time::timeout(timeout, write,).await.map_err(|_| self.timeout_error(),)?
+            },
+            None => write.await,
+        }
+    }
+
+    /// When a batch starting now runs out of time, for writes that can't be wrapped in
+    /// [`BatchConfig::run`] (SQLite's blocking inserts check it between rows).
+    pub fn deadline(&self,) -> Option<Instant,> {
+        self.timeout.map(|timeout| Instant::now() + timeout,)
+    }
+
+    /// Fails once `deadline` has passed, with the same error as a timed-out [`BatchConfig::run`].
+    pub fn check_deadline(&self, deadline: Option<Instant,>,) -> Result<(),> {
+        match deadline {
+            Some(deadline,) if Instant::now() > deadline => Err(self.timeout_error(),),
+            _ => Ok((),),
+        }
+    }
+
+    fn timeout_error(&self,) -> IngestorError {
+        IngestorError::DatabaseError(format!(
+            "Batch write timeout after {} ms",
+            self.timeout.unwrap_or_default().as_millis()
+        ),)
+    }
+}
+
+fn deserialize_millis<'de, D,>(
+    deserializer: D,
+) -> std::result::Result<Option<Duration,>, D::Error,>
+where
+    D: serde::Deserializer<'de,>,
+{
+    let millis: Option<u64,> = serde::Deserialize::deserialize(deserializer,)?;
+    Ok(millis.map(Duration::from_millis,),)
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize,)]
+#[serde(deny_unknown_fields)]
 pub struct RelationshipConfig {
     pub source_field:      String, // e.g. "user_id"
     pub target_label:      String, // e.g. "User"
     pub target_field:      String, // e.g. "id"
     pub relationship_type: String, // e.g. "BELONGS_TO"
+    #[serde(default)]
+    pub direction:         RelationshipDirection,
+}
+
+/// Which way a [`RelationshipConfig`] edge points, seen from the record's node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize,)]
+#[serde(rename_all = "lowercase")]
+pub enum RelationshipDirection {
+    /// `(record)-[:TYPE]->(target)`
+    #[default]
+    Outgoing,
+    /// `(record)<-[:TYPE]-(target)`
+    Incoming,
+    /// One edge each way, so directed queries from either end find it
+    Undirected,
+}
+
+/// Rows that are already structured, together with the schema typed backends create tables from.
+/// Used when records are produced outside nc_reader, e.g. from the schema cache.
+#[derive(Debug, Clone, Default,)]
+pub struct RecordSet {
+    pub rows:    Vec<serde_json::Value,>,
+    pub schema:  Option<HashMap<String, DataType,>,>,
+    /// Field names in the source's order, such as a CSV header, for `--column-order source`.
+    /// Empty when the source has no order of its own.
+    pub columns: Vec<String,>,
+}
+
+/// Original content of an ingested file, stored in a `<name>_raw` companion table/collection so
+/// structured rows can be traced back to their source.
+#[derive(Debug, Clone, serde::Serialize,)]
+pub struct RawFile {
+    pub path:    String,
+    pub size:    u64,
+    pub sha256:  String,
+    /// File content when it is valid UTF-8; binary files are only referenced by hash.
+    pub content: Option<String,>,
+}
+
+impl RawFile {
+    pub fn read(path: &Path,) -> Result<Self,> {
+        let bytes = std::fs::read(path,)?;
+        let sha256 = format!("{:x}", Sha256::digest(&bytes));
+        Ok(RawFile {
+            path: path.to_string_lossy().to_string(),
+            size: bytes.len() as u64,
+            sha256,
+            content: String::from_utf8(bytes,).ok(),
+        },)
+    }
+
+    pub fn to_value(&self,) -> serde_json::Value {
+        serde_json::to_value(self,).unwrap_or_default()
+    }
+}
+
+/// Name of the companion table/collection holding raw files for `name`.
+pub fn raw_target_name(name: &str,) -> String {
+    format!("{}_raw", name)
 }
 
 /// Trait for all data ingestors.
@@ -39,6 +442,134 @@ pub trait Ingestor: Send + Sync {
 
     /// Ingests data into the target database.
     async fn ingest(&self, data: DataReaderResult,) -> Result<(),>;
+
+    /// Ingests pre-structured records. SQL backends use `schema` like an inferred CSV schema;
+    /// by default the rows are handed to `ingest` as a record stream.
+    async fn ingest_records(&self, records: RecordSet,) -> Result<(),> {
+        let metadata = FileMetadata {
+            size:       0,
+            line_count: Some(records.rows.len() as _,),
+        };
+        let stream: RecordStream = Box::new(records.rows.into_iter().map(Ok,),);
+        self.ingest(DataReaderResult::Stream(stream, metadata,),).await
+    }
+
+    /// Whether CSV and line-delimited JSON files should reach `ingest` as a row stream rather
+    /// than read whole. Worth it for backends that don't need an inferred schema.
+    fn streams_csv(&self,) -> bool {
+        false
+    }
+
+    /// Stores a file's original content next to its structured rows (`--store-raw`).
+    async fn ingest_raw(&self, _raw: RawFile,) -> Result<(),> {
+        Err(IngestorError::ConfigurationError(
+            "Storing raw files is not supported by this backend".to_string(),
+        ),)
+    }
+
+    /// Number of records already present in the target, or `None` if the backend can't count.
+    /// A target that doesn't exist yet counts as zero.
+    async fn target_count(&self,) -> Result<Option<u64,>,> {
+        Ok(None,)
+    }
+
+    /// Removes the whole target (table, collection or labeled nodes) for the `clean` subcommand
+    /// and describes what was removed.
+    async fn clean(&self,) -> Result<String,> {
+        Err(IngestorError::ConfigurationError(
+            "Cleaning targets is not supported by this backend".to_string(),
+        ),)
+    }
+
+    /// Runs user SQL (`--pre-sql` / `--post-sql`), possibly several `;`-separated statements,
+    /// against the target database.
+    async fn execute_sql(&self, _sql: &str,) -> Result<(),> {
+        Err(IngestorError::ConfigurationError(
+            "SQL hooks are only supported by SQL backends".to_string(),
+        ),)
+    }
+
+    /// Tables compared by `--dry-run-schema-diff` so far, for the run report. Only SQL backends
+    /// compare schemas.
+    fn schema_diffs(&self,) -> Vec<SchemaDiff,> {
+        Vec::new()
+    }
+
+    /// Whether a successful `ingest` may leave records that only `flush` writes or confirms, so a
+    /// file must not count as ingested before the flush succeeds.
+    fn defers_writes(&self,) -> bool {
+        false
+    }
+
+    /// Writes records buffered across files (`batch.across_files`) and reports how many went to
+    /// each table or collection, or waits for writes sent without confirmation. Runs once after
+    /// the last file, whether or not every file succeeded, and before `finalize`.
+    ///
+    /// This is separate from `finalize` because buffered records belong to files that did
+    /// succeed and must be written even when others failed, while `finalize` is skipped then.
+    async fn flush(&self,) -> Result<Vec<IngestStats,>,> {
+        Ok(Vec::new(),)
+    }
+
+    /// Runs once at the end of a run in which every file was ingested successfully, for work
+    /// that must not happen per file (e.g. switching an alias). Writing buffered records
+    /// belongs in `flush`, which also runs after failed files.
+    async fn finalize(&self,) -> Result<(),> {
+        Ok((),)
+    }
+}
+
+/// Serializes a reader result for the blob fallback paths.
+///
+/// By default the externally tagged enum is stored as-is (e.g. `{"Text":{...}}`). With
+/// `unwrap_variant` the variant tag is stripped and only the inner content is kept, so the stored
+/// document can be queried without knowing which reader produced it. The config's
+/// `field_filter` applies to the keys of the inner content either way, so excluded fields never
+/// reach the database through a blob.
+pub fn blob_value(data: &DataReaderResult, config: &IngestorConfig,) -> Result<serde_json::Value,> {
+    let mut value = serde_json::to_value(data,)
+        .map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
+    if let serde_json::Value::Object(obj,) = &mut value
+        && obj.len() == 1
+        && let Some(inner,) = obj.values_mut().next()
+    {
+        *inner = config.field_filter.filter_record(inner.take(),);
+    }
+
+    if !config.unwrap_variant {
+        return Ok(value,);
+    }
+
+    match value {
+        serde_json::Value::Object(obj,) if obj.len() == 1 => {
+            Ok(obj.into_iter().next().map(|(_, inner,)| inner,).unwrap_or_default(),)
+        },
+        other => Ok(other,),
+    }
+}
+
+/// Stable SHA-256 hex digest of a record's JSON serialization. Object keys are sorted first
+/// (maps keep source order), so equal records always hash the same across runs and Rust versions.
+pub fn content_hash(value: &serde_json::Value,) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(sorted_keys(value,).to_string().as_bytes(),);
+    format!("{:x}", hasher.finalize())
+}
+
+fn sorted_keys(value: &serde_json::Value,) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(obj,) => {
+            let mut entries: Vec<_,> = obj.iter().collect();
+            entries.sort_by_key(|(key, _,)| *key,);
+            serde_json::Value::Object(
+                entries.into_iter().map(|(key, v,)| (key.clone(), sorted_keys(v,),),).collect(),
+            )
+        },
+        serde_json::Value::Array(items,) => {
+            serde_json::Value::Array(items.iter().map(sorted_keys,).collect(),)
+        },
+        other => other.clone(),
+    }
 }
 
 // Example concrete ingestor (conceptual)
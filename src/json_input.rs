@@ -0,0 +1,336 @@
+// nc_ingestor/src/json_input.rs
+// Reads `.json` files holding a top-level array of objects, or a single object, and
+// line-delimited `.jsonl` / `.ndjson` files as records.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::Arc;
+
+use nc_reader::error::DataReaderError;
+use nc_reader::nc_reader_result::{DataReaderResult, FileMetadata, RecordStream};
+use nc_schema::DataType;
+use serde::de::{DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+
+use crate::csv_input::STREAM_SAMPLE_ROWS;
+use crate::error::{IngestorError, Result};
+use crate::ingestor::RecordSet;
+
+pub fn is_json(path: &Path,) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str(),)
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json",),)
+}
+
+/// Whether `path` holds line-delimited JSON: one document per line, `.jsonl` or `.ndjson`.
+pub fn is_json_lines(path: &Path,) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str(),)
+        .is_some_and(|ext| ["jsonl", "ndjson"].iter().any(|e| ext.eq_ignore_ascii_case(e,),),)
+}
+
+/// Which `.json` documents are read as records instead of blobs.
+#[derive(Debug, Clone, Default, PartialEq,)]
+pub struct JsonLayout {
+    /// A top-level array of objects becomes one record per element (`--json-array`)
+    pub arrays:        bool,
+    /// A single top-level object becomes one record
+    pub objects:       bool,
+    /// Column types over every single-object file of the run (see [`object_schema`]). Such a
+    /// file is typed against them instead of on its own, so a field that is null in one file
+    /// takes its type from the others.
+    pub object_schema: Option<Arc<HashMap<String, DataType,>,>,>,
+}
+
+impl JsonLayout {
+    pub fn is_empty(&self,) -> bool {
+        !self.arrays && !self.objects
+    }
+}
+
+/// Column types over every `.json` file in `paths` that holds a single top-level object, as if
+/// the files were the rows of one document. Each file is parsed and dropped in turn; files that
+/// aren't valid JSON are left for their own read to report.
+pub fn object_schema<'a,>(
+    paths: impl IntoIterator<Item = &'a Path,>,
+) -> HashMap<String, DataType,> {
+    let mut inference = SchemaInference::default();
+    for path in paths.into_iter().filter(|path| is_json(path,),) {
+        let Ok(file,) = std::fs::File::open(path,) else { continue };
+        let reader = std::io::BufReader::new(file,);
+        if let Ok(object @ Value::Object(_,),) = serde_json::from_reader::<_, Value,>(reader,) {
+            inference.add(&object,);
+        }
+    }
+    inference.finish()
+}
+
+/// First key of the first record of a `.json` or line-delimited JSON file, as the file writes
+/// it: of the top-level object, the first element of a top-level array or the first line.
+/// Parsed records don't keep their key order, and parsing stops at that key, so the rest of the
+/// file is never read.
+pub fn first_key(path: &Path,) -> Result<Option<String,>,> {
+    let file = std::fs::File::open(path,)?;
+    let found = RefCell::new(None,);
+    let mut document = serde_json::Deserializer::from_reader(std::io::BufReader::new(file,),);
+    // Stopping early leaves the document unfinished, which serde_json reports as an error
+    let _ = FirstKey(&found,).deserialize(&mut document,);
+    Ok(found.into_inner(),)
+}
+
+/// Records the first key of an object, or of an array's first element, and stops there.
+struct FirstKey<'a,>(&'a RefCell<Option<String,>,>,);
+
+impl<'de,> DeserializeSeed<'de,> for FirstKey<'_,> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de,>,>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<(), D::Error,> {
+        deserializer.deserialize_any(self,)
+    }
+}
+
+impl<'de,> Visitor<'de,> for FirstKey<'_,> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_,>,) -> std::fmt::Result {
+        f.write_str("a JSON object or an array of objects",)
+    }
+
+    fn visit_map<A: MapAccess<'de,>,>(self, mut map: A,) -> std::result::Result<(), A::Error,> {
+        *self.0.borrow_mut() = map.next_key()?;
+        Ok((),)
+    }
+
+    fn visit_seq<A: SeqAccess<'de,>,>(self, mut seq: A,) -> std::result::Result<(), A::Error,> {
+        seq.next_element_seed(self,)?;
+        Ok((),)
+    }
+}
+
+/// Parses `path` and returns its elements as records with an inferred schema when the document is
+/// a top-level array of objects. Anything else returns `None` so the caller can fall back to the
+/// reader (and its blob handling).
+pub fn read_array(path: &Path,) -> Result<Option<RecordSet,>,> {
+    read_records(path, &JsonLayout { arrays: true, ..Default::default() },)
+}
+
+/// Parses `path` and returns the records of the document shapes `layout` enables, with an
+/// inferred schema: the elements of a top-level array of objects, or a top-level object as a
+/// single record typed against `layout.object_schema`. Other documents return `None` so the
+/// caller can fall back to the reader.
+pub fn read_records(path: &Path, layout: &JsonLayout,) -> Result<Option<RecordSet,>,> {
+    let file = std::fs::File::open(path,)?;
+    let document: Value = serde_json::from_reader(std::io::BufReader::new(file,),).map_err(|e| {
+        IngestorError::IngestionError(format!("Failed to parse JSON {}: {}", path.display(), e),)
+    },)?;
+
+    let (rows, schema,) = match document {
+        Value::Array(rows,) if layout.arrays && rows.iter().all(Value::is_object,) => {
+            let schema = infer_schema(&rows,);
+            (rows, schema,)
+        },
+        object @ Value::Object(_,) if layout.objects => {
+            let mut schema = infer_schema(std::slice::from_ref(&object,),);
+            if let Some(shared,) = &layout.object_schema {
+                schema.extend(shared.iter().map(|(k, v,)| (k.clone(), v.clone(),),),);
+            }
+            (vec![typed_line(object, &schema,)], schema,)
+        },
+        _ => return Ok(None,),
+    };
+    Ok(Some(RecordSet { rows, schema: Some(schema,), columns: Vec::new(), },),)
+}
+
+/// A line-delimited JSON file being read: the column types of its sampled head and a lazy
+/// stream of every record typed against them.
+pub struct JsonLines {
+    pub schema: HashMap<String, DataType,>,
+    rows:       RecordStream,
+    metadata:   FileMetadata,
+}
+
+impl JsonLines {
+    /// The records as a stream, for backends that take rows one batch at a time.
+    pub fn into_reader(self,) -> DataReaderResult {
+        DataReaderResult::Stream(self.rows, self.metadata,)
+    }
+
+    /// Every record read into memory, with the sampled columns. Since every record is at hand,
+    /// their types are inferred over all of them, so a later value that doesn't fit the sampled
+    /// type widens the column instead of reaching a typed SQL column as a string.
+    pub fn into_records(self,) -> Result<RecordSet,> {
+        let rows = self
+            .rows
+            .collect::<std::result::Result<Vec<Value,>, _,>>()
+            .map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
+        let mut schema = self.schema;
+        let types = infer_schema(&rows,);
+        for (column, data_type,) in &mut schema {
+            if let Some(widest,) = types.get(column,) {
+                *data_type = widest.clone();
+            }
+        }
+        let rows = rows.into_iter().map(|row| typed_line(row, &schema,),).collect();
+        let schema = (!schema.is_empty()).then_some(schema,);
+        Ok(RecordSet { rows, schema, columns: Vec::new(), },)
+    }
+}
+
+/// Reads a line-delimited JSON file, one object per line, as records. Lines are parsed one at a
+/// time and blank lines are skipped. Column types are inferred from the first `sample` records
+/// ([`STREAM_SAMPLE_ROWS`] when unset), like [`infer_schema`], and only that head is read up
+/// front; later records are typed against it as they are read. Fields the head never has
+/// aren't in the schema.
+pub fn read_lines(path: &Path, sample: Option<usize,>,) -> Result<JsonLines,> {
+    let metadata = FileMetadata {
+        size:       std::fs::metadata(path,)?.len(),
+        line_count: None,
+    };
+    let file = std::fs::File::open(path,)?;
+    let name = path.display().to_string();
+    let mut lines = std::io::BufReader::new(file,)
+        .lines()
+        .enumerate()
+        .filter(|(_, line,)| !line.as_ref().is_ok_and(|line| line.trim().is_empty(),),)
+        .map(move |(index, line,)| parse_line(index + 1, line?, &name,),);
+    let head = lines
+        .by_ref()
+        .take(sample.unwrap_or(STREAM_SAMPLE_ROWS,),)
+        .collect::<Result<Vec<Value,>,>>()?;
+    let schema = infer_schema(&head,);
+
+    let columns = schema.clone();
+    let rows: RecordStream = Box::new(head.into_iter().map(Ok,).chain(lines,).map(move |row| {
+        row.map(|row| typed_line(row, &columns,),)
+            .map_err(|e| DataReaderError::Other(e.to_string(),),)
+    },),);
+    Ok(JsonLines { schema, rows, metadata, },)
+}
+
+fn parse_line(line_no: usize, line: String, path: &str,) -> Result<Value,> {
+    let row: Value = serde_json::from_str(&line,).map_err(|e| {
+        IngestorError::IngestionError(format!(
+            "Failed to parse JSON on line {} of {}: {}",
+            line_no, path, e
+        ),)
+    },)?;
+    if !row.is_object() {
+        return Err(IngestorError::IngestionError(format!(
+            "Line {} of {} is not a JSON object",
+            line_no, path
+        ),),);
+    }
+    Ok(row,)
+}
+
+/// `row` with its values converted to their sampled column's type the way [`infer_schema`]
+/// merges types: integers in a `Float` column become floats, and numbers and booleans in a
+/// `String` column strings. Other values, and fields the sample didn't have, are left as they
+/// are.
+fn typed_line(mut row: Value, schema: &HashMap<String, DataType,>,) -> Value {
+    let Value::Object(obj,) = &mut row else { return row };
+    for (key, value,) in obj.iter_mut() {
+        let column = match schema.get(key,) {
+            Some(DataType::Union(types,),) => types.iter().find(|t| **t != DataType::Null,),
+            column => column,
+        };
+        match (column, &*value,) {
+            (Some(DataType::Float,), Value::Number(n,),) if !n.is_f64() => {
+                if let Some(n,) = n.as_f64().and_then(serde_json::Number::from_f64,) {
+                    *value = Value::Number(n,);
+                }
+            },
+            (Some(DataType::String,), Value::Number(_,) | Value::Bool(_,),) => {
+                *value = Value::String(value.to_string(),);
+            },
+            _ => {},
+        }
+    }
+    row
+}
+
+/// Column types over all `rows`. A column that is null or missing in some rows becomes a
+/// nullable union, integers mixed with floats widen to `Float`, and any other conflict falls back
+/// to `String`.
+pub fn infer_schema(rows: &[Value],) -> HashMap<String, DataType,> {
+    let mut inference = SchemaInference::default();
+    for row in rows {
+        inference.add(row,);
+    }
+    inference.finish()
+}
+
+/// [`infer_schema`] one row at a time, for rows that aren't held together.
+#[derive(Default,)]
+struct SchemaInference {
+    /// Column -> merged type of its non-null values and how many rows had one
+    columns: HashMap<String, (Option<DataType,>, usize,),>,
+    rows:    usize,
+}
+
+impl SchemaInference {
+    fn add(&mut self, row: &Value,) {
+        self.rows += 1;
+        let Value::Object(obj,) = row else { return };
+        for (key, value,) in obj {
+            let (data_type, seen,) = self.columns.entry(key.clone(),).or_insert((None, 0,),);
+            if !value.is_null() {
+                let value_type = value_type(value,);
+                *data_type = Some(match data_type.take() {
+                    Some(existing,) => merge(existing, value_type,),
+                    None => value_type,
+                },);
+                *seen += 1;
+            }
+        }
+    }
+
+    fn finish(self,) -> HashMap<String, DataType,> {
+        let rows = self.rows;
+        self.columns
+            .into_iter()
+            .map(|(key, (data_type, seen,),)| {
+                let data_type = match data_type {
+                    Some(t,) if seen < rows => DataType::Union(vec![t, DataType::Null],),
+                    Some(t,) => t,
+                    None => DataType::Null,
+                };
+                (key, data_type,)
+            },)
+            .collect()
+    }
+}
+
+fn value_type(value: &Value,) -> DataType {
+    match value {
+        Value::Null => DataType::Null,
+        Value::Bool(_,) => DataType::Boolean,
+        Value::Number(n,) if n.is_f64() => DataType::Float,
+        Value::Number(_,) => DataType::Integer,
+        Value::String(_,) => DataType::String,
+        Value::Array(items,) => {
+            let item_type = items.first().map(value_type,).unwrap_or(DataType::Unknown,);
+            DataType::Array(Box::new(item_type,),)
+        },
+        Value::Object(obj,) => {
+            DataType::Object(obj.iter().map(|(k, v,)| (k.clone(), value_type(v,),),).collect(),)
+        },
+    }
+}
+
+fn merge(a: DataType, b: DataType,) -> DataType {
+    match (a, b,) {
+        (a, b,) if a == b => a,
+        (DataType::Integer, DataType::Float,) | (DataType::Float, DataType::Integer,) => {
+            DataType::Float
+        },
+        (a @ DataType::Array(_,), DataType::Array(_,),) => a,
+        (a @ DataType::Object(_,), DataType::Object(_,),) => a,
+        _ => DataType::String,
+    }
+}
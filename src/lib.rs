@@ -1,18 +1,38 @@
 // nc_ingestor/src/lib.rs
 // This file will contain the public API for the nc_ingestor module.
 
+pub mod avro_input;
+pub mod capabilities;
+pub mod checkpoint;
 pub mod cli;
+pub mod csv_input;
+pub mod defaults;
 pub mod embeddings;
 pub mod error;
+pub mod excel_input;
 pub mod ingestor;
+pub mod json_input;
+pub mod manifest;
+pub mod memory;
 pub mod mongo;
 pub mod neo4j;
+pub mod nested;
+pub mod pipeline;
 pub mod postgres;
 pub mod qdrant;
+pub mod record_source;
+pub mod remote_input;
+pub mod report;
 pub mod retry;
 pub mod schema_builder;
+pub mod schema_cache;
+pub mod script;
 pub mod sqlite;
+pub mod telemetry;
+pub mod transform;
+pub mod weaviate;
 
+// Fallbacks for `crate::defaults::Defaults`; ingestors read the defaults, not these constants
 pub const DEFAULT_COLLECTION_NAME: &str = "ingested_nc_collection";
 pub const DEFAULT_VECTOR_SIZE: u64 = 4;
 pub const DEFAULT_SQL_TABLE_NAME: &str = "ingested_data";
@@ -1,269 +1,2469 @@
 // nc_ingestor/src/main.rs
 // This file will contain the main entry point for the nc_ingestor CLI application.
 
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 use clap::Parser;
-use nc_ingestor::cli::{Cli, Commands, MongoArgs, Neo4jArgs, PostgresArgs, QdrantArgs, SqliteArgs};
-use nc_ingestor::error::{IngestorError, Result};
-use nc_ingestor::ingestor::{Ingestor, IngestorConfig};
-use nc_ingestor::mongo::MongoIngestor;
+use nc_ingestor::cli::{
+    CleanArgs, Cli, Commands, CommonIngestorArgs, MemoryArgs, MongoArgs, Neo4jArgs, PipelineArgs,
+    PostgresArgs, QdrantArgs, SqlHookArgs, SqliteArgs, WeaviateArgs,
+};
+use nc_ingestor::avro_input;
+use nc_ingestor::capabilities;
+use nc_ingestor::checkpoint::{Checkpoint, CheckpointGranularity};
+use nc_ingestor::csv_input::{self, CsvLayout, RowTrim};
+use nc_ingestor::embeddings;
+use nc_ingestor::error::{IngestorError, Result, redact_uri};
+use nc_ingestor::excel_input;
+use nc_ingestor::ingestor::{
+    BatchConfig, CommitTracker, DeferredFiles, Ingestor, IngestorConfig, RawFile, RecordSet,
+};
+use nc_ingestor::json_input::{self, JsonLayout};
+use nc_ingestor::manifest::{extension_route, read_manifest};
+use nc_ingestor::memory::MemoryIngestor;
+use nc_ingestor::mongo::{MongoIngestor, MongoOptions};
 use nc_ingestor::neo4j::Neo4jIngestor;
-use nc_ingestor::postgres::PostgresIngestor;
-use nc_ingestor::qdrant::QdrantIngestor;
-use nc_ingestor::sqlite::SqliteIngestor;
+use nc_ingestor::pipeline::PipelineConfig;
+use nc_ingestor::postgres::{PostgresIngestor, PostgresOptions};
+use nc_ingestor::qdrant::{QdrantIngestor, QdrantOptions};
+use nc_ingestor::record_source::open_source;
+use nc_ingestor::remote_input::{self, RemoteStore};
+use nc_ingestor::report::{
+    DeadLetter, FileClock, FlushPolicy, OversizedEntry, ProcessingError, Progress, Report,
+    RetryBudgetUsage, SkippedFile, TargetReport,
+};
+use nc_ingestor::retry;
+use nc_ingestor::schema_builder::{
+    ColumnOrder, ColumnType, PartitionConfig, SchemaDiff, TableComments,
+};
+use nc_ingestor::schema_cache::SchemaCache;
+use nc_ingestor::script::TransformScript;
+use nc_ingestor::sqlite::{SqliteIngestor, SqliteOptions};
+use nc_ingestor::telemetry::{self, Telemetry};
+use nc_ingestor::transform::{
+    DuplicateFilter, FieldFilter, FieldNames, KeyCardinality, KeyCheck, NameStyle,
+    NullColumnFilter, OversizeHandler, OversizedRecord, RecordSizeGuard, ReservoirSample,
+    ResumeOffset, RunSample, SchemaGuard, TypeOverrides, explode, explode_records, normalize_name,
+};
+use nc_ingestor::weaviate::{WeaviateIngestor, WeaviateOptions};
 use nc_reader::file_reader::{FileReaderOptions, read_file_content};
+use nc_reader::nc_reader_result::{DataReaderResult, FileMetadata};
 use nc_reader::output::{OutputFormat, OutputMode};
-use serde::Serialize;
-use tracing::{error, info};
+use nc_schema::DataType;
+use tracing::{Instrument, error, info, info_span, warn};
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{EnvFilter, fmt};
 
-#[derive(Serialize,)]
-struct ProcessingError {
-    path:  String,
-    error: String,
+struct ProcessingRegistry {
+    report:         std::sync::Mutex<Report,>,
+    strict:         bool,
+    flush:          Option<FlushPolicy,>,
+    /// Files processed and time of the last snapshot
+    last_flush:     std::sync::Mutex<(usize, Instant,),>,
+    /// `--timeout-secs` deadline after which no new file is started
+    deadline:       Option<tokio::time::Instant,>,
+    /// `--slow-file-threshold-secs`
+    slow_threshold: Option<Duration,>,
+    /// `--compress-output`: gzip the report on write
+    compress:       bool,
+    /// `--dead-letter`: where records left out of the run are written
+    dead_letter:    Option<DeadLetter,>,
+    /// When the run started, for progress snapshots
+    started:        Instant,
+    /// Files found, once the handler listed them
+    files_total:    std::sync::Mutex<Option<usize,>,>,
+    /// Records written by finished files
+    records:        AtomicU64,
+}
+
+/// How long files already in flight at the `--timeout-secs` deadline may keep running.
+const TIMEOUT_GRACE: Duration = Duration::from_secs(10,);
+
+/// Exit code of a run cut short by `--timeout-secs`, as with coreutils `timeout`.
+const TIMED_OUT_EXIT_CODE: i32 = 124;
+
+impl ProcessingRegistry {
+    fn new(
+        strict: bool,
+        flush: Option<FlushPolicy,>,
+        deadline: Option<tokio::time::Instant,>,
+        slow_threshold: Option<Duration,>,
+        compress: bool,
+        dead_letter: Option<DeadLetter,>,
+    ) -> Self {
+        Self {
+            report: std::sync::Mutex::new(Report::default(),),
+            strict,
+            flush,
+            last_flush: std::sync::Mutex::new((0, Instant::now(),),),
+            deadline,
+            slow_threshold,
+            compress,
+            dead_letter,
+            started: Instant::now(),
+            files_total: std::sync::Mutex::new(None,),
+            records: AtomicU64::new(0,),
+        }
+    }
+
+    fn set_files_total(&self, files: usize,) {
+        *self.files_total.lock().unwrap() = Some(files,);
+    }
+
+    /// Writes a progress snapshot. `previous` is the last snapshot's record count and time, for
+    /// the rate; returns the ones of this snapshot.
+    fn write_progress(
+        &self,
+        path: &Path,
+        previous: (u64, Instant,),
+        finished: bool,
+    ) -> Result<(u64, Instant,),> {
+        let progress = Progress::new(
+            &self.report.lock().unwrap(),
+            *self.files_total.lock().unwrap(),
+            self.records.load(Ordering::Relaxed,),
+            self.started.elapsed(),
+            previous,
+            finished,
+        );
+        progress.write(path,)?;
+        Ok((progress.records, Instant::now(),),)
+    }
+
+    fn record_success(&self,) {
+        {
+            let mut report = self.report.lock().unwrap();
+            report.total_files += 1;
+            report.success_count += 1;
+        }
+        self.maybe_flush();
+    }
+
+    fn record_skipped(&self, path: &str, reason: String,) {
+        {
+            let mut report = self.report.lock().unwrap();
+            report.total_files += 1;
+            report.skipped_count += 1;
+            report.skipped.push(SkippedFile {
+                path:   path.to_string(),
+                reason: reason.clone(),
+            },);
+        }
+        self.maybe_flush();
+
+        warn!("Skipping {}: {}", path, reason);
+    }
+
+    fn record_schema_diffs(&self, diffs: Vec<SchemaDiff,>,) {
+        let mut report = self.report.lock().unwrap();
+        report.schema_diffs.extend(diffs,);
+        report.schema_diffs.sort_by(|a, b| a.table.cmp(&b.table,),);
+    }
+
+    fn record_post_sql_error(&self, err: String,) {
+        warn!("--post-sql failed; the ingested data is kept: {}", err);
+        self.report.lock().unwrap().post_sql_error = Some(err,);
+        self.maybe_flush();
+    }
+
+    fn record_oversized(
+        &self,
+        path: &str,
+        record: OversizedRecord,
+        left_out: Option<&serde_json::Value,>,
+    ) {
+        let action = if record.truncated { "truncated" } else { "skipped" };
+        self.report.lock().unwrap().oversized.push(OversizedEntry {
+            path: path.to_string(),
+            record: record.record,
+            size: record.size,
+            action,
+        },);
+        warn!("Record {} of {} is {} bytes; {}", record.record, path, record.size, action);
+        if let (Some(dead_letter,), Some(data,),) = (&self.dead_letter, left_out,)
+            && let Err(e,) = dead_letter.write(path, record.record, data,)
+        {
+            warn!("{}", e);
+        }
+    }
+
+    /// Ends the `--dead-letter` file, once no file writes to it anymore.
+    fn finish_dead_letter(&self,) {
+        let Some(dead_letter,) = &self.dead_letter else {
+            return;
+        };
+        match dead_letter.finish() {
+            Ok((),) => info!("Left-out records saved to {}", dead_letter.path().display()),
+            Err(e,) => warn!("{}", e),
+        }
+    }
+
+    fn record_duplicates(&self, path: &str, filter: Option<&DuplicateFilter,>,) {
+        let dropped = filter.map_or(0, DuplicateFilter::dropped,);
+        if dropped == 0 {
+            return;
+        }
+        self.report.lock().unwrap().duplicates.insert(path.to_string(), dropped,);
+        info!("Dropped {} duplicate record(s) in {}", dropped, path);
+    }
+
+    fn record_empty_embeddings(&self, path: &str, skipped: u64,) {
+        if skipped == 0 {
+            return;
+        }
+        let mut report = self.report.lock().unwrap();
+        *report.empty_embeddings.entry(path.to_string(),).or_default() += skipped;
+    }
+
+    fn record_null_columns(&self, path: &str, columns: Vec<String,>,) {
+        if columns.is_empty() {
+            return;
+        }
+        info!("Dropped mostly-empty column(s) {} in {}", columns.join(", ",), path);
+        self.report.lock().unwrap().null_columns.insert(path.to_string(), columns,);
+    }
+
+    fn record_error(&self, path: &str, err: String,) -> Result<(),> {
+        {
+            let mut report = self.report.lock().unwrap();
+            report.total_files += 1;
+            report.failure_count += 1;
+            report.errors.push(ProcessingError {
+                path:   path.to_string(),
+                error:  err.clone(),
+                target: None,
+            },);
+        }
+        self.maybe_flush();
+        telemetry::record_failure();
+
+        error!("Error at {}: {}", path, err);
+
+        if self.strict {
+            return Err(IngestorError::IngestionError(format!(
+                "Strict mode enabled. Halting on error at {}: {}",
+                path, err
+            ),),);
+        }
+        Ok((),)
+    }
+
+    /// Adds how long `path` took to the report, warning when it reached the slow-file threshold.
+    fn record_timing(&self, path: &str, clock: FileClock, records: Option<u64,>,) {
+        let timing = clock.finish(path, records, self.slow_threshold,);
+        if let Some(records,) = records {
+            self.records.fetch_add(records, Ordering::Relaxed,);
+        }
+        if timing.slow {
+            warn!("Slow file {}: took {:.1}s", path, timing.duration_ms as f64 / 1000.0);
+        }
+        self.report.lock().unwrap().files.push(timing,);
+    }
+
+    fn record_file_failure(&self,) {
+        {
+            let mut report = self.report.lock().unwrap();
+            report.total_files += 1;
+            report.failure_count += 1;
+        }
+        self.maybe_flush();
+    }
+
+    fn record_target_success(&self, target: &str,) {
+        let mut report = self.report.lock().unwrap();
+        report
+            .targets
+            .entry(target.to_string(),)
+            .or_default()
+            .success_count += 1;
+    }
+
+    fn record_target_error(&self, target: &str, path: &str, err: String,) -> Result<(),> {
+        let mut report = self.report.lock().unwrap();
+        report
+            .targets
+            .entry(target.to_string(),)
+            .or_default()
+            .failure_count += 1;
+        report.errors.push(ProcessingError {
+            path:   path.to_string(),
+            error:  err.clone(),
+            target: Some(target.to_string(),),
+        },);
+
+        telemetry::record_failure();
+        error!("Error at {} for target '{}': {}", path, target, err);
+
+        if self.strict {
+            return Err(IngestorError::IngestionError(format!(
+                "Strict mode enabled. Halting on error at {} for target '{}': {}",
+                path, target, err
+            ),),);
+        }
+        Ok((),)
+    }
+
+    fn mark_timed_out(&self,) {
+        self.report.lock().unwrap().timed_out = true;
+    }
+
+    fn timed_out(&self,) -> bool {
+        self.report.lock().unwrap().timed_out
+    }
+
+    fn failure_count(&self,) -> usize {
+        self.report.lock().unwrap().failure_count
+    }
+
+    fn target_failure_count(&self, target: &str,) -> usize {
+        self.report
+            .lock()
+            .unwrap()
+            .targets
+            .get(target,)
+            .map_or(0, |t| t.failure_count,)
+    }
+
+    /// Writes a report snapshot if the flush policy says one is due.
+    fn maybe_flush(&self,) {
+        let Some(policy,) = &self.flush else {
+            return;
+        };
+        let processed = self.report.lock().unwrap().total_files;
+        let mut last_flush = self.last_flush.lock().unwrap();
+        if !policy.is_due(processed, *last_flush,) {
+            return;
+        }
+
+        *last_flush = (processed, Instant::now(),);
+        if let Err(e,) = self.write_report() {
+            warn!("Failed to write report snapshot: {}", e);
+        }
+    }
+
+    fn save_report(&self,) -> Result<(),> {
+        self.write_report()?;
+        info!("Ingestion report saved to {}", self.report_path());
+        Ok((),)
+    }
+
+    fn report_path(&self,) -> &'static str {
+        if self.compress { "ingestion_report.json.gz" } else { "ingestion_report.json" }
+    }
+
+    /// Writes to a temporary file and renames it over the report, so readers never see a
+    /// partially written file. With `--compress-output` the file is gzipped.
+    fn write_report(&self,) -> Result<(),> {
+        let mut report = self.report.lock().unwrap();
+        report.sort_by_path();
+        let budget = retry::run_budget();
+        report.retry_budget = budget.limit().map(|limit| RetryBudgetUsage {
+            budget:    limit,
+            used:      budget.used(),
+            exhausted: budget.is_exhausted(),
+        },);
+        report.embedders = embeddings::provider_usage();
+        report.write(Path::new(self.report_path(),), self.compress,)
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(),> {
+    let cli = Cli::parse();
+    let telemetry = cli.otel_endpoint.as_deref().map(Telemetry::init,).transpose()?;
+
+    // Initialize tracing
+    let file_appender = tracing_appender::rolling::never(".", "ingestor.log",);
+    let (non_blocking, _guard,) = tracing_appender::non_blocking(file_appender,);
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info",),),)
+        .with(fmt::layer().with_writer(std::io::stderr,),)
+        .with(fmt::layer().with_writer(non_blocking,).with_ansi(false,),)
+        .with(telemetry.as_ref().map(Telemetry::layer,),)
+        .init();
+
+    nc_ingestor::error::set_transient_patterns(&cli.retry_on,);
+    nc_ingestor::error::set_verbose_errors(cli.verbose_errors,);
+    nc_ingestor::retry::run_budget().set_limit(cli.retry_budget,);
+    nc_ingestor::embeddings::set_max_concurrent_embeddings(cli.max_concurrent_embeddings,);
+    let flush = (cli.report && (cli.report_every.is_some() || cli.report_interval_secs.is_some()))
+        .then(|| FlushPolicy {
+            every_files: cli.report_every,
+            interval:    cli.report_interval_secs.map(Duration::from_secs,),
+        },);
+    let deadline = cli
+        .timeout_secs
+        .map(|secs| tokio::time::Instant::now() + Duration::from_secs(secs,),);
+    let slow_threshold = cli.slow_file_threshold_secs.map(Duration::from_secs,);
+    let dead_letter = cli
+        .dead_letter
+        .as_deref()
+        .map(|path| DeadLetter::create(path, cli.compress_output,),)
+        .transpose()?;
+    let registry = std::sync::Arc::new(ProcessingRegistry::new(
+        cli.strict,
+        flush,
+        deadline,
+        slow_threshold,
+        cli.compress_output,
+        dead_letter,
+    ),);
+
+    let progress_task = cli.progress_file.clone().map(|path| {
+        let registry = std::sync::Arc::clone(&registry,);
+        let every = Duration::from_secs(cli.progress_interval_secs.max(1,),);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(every,);
+            let mut previous = (0, Instant::now(),);
+            loop {
+                ticks.tick().await;
+                match registry.write_progress(&path, previous, false,) {
+                    Ok(taken,) => previous = taken,
+                    Err(e,) => warn!("Failed to write progress snapshot: {}", e),
+                }
+            }
+        },)
+    },);
+
+    let run = async {
+        match &cli.command {
+            Commands::Mongo(args,) => {
+                handle_ingestion(
+                    args,
+                    MongoIngestor::new,
+                    std::sync::Arc::clone(&registry,),
+                    &cli,
+                )
+                .await
+            },
+            Commands::Neo4j(args,) => {
+                handle_ingestion(
+                    args,
+                    Neo4jIngestor::new,
+                    std::sync::Arc::clone(&registry,),
+                    &cli,
+                )
+                .await
+            },
+            Commands::Postgres(args,) => {
+                handle_ingestion(
+                    args,
+                    PostgresIngestor::new,
+                    std::sync::Arc::clone(&registry,),
+                    &cli,
+                )
+                .await
+            },
+            Commands::Qdrant(args,) => {
+                handle_ingestion(
+                    args,
+                    QdrantIngestor::new,
+                    std::sync::Arc::clone(&registry,),
+                    &cli,
+                )
+                .await
+            },
+
+            Commands::Sqlite(args,) => {
+                handle_ingestion(
+                    args,
+                    SqliteIngestor::new,
+                    std::sync::Arc::clone(&registry,),
+                    &cli,
+                )
+                .await
+            },
+
+            Commands::Weaviate(args,) => {
+                handle_ingestion(
+                    args,
+                    WeaviateIngestor::new,
+                    std::sync::Arc::clone(&registry,),
+                    &cli,
+                )
+                .await
+            },
+
+            Commands::Pipeline(args,) => {
+                handle_pipeline(args, std::sync::Arc::clone(&registry,), &cli,).await
+            },
+
+            Commands::Clean(args,) => handle_clean(args,).await,
+
+            Commands::Capabilities => handle_capabilities(),
+
+            Commands::Memory(args,) => {
+                handle_ingestion(
+                    args,
+                    MemoryIngestor::new,
+                    std::sync::Arc::clone(&registry,),
+                    &cli,
+                )
+                .await
+            },
+        }
+    };
+    // The handlers stop at the deadline themselves; this backstop covers setup that hangs
+    // (e.g. connection retries) and in-flight files that ignore their abort
+    let res = match deadline {
+        Some(deadline,) => {
+            match tokio::time::timeout_at(deadline + 2 * TIMEOUT_GRACE, run,).await {
+                Ok(res,) => res,
+                Err(_,) => {
+                    registry.mark_timed_out();
+                    Ok((),)
+                },
+            }
+        },
+        None => run.await,
+    };
+
+    if let (Some(path,), Some(task,),) = (&cli.progress_file, progress_task,) {
+        task.abort();
+        // The final snapshot's rate is the average over the whole run
+        if let Err(e,) = registry.write_progress(path, (0, registry.started,), true,) {
+            warn!("Failed to write progress snapshot: {}", e);
+        }
+    }
+    registry.finish_dead_letter();
+    if cli.report {
+        registry.save_report()?;
+    }
+    if let Some(telemetry,) = &telemetry {
+        telemetry.shutdown();
+    }
+
+    let providers = embeddings::provider_usage();
+    if providers.len() > 1 {
+        let served: Vec<String,> =
+            providers.iter().map(|(name, batches,)| format!("{} {}", name, batches),).collect();
+        warn!(
+            "Embeddings came from more than one provider (batches: {}); vectors of different \
+             models aren't comparable",
+            served.join(", ")
+        );
+    }
+    if retry::run_budget().is_exhausted() {
+        error!(
+            "Retry budget of {} exhausted; later transient errors failed and unstarted files \
+             were skipped",
+            cli.retry_budget.unwrap_or_default()
+        );
+    }
+    if registry.timed_out() {
+        error!(
+            "Run timed out after {} seconds",
+            cli.timeout_secs.unwrap_or_default()
+        );
+        // process::exit skips destructors, so flush the log writer first
+        drop(_guard,);
+        std::process::exit(TIMED_OUT_EXIT_CODE,);
+    }
+    res
+}
+
+async fn handle_ingestion<T: Ingestor + Send + Sync + 'static, F,>(
+    args: &impl IngestionArgs,
+    ingestor_factory: impl Fn(IngestorConfig,) -> F,
+    registry: std::sync::Arc<ProcessingRegistry,>,
+    cli: &Cli,
+) -> Result<(),>
+where
+    F: Future<Output = Result<T,>,> + Send + 'static,
+{
+    let source = input_label(args.path(), args.manifest(),);
+    let database_url = args.database_url();
+
+    let mut config = IngestorConfig {
+        database_url:       database_url.to_string(),
+        collection_name:    args.collection_name(),
+        vector_size:        args.vector_size(),
+        mappings:           args.mappings(),
+        openai_api_key:     args.openai_api_key(),
+        openai_headers:     openai_headers(args.common(),),
+        embedders:          args.common().embedder.clone(),
+        ollama_url:         args.common().ollama_url.clone(),
+        ollama_model:       args.common().ollama_model.clone(),
+        embed_field:        args.embed_field(),
+        id_field:           args.common().id_field.clone(),
+        label_field:        args.common().label_field.clone(),
+        relationships:      args.relationships(),
+        unwrap_variant:     args.common().unwrap_variant,
+        connection_retries: Some(cli.connection_retries,),
+        reconnect_after:    cli.reconnect_after,
+        float_precision:    args.common().float_precision,
+        rename_on_conflict: args.common().rename_on_conflict,
+        schema_diff:        args.schema_diff(),
+        pool_size:          Some(file_concurrency(cli,) as u32,),
+        type_overrides:     type_overrides(cli,),
+        column_order:       args.common().column_order.clone(),
+        collation:          args.common().collation.clone(),
+        normalize_nested:   args.common().normalize_nested,
+        comments:           table_comments(args.common(),)?,
+        partition:          args.partition(),
+        mongo:              args.mongo_options(),
+        postgres:           args.postgres_options(),
+        qdrant:             args.qdrant_options(),
+        sqlite:             args.sqlite_options(),
+        weaviate:           args.weaviate_options(),
+        batch:              BatchConfig {
+            preserve_order: cli.preserve_order,
+            ..batch_config(args.common(),)
+        },
+        field_filter:       field_filter(args.common(),),
+        on_batch_committed: None,
+    };
+
+    let script = match cli.transform_script.as_deref().map(TransformScript::load,).transpose() {
+        Ok(script,) => script,
+        Err(e,) => {
+            registry.record_error(&source, e.detailed(),)?;
+            return Ok((),);
+        },
+    };
+    // A schema diff writes nothing, hooks included
+    let hooks = if args.schema_diff() {
+        Ok(SqlHooks::default(),)
+    } else {
+        SqlHooks::load(&args.sql_hooks(),)
+    };
+    let hooks = match hooks {
+        Ok(hooks,) => hooks,
+        Err(e,) => {
+            registry.record_error(&source, e.detailed(),)?;
+            return Ok((),);
+        },
+    };
+    if let Some(uri,) = &args.common().source {
+        let label = redact_uri(uri,);
+        let ingestor = match ingestor_factory(config,).await {
+            Ok(i,) => i,
+            Err(e,) => {
+                registry.record_error(&label, e.detailed(),)?;
+                return Ok((),);
+            },
+        };
+        if let Err(e,) = hooks.run_pre(&ingestor,).await {
+            registry.record_error(&label, e.detailed(),)?;
+            return Ok((),);
+        }
+        let script = script.as_ref();
+        ingest_source(uri, &ingestor, script, args.common(), &registry, cli,).await?;
+        return hooks.run_post(&ingestor, &registry, &label,).await;
+    }
+    let listed = match list_remote(args.path(), &registry, cli,).await {
+        Ok(listed,) => listed,
+        Err(e,) => {
+            registry.record_error(&source, e.detailed(),)?;
+            return Ok((),);
+        },
+    };
+    let (remote, mut files,) = match listed {
+        Some((store, files,),) => (Some(store,), files,),
+        None => (None, collect_inputs(args.path(), args.manifest(), &registry, cli,)?,),
+    };
+    route_by_extension(&mut files, &args.common().route,);
+
+    let checkpoint = match open_checkpoint(cli, args,) {
+        Ok(checkpoint,) => checkpoint,
+        Err(e,) => {
+            registry.record_error(&source, e.detailed(),)?;
+            return Ok((),);
+        },
+    };
+    if let Some(checkpoint,) = &checkpoint {
+        let before = files.len();
+        files.retain(|file| !checkpoint.is_complete(&file.path.to_string_lossy(),),);
+        if files.len() < before {
+            info!("Skipping {} file(s) completed in the checkpoint", before - files.len());
+        }
+    }
+    // A schema diff writes nothing: files completed earlier are still skipped, but nothing is
+    // marked complete
+    let checkpoint = checkpoint.filter(|_| !args.schema_diff(),);
+
+    // The key column is resolved before connecting so upserts and MERGEs are keyed from the
+    // start
+    let mut key_column = None;
+    if args.common().first_col_as_key
+        && let Some(first,) = files.first()
+    {
+        let column = fetch_remote(remote.as_deref(), &first.path,).await.and_then(|staged| {
+            let local = staged.as_deref().unwrap_or(&first.path,);
+            first_column(local, csv_layout(cli,), cli.sheet.as_deref(), cli.normalize_field_names,)
+        },);
+        match column {
+            Ok(Some(column,),) => {
+                info!("Using first column '{}' as the record key", column);
+                config.id_field = Some(column.clone(),);
+                key_column = Some(column,);
+            },
+            Ok(None,) => {
+                warn!("No columns in {}; records keep their default keys", first.path.display());
+            },
+            Err(e,) => {
+                registry.record_error(&source, e.detailed(),)?;
+                return Ok((),);
+            },
+        }
+    }
+
+    let ingestor_res = ingestor_factory(config.clone(),).await;
+    let ingestor = match ingestor_res {
+        Ok(i,) => std::sync::Arc::new(i,),
+        Err(e,) => {
+            registry.record_error(&source, e.detailed(),)?;
+            return Ok((),);
+        },
+    };
+    if let Err(e,) = hooks.run_pre(&*ingestor,).await {
+        registry.record_error(&source, e.detailed(),)?;
+        return Ok((),);
+    }
+
+    // A schema diff writes nothing, metadata rows included
+    let metadata_ingestor = match &cli.metadata_table {
+        Some(table,) if !config.schema_diff => {
+            match ingestor_factory(metadata_config(&config, table,),).await {
+                Ok(i,) => Some(std::sync::Arc::new(i,),),
+                Err(e,) => {
+                    registry.record_error(&source, e.detailed(),)?;
+                    return Ok((),);
+                },
+            }
+        },
+        _ => None,
+    };
+
+    // Manifest entries and --route may send files to other collections, each with its own
+    // ingestor
+    let mut ingestors = HashMap::from([(None, std::sync::Arc::clone(&ingestor,),),],);
+    let overrides: BTreeSet<String,> = files
+        .iter()
+        .filter_map(|file| file.collection_name.clone(),)
+        .collect();
+    for name in overrides {
+        let override_config = IngestorConfig {
+            collection_name: Some(name.clone(),),
+            ..config.clone()
+        };
+        match ingestor_factory(override_config,).await {
+            Ok(i,) => {
+                ingestors.insert(Some(name,), std::sync::Arc::new(i,),);
+            },
+            Err(e,) => {
+                let routed = |file: &InputFile| file.collection_name.as_ref() == Some(&name,);
+                for file in files.iter().filter(|file| routed(file,),) {
+                    registry.record_error(&file.path.to_string_lossy(), e.detailed(),)?;
+                }
+                files.retain(|file| !routed(file,),);
+            },
+        }
+    }
+
+    let deferred: HashMap<_, std::sync::Arc<DeferredFiles,>,> =
+        ingestors.keys().map(|name| (name.clone(), Default::default(),),).collect();
+    // Single-object JSON files are typed over every such file going to the same collection
+    let json_layouts: HashMap<_, JsonLayout,> = ingestors
+        .keys()
+        .map(|name| {
+            let local = files
+                .iter()
+                .filter(|file| remote.is_none() && file.collection_name == *name,)
+                .map(|file| file.path.as_path(),);
+            (name.clone(), json_layout(cli, local,),)
+        },)
+        .collect();
+    let null_filters: HashMap<_, _,> =
+        ingestors.keys().map(|name| (name.clone(), null_column_filter(cli,),),).collect();
+    // --sample-size draws one sample per collection, ingested once every file has been read
+    let pools: HashMap<_, _,> =
+        ingestors.keys().map(|name| (name.clone(), run_sample(cli,),),).collect();
+
+    let concurrency = file_concurrency(cli,);
+    registry.set_files_total(files.len(),);
+    info!(
+        "Found {} files to process with concurrency {}",
+        files.len(),
+        concurrency
+    );
+
+    let mut join_set = tokio::task::JoinSet::new();
+    let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(concurrency,),);
+    let schema_cache = cli.reuse_schema.then(|| std::sync::Arc::new(SchemaCache::new(),),);
+    let schema_guard = cli.strict_schema.then(|| std::sync::Arc::new(SchemaGuard::new(),),);
+    let fields = std::sync::Arc::new(field_filter(args.common(),),);
+
+    let mut in_flight = HashMap::new();
+    let mut pending = files.into_iter();
+    while let Some(input,) = pending.next() {
+        let Some(permit,) = acquire_slot(&semaphore, &registry,).await else {
+            skip_timed_out(&registry, std::iter::once(input,).chain(pending,),);
+            break;
+        };
+        if retry::run_budget().is_exhausted() {
+            skip_unstarted(
+                &registry,
+                std::iter::once(input,).chain(pending,),
+                "retry budget exhausted",
+            );
+            break;
+        }
+        let ingestor_task = std::sync::Arc::clone(&ingestors[&input.collection_name],);
+        let deferred_task = std::sync::Arc::clone(&deferred[&input.collection_name],);
+        let json = json_layouts[&input.collection_name].clone();
+        let null_filter = null_filters[&input.collection_name].clone();
+        let pool_task = pools[&input.collection_name].clone();
+        let remote_task = remote.clone();
+        let file = input.path;
+        let registry_task = std::sync::Arc::clone(&registry,);
+        let cache_task = schema_cache.clone();
+        let guard_task = schema_guard.clone();
+        let infer_sample = cli.infer_sample;
+        let csv = CsvLayout {
+            header_order: args.common().column_order == ColumnOrder::Source,
+            ..csv_layout(cli,)
+        };
+        let retype = TypeOverrides { types: type_overrides(cli,), };
+        let names = field_names(cli,);
+        let sheet = cli.sheet.clone();
+        let key_task = key_column.clone();
+        let style = cli.normalize_field_names;
+        let fields_task = std::sync::Arc::clone(&fields,);
+        let explode_task = args.common().explode.clone();
+        let script_task = script.clone();
+        let size_guard = record_size_guard(cli,);
+        let sample = reservoir_sample(cli,);
+        let dedupe = cli.dedupe_within_file;
+        let key_check = args.common().count_distinct.zip(config.id_field.clone(),).map(
+            |(sample, field,)| KeyCheck { field, sample, },
+        );
+        let metadata_task = metadata_ingestor.clone();
+        let checkpoint_task = checkpoint.clone();
+        let store_raw = cli.store_raw && !args.schema_diff();
+        let path_str = file.to_string_lossy().to_string();
+        let span = info_span!("process_file", path = %file.display());
+
+        let task = async move {
+            let _permit = permit; // Hold permit until task is done
+            let file_str = file.to_string_lossy().to_string();
+
+            info!("Processing: {}", file_str);
+            let clock = FileClock::start();
+            let duplicates = dedupe.then(DuplicateFilter::new,);
+            let stream_csv = ingestor_task.streams_csv();
+            let (staged, nc_res,) = match fetch_remote(remote_task.as_deref(), &file,).await {
+                Ok(staged,) => {
+                    let local = staged.as_deref().unwrap_or(file.as_path(),);
+                    let checked = match &key_task {
+                        Some(key,) => {
+                            check_first_column(local, key, csv.clone(), sheet.as_deref(), style,)
+                        },
+                        None => Ok((),),
+                    };
+                    let loaded = match checked {
+                        Ok((),) => {
+                            load_file(
+                                local,
+                                cache_task.as_deref(),
+                                &json,
+                                stream_csv,
+                                infer_sample,
+                                csv,
+                                sheet.as_deref(),
+                            )
+                            .await
+                        },
+                        Err(e,) => Err(e,),
+                    };
+                    (staged, loaded,)
+                },
+                Err(e,) => (None, Err(e,),),
+            };
+            // A fetched object is read from its temporary copy but reported under its URL
+            let local = staged.as_deref().unwrap_or(file.as_path(),);
+
+            let data = nc_res
+                .and_then(|d| d.retyped(&retype,),)
+                .and_then(|d| d.normalized(names,),)
+                .and_then(|d| d.scripted(script_task.as_ref(),),)
+                .and_then(|d| {
+                    d.size_guarded(size_guard, oversize_handler(&registry_task, &file_str,),)
+                },)
+                .map(|d| d.deduplicated(duplicates.as_ref(),),)
+                .and_then(|d| d.sampled(sample.map(|s| s.for_file(&file_str,),),),)
+                .map(|d| d.null_filtered(null_filter.as_deref(), &registry_task, &file_str,),)
+                .and_then(|d| d.schema_checked(guard_task.as_deref(), &file_str,),);
+            let committed = checkpoint_task.as_ref().map_or(0, |c| c.committed(&file_str,),);
+            if committed > 0 {
+                info!("Resuming {} after {} committed record(s)", file_str, committed);
+            }
+            let data = match data {
+                Ok(d,) => d
+                    .filtered(&fields_task,)
+                    .exploded(explode_task.as_deref(),)
+                    .key_checked(key_check.as_ref(), &file_str,)
+                    .resumed(committed,),
+                Err(e,) => {
+                    if let Some(metadata_ingestor,) = &metadata_task {
+                        let outcome = Err(e.to_string(),);
+                        write_file_metadata(&**metadata_ingestor, &file, None, outcome,).await;
+                    }
+                    registry_task.record_timing(&file_str, clock, None,);
+                    let _ = registry_task.record_error(&file_str, e.detailed(),);
+                    return;
+                },
+            };
+            // With --sample-size the records wait for the sample; blob fallbacks go in as usual
+            let data = match pool_task.as_deref() {
+                Some(pool,) => match data.pooled(pool, &file_str,) {
+                    Ok(Ok(offered,),) => {
+                        registry_task.record_timing(&file_str, clock, Some(offered,),);
+                        registry_task.record_duplicates(&file_str, duplicates.as_ref(),);
+                        return;
+                    },
+                    Ok(Err(data,),) => data,
+                    Err(e,) => {
+                        registry_task.record_timing(&file_str, clock, None,);
+                        let _ = registry_task.record_error(&file_str, e.detailed(),);
+                        return;
+                    },
+                },
+                None => data,
+            };
+
+            let file_metadata = data.file_metadata();
+            let ingest = data.ingest_into(&*ingestor_task,);
+            let mut res = match commit_tracker(checkpoint_task.as_ref(), &file_str, committed,) {
+                Some(tracker,) => tracker.scope(ingest,).await,
+                None => ingest.await,
+            };
+            if store_raw && let Ok(ingested,) = res {
+                res = store_raw_file(&*ingestor_task, local, &file_str,).await.map(|_| ingested,);
+            }
+            if let Some(metadata_ingestor,) = &metadata_task {
+                let outcome = res.as_ref().map(|i| i.inserted,).map_err(|e| e.to_string(),);
+                write_file_metadata(&**metadata_ingestor, &file, file_metadata, outcome,).await;
+            }
+            registry_task.record_timing(&file_str, clock, res.as_ref().ok().map(|i| i.records,),);
+            registry_task.record_duplicates(&file_str, duplicates.as_ref(),);
+            if let Ok(ingested,) = &res {
+                registry_task.record_empty_embeddings(&file_str, ingested.skipped,);
+            }
+
+            match res {
+                // Its records may still be buffered, so it only counts once the flush succeeds
+                Ok(_,) if ingestor_task.defers_writes() => deferred_task.push(&file_str,),
+                Ok(_,) => complete_file(&registry_task, checkpoint_task.as_deref(), &file_str,),
+                Err(e,) => {
+                    let _ = registry_task.record_error(&file_str, e.detailed(),);
+                },
+            }
+        };
+        let handle = join_set.spawn(task.instrument(span,),);
+        in_flight.insert(handle.id(), path_str,);
+    }
+    join_files(&mut join_set, &in_flight, &registry,).await;
+
+    for (name, pool,) in &pools {
+        let Some((records, files,),) = pool.as_deref().map(RunSample::take,) else { continue };
+        if files.is_empty() {
+            continue;
+        }
+        if registry.timed_out() {
+            for file in &files {
+                registry.record_error(file, SAMPLE_TIMED_OUT.to_string(),)?;
+            }
+            continue;
+        }
+        let ingestor = &ingestors[name];
+        info!("Ingesting a sample of {} records from {} files", records.rows.len(), files.len());
+        match FileData::Records(records,).ingest_into(&**ingestor,).await {
+            Ok(_,) if ingestor.defers_writes() => {
+                for file in &files {
+                    deferred[name].push(file,);
+                }
+            },
+            Ok(_,) => {
+                for file in &files {
+                    complete_file(&registry, checkpoint.as_deref(), file,);
+                }
+            },
+            Err(e,) => {
+                for file in &files {
+                    registry.record_error(file, e.detailed(),)?;
+                }
+            },
+        }
+    }
+
+    for (name, ingestor,) in &ingestors {
+        registry.record_schema_diffs(ingestor.schema_diffs(),);
+        let (files, flushed,) = deferred[name].flush(&**ingestor,).await;
+        match flushed {
+            Ok(stats,) => {
+                for stats in stats {
+                    info!("Flushed {} buffered records into {}", stats.records, stats.target);
+                }
+                for file in &files {
+                    complete_file(&registry, checkpoint.as_deref(), file,);
+                }
+            },
+            Err(e,) if files.is_empty() => registry.record_error(&source, e.detailed(),)?,
+            Err(e,) => {
+                for file in &files {
+                    registry.record_error(file, e.detailed(),)?;
+                }
+            },
+        }
+    }
+    // Every ingestor of the run writes to the same database, so the hook runs once
+    if !registry.timed_out() {
+        hooks.run_post(&*ingestor, &registry, &source,).await?;
+    }
+
+    // End-of-run work such as alias switches only happens for fully successful runs
+    let failures = registry.failure_count();
+    if registry.timed_out() {
+        warn!("Skipping finalization: run timed out");
+    } else if failures > 0 {
+        warn!("Skipping finalization: {} file(s) failed", failures);
+    } else {
+        for ingestor in ingestors.values() {
+            if let Err(e,) = ingestor.finalize().await {
+                registry.record_error(&source, e.detailed(),)?;
+            }
+        }
+    }
+
+    Ok((),)
+}
+
+/// Counts `file` as ingested and marks it complete in the checkpoint.
+fn complete_file(registry: &ProcessingRegistry, checkpoint: Option<&Checkpoint,>, file: &str,) {
+    registry.record_success();
+    info!("Successfully ingested: {}", file);
+    if let Some(checkpoint,) = checkpoint
+        && let Err(e,) = checkpoint.complete(file,)
+    {
+        warn!("Failed to save the checkpoint for {}: {}", file, e);
+    }
+}
+
+/// `--pre-sql` / `--post-sql` statements, read before connecting so a missing file fails
+/// before anything is written.
+#[derive(Default,)]
+struct SqlHooks {
+    pre:         Option<String,>,
+    post:        Option<String,>,
+    post_strict: bool,
+}
+
+impl SqlHooks {
+    fn load(args: &SqlHookArgs,) -> Result<Self,> {
+        let read = |path: &Option<PathBuf,>, flag: &str| {
+            path.as_ref()
+                .map(|path| {
+                    std::fs::read_to_string(path,).map_err(|e| {
+                        IngestorError::ConfigurationError(format!(
+                            "Failed to read {} file {}: {}",
+                            flag,
+                            path.display(),
+                            e
+                        ),)
+                    },)
+                },)
+                .transpose()
+        };
+        Ok(SqlHooks {
+            pre:         read(&args.pre_sql, "--pre-sql",)?,
+            post:        read(&args.post_sql, "--post-sql",)?,
+            post_strict: args.post_sql_strict,
+        },)
+    }
+
+    async fn run_pre(&self, ingestor: &dyn Ingestor,) -> Result<(),> {
+        if let Some(sql,) = &self.pre {
+            ingestor.execute_sql(sql,).await?;
+            info!("Ran --pre-sql");
+        }
+        Ok((),)
+    }
+
+    /// Runs `--post-sql`. A failure is only reported, or recorded as an error of `source` with
+    /// `--post-sql-strict`.
+    async fn run_post(
+        &self,
+        ingestor: &dyn Ingestor,
+        registry: &ProcessingRegistry,
+        source: &str,
+    ) -> Result<(),> {
+        let Some(sql,) = &self.post else { return Ok((),) };
+        match ingestor.execute_sql(sql,).await {
+            Ok((),) => {
+                info!("Ran --post-sql");
+                Ok((),)
+            },
+            Err(e,) if self.post_strict => registry.record_error(source, e.detailed(),),
+            Err(e,) => {
+                registry.record_post_sql_error(e.detailed(),);
+                Ok((),)
+            },
+        }
+    }
+}
+
+/// Ingests the rows of a `--source` query. Each fetched chunk goes through the per-record
+/// transforms a file would (type overrides, the transform script, field filters, `--explode`)
+/// and into `ingestor`; the whole query is reported as one input.
+async fn ingest_source(
+    uri: &str,
+    ingestor: &dyn Ingestor,
+    script: Option<&TransformScript,>,
+    common: &CommonIngestorArgs,
+    registry: &ProcessingRegistry,
+    cli: &Cli,
+) -> Result<(),> {
+    let label = redact_uri(uri,);
+    registry.set_files_total(1,);
+    info!("Reading records from {}", label);
+    let clock = FileClock::start();
+    let retype = TypeOverrides { types: type_overrides(cli,), };
+    let names = field_names(cli,);
+    let fields = field_filter(common,);
+
+    let run = async {
+        let mut source = open_source(uri,).await?;
+        let mut records = 0;
+        while let Some(batch,) = source.next_batch().await? {
+            records += FileData::Records(batch,)
+                .retyped(&retype,)?
+                .normalized(names,)?
+                .scripted(script,)?
+                .filtered(&fields,)
+                .exploded(common.explode.as_deref(),)
+                .ingest_into(ingestor,)
+                .await?
+                .records;
+        }
+        for stats in ingestor.flush().await? {
+            info!("Flushed {} buffered records into {}", stats.records, stats.target);
+        }
+        Ok::<_, IngestorError,>(records,)
+    };
+    let res = run.await;
+    registry.record_timing(&label, clock, res.as_ref().ok().copied(),);
+    match res {
+        Ok(records,) => {
+            registry.record_success();
+            info!("Successfully ingested {} records from {}", records, label);
+            ingestor.finalize().await.or_else(|e| registry.record_error(&label, e.detailed(),),)
+        },
+        Err(e,) => registry.record_error(&label, e.detailed(),),
+    }
+}
+
+/// Prints [`capabilities::capabilities`] to stdout as pretty JSON.
+fn handle_capabilities() -> Result<(),> {
+    let json = serde_json::to_string_pretty(&capabilities::capabilities(),).map_err(|e| {
+        IngestorError::IngestionError(format!("Failed to serialize capabilities: {}", e),)
+    },)?;
+    println!("{}", json);
+    Ok((),)
+}
+
+async fn handle_clean(args: &CleanArgs,) -> Result<(),> {
+    if !args.yes {
+        return Err(IngestorError::ConfigurationError(format!(
+            "Refusing to remove '{}' without --yes",
+            args.collection_name
+        ),),);
+    }
+
+    let config = IngestorConfig {
+        database_url: args.uri.clone(),
+        collection_name: Some(args.collection_name.clone(),),
+        weaviate: WeaviateOptions { api_key: args.weaviate_api_key.clone(), },
+        ..Default::default()
+    };
+    let ingestor = args.backend.connect(config,).await?;
+    let removed = ingestor.clean().await?;
+    info!("Clean complete: {}", removed);
+    Ok((),)
+}
+
+struct ConnectedTarget {
+    label:    String,
+    fields:   FieldFilter,
+    explode:  Option<String,>,
+    ingestor: Box<dyn Ingestor,>,
+    /// `--metadata-table` ingestor on the same database
+    metadata: Option<Box<dyn Ingestor,>,>,
+}
+
+async fn handle_pipeline(
+    args: &PipelineArgs,
+    registry: std::sync::Arc<ProcessingRegistry,>,
+    cli: &Cli,
+) -> Result<(),> {
+    let mut pipeline = PipelineConfig::from_file(&args.config,)?;
+    let config_str = args.config.to_string_lossy().to_string();
+
+    let mut targets = Vec::new();
+    for target in &mut pipeline.targets {
+        target.config.batch.preserve_order |= cli.preserve_order;
+        target.config.connection_retries.get_or_insert(cli.connection_retries,);
+        target.config.reconnect_after = target.config.reconnect_after.or(cli.reconnect_after,);
+        for (field, column_type,) in type_overrides(cli,) {
+            target.config.type_overrides.entry(field,).or_insert(column_type,);
+        }
+        target.config.pool_size.get_or_insert(file_concurrency(cli,) as u32,);
+        let label = target.label();
+        let connected = match &cli.metadata_table {
+            Some(table,) => match target.connect().await {
+                Ok(ingestor,) => target
+                    .backend
+                    .connect(metadata_config(&target.config, table,),)
+                    .await
+                    .map(|metadata| (ingestor, Some(metadata,),),),
+                Err(e,) => Err(e,),
+            },
+            None => target.connect().await.map(|ingestor| (ingestor, None,),),
+        };
+        match connected {
+            Ok((ingestor, metadata,),) => targets.push(ConnectedTarget {
+                label,
+                fields: target.fields.clone(),
+                explode: target.explode.clone(),
+                ingestor,
+                metadata,
+            },),
+            Err(e,) => registry.record_target_error(&label, &config_str, e.detailed(),)?,
+        }
+    }
+    if targets.is_empty() {
+        return Ok((),);
+    }
+    let targets = std::sync::Arc::new(targets,);
+    let header_order =
+        pipeline.targets.iter().any(|target| target.config.column_order == ColumnOrder::Source,);
+    // Every target's overrides (each including --type-override) apply while reading
+    let overrides = match pipeline.type_overrides() {
+        Ok(overrides,) => overrides,
+        Err(e,) => {
+            registry.record_error(&config_str, e.detailed(),)?;
+            return Ok((),);
+        },
+    };
+
+    let script = match cli.transform_script.as_deref().map(TransformScript::load,).transpose() {
+        Ok(script,) => script,
+        Err(e,) => {
+            registry.record_error(&config_str, e.detailed(),)?;
+            return Ok((),);
+        },
+    };
+    let listed = match list_remote(args.path.as_deref(), &registry, cli,).await {
+        Ok(listed,) => listed,
+        Err(e,) => {
+            registry.record_error(&config_str, e.detailed(),)?;
+            return Ok((),);
+        },
+    };
+    let (remote, files,) = match listed {
+        Some((store, files,),) => (Some(store,), files,),
+        None => {
+            let manifest = args.manifest.as_deref();
+            let files = collect_inputs(args.path.as_deref(), manifest, &registry, cli,)?;
+            (None, files,)
+        },
+    };
+    if files.iter().any(|file| file.collection_name.is_some(),) {
+        warn!("Ignoring manifest collection_name overrides: pipeline targets name their own");
+    }
+    registry.set_files_total(files.len(),);
+    info!(
+        "Found {} files to fan out to {} targets with concurrency {}",
+        files.len(),
+        targets.len(),
+        file_concurrency(cli,)
+    );
+
+    let local = files.iter().filter(|_| remote.is_none(),).map(|file| file.path.as_path(),);
+    let json = json_layout(cli, local,);
+    let null_filter = null_column_filter(cli,);
+    let pool = run_sample(cli,);
+
+    let mut join_set = tokio::task::JoinSet::new();
+    let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(file_concurrency(cli,),),);
+    let schema_cache = cli.reuse_schema.then(|| std::sync::Arc::new(SchemaCache::new(),),);
+    let schema_guard = cli.strict_schema.then(|| std::sync::Arc::new(SchemaGuard::new(),),);
+
+    let mut in_flight = HashMap::new();
+    let mut pending = files.into_iter();
+    while let Some(input,) = pending.next() {
+        let Some(permit,) = acquire_slot(&semaphore, &registry,).await else {
+            skip_timed_out(&registry, std::iter::once(input,).chain(pending,),);
+            break;
+        };
+        if retry::run_budget().is_exhausted() {
+            skip_unstarted(
+                &registry,
+                std::iter::once(input,).chain(pending,),
+                "retry budget exhausted",
+            );
+            break;
+        }
+        let file = input.path;
+        let remote_task = remote.clone();
+        let targets_task = std::sync::Arc::clone(&targets,);
+        let registry_task = std::sync::Arc::clone(&registry,);
+        let cache_task = schema_cache.clone();
+        let guard_task = schema_guard.clone();
+        let json = json.clone();
+        let infer_sample = cli.infer_sample;
+        let csv = CsvLayout {
+            type_overrides: overrides.clone(),
+            header_order,
+            ..csv_layout(cli,)
+        };
+        let retype = TypeOverrides { types: overrides.clone(), };
+        let names = field_names(cli,);
+        let sheet = cli.sheet.clone();
+        let script_task = script.clone();
+        let size_guard = record_size_guard(cli,);
+        let sample = reservoir_sample(cli,);
+        let null_filter = null_filter.clone();
+        let pool_task = pool.clone();
+        let dedupe = cli.dedupe_within_file;
+        let store_raw = cli.store_raw;
+        let path_str = file.to_string_lossy().to_string();
+        let span = info_span!("process_file", path = %file.display());
+
+        let task = async move {
+            let _permit = permit; // Hold permit until task is done
+            let file_str = file.to_string_lossy().to_string();
+
+            info!("Processing: {}", file_str);
+            let clock = FileClock::start();
+            let duplicates = dedupe.then(DuplicateFilter::new,);
+            let (staged, loaded,) = match fetch_remote(remote_task.as_deref(), &file,).await {
+                Ok(staged,) => {
+                    let local = staged.as_deref().unwrap_or(file.as_path(),);
+                    let loaded = load_file(
+                        local,
+                        cache_task.as_deref(),
+                        &json,
+                        false,
+                        infer_sample,
+                        csv,
+                        sheet.as_deref(),
+                    )
+                    .await;
+                    (staged, loaded,)
+                },
+                Err(e,) => (None, Err(e,),),
+            };
+            let local = staged.as_deref().unwrap_or(file.as_path(),);
+            // Type overrides, scripts, size checks, deduplication, sampling, null-column drops and
+            // the strict schema check run once per file, before the copies for each target are
+            // made, so every target gets the same records
+            let data = loaded
+                .and_then(|d| d.retyped(&retype,),)
+                .and_then(|d| d.normalized(names,),)
+                .and_then(|d| d.scripted(script_task.as_ref(),),)
+                .and_then(|d| {
+                    d.size_guarded(size_guard, oversize_handler(&registry_task, &file_str,),)
+                },)
+                .map(|d| d.deduplicated(duplicates.as_ref(),),)
+                .and_then(|d| d.sampled(sample.map(|s| s.for_file(&file_str,),),),)
+                .map(|d| d.null_filtered(null_filter.as_deref(), &registry_task, &file_str,),)
+                .and_then(|d| d.schema_checked(guard_task.as_deref(), &file_str,),)
+                .and_then(|d| match pool_task.as_deref() {
+                    Some(pool,) => d.pooled(pool, &file_str,),
+                    None => Ok(Err(d,),),
+                },);
+            let data = match data {
+                Ok(Ok(offered,),) => {
+                    registry_task.record_timing(&file_str, clock, Some(offered,),);
+                    registry_task.record_duplicates(&file_str, duplicates.as_ref(),);
+                    return;
+                },
+                Ok(Err(d,),) => Ok(d,),
+                Err(e,) => Err(e,),
+            };
+            let data = match data {
+                Ok(d,) => d,
+                Err(e,) => {
+                    for target in targets_task.iter() {
+                        if let Some(metadata,) = &target.metadata {
+                            let outcome = Err(e.to_string(),);
+                            write_file_metadata(&**metadata, &file, None, outcome,).await;
+                        }
+                    }
+                    registry_task.record_timing(&file_str, clock, None,);
+                    let _ = registry_task.record_error(&file_str, e.detailed(),);
+                    return;
+                },
+            };
+
+            let file_metadata = data.file_metadata();
+            let copies: Vec<Result<FileData,>,> = match data {
+                FileData::Reader(data,) => fan_out(data, local, targets_task.len(),)
+                    .await
+                    .into_iter()
+                    .map(|copy| copy.map(FileData::Reader,),)
+                    .collect(),
+                FileData::Records(records,) => (0..targets_task.len())
+                    .map(|_| Ok(FileData::Records(records.clone(),),),)
+                    .collect(),
+            };
+            let file = file.as_path();
+            let file_label = file_str.as_str();
+            let file_metadata = &file_metadata;
+            let results = futures_util::future::join_all(targets_task.iter().zip(copies,).map(
+                |(target, data,)| async move {
+                    let mut res = match data {
+                        Ok(data,) => {
+                            data.filtered(&target.fields,)
+                                .exploded(target.explode.as_deref(),)
+                                .ingest_into(&*target.ingestor,)
+                                .await
+                        },
+                        Err(e,) => Err(e,),
+                    };
+                    if store_raw && let Ok(ingested,) = res {
+                        res = store_raw_file(&*target.ingestor, local, file_label,)
+                            .await
+                            .map(|_| ingested,);
+                    }
+                    if let Some(metadata,) = &target.metadata {
+                        let outcome = res.as_ref().map(|i| i.inserted,).map_err(|e| e.to_string(),);
+                        write_file_metadata(&**metadata, file, file_metadata.clone(), outcome,)
+                            .await;
+                    }
+                    (&target.label, res,)
+                },
+            ),)
+            .await;
+            // Every target sees the same rows, so the file counts the most any of them wrote
+            let records = results
+                .iter()
+                .filter_map(|(_, res,)| res.as_ref().ok().map(|i| i.records,),)
+                .max();
+            registry_task.record_timing(&file_str, clock, records,);
+            registry_task.record_duplicates(&file_str, duplicates.as_ref(),);
+
+            let mut all_ok = true;
+            for (label, res,) in results {
+                match res {
+                    Ok(ingested,) => {
+                        registry_task.record_target_success(label,);
+                        registry_task.record_empty_embeddings(&file_str, ingested.skipped,);
+                    },
+                    Err(e,) => {
+                        all_ok = false;
+                        let _ = registry_task.record_target_error(label, &file_str, e.detailed(),);
+                    },
+                }
+            }
+
+            if all_ok {
+                registry_task.record_success();
+                info!("Successfully ingested: {}", file_str);
+            } else {
+                registry_task.record_file_failure();
+            }
+        };
+        let handle = join_set.spawn(task.instrument(span,),);
+        in_flight.insert(handle.id(), path_str,);
+    }
+    join_files(&mut join_set, &in_flight, &registry,).await;
+
+    if let Some((records, files,),) = pool.as_deref().map(RunSample::take,)
+        && !files.is_empty()
+    {
+        ingest_sample_into_targets(records, &files, &targets, &registry,).await?;
+    }
+
+    for target in targets.iter() {
+        registry.record_schema_diffs(target.ingestor.schema_diffs(),);
+        match target.ingestor.flush().await {
+            Ok(stats,) => {
+                for stats in stats {
+                    info!(
+                        "Flushed {} buffered records into {} for target '{}'",
+                        stats.records, stats.target, target.label
+                    );
+                }
+            },
+            Err(e,) => registry.record_target_error(&target.label, &config_str, e.detailed(),)?,
+        }
+    }
+
+    for target in targets.iter() {
+        let failures = registry.target_failure_count(&target.label,);
+        if registry.timed_out() {
+            warn!("Skipping finalization for target '{}': run timed out", target.label);
+        } else if failures > 0 {
+            warn!(
+                "Skipping finalization for target '{}': {} file(s) failed",
+                target.label, failures
+            );
+        } else if let Err(e,) = target.ingestor.finalize().await {
+            registry.record_target_error(&target.label, &config_str, e.detailed(),)?;
+        }
+    }
+
+    Ok((),)
+}
+
+/// Why files pooled by `--sample-size` fail when the run times out before the sample is written.
+const SAMPLE_TIMED_OUT: &str = "run timed out before the sample was ingested";
+
+/// Ingests the `--sample-size` sample drawn from `files` into every pipeline target. Each file
+/// counts as ingested into the targets that took the sample, and as successful if all did.
+async fn ingest_sample_into_targets(
+    records: RecordSet,
+    files: &[String],
+    targets: &[ConnectedTarget],
+    registry: &ProcessingRegistry,
+) -> Result<(),> {
+    if registry.timed_out() {
+        for file in files {
+            registry.record_error(file, SAMPLE_TIMED_OUT.to_string(),)?;
+        }
+        return Ok((),);
+    }
+    info!(
+        "Ingesting a sample of {} records from {} files into {} targets",
+        records.rows.len(),
+        files.len(),
+        targets.len()
+    );
+    let results = futures_util::future::join_all(targets.iter().map(|target| {
+        FileData::Records(records.clone(),)
+            .filtered(&target.fields,)
+            .exploded(target.explode.as_deref(),)
+            .ingest_into(&*target.ingestor,)
+    },),)
+    .await;
+
+    let mut all_ok = true;
+    for (target, res,) in targets.iter().zip(results,) {
+        for file in files {
+            match &res {
+                Ok(_,) => registry.record_target_success(&target.label,),
+                Err(e,) => registry.record_target_error(&target.label, file, e.detailed(),)?,
+            }
+        }
+        all_ok &= res.is_ok();
+    }
+    for file in files {
+        if all_ok {
+            registry.record_success();
+            info!("Successfully ingested: {}", file);
+        } else {
+            registry.record_file_failure();
+        }
+    }
+    Ok((),)
+}
+
+/// Waits for a free file slot. Returns `None` (and marks the run timed out) once the
+/// `--timeout-secs` deadline passes.
+async fn acquire_slot(
+    semaphore: &std::sync::Arc<tokio::sync::Semaphore,>,
+    registry: &ProcessingRegistry,
+) -> Option<tokio::sync::OwnedSemaphorePermit,> {
+    let acquire = semaphore.clone().acquire_owned();
+    let permit = match registry.deadline {
+        Some(deadline,) => tokio::time::timeout_at(deadline, acquire,).await.ok()?,
+        None => acquire.await,
+    };
+    if registry.deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline,) {
+        registry.mark_timed_out();
+        return None;
+    }
+    permit.ok()
+}
+
+/// Reports files that were never started because the run timed out.
+fn skip_timed_out(registry: &ProcessingRegistry, files: impl Iterator<Item = InputFile,>,) {
+    registry.mark_timed_out();
+    skip_unstarted(registry, files, "run timed out",);
+}
+
+/// Reports files that were never started, with the reason the run stopped starting them.
+fn skip_unstarted(
+    registry: &ProcessingRegistry,
+    files: impl Iterator<Item = InputFile,>,
+    reason: &str,
+) {
+    for file in files {
+        registry.record_skipped(&file.path.to_string_lossy(), reason.to_string(),);
+    }
+}
+
+/// Waits for every spawned file task. Past the `--timeout-secs` deadline, files still running
+/// get `TIMEOUT_GRACE` to finish; the rest are aborted and reported as failed.
+async fn join_files(
+    join_set: &mut tokio::task::JoinSet<(),>,
+    in_flight: &HashMap<tokio::task::Id, String,>,
+    registry: &ProcessingRegistry,
+) {
+    let mut grace_end = registry.deadline.map(|deadline| deadline + TIMEOUT_GRACE,);
+    loop {
+        let next = match grace_end {
+            Some(end,) => match tokio::time::timeout_at(end, join_set.join_next_with_id(),).await {
+                Ok(next,) => next,
+                Err(_,) => {
+                    warn!("Run timed out; aborting {} in-flight file(s)", join_set.len());
+                    registry.mark_timed_out();
+                    join_set.abort_all();
+                    grace_end = None;
+                    continue;
+                },
+            },
+            None => join_set.join_next_with_id().await,
+        };
+
+        match next {
+            None => break,
+            Some(Ok(_,),) => {},
+            Some(Err(e,),) if e.is_cancelled() => {
+                let path = in_flight.get(&e.id(),).map_or("<unknown>", String::as_str,);
+                let _ = registry.record_error(path, "aborted: run timed out".to_string(),);
+            },
+            Some(Err(e,),) => error!("Task panicked: {}", e),
+        }
+    }
+}
+
+/// A file's contents, either as read by nc_reader or typed from a cached schema.
+enum FileData {
+    Reader(DataReaderResult,),
+    Records(RecordSet,),
+}
+
+impl FileData {
+    fn retyped(self, overrides: &TypeOverrides,) -> Result<Self,> {
+        Ok(match self {
+            FileData::Reader(data,) => FileData::Reader(overrides.apply(data,)?,),
+            FileData::Records(records,) => FileData::Records(overrides.apply_records(records,)?,),
+        },)
+    }
+
+    fn normalized(self, names: FieldNames,) -> Result<Self,> {
+        Ok(match self {
+            FileData::Reader(data,) => FileData::Reader(names.apply(data,)?,),
+            FileData::Records(records,) => FileData::Records(names.apply_records(records,)?,),
+        },)
+    }
+
+    fn scripted(self, script: Option<&TransformScript,>,) -> Result<Self,> {
+        let Some(script,) = script else { return Ok(self,) };
+        Ok(match self {
+            FileData::Reader(data,) => FileData::Reader(script.apply(data,)?,),
+            FileData::Records(records,) => FileData::Records(script.apply_records(records,)?,),
+        },)
+    }
+
+    fn size_guarded(
+        self,
+        guard: Option<RecordSizeGuard,>,
+        on_oversize: OversizeHandler,
+    ) -> Result<Self,> {
+        let Some(guard,) = guard else { return Ok(self,) };
+        Ok(match self {
+            FileData::Reader(data,) => FileData::Reader(guard.apply(data, on_oversize,)?,),
+            FileData::Records(records,) => {
+                FileData::Records(guard.apply_records(records, on_oversize,)?,)
+            },
+        },)
+    }
+
+    fn deduplicated(self, filter: Option<&DuplicateFilter,>,) -> Self {
+        let Some(filter,) = filter else { return self };
+        match self {
+            FileData::Reader(data,) => FileData::Reader(filter.apply(data,),),
+            FileData::Records(records,) => FileData::Records(filter.apply_records(records,),),
+        }
+    }
+
+    fn sampled(self, sample: Option<ReservoirSample,>,) -> Result<Self,> {
+        let Some(sample,) = sample else { return Ok(self,) };
+        Ok(match self {
+            FileData::Reader(data,) => FileData::Reader(sample.apply(data,)?,),
+            FileData::Records(records,) => FileData::Records(sample.apply_records(records,)?,),
+        },)
+    }
+
+    /// Offers the records to the run's `--sample-size` pool instead of ingesting them, and
+    /// returns how many there were. Blob fallbacks are handed back to be ingested as usual.
+    fn pooled(self, pool: &RunSample, path: &str,) -> Result<std::result::Result<u64, Self,>,> {
+        Ok(match self {
+            FileData::Reader(data,) => {
+                pool.apply(data, path,)?.map(|offered| offered as u64,).map_err(FileData::Reader,)
+            },
+            FileData::Records(records,) => Ok(pool.apply_records(records, path,)? as u64,),
+        },)
+    }
+
+    fn null_filtered(
+        self,
+        filter: Option<&NullColumnFilter,>,
+        registry: &ProcessingRegistry,
+        path: &str,
+    ) -> Self {
+        let Some(filter,) = filter else { return self };
+        let (data, dropped,) = match self {
+            FileData::Reader(data,) => {
+                let (data, dropped,) = filter.apply(data,);
+                (FileData::Reader(data,), dropped,)
+            },
+            FileData::Records(records,) => {
+                let (records, dropped,) = filter.apply_records(records,);
+                (FileData::Records(records,), dropped,)
+            },
+        };
+        registry.record_null_columns(path, dropped,);
+        data
+    }
+
+    fn schema_checked(self, guard: Option<&SchemaGuard,>, path: &str,) -> Result<Self,> {
+        let Some(guard,) = guard else { return Ok(self,) };
+        Ok(match self {
+            FileData::Reader(data,) => FileData::Reader(guard.apply(data, path,)?,),
+            FileData::Records(records,) => FileData::Records(guard.apply_records(records, path,)?,),
+        },)
+    }
+
+    fn filtered(self, fields: &FieldFilter,) -> Self {
+        match self {
+            FileData::Reader(data,) => FileData::Reader(fields.apply(data,),),
+            FileData::Records(records,) => FileData::Records(fields.apply_records(records,),),
+        }
+    }
+
+    fn exploded(self, field: Option<&str,>,) -> Self {
+        match (self, field,) {
+            (FileData::Reader(data,), Some(field,),) => FileData::Reader(explode(data, field,),),
+            (FileData::Records(records,), Some(field,),) => {
+                FileData::Records(explode_records(records, field,),)
+            },
+            (data, None,) => data,
+        }
+    }
+
+    fn resumed(self, committed: usize,) -> Self {
+        if committed == 0 {
+            return self;
+        }
+        let offset = ResumeOffset { records: committed, };
+        match self {
+            FileData::Reader(data,) => FileData::Reader(offset.apply(data,),),
+            FileData::Records(records,) => FileData::Records(offset.apply_records(records,),),
+        }
+    }
+
+    fn key_checked(self, check: Option<&KeyCheck,>, path: &str,) -> Self {
+        let Some(check,) = check else { return self };
+        let (data, cardinality,) = match self {
+            FileData::Reader(data,) => {
+                let (data, cardinality,) = check.apply(data,);
+                (FileData::Reader(data,), cardinality,)
+            },
+            FileData::Records(records,) => {
+                let cardinality = check.apply_records(&records,);
+                (FileData::Records(records,), Some(cardinality,),)
+            },
+        };
+        if let Some(cardinality,) = cardinality {
+            log_key_cardinality(path, &check.field, cardinality,);
+        }
+        data
+    }
+
+    /// Size and line count reported by nc_reader, when the file went through it.
+    fn file_metadata(&self,) -> Option<FileMetadata,> {
+        match self {
+            FileData::Reader(
+                DataReaderResult::Csv(_, metadata,) | DataReaderResult::Stream(_, metadata,),
+            ) => Some(metadata.clone(),),
+            _ => None,
+        }
+    }
+
+    /// Ingests the data and returns how many records it held and how many the ingestor
+    /// committed. Commits are counted by the running [`CommitTracker`], or by one scoped here.
+    async fn ingest_into(self, ingestor: &dyn Ingestor,) -> Result<Ingested,> {
+        let count = std::sync::Arc::new(AtomicU64::new(0,),);
+        let ingest = async {
+            match self {
+                FileData::Reader(data,) => ingestor.ingest(counted(data, &count,),).await,
+                FileData::Records(records,) => {
+                    count.store(records.rows.len() as u64, Ordering::Relaxed,);
+                    ingestor.ingest_records(records,).await
+                },
+            }
+        };
+        let (res, inserted, skipped,) = match CommitTracker::current() {
+            Some(tracker,) => {
+                let (committed, skipped,) = (tracker.committed(), tracker.skipped(),);
+                let res = ingest.await;
+                (res, tracker.committed() - committed, tracker.skipped() - skipped,)
+            },
+            None => {
+                let tracker = CommitTracker::new(0, |_| {},);
+                let res = tracker.clone().scope(ingest,).await;
+                (res, tracker.committed(), tracker.skipped(),)
+            },
+        };
+        let count = count.load(Ordering::Relaxed,);
+        if res.is_ok() {
+            telemetry::record_ingested(count,);
+        }
+        res.map(|_| Ingested {
+            records:  count,
+            inserted: inserted as u64,
+            skipped:  skipped as u64,
+        },)
+    }
+}
+
+/// Outcome of one [`FileData::ingest_into`].
+#[derive(Debug, Clone, Copy,)]
+struct Ingested {
+    /// Records handed to the ingestor
+    records:  u64,
+    /// Records the ingestor reported committed, which leaves out any it skipped
+    inserted: u64,
+    /// Records the ingestor left out on purpose, e.g. with nothing to embed
+    skipped:  u64,
+}
+
+/// Tallies the records in `data` into `count` for the telemetry counter. Streams are counted as
+/// the ingestor consumes them; blob fallbacks count as one record.
+fn counted(data: DataReaderResult, count: &std::sync::Arc<AtomicU64,>,) -> DataReaderResult {
+    match data {
+        DataReaderResult::Csv(csv_data, metadata,) => {
+            count.store(csv_data.nc_rows.len() as u64, Ordering::Relaxed,);
+            DataReaderResult::Csv(csv_data, metadata,)
+        },
+        DataReaderResult::Stream(stream, metadata,) => {
+            let count = std::sync::Arc::clone(count,);
+            let stream = stream.inspect(move |_| {
+                count.fetch_add(1, Ordering::Relaxed,);
+            },);
+            DataReaderResult::Stream(Box::new(stream,), metadata,)
+        },
+        other => {
+            count.store(1, Ordering::Relaxed,);
+            other
+        },
+    }
+}
+
+/// Config for the `--metadata-table` ingestor: the same database, but none of the mapping,
+/// embedding, keying or aliasing applied to data records.
+fn metadata_config(config: &IngestorConfig, table: &str,) -> IngestorConfig {
+    IngestorConfig {
+        database_url: config.database_url.clone(),
+        collection_name: Some(table.to_string(),),
+        vector_size: config.vector_size,
+        connection_retries: config.connection_retries,
+        reconnect_after: config.reconnect_after,
+        pool_size: Some(1,),
+        mongo: config.mongo.clone(),
+        postgres: config.postgres.clone(),
+        weaviate: config.weaviate.clone(),
+        ..Default::default()
+    }
+}
+
+/// Writes `file`'s row to the `--metadata-table`. `outcome` is the number of records the
+/// ingestor committed or the error. A failed write is logged but doesn't fail the file.
+async fn write_file_metadata(
+    ingestor: &dyn Ingestor,
+    file: &Path,
+    metadata: Option<FileMetadata,>,
+    outcome: std::result::Result<u64, String,>,
+) {
+    let size = match &metadata {
+        Some(metadata,) => Some(metadata.size,),
+        None => std::fs::metadata(file,).ok().map(|m| m.len(),),
+    };
+    let row = serde_json::json!({
+        "path": file.to_string_lossy(),
+        "size": size,
+        "line_count": metadata.and_then(|m| m.line_count),
+        "record_count": outcome.as_ref().ok(),
+        "ingested_at": chrono::Utc::now().to_rfc3339(),
+        "status": if outcome.is_ok() { "success" } else { "failed" },
+        "error": outcome.err(),
+    });
+    let nullable = |data_type| DataType::Union(vec![data_type, DataType::Null],);
+    let schema = HashMap::from([
+        ("path".to_string(), DataType::String,),
+        ("size".to_string(), nullable(DataType::Integer,),),
+        ("line_count".to_string(), nullable(DataType::Integer,),),
+        ("record_count".to_string(), nullable(DataType::Integer,),),
+        ("ingested_at".to_string(), DataType::String,),
+        ("status".to_string(), DataType::String,),
+        ("error".to_string(), nullable(DataType::String,),),
+    ],);
+    let records = RecordSet { rows: vec![row], schema: Some(schema,), columns: Vec::new(), };
+    if let Err(e,) = ingestor.ingest_records(records,).await {
+        warn!("Failed to write metadata row for {}: {}", file.display(), e);
+    }
+}
+
+/// Stores the file's original content in the ingestor's `<name>_raw` companion target.
+async fn store_raw_file(ingestor: &dyn Ingestor, local: &Path, file: &str,) -> Result<(),> {
+    let raw = RawFile { path: file.to_string(), ..RawFile::read(local,)? };
+    ingestor.ingest_raw(raw,).await
+}
+
+/// Reads a file. With a schema cache, CSVs whose header set was already inferred are parsed
+/// directly against the cached schema and skip nc_reader's inference. With `json.arrays`, JSON
+/// files holding a top-level array of objects are split into one record per element, and with
+/// `json.objects` a file holding a single object is read as one record. With `stream_csv`, CSVs
+/// are streamed row by row, typed from their first `infer_sample` rows, instead of read whole.
+/// With `infer_sample`, CSV types are inferred from only the first rows and the whole file is
+/// then parsed against them.
+/// CSV rows outside `csv.trim` are dropped before the header is read, and such files are parsed
+/// here since nc_reader always starts at the first row; so are CSVs with repeated header names,
+/// which `csv.on_duplicate` resolves, all CSVs with `csv.all_text` or `csv.type_overrides`,
+/// whose cells nc_reader would type before we see them, and all CSVs with `csv.header_order`.
+/// Avro files are always read as records typed from their writer schema, and `.jsonl` /
+/// `.ndjson` files as records typed from their first `infer_sample` lines, streamed like CSVs
+/// with `stream_csv`.
+async fn load_file(
+    file: &Path,
+    cache: Option<&SchemaCache,>,
+    json: &JsonLayout,
+    stream_csv: bool,
+    infer_sample: Option<usize,>,
+    csv: CsvLayout,
+    sheet: Option<&str,>,
+) -> Result<FileData,> {
+    if avro_input::is_avro(file,) {
+        return Ok(FileData::Records(avro_input::read_records(file,)?,),);
+    }
+    if excel_input::is_excel(file,) {
+        return Ok(FileData::Records(excel_input::read_records(file, sheet, csv.trim,)?,),);
+    }
+    if json_input::is_json_lines(file,) {
+        let lines = json_input::read_lines(file, infer_sample,)?;
+        if stream_csv {
+            return Ok(FileData::Reader(lines.into_reader(),),);
+        }
+        return Ok(FileData::Records(lines.into_records()?,),);
+    }
+    if !json.is_empty()
+        && json_input::is_json(file,)
+        && let Some(records,) = json_input::read_records(file, json,)?
+    {
+        return Ok(FileData::Records(records,),);
+    }
+    if stream_csv && csv_input::is_csv(file,) {
+        return Ok(FileData::Reader(csv_input::stream_rows(file, csv, infer_sample,)?,),);
+    }
+
+    // The header row is read once, for both the cache key and the duplicate check
+    let header = csv_input::is_csv(file,)
+        .then(|| csv_input::read_header_row(file, csv.trim,),)
+        .transpose()?;
+    let key = match (cache, &header,) {
+        (Some(_,), Some(header,),) => Some(SchemaCache::key_of(header, csv.on_duplicate,)?,),
+        _ => None,
+    };
+    if let (Some(cache,), Some(key,),) = (cache, &key,)
+        && let Some(schema,) = cache.get(key,)
+    {
+        return Ok(FileData::Records(csv_input::read_typed_rows(file, &schema, csv,)?,),);
+    }
+
+    // nc_reader can't skip junk rows, collapses repeated header names, always infers types and
+    // loses the header's column order, so such files are read by the ingestor itself
+    if let Some(header,) = &header
+        && (csv.all_text
+            || csv.header_order
+            || !csv.type_overrides.is_empty()
+            || !csv.trim.is_empty()
+            || csv_input::has_duplicate_headers(header,))
+    {
+        let records = csv_input::read_region(file, csv, infer_sample,)?;
+        if let (Some(cache,), Some(key,), Some(schema,),) = (cache, key, &records.schema,) {
+            cache.insert(key, schema.clone(),);
+        }
+        return Ok(FileData::Records(records,),);
+    }
+
+    if let Some(sample,) = infer_sample
+        && header.is_some()
+    {
+        let options = FileReaderOptions {
+            head: Some(sample,),
+            ..reader_options()
+        };
+        if let DataReaderResult::Csv(csv_data, _,) = read_file_content(file, options,).await?
+            && let Some(schema,) = csv_data.inferred_schema
+        {
+            if let (Some(cache,), Some(key,),) = (cache, &key,) {
+                cache.insert(key.clone(), schema.clone(),);
+            }
+            return Ok(FileData::Records(csv_input::read_typed_rows(file, &schema, csv,)?,),);
+        }
+    }
+
+    let data = read_file_content(file, reader_options(),).await?;
+    match (cache, key,) {
+        (Some(cache,), Some(key,),) => match cache.fill(key, data,) {
+            Ok(records,) => Ok(FileData::Records(records,),),
+            Err(data,) => Ok(FileData::Reader(data,),),
+        },
+        _ => Ok(FileData::Reader(data,),),
+    }
+}
+
+/// Splits one reader result into a copy per pipeline target. Row-based results are shared in
+/// memory; other variants can't be cloned, so additional targets re-read them from disk.
+async fn fan_out(
+    data: DataReaderResult,
+    file: &Path,
+    count: usize,
+) -> Vec<Result<DataReaderResult,>,> {
+    match data {
+        DataReaderResult::Csv(csv_data, metadata,) => (0..count)
+            .map(|_| Ok(DataReaderResult::Csv(csv_data.clone(), metadata.clone(),),),)
+            .collect(),
+        DataReaderResult::Stream(stream, metadata,) => {
+            match stream.collect::<std::result::Result<Vec<serde_json::Value,>, _,>>() {
+                Ok(records,) => (0..count)
+                    .map(|_| {
+                        let stream = records.clone().into_iter().map(Ok,);
+                        Ok(DataReaderResult::Stream(Box::new(stream,), metadata.clone(),),)
+                    },)
+                    .collect(),
+                Err(e,) => (0..count)
+                    .map(|_| Err(IngestorError::IngestionError(e.to_string(),),),)
+                    .collect(),
+            }
+        },
+        other => {
+            let mut copies = vec![Ok(other,)];
+            for _ in 1..count {
+                copies.push(read_file_content(file, reader_options(),).await.map_err(Into::into,),);
+            }
+            copies
+        },
+    }
+}
+
+/// A file to ingest. Manifest entries can send it to a collection/table other than the
+/// configured one.
+struct InputFile {
+    path:            PathBuf,
+    collection_name: Option<String,>,
+}
+
+/// Name used in the report for run-level errors: the manifest, else `--path`.
+fn input_label(path: Option<&Path,>, manifest: Option<&Path,>,) -> String {
+    manifest
+        .or(path,)
+        .map(|p| p.to_string_lossy().to_string(),)
+        .unwrap_or_default()
+}
+
+/// Lists an `s3://` or `gs://` `--path` as input files named by their object URLs, which are
+/// what checkpoints and the report see. Nothing is downloaded yet: each object is fetched
+/// through the returned store when its file is processed. Local paths return `None`.
+async fn list_remote(
+    path: Option<&Path,>,
+    registry: &ProcessingRegistry,
+    cli: &Cli,
+) -> Result<Option<(std::sync::Arc<RemoteStore,>, Vec<InputFile,>,),>,> {
+    let Some(path,) = path.filter(|path| remote_input::is_remote(path,),) else {
+        return Ok(None,);
+    };
+    let url = path.to_string_lossy();
+    let store = RemoteStore::open(&url,)?;
+    let mut files = Vec::new();
+    for object in store.list(&url,).await? {
+        if let Some(max_file_size,) = cli.max_file_size
+            && object.size > max_file_size
+        {
+            registry.record_skipped(
+                &object.url,
+                format!("too large ({} bytes > {} bytes)", object.size, max_file_size),
+            );
+            continue;
+        }
+        files.push(InputFile { path: PathBuf::from(object.url,), collection_name: None, },);
+    }
+    info!("Found {} object(s) under {}", files.len(), url);
+    Ok(Some((std::sync::Arc::new(store,), files,),),)
+}
+
+/// Temporary local copy of `file` when it is an object URL listed by `remote`, streamed from
+/// object storage and removed when dropped. Local files return `None`.
+async fn fetch_remote(
+    remote: Option<&RemoteStore,>,
+    file: &Path,
+) -> Result<Option<tempfile::TempPath,>,> {
+    match remote {
+        Some(remote,) if remote_input::is_remote(file,) => {
+            remote.fetch(&file.to_string_lossy(),).await.map(Some,)
+        },
+        _ => Ok(None,),
+    }
+}
+
+/// Files listed in `--manifest`, or discovered under `--path`. Manifest entries that don't exist
+/// are recorded as errors; directories in a manifest are walked like `--path`.
+fn collect_inputs(
+    path: Option<&Path,>,
+    manifest: Option<&Path,>,
+    registry: &ProcessingRegistry,
+    cli: &Cli,
+) -> Result<Vec<InputFile,>,> {
+    let Some(manifest,) = manifest else {
+        let files = path
+            .map(|path| discover_files(path, registry, cli,),)
+            .unwrap_or_default();
+        return Ok(files
+            .into_iter()
+            .map(|path| InputFile { path, collection_name: None, },)
+            .collect(),);
+    };
+
+    let mut inputs = Vec::new();
+    for entry in read_manifest(manifest,)? {
+        if !entry.path.exists() {
+            registry.record_error(
+                &entry.path.to_string_lossy(),
+                "listed in manifest but not found".to_string(),
+            )?;
+            continue;
+        }
+        for file in discover_files(&entry.path, registry, cli,) {
+            inputs.push(InputFile {
+                path:            file,
+                collection_name: entry.collection_name.clone(),
+            },);
+        }
+    }
+    Ok(inputs,)
+}
+
+/// Applies `--route EXT:NAME` to files without a manifest `collection_name`, which takes
+/// precedence.
+fn route_by_extension(files: &mut [InputFile], routes: &[(String, String,)],) {
+    for file in files.iter_mut().filter(|file| file.collection_name.is_none(),) {
+        file.collection_name = extension_route(&file.path, routes,).map(str::to_string,);
+    }
+}
+
+/// `--checkpoint`, with the progress of an earlier run if its file exists. Batch granularity
+/// needs every file to yield the same records in the same order on every run, and its batches
+/// to be committed in that order.
+fn open_checkpoint(
+    cli: &Cli,
+    args: &impl IngestionArgs,
+) -> Result<Option<std::sync::Arc<Checkpoint,>,>,> {
+    let Some(path,) = &cli.checkpoint else { return Ok(None,) };
+    if cli.checkpoint_granularity == CheckpointGranularity::Batch {
+        if args.common().batch_across_files {
+            return Err(IngestorError::ConfigurationError(
+                "--checkpoint-granularity batch can't track records buffered by \
+                 --batch-across-files"
+                    .to_string(),
+            ),);
+        }
+        // Records are grouped by collection, so batches aren't committed in input order
+        if args.mongo_options().collection_field.is_some() {
+            return Err(IngestorError::ConfigurationError(
+                "--checkpoint-granularity batch can't resume records routed by \
+                 --collection-field"
+                    .to_string(),
+            ),);
+        }
+        // Unwaited upserts are only confirmed by the final flush, after every batch
+        if args.qdrant_options().no_wait {
+            return Err(IngestorError::ConfigurationError(
+                "--checkpoint-granularity batch can't save batches sent with --qdrant-wait false"
+                    .to_string(),
+            ),);
+        }
+        // The sample is ingested after every file has been read, in no file's batches
+        if cli.sample_size.is_some() {
+            return Err(IngestorError::ConfigurationError(
+                "--checkpoint-granularity batch can't track records sampled by --sample-size"
+                    .to_string(),
+            ),);
+        }
+        if cli.sample_per_file.is_some() && cli.seed.is_none() {
+            return Err(IngestorError::ConfigurationError(
+                "--checkpoint-granularity batch with --sample-per-file needs --seed, so a resumed \
+                 file yields the same sample"
+                    .to_string(),
+            ),);
+        }
+    }
+    Ok(Some(std::sync::Arc::new(Checkpoint::open(path, cli.checkpoint_granularity,)?,),),)
+}
+
+/// Saves every committed batch of `file` to a batch-granularity checkpoint, counting on from
+/// the `committed` records of an earlier run.
+fn commit_tracker(
+    checkpoint: Option<&std::sync::Arc<Checkpoint,>,>,
+    file: &str,
+    committed: usize,
+) -> Option<CommitTracker,> {
+    let checkpoint = checkpoint.filter(|c| c.granularity() == CheckpointGranularity::Batch,)?;
+    let checkpoint = std::sync::Arc::clone(checkpoint,);
+    let file = file.to_string();
+    Some(CommitTracker::new(committed, move |total| {
+        if let Err(e,) = checkpoint.record_batch(&file, total,) {
+            warn!("Failed to save the checkpoint for {}: {}", file, e);
+        }
+    },),)
+}
+
+fn log_key_cardinality(path: &str, field: &str, cardinality: KeyCardinality,) {
+    let KeyCardinality { sampled, distinct, missing, } = cardinality;
+    if cardinality.is_constant() {
+        warn!(
+            "{}: all {} keyed record(s) sampled share one '{}' value, so they would collide",
+            path,
+            sampled - missing,
+            field
+        );
+    } else {
+        info!(
+            "{}: {} distinct '{}' value(s) in {} sampled record(s)",
+            path, distinct, field, sampled
+        );
+    }
+    if missing > 0 {
+        warn!("{}: {} sampled record(s) have no '{}' value", path, missing, field);
+    }
 }
 
-#[derive(Serialize, Default,)]
-struct Report {
-    total_files:   usize,
-    success_count: usize,
-    failure_count: usize,
-    errors:        Vec<ProcessingError,>,
+/// `--table-comment` and the `--column-comments` sidecar file.
+fn table_comments(common: &CommonIngestorArgs,) -> Result<TableComments,> {
+    let columns = match &common.column_comments {
+        Some(path,) => {
+            let text = std::fs::read_to_string(path,)?;
+            serde_json::from_str(&text,).map_err(|e| {
+                IngestorError::ConfigurationError(format!(
+                    "{} should be a JSON object of column name to comment: {}",
+                    path.display(),
+                    e
+                ),)
+            },)?
+        },
+        None => HashMap::new(),
+    };
+    Ok(TableComments { table: common.table_comment.clone(), columns, },)
 }
 
-struct ProcessingRegistry {
-    report: std::sync::Mutex<Report,>,
-    strict: bool,
+/// First column of `file` in source order, as --normalize-field-names renames it: the header of
+/// a CSV file or Excel sheet, an Avro file's first schema field, or the first key of a JSON
+/// file's first record as written. Only the header or first record is read. Other formats have
+/// none.
+fn first_column(
+    file: &Path,
+    csv: CsvLayout,
+    sheet: Option<&str,>,
+    style: NameStyle,
+) -> Result<Option<String,>,> {
+    let first = if csv_input::is_csv(file,) {
+        csv_input::read_headers(file, csv,)?.into_iter().next()
+    } else if excel_input::is_excel(file,) {
+        excel_input::read_headers(file, sheet, csv.trim,)?.into_iter().next()
+    } else if avro_input::is_avro(file,) {
+        avro_input::read_columns(file,)?.into_iter().next()
+    } else if json_input::is_json(file,) || json_input::is_json_lines(file,) {
+        json_input::first_key(file,)?
+    } else {
+        None
+    };
+    Ok(first.map(|name| normalize_name(&name, style,),),)
 }
 
-impl ProcessingRegistry {
-    fn new(strict: bool,) -> Self {
-        Self {
-            report: std::sync::Mutex::new(Report::default(),),
-            strict,
-        }
-    }
-
-    fn record_success(&self,) {
-        let mut report = self.report.lock().unwrap();
-        report.total_files += 1;
-        report.success_count += 1;
+/// Fails a file whose first column isn't `key`, the --first-col-as-key column taken from the
+/// first file, since its records would be keyed on a different field.
+fn check_first_column(
+    file: &Path,
+    key: &str,
+    csv: CsvLayout,
+    sheet: Option<&str,>,
+    style: NameStyle,
+) -> Result<(),> {
+    match first_column(file, csv, sheet, style,)? {
+        Some(column,) if column != key => Err(IngestorError::IngestionError(format!(
+            "First column '{}' isn't the key column '{}' taken from the first file",
+            column, key
+        ),),),
+        _ => Ok((),),
     }
+}
 
-    fn record_error(&self, path: &str, err: String,) -> Result<(),> {
-        let mut report = self.report.lock().unwrap();
-        report.total_files += 1;
-        report.failure_count += 1;
-        report.errors.push(ProcessingError {
-            path:  path.to_string(),
-            error: err.clone(),
-        },);
-
-        error!("Error at {}: {}", path, err);
-
-        if self.strict {
-            return Err(IngestorError::IngestionError(format!(
-                "Strict mode enabled. Halting on error at {}: {}",
-                path, err
-            ),),);
-        }
-        Ok((),)
+/// `.json` files holding a single object are read as records typed over all such `files`;
+/// files fetched from object storage aren't scanned ahead, so they are typed on their own.
+fn json_layout<'a,>(cli: &Cli, files: impl IntoIterator<Item = &'a Path,>,) -> JsonLayout {
+    let object_schema = json_input::object_schema(files,);
+    JsonLayout {
+        arrays:        cli.json_array,
+        objects:       true,
+        object_schema: (!object_schema.is_empty()).then(|| std::sync::Arc::new(object_schema,),),
     }
+}
 
-    fn save_report(&self,) -> Result<(),> {
-        let report = self.report.lock().unwrap();
-        let json = serde_json::to_string_pretty(&*report,).map_err(|e| {
-            IngestorError::Other(format!("Failed to serialize error report: {}", e),)
-        },)?;
-        std::fs::write("ingestion_report.json", json,).map_err(|e| {
-            IngestorError::Other(format!("Failed to write ingestion_report.json: {}", e),)
-        },)?;
-        info!("Ingestion report saved to ingestion_report.json");
-        Ok((),)
+fn csv_layout(cli: &Cli,) -> CsvLayout {
+    CsvLayout {
+        trim:           RowTrim {
+            skip_rows:        cli.skip_rows,
+            skip_footer_rows: cli.skip_footer_rows,
+        },
+        on_duplicate:   cli.on_duplicate_column,
+        all_text:       cli.preserve_source_types,
+        type_overrides: type_overrides(cli,),
+        header_order:   false,
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(),> {
-    // Initialize tracing
-    let file_appender = tracing_appender::rolling::never(".", "ingestor.log",);
-    let (non_blocking, _guard,) = tracing_appender::non_blocking(file_appender,);
+/// `--type-override` pairs by field name; a later pair for the same field wins.
+fn type_overrides(cli: &Cli,) -> HashMap<String, ColumnType,> {
+    cli.type_override.iter().cloned().collect()
+}
 
-    tracing_subscriber::registry()
-        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info",),),)
-        .with(fmt::layer().with_writer(std::io::stderr,),)
-        .with(fmt::layer().with_writer(non_blocking,).with_ansi(false,),)
-        .init();
+fn reservoir_sample(cli: &Cli,) -> Option<ReservoirSample,> {
+    cli.sample_per_file.map(|size| ReservoirSample { size, seed: cli.seed, },)
+}
 
-    let cli = Cli::parse();
-    let registry = std::sync::Arc::new(ProcessingRegistry::new(cli.strict,),);
-
-    let res = match &cli.command {
-        Commands::Mongo(args,) => {
-            handle_ingestion(
-                args,
-                MongoIngestor::new,
-                std::sync::Arc::clone(&registry,),
-                cli.concurrency,
-            )
-            .await
-        },
-        Commands::Neo4j(args,) => {
-            handle_ingestion(
-                args,
-                Neo4jIngestor::new,
-                std::sync::Arc::clone(&registry,),
-                cli.concurrency,
-            )
-            .await
-        },
-        Commands::Postgres(args,) => {
-            handle_ingestion(
-                args,
-                PostgresIngestor::new,
-                std::sync::Arc::clone(&registry,),
-                cli.concurrency,
-            )
-            .await
-        },
-        Commands::Qdrant(args,) => {
-            handle_ingestion(
-                args,
-                QdrantIngestor::new,
-                std::sync::Arc::clone(&registry,),
-                cli.concurrency,
-            )
-            .await
-        },
+/// `--sample-size`, shared by the files going to one collection.
+fn run_sample(cli: &Cli,) -> Option<std::sync::Arc<RunSample,>,> {
+    cli.sample_size.map(|size| std::sync::Arc::new(RunSample::new(size, cli.seed,),),)
+}
 
-        Commands::Sqlite(args,) => {
-            handle_ingestion(
-                args,
-                SqliteIngestor::new,
-                std::sync::Arc::clone(&registry,),
-                cli.concurrency,
-            )
-            .await
-        },
-    };
+fn field_names(cli: &Cli,) -> FieldNames {
+    FieldNames { style: cli.normalize_field_names, on_duplicate: cli.on_duplicate_column, }
+}
 
-    if cli.report {
-        registry.save_report()?;
-    }
+/// Rows `--drop-null-columns` measures when `--infer-sample` isn't set.
+const NULL_COLUMN_SAMPLE: usize = 10_000;
 
-    res
+/// `--drop-null-columns`, shared by the files of one target so they drop the same columns.
+fn null_column_filter(cli: &Cli,) -> Option<std::sync::Arc<NullColumnFilter,>,> {
+    cli.drop_null_columns.map(|threshold| {
+        let sample = cli.infer_sample.unwrap_or(NULL_COLUMN_SAMPLE,);
+        std::sync::Arc::new(NullColumnFilter::new(threshold, sample,),)
+    },)
 }
 
-async fn handle_ingestion<T: Ingestor + Send + Sync + 'static, F,>(
-    args: &impl IngestionArgs,
-    ingestor_factory: impl FnOnce(IngestorConfig,) -> F,
-    registry: std::sync::Arc<ProcessingRegistry,>,
-    concurrency: usize,
-) -> Result<(),>
-where
-    F: Future<Output = Result<T,>,> + Send + 'static,
-{
-    let path = args.path();
-    let database_url = args.database_url();
+fn record_size_guard(cli: &Cli,) -> Option<RecordSizeGuard,> {
+    cli.max_record_size
+        .map(|max_bytes| RecordSizeGuard { max_bytes, policy: cli.on_oversize, },)
+}
 
-    let config = IngestorConfig {
-        database_url:    database_url.to_string(),
-        collection_name: args.collection_name(),
-        vector_size:     args.vector_size(),
-        mappings:        args.mappings(),
-        openai_api_key:  args.openai_api_key(),
-        embed_field:     args.embed_field(),
-        relationships:   args.relationships(),
-    };
+/// Lists `path`'s skipped and truncated records in the report.
+fn oversize_handler(
+    registry: &std::sync::Arc<ProcessingRegistry,>,
+    path: &str,
+) -> OversizeHandler {
+    let registry = std::sync::Arc::clone(registry,);
+    let path = path.to_string();
+    std::sync::Arc::new(move |record, left_out| {
+        registry.record_oversized(&path, record, left_out,)
+    },)
+}
 
-    let ingestor_res = ingestor_factory(config,).await;
-    let ingestor = match ingestor_res {
-        Ok(i,) => std::sync::Arc::new(i,),
-        Err(e,) => {
-            registry.record_error(&path.to_string_lossy(), e.to_string(),)?;
-            return Ok((),);
-        },
-    };
+/// Files processed at once. `--preserve-order` ingests one file at a time.
+fn file_concurrency(cli: &Cli,) -> usize {
+    if cli.preserve_order { 1 } else { cli.concurrency }
+}
 
+/// Collects the files under `path`, recording any that are skipped by discovery filters.
+/// With `--preserve-order` they are returned sorted by path.
+fn discover_files(path: &Path, registry: &ProcessingRegistry, cli: &Cli,) -> Vec<PathBuf,> {
     let mut files = Vec::new();
     if path.is_file() {
         files.push(path.to_path_buf(),);
     } else if path.is_dir() {
+        // With symlinks followed, one file can be reachable through several links
+        let mut seen = HashSet::new();
         for entry in walkdir::WalkDir::new(path,)
+            .follow_links(cli.follow_symlinks,)
             .into_iter()
-            .filter_map(|e| e.ok(),)
         {
-            if entry.path().is_file() {
-                files.push(entry.path().to_path_buf(),);
-            }
-        }
-    }
-
-    info!(
-        "Found {} files to process with concurrency {}",
-        files.len(),
-        concurrency
-    );
-
-    let mut join_set = tokio::task::JoinSet::new();
-    let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(concurrency,),);
-
-    for file in files {
-        let ingestor_task = std::sync::Arc::clone(&ingestor,);
-        let registry_task = std::sync::Arc::clone(&registry,);
-        let permit = semaphore.clone().acquire_owned().await.unwrap();
-
-        join_set.spawn(async move {
-            let _permit = permit; // Hold permit until task is done
-            let file_str = file.to_string_lossy().to_string();
-
-            let reader_options = FileReaderOptions {
-                head:               None,
-                file_type_override: None,
-                output_mode:        OutputMode::Default,
-                output_format:      OutputFormat::Json,
-                recursive:          false,
-                filter_exts:        None,
-                output_path:        None,
-            };
-
-            info!("Processing: {}", file_str);
-            let nc_res = read_file_content(&file, reader_options,).await;
-
-            let data = match nc_res {
-                Ok(d,) => d,
+            let entry = match entry {
+                Ok(entry,) => entry,
                 Err(e,) => {
-                    let _ = registry_task.record_error(&file_str, e.to_string(),);
-                    return;
+                    // walkdir detects cycles when following links; report instead of looping
+                    if e.loop_ancestor().is_some() {
+                        warn!("Skipping symlink cycle: {}", e);
+                    }
+                    continue;
                 },
             };
+            if !entry.path().is_file() {
+                continue;
+            }
+            if cli.follow_symlinks
+                && let Ok(canonical,) = entry.path().canonicalize()
+                && !seen.insert(canonical,)
+            {
+                continue;
+            }
+            files.push(entry.path().to_path_buf(),);
+        }
+    }
 
-            match ingestor_task.ingest(data,).await {
-                Ok(_,) => {
-                    registry_task.record_success();
-                    info!("Successfully ingested: {}", file_str);
-                },
-                Err(e,) => {
-                    let _ = registry_task.record_error(&file_str, e.to_string(),);
-                },
+    if let Some(max_file_size,) = cli.max_file_size {
+        files.retain(|file| {
+            let size = std::fs::metadata(file,).map(|m| m.len(),).unwrap_or(0,);
+            if size > max_file_size {
+                registry.record_skipped(
+                    &file.to_string_lossy(),
+                    format!("too large ({} bytes > {} bytes)", size, max_file_size),
+                );
+                return false;
             }
+            true
         },);
     }
 
-    while let Some(res,) = join_set.join_next().await {
-        if let Err(e,) = res {
-            error!("Task panicked: {}", e);
-        }
+    if cli.preserve_order {
+        files.sort();
     }
+    files
+}
 
-    Ok((),)
+fn reader_options() -> FileReaderOptions {
+    FileReaderOptions {
+        head:               None,
+        file_type_override: None,
+        output_mode:        OutputMode::Default,
+        output_format:      OutputFormat::Json,
+        recursive:          false,
+        filter_exts:        None,
+        output_path:        None,
+    }
 }
 
 // Trait to generalize over different database argument types
 trait IngestionArgs {
-    fn path(&self,) -> &std::path::Path;
+    fn path(&self,) -> Option<&std::path::Path,>;
+    fn manifest(&self,) -> Option<&std::path::Path,>;
     fn database_url(&self,) -> &str;
     fn collection_name(&self,) -> Option<String,>;
     fn vector_size(&self,) -> Option<u64,>;
@@ -271,6 +2471,66 @@ trait IngestionArgs {
     fn openai_api_key(&self,) -> Option<String,>;
     fn embed_field(&self,) -> Option<String,>;
     fn relationships(&self,) -> Option<Vec<nc_ingestor::ingestor::RelationshipConfig,>,>;
+    fn common(&self,) -> &CommonIngestorArgs;
+
+    fn partition(&self,) -> Option<PartitionConfig,> {
+        None
+    }
+
+    fn schema_diff(&self,) -> bool {
+        false
+    }
+
+    fn mongo_options(&self,) -> MongoOptions {
+        MongoOptions::default()
+    }
+
+    fn postgres_options(&self,) -> PostgresOptions {
+        PostgresOptions::default()
+    }
+
+    fn qdrant_options(&self,) -> QdrantOptions {
+        QdrantOptions::default()
+    }
+
+    fn sqlite_options(&self,) -> SqliteOptions {
+        SqliteOptions::default()
+    }
+
+    fn sql_hooks(&self,) -> SqlHookArgs {
+        SqlHookArgs::default()
+    }
+
+    fn weaviate_options(&self,) -> WeaviateOptions {
+        WeaviateOptions::default()
+    }
+}
+
+fn openai_headers(common: &CommonIngestorArgs,) -> Option<Vec<(String, String,),>,> {
+    let headers = embeddings::openai_headers(
+        &common.openai_header,
+        common.openai_organization.as_deref(),
+        common.openai_project.as_deref(),
+    );
+
+    if headers.is_empty() { None } else { Some(headers,) }
+}
+
+fn batch_config(common: &CommonIngestorArgs,) -> BatchConfig {
+    BatchConfig {
+        size:           common.batch_size,
+        ordered:        common.ordered,
+        timeout:        common.batch_timeout_ms.map(Duration::from_millis,),
+        preserve_order: false,
+        across_files:   common.batch_across_files,
+    }
+}
+
+fn field_filter(common: &CommonIngestorArgs,) -> FieldFilter {
+    FieldFilter {
+        include_fields: common.include_fields.clone(),
+        exclude_fields: common.exclude_fields.clone(),
+    }
 }
 
 fn map_to_hashmap(
@@ -280,8 +2540,12 @@ fn map_to_hashmap(
 }
 
 impl IngestionArgs for MongoArgs {
-    fn path(&self,) -> &std::path::Path {
-        &self.path
+    fn path(&self,) -> Option<&std::path::Path,> {
+        self.path.as_deref()
+    }
+
+    fn manifest(&self,) -> Option<&std::path::Path,> {
+        self.manifest.as_deref()
     }
 
     fn database_url(&self,) -> &str {
@@ -314,11 +2578,32 @@ impl IngestionArgs for MongoArgs {
             .as_ref()
             .and_then(|s| serde_json::from_str(s,).ok(),)
     }
+
+    fn common(&self,) -> &CommonIngestorArgs {
+        &self.common
+    }
+
+    fn mongo_options(&self,) -> MongoOptions {
+        MongoOptions {
+            app_name:                      self.mongo_app_name.clone(),
+            server_selection_timeout_secs: self.mongo_server_selection_timeout_secs,
+            tls_ca_file:                   self.mongo_tls_ca_file.clone(),
+            tls_cert_key_file:             self.mongo_tls_cert_key_file.clone(),
+            atomic_file:                   self.atomic_file,
+            api_version:                   self.mongo_api_version,
+            api_strict:                    self.mongo_api_strict,
+            collection_field:              self.collection_field.clone(),
+        }
+    }
 }
 
 impl IngestionArgs for Neo4jArgs {
-    fn path(&self,) -> &std::path::Path {
-        &self.path
+    fn path(&self,) -> Option<&std::path::Path,> {
+        self.path.as_deref()
+    }
+
+    fn manifest(&self,) -> Option<&std::path::Path,> {
+        self.manifest.as_deref()
     }
 
     fn database_url(&self,) -> &str {
@@ -351,11 +2636,19 @@ impl IngestionArgs for Neo4jArgs {
             .as_ref()
             .and_then(|s| serde_json::from_str(s,).ok(),)
     }
+
+    fn common(&self,) -> &CommonIngestorArgs {
+        &self.common
+    }
 }
 
 impl IngestionArgs for PostgresArgs {
-    fn path(&self,) -> &std::path::Path {
-        &self.path
+    fn path(&self,) -> Option<&std::path::Path,> {
+        self.path.as_deref()
+    }
+
+    fn manifest(&self,) -> Option<&std::path::Path,> {
+        self.manifest.as_deref()
     }
 
     fn database_url(&self,) -> &str {
@@ -388,11 +2681,46 @@ impl IngestionArgs for PostgresArgs {
             .as_ref()
             .and_then(|s| serde_json::from_str(s,).ok(),)
     }
+
+    fn common(&self,) -> &CommonIngestorArgs {
+        &self.common
+    }
+
+    fn partition(&self,) -> Option<PartitionConfig,> {
+        self.partition_by.as_ref().map(|column| PartitionConfig {
+            column:          column.clone(),
+            strategy:        self.partition_strategy,
+            create_default:  !self.no_default_partition,
+            hash_partitions: self.hash_partitions,
+        },)
+    }
+
+    fn schema_diff(&self,) -> bool {
+        self.dry_run_schema_diff
+    }
+
+    fn postgres_options(&self,) -> PostgresOptions {
+        PostgresOptions {
+            statement_timeout_ms: self.pg_statement_timeout_ms,
+            embedding_column:     self.embedding_column.clone(),
+            fulltext_fields:      self.fulltext_fields.clone(),
+            unlogged:             self.unlogged,
+            optimize_bulk_load:   self.optimize_bulk_load,
+        }
+    }
+
+    fn sql_hooks(&self,) -> SqlHookArgs {
+        self.sql_hooks.clone()
+    }
 }
 
 impl IngestionArgs for QdrantArgs {
-    fn path(&self,) -> &std::path::Path {
-        &self.path
+    fn path(&self,) -> Option<&std::path::Path,> {
+        self.path.as_deref()
+    }
+
+    fn manifest(&self,) -> Option<&std::path::Path,> {
+        self.manifest.as_deref()
     }
 
     fn database_url(&self,) -> &str {
@@ -425,11 +2753,35 @@ impl IngestionArgs for QdrantArgs {
             .as_ref()
             .and_then(|s| serde_json::from_str(s,).ok(),)
     }
+
+    fn common(&self,) -> &CommonIngestorArgs {
+        &self.common
+    }
+
+    fn qdrant_options(&self,) -> QdrantOptions {
+        QdrantOptions {
+            alias:              self.qdrant_alias.clone(),
+            delete_old:         self.qdrant_delete_old,
+            strict:             self.qdrant_strict,
+            payload_schema:     self.qdrant_payload_schema.iter().cloned().collect(),
+            dedup_threshold:    self.dedup_threshold,
+            on_empty_embedding: self.on_empty_embedding,
+            quantization:       self.quantization,
+            quantile:           self.quantization_quantile,
+            always_ram:         self.quantization_always_ram,
+            no_wait:            !self.qdrant_wait,
+            change_detect:      self.change_detect.clone(),
+        }
+    }
 }
 
 impl IngestionArgs for SqliteArgs {
-    fn path(&self,) -> &std::path::Path {
-        &self.path
+    fn path(&self,) -> Option<&std::path::Path,> {
+        self.path.as_deref()
+    }
+
+    fn manifest(&self,) -> Option<&std::path::Path,> {
+        self.manifest.as_deref()
     }
 
     fn database_url(&self,) -> &str {
@@ -462,4 +2814,114 @@ impl IngestionArgs for SqliteArgs {
             .as_ref()
             .and_then(|s| serde_json::from_str(s,).ok(),)
     }
+
+    fn common(&self,) -> &CommonIngestorArgs {
+        &self.common
+    }
+
+    fn schema_diff(&self,) -> bool {
+        self.dry_run_schema_diff
+    }
+
+    fn sqlite_options(&self,) -> SqliteOptions {
+        SqliteOptions { fts_fields: self.sqlite_fts.clone(), }
+    }
+
+    fn sql_hooks(&self,) -> SqlHookArgs {
+        self.sql_hooks.clone()
+    }
+}
+
+impl IngestionArgs for WeaviateArgs {
+    fn path(&self,) -> Option<&std::path::Path,> {
+        self.path.as_deref()
+    }
+
+    fn manifest(&self,) -> Option<&std::path::Path,> {
+        self.manifest.as_deref()
+    }
+
+    fn database_url(&self,) -> &str {
+        &self.uri
+    }
+
+    fn collection_name(&self,) -> Option<String,> {
+        self.common.collection_name.clone()
+    }
+
+    fn vector_size(&self,) -> Option<u64,> {
+        self.common.vector_size
+    }
+
+    fn mappings(&self,) -> Option<std::collections::HashMap<String, String,>,> {
+        map_to_hashmap(&self.common.map,)
+    }
+
+    fn openai_api_key(&self,) -> Option<String,> {
+        self.common.openai_api_key.clone()
+    }
+
+    fn embed_field(&self,) -> Option<String,> {
+        self.common.embed_field.clone()
+    }
+
+    fn relationships(&self,) -> Option<Vec<nc_ingestor::ingestor::RelationshipConfig,>,> {
+        self.common
+            .relationships
+            .as_ref()
+            .and_then(|s| serde_json::from_str(s,).ok(),)
+    }
+
+    fn common(&self,) -> &CommonIngestorArgs {
+        &self.common
+    }
+
+    fn weaviate_options(&self,) -> WeaviateOptions {
+        WeaviateOptions { api_key: self.weaviate_api_key.clone(), }
+    }
+}
+
+impl IngestionArgs for MemoryArgs {
+    fn path(&self,) -> Option<&std::path::Path,> {
+        self.path.as_deref()
+    }
+
+    fn manifest(&self,) -> Option<&std::path::Path,> {
+        self.manifest.as_deref()
+    }
+
+    fn database_url(&self,) -> &str {
+        "memory://"
+    }
+
+    fn collection_name(&self,) -> Option<String,> {
+        self.common.collection_name.clone()
+    }
+
+    fn vector_size(&self,) -> Option<u64,> {
+        self.common.vector_size
+    }
+
+    fn mappings(&self,) -> Option<std::collections::HashMap<String, String,>,> {
+        map_to_hashmap(&self.common.map,)
+    }
+
+    fn openai_api_key(&self,) -> Option<String,> {
+        self.common.openai_api_key.clone()
+    }
+
+    fn embed_field(&self,) -> Option<String,> {
+        self.common.embed_field.clone()
+    }
+
+    fn relationships(&self,) -> Option<Vec<nc_ingestor::ingestor::RelationshipConfig,>,> {
+        self.common
+            .relationships
+            .as_ref()
+            .and_then(|s| serde_json::from_str(s,).ok(),)
+    }
+
+    fn common(&self,) -> &CommonIngestorArgs {
+        &self.common
+    }
 }
@@ -0,0 +1,65 @@
+// nc_ingestor/src/manifest.rs
+// Explicit file lists (`--manifest`) as an alternative to walking `--path`.
+
+use std::path::{Path, PathBuf};
+
+use crate::error::{IngestorError, Result};
+
+/// One manifest line: a bare path, or an NDJSON object with per-file overrides.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize,)]
+pub struct ManifestEntry {
+    pub path:            PathBuf,
+    /// Table/collection for this file instead of `--collection-name`
+    #[serde(default)]
+    pub collection_name: Option<String,>,
+}
+
+/// Reads a manifest. Lines are either paths or JSON objects like
+/// `{"path": "a.csv", "collection_name": "events"}`; blank lines and `#` comments are skipped.
+/// Relative paths are resolved against the manifest's directory.
+pub fn read_manifest(path: &Path,) -> Result<Vec<ManifestEntry,>,> {
+    let content = std::fs::read_to_string(path,).map_err(|e| {
+        IngestorError::ConfigurationError(format!(
+            "Failed to read manifest {}: {}",
+            path.display(),
+            e
+        ),)
+    },)?;
+    let base = path.parent().unwrap_or(Path::new("",),);
+
+    let mut entries = Vec::new();
+    for (index, line,) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#',) {
+            continue;
+        }
+
+        let mut entry = if line.starts_with('{',) {
+            serde_json::from_str::<ManifestEntry,>(line,).map_err(|e| {
+                IngestorError::ConfigurationError(format!(
+                    "Invalid manifest entry at {}:{}: {}",
+                    path.display(),
+                    index + 1,
+                    e
+                ),)
+            },)?
+        } else {
+            ManifestEntry { path: PathBuf::from(line,), collection_name: None, }
+        };
+        if entry.path.is_relative() {
+            entry.path = base.join(&entry.path,);
+        }
+        entries.push(entry,);
+    }
+    Ok(entries,)
+}
+
+/// The `--route EXT:NAME` target for a file. Extensions match case-insensitively, with or
+/// without a leading dot; the first matching route wins.
+pub fn extension_route<'a,>(path: &Path, routes: &'a [(String, String,)],) -> Option<&'a str,> {
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    routes
+        .iter()
+        .find(|(route, _,)| route.trim_start_matches('.',).eq_ignore_ascii_case(&ext,),)
+        .map(|(_, name,)| name.as_str(),)
+}
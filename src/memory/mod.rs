@@ -0,0 +1,118 @@
+// nc_ingestor/src/memory/mod.rs
+// In-memory ingestor for tests and dry runs.
+
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use nc_reader::nc_reader_result::DataReaderResult;
+use serde_json::Value;
+use tracing::info;
+
+use crate::error::{IngestorError, Result};
+use crate::ingestor::{IngestStats, Ingestor, IngestorConfig, RawFile, blob_value};
+
+/// Collects ingested records in memory instead of writing them anywhere. Use
+/// [`MemoryIngestor::records`] to inspect what a run produced, e.g. to test field filters,
+/// mappings or embeddings without a database. With `batch.across_files`, records are held back
+/// until [`Ingestor::flush`], like a backend that buffers across files.
+#[derive(Default,)]
+pub struct MemoryIngestor {
+    config:  IngestorConfig,
+    records: Arc<Mutex<Vec<Value,>,>,>,
+    pending: Mutex<Vec<Value,>,>,
+    raw:     Arc<Mutex<Vec<RawFile,>,>,>,
+}
+
+impl MemoryIngestor {
+    /// Shared handle to the ingested records, in ingestion order.
+    pub fn records(&self,) -> Arc<Mutex<Vec<Value,>,>,> {
+        Arc::clone(&self.records,)
+    }
+
+    /// Shared handle to the files stored with `--store-raw`.
+    pub fn raw_files(&self,) -> Arc<Mutex<Vec<RawFile,>,>,> {
+        Arc::clone(&self.raw,)
+    }
+
+    fn push(&self, records: impl IntoIterator<Item = Value,>,) {
+        if self.config.batch.across_files {
+            self.pending.lock().unwrap().extend(records,);
+            return;
+        }
+        self.store(records,);
+    }
+
+    fn store(&self, records: impl IntoIterator<Item = Value,>,) -> usize {
+        let mut stored = self.records.lock().unwrap();
+        let before = stored.len();
+        stored.extend(records,);
+        let added = stored.len() - before;
+        drop(stored,);
+        if added > 0 {
+            self.config.batch_committed(
+                &self.config.collection_name(),
+                added,
+            );
+        }
+        added
+    }
+}
+
+#[async_trait]
+impl Ingestor for MemoryIngestor {
+    async fn new(config: IngestorConfig,) -> Result<Self,> {
+        Ok(MemoryIngestor { config, ..Default::default() },)
+    }
+
+    async fn ingest(&self, data: DataReaderResult,) -> Result<(),> {
+        match data {
+            DataReaderResult::Csv(csv_data, _,) => self.push(csv_data.nc_rows,),
+            DataReaderResult::Stream(stream, _,) => {
+                let records = stream
+                    .map(|record_res| {
+                        record_res.map_err(|e| IngestorError::IngestionError(e.to_string(),),)
+                    },)
+                    .collect::<Result<Vec<_,>,>>()?;
+                self.push(records,);
+            },
+            _ => {
+                let value = blob_value(&data, &self.config,)?;
+                self.push(std::iter::once(value,),);
+            },
+        }
+        Ok((),)
+    }
+
+    async fn ingest_raw(&self, raw: RawFile,) -> Result<(),> {
+        self.raw.lock().unwrap().push(raw,);
+        Ok((),)
+    }
+
+    async fn target_count(&self,) -> Result<Option<u64,>,> {
+        Ok(Some(self.records.lock().unwrap().len() as u64,),)
+    }
+
+    async fn clean(&self,) -> Result<String,> {
+        let removed = std::mem::take(&mut *self.records.lock().unwrap(),).len();
+        self.raw.lock().unwrap().clear();
+        Ok(format!("cleared {} in-memory records", removed),)
+    }
+
+    fn defers_writes(&self,) -> bool {
+        self.config.batch.across_files
+    }
+
+    async fn flush(&self,) -> Result<Vec<IngestStats,>,> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap(),);
+        let records = self.store(pending,);
+        if records == 0 {
+            return Ok(Vec::new(),);
+        }
+        Ok(vec![IngestStats { target: self.config.collection_name(), records, }],)
+    }
+
+    async fn finalize(&self,) -> Result<(),> {
+        info!("Memory backend collected {} records", self.records.lock().unwrap().len());
+        Ok((),)
+    }
+}
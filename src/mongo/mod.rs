@@ -1,86 +1,172 @@
 // nc_ingestor/src/mongo/mod.rs
 // MongoDB specific ingestion logic.
 
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
 use async_trait::async_trait;
-use mongodb::Client;
-use mongodb::bson::doc;
-use mongodb::options::ClientOptions;
+use mongodb::{Client, Collection};
+use mongodb::bson::{Bson, Document, doc};
+use mongodb::error::{ErrorKind, RETRYABLE_WRITE_ERROR, TRANSIENT_TRANSACTION_ERROR};
+use mongodb::options::{
+    ClientOptions, InsertManyOptions, ServerApi, ServerApiVersion, Tls, TlsOptions,
+};
 use nc_reader::nc_reader_result::DataReaderResult;
 use tracing::info;
 
-use crate::error::{IngestorError, Result};
-use crate::ingestor::{Ingestor, IngestorConfig};
-use crate::retry::{execute_with_retry, wrap_error};
+use crate::error::{IngestorError, Result, redact_message};
+use crate::ingestor::{
+    IngestStats, Ingestor, IngestorConfig, RawFile, RecordSet, blob_value, raw_target_name,
+};
+use crate::retry::{execute_with_connection_retry, execute_with_reconnect, wrap_error};
+use crate::transform::round_floats;
+
+/// Default `appName` reported to the server, visible in MongoDB logs and `currentOp`.
+pub const DEFAULT_APP_NAME: &str = "nc-ingestor";
+
+/// MongoDB connection options that aren't taken from the URI.
+#[derive(Debug, Clone, Default, serde::Deserialize,)]
+#[serde(default, deny_unknown_fields)]
+pub struct MongoOptions {
+    pub app_name:                      Option<String,>,
+    pub server_selection_timeout_secs: Option<u64,>,
+    /// PEM bundle of CAs trusted for the server certificate, for private trust chains
+    pub tls_ca_file:                   Option<PathBuf,>,
+    /// PEM file holding the client certificate and key, for mutual TLS
+    pub tls_cert_key_file:             Option<PathBuf,>,
+    /// Insert each file's records in one multi-document transaction (needs a replica set)
+    pub atomic_file:                   bool,
+    /// Stable API version declared on every command, so a server upgrade can't change the
+    /// behaviour of the commands we use
+    pub api_version:                   Option<MongoApiVersion,>,
+    /// With `api_version`, have the server reject commands outside that API version
+    pub api_strict:                    bool,
+    /// Record field whose (sanitized) value names the record's collection; records without it
+    /// go to `collection_name`
+    pub collection_field:              Option<String,>,
+}
+
+/// MongoDB Stable API versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize,)]
+pub enum MongoApiVersion {
+    #[value(name = "1")]
+    #[serde(rename = "1")]
+    V1,
+}
 
 pub struct MongoIngestor {
     #[allow(dead_code)]
-    config: IngestorConfig,
-    client: Client,
+    config:  IngestorConfig,
+    /// Replaced by [`MongoIngestor::reconnect`]; clone it with [`MongoIngestor::client`]
+    client:  RwLock<Client,>,
+    /// Documents waiting for a full batch when `batch.across_files` is set, per collection
+    pending: tokio::sync::Mutex<BTreeMap<String, Vec<Document,>,>,>,
+}
+
+/// Builds the client from `config` and pings the server, retrying up to `connection_retries`
+/// times. With `atomic_file`, also checks that the deployment supports transactions.
+async fn connect(config: &IngestorConfig,) -> Result<Client,> {
+    let mut client_options = ClientOptions::parse(&config.database_url,)
+        .await
+        .map_err(|e| {
+            IngestorError::ConfigurationError(format!(
+                "Failed to parse MongoDB URI: {}",
+                redact_message(&e.to_string(), &config.database_url,)
+            ),)
+        },)?;
+
+    // Explicit options win over the URI; fall back to our own app name
+    client_options.app_name = config
+        .mongo
+        .app_name
+        .clone()
+        .or(client_options.app_name.take(),)
+        .or_else(|| Some(DEFAULT_APP_NAME.to_string(),),);
+    if let Some(secs,) = config.mongo.server_selection_timeout_secs {
+        client_options.server_selection_timeout = Some(Duration::from_secs(secs,),);
+    }
+    apply_tls_files(&mut client_options, &config.mongo,)?;
+    if let Some(version,) = config.mongo.api_version {
+        let version = match version {
+            MongoApiVersion::V1 => ServerApiVersion::V1,
+        };
+        let server_api =
+            ServerApi::builder().version(version,).strict(config.mongo.api_strict,).build();
+        client_options.server_api = Some(server_api,);
+    }
+    let client = Client::with_options(client_options,).map_err(|e| {
+        IngestorError::ConnectionError(format!(
+            "Failed to create MongoDB client: {}",
+            redact_message(&e.to_string(), &config.database_url,)
+        ),)
+    },)?;
+
+    execute_with_connection_retry(config.connection_retries(), || async {
+        client
+            .database("admin",)
+            .run_command(doc! {"ping": 1}, None,)
+            .await
+            .map(|_| (),)
+            .map_err(|e| {
+                wrap_error(IngestorError::ConnectionError(format!(
+                    "Failed to connect to MongoDB: {}",
+                    redact_message(&e.to_string(), &config.database_url,)
+                ),),)
+            },)
+    },)
+    .await?;
+
+    if config.mongo.atomic_file {
+        ensure_transactions_supported(&client,).await?;
+    }
+    Ok(client,)
 }
 
 #[async_trait]
 impl Ingestor for MongoIngestor {
     async fn new(config: IngestorConfig,) -> Result<Self,> {
-        let client_options = ClientOptions::parse(&config.database_url,)
-            .await
-            .map_err(|e| {
-                IngestorError::ConfigurationError(format!("Failed to parse MongoDB URI: {}", e),)
-            },)?;
-        let client = Client::with_options(client_options,).map_err(|e| {
-            IngestorError::ConnectionError(format!("Failed to create MongoDB client: {}", e),)
-        },)?;
+        let client = connect(&config,).await?;
 
-        execute_with_retry(|| async {
-            client
-                .database("admin",)
-                .run_command(doc! {"ping": 1}, None,)
-                .await
-                .map(|_| (),)
-                .map_err(|e| {
-                    wrap_error(IngestorError::ConnectionError(format!(
-                        "Failed to connect to MongoDB: {}",
-                        e
-                    ),),)
-                },)
+        Ok(MongoIngestor {
+            config,
+            client: RwLock::new(client,),
+            pending: tokio::sync::Mutex::new(BTreeMap::new(),),
         },)
-        .await?;
-
-        Ok(MongoIngestor { config, client, },)
     }
 
     async fn ingest(&self, data: DataReaderResult,) -> Result<(),> {
         let database_name = "scm_db"; // Default database name
-        let collection_name = self
-            .config
-            .collection_name
-            .as_deref()
-            .unwrap_or(crate::DEFAULT_COLLECTION_NAME,);
-
-        let collection = self
-            .client
-            .database(database_name,)
-            .collection(collection_name,);
-
-        let bson_document = mongodb::bson::to_document(&data,).map_err(|e| {
-            IngestorError::IngestionError(format!(
-                "Failed to serialize DataReaderResult to BSON: {}",
-                e
-            ),)
-        },)?;
+        let collection_name = self.config.collection_name();
+
+        let blob = blob_value(&data, &self.config,)?;
+        let bson_document = match mongodb::bson::to_bson(&blob,) {
+            Ok(Bson::Document(document,),) => document,
+            // Unwrapped variants may hold a scalar or array, which can't be a top-level document
+            Ok(other,) => doc! { "data": other },
+            Err(e,) => {
+                return Err(IngestorError::IngestionError(format!(
+                    "Failed to serialize DataReaderResult to BSON: {}",
+                    e
+                ),),);
+            },
+        };
 
-        execute_with_retry(|| async {
-            collection
+        if self.buffers_across_files() {
+            return self.buffer(&collection_name, vec![bson_document],).await;
+        }
+
+        self.with_retry(|| async {
+            self.collection(&collection_name,)
                 .insert_one(bson_document.clone(), None,)
                 .await
                 .map(|_| (),)
-                .map_err(|e| {
-                    wrap_error(IngestorError::IngestionError(format!(
-                        "Failed to insert data into MongoDB: {}",
-                        e
-                    ),),)
-                },)
+                .map_err(|e| wrap_error(write_error("Failed to insert data into MongoDB", e,),),)
         },)
         .await?;
+        self.config.batch_committed(&collection_name, 1,);
 
         info!(
             "Successfully ingested data to MongoDB into collection '{}' in database '{}'.",
@@ -88,4 +174,415 @@ impl Ingestor for MongoIngestor {
         );
         Ok((),)
     }
+
+    async fn ingest_records(&self, mut records: RecordSet,) -> Result<(),> {
+        let database_name = "scm_db";
+        let collection_name = self.config.collection_name();
+        if records.rows.is_empty() {
+            return Ok((),);
+        }
+        if let Some(decimals,) = self.config.float_precision() {
+            records.rows.iter_mut().for_each(|row| round_floats(row, decimals,),);
+        }
+
+        // One document per record; non-object rows are wrapped like the blob path does. With
+        // `collection_field`, documents are grouped by collection in order of first appearance.
+        let mut groups: Vec<(String, Vec<Document,>,),> = Vec::new();
+        for row in &records.rows {
+            let document = match mongodb::bson::to_bson(row,) {
+                Ok(Bson::Document(document,),) => document,
+                Ok(other,) => doc! { "data": other },
+                Err(e,) => {
+                    return Err(IngestorError::IngestionError(format!(
+                        "Failed to serialize record to BSON: {}",
+                        e
+                    ),),);
+                },
+            };
+            let collection = self.resolve_collection(row, &collection_name,);
+            match groups.iter_mut().find(|(name, _,)| *name == collection,) {
+                Some((_, documents,),) => documents.push(document,),
+                None => groups.push((collection, vec![document],),),
+            }
+        }
+
+        if self.buffers_across_files() {
+            for (collection, documents,) in groups {
+                self.buffer(&collection, documents,).await?;
+            }
+            return Ok((),);
+        }
+
+        let options = self.insert_options();
+        let total: usize = groups.iter().map(|(_, documents,)| documents.len(),).sum();
+        if self.config.mongo.atomic_file {
+            // A transient failure aborts the transaction, so the whole file is retried
+            self.with_retry(|| async {
+                self.insert_in_transaction(&groups, &options,).await.map_err(wrap_error,)
+            },)
+            .await?;
+            self.config.batch_committed(&collection_name, total,);
+        } else {
+            for (collection, documents,) in &groups {
+                for batch in documents.chunks(self.config.batch.size(),) {
+                    self.insert_batch(collection, batch, &options,).await?;
+                }
+            }
+        }
+
+        let collections: Vec<&str,> = groups.iter().map(|(name, _,)| name.as_str(),).collect();
+        info!(
+            "Successfully ingested {} records to MongoDB into collection(s) '{}' in database '{}'.",
+            total,
+            collections.join("', '"),
+            database_name
+        );
+        Ok((),)
+    }
+
+    async fn ingest_raw(&self, raw: RawFile,) -> Result<(),> {
+        let collection_name = raw_target_name(&self.config.collection_name(),);
+
+        let document = mongodb::bson::to_document(&raw,).map_err(|e| {
+            IngestorError::IngestionError(format!("Failed to serialize raw file to BSON: {}", e),)
+        },)?;
+
+        self.with_retry(|| async {
+            self.collection(&collection_name,)
+                .insert_one(document.clone(), None,)
+                .await
+                .map(|_| (),)
+                .map_err(|e| {
+                    wrap_error(write_error("Failed to insert raw file into MongoDB", e,),)
+                },)
+        },)
+        .await
+    }
+
+    async fn target_count(&self,) -> Result<Option<u64,>,> {
+        let collection_name = self.config.collection_name();
+
+        let count = self
+            .with_retry(|| async {
+                self.collection(&collection_name,)
+                    .count_documents(None, None,)
+                    .await
+                    .map_err(|e| {
+                        wrap_error(IngestorError::DatabaseError(format!(
+                            "Failed to count MongoDB documents: {}",
+                            e
+                        ),),)
+                    },)
+            },)
+            .await?;
+        Ok(Some(count,),)
+    }
+
+    async fn clean(&self,) -> Result<String,> {
+        let collection_name = self.config.collection_name();
+        let count = self.target_count().await?.unwrap_or_default();
+
+        self.collection(&collection_name,).drop(None,).await.map_err(|e| {
+            IngestorError::DatabaseError(format!("Failed to drop MongoDB collection: {}", e),)
+        },)?;
+        Ok(format!("dropped MongoDB collection `{}` ({} documents)", collection_name, count),)
+    }
+
+    fn defers_writes(&self,) -> bool {
+        self.buffers_across_files()
+    }
+
+    async fn flush(&self,) -> Result<Vec<IngestStats,>,> {
+        let mut pending = self.pending.lock().await;
+        let batch_size = self.config.batch.size();
+        let options = self.insert_options();
+        let mut stats = Vec::new();
+        // Documents leave the buffer only once written, so a failed flush can be retried
+        for (collection, documents,) in pending.iter_mut() {
+            let records = documents.len();
+            while !documents.is_empty() {
+                let len = documents.len().min(batch_size,);
+                self.insert_batch(collection, &documents[..len], &options,).await?;
+                documents.drain(..len,);
+            }
+            if records > 0 {
+                stats.push(IngestStats { target: collection.clone(), records, },);
+            }
+        }
+        pending.clear();
+        Ok(stats,)
+    }
+}
+
+impl MongoIngestor {
+    fn client(&self,) -> Client {
+        self.client.read().unwrap().clone()
+    }
+
+    /// `name` in the `scm_db` database of the current client.
+    fn collection(&self, name: &str,) -> Collection<Document,> {
+        self.client().database("scm_db",).collection(name,)
+    }
+
+    /// Collection for `record`: the sanitized value of `mongo.collection_field` when the record
+    /// has one, otherwise `default_collection`.
+    fn resolve_collection(&self, record: &serde_json::Value, default_collection: &str,) -> String {
+        self.config
+            .mongo
+            .collection_field
+            .as_ref()
+            .and_then(|field| record.get(field,),)
+            .and_then(|value| match value {
+                serde_json::Value::String(s,) => Some(s.clone(),),
+                serde_json::Value::Number(n,) => Some(n.to_string(),),
+                serde_json::Value::Bool(b,) => Some(b.to_string(),),
+                _ => None,
+            },)
+            .and_then(|value| sanitize_collection_name(&value,),)
+            .unwrap_or_else(|| default_collection.to_string(),)
+    }
+
+    /// Replaces the client with a freshly connected one.
+    async fn reconnect(&self,) -> Result<(),> {
+        let client = connect(&self.config,).await?;
+        *self.client.write().unwrap() = client;
+        Ok((),)
+    }
+
+    /// Retries `operation` with backoff, reconnecting after `reconnect_after` consecutive
+    /// transient failures.
+    async fn with_retry<F, Fut, T,>(&self, operation: F,) -> Result<T,>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = std::result::Result<T, backoff::Error<IngestorError,>,>,>,
+    {
+        execute_with_reconnect(self.config.reconnect_after, || self.reconnect(), operation,).await
+    }
+
+    /// Per-file transactions need each file written on its own, so they disable cross-file
+    /// batching.
+    fn buffers_across_files(&self,) -> bool {
+        self.config.batch.across_files && !self.config.mongo.atomic_file
+    }
+
+    fn insert_options(&self,) -> InsertManyOptions {
+        InsertManyOptions::builder()
+            .ordered(self.config.batch.is_ordered(),)
+            .build()
+    }
+
+    /// Adds `documents` to the cross-file buffer and writes every batch it fills. The lock is
+    /// held while writing, so batches stay whole and in arrival order, and a batch leaves the
+    /// buffer only once it is written.
+    async fn buffer(&self, collection_name: &str, documents: Vec<Document,>,) -> Result<(),> {
+        let mut pending = self.pending.lock().await;
+        let pending = pending.entry(collection_name.to_string(),).or_default();
+        pending.extend(documents,);
+        let batch_size = self.config.batch.size();
+        let options = self.insert_options();
+        while pending.len() >= batch_size {
+            self.insert_batch(collection_name, &pending[..batch_size], &options,).await?;
+            pending.drain(..batch_size,);
+        }
+        Ok((),)
+    }
+
+    /// Inserts one batch, retrying transient failures. Documents get their `_id` before the
+    /// first attempt, so a retry after a timeout, which the server may have applied in part or
+    /// in full, inserts only the documents that aren't there yet instead of writing them twice.
+    async fn insert_batch(
+        &self,
+        collection_name: &str,
+        batch: &[Document],
+        options: &InsertManyOptions,
+    ) -> Result<(),> {
+        let batch: Vec<Document,> = batch.iter().cloned().map(with_object_id,).collect();
+        let attempts = AtomicUsize::new(0,);
+        self.with_retry(|| async {
+            let pending = if attempts.fetch_add(1, Ordering::Relaxed,) == 0 {
+                batch.clone()
+            } else {
+                self.unwritten(collection_name, &batch,).await.map_err(wrap_error,)?
+            };
+            if pending.is_empty() {
+                return Ok((),);
+            }
+            self.config
+                .batch
+                .run(async {
+                    self.collection(collection_name,)
+                        .insert_many(pending, options.clone(),)
+                        .await
+                        .map(|_| (),)
+                        .map_err(|e| write_error("Failed to insert records into MongoDB", e,),)
+                },)
+                .await
+                .map_err(wrap_error,)
+        },)
+        .await?;
+        self.config.batch_committed(collection_name, batch.len(),);
+        Ok((),)
+    }
+
+    /// The documents of `batch` whose `_id` isn't in the collection yet.
+    async fn unwritten(
+        &self,
+        collection_name: &str,
+        batch: &[Document],
+    ) -> Result<Vec<Document,>,> {
+        let ids: Vec<Bson,> = batch.iter().filter_map(|d| d.get("_id",).cloned(),).collect();
+        let written = self
+            .collection(collection_name,)
+            .distinct("_id", doc! { "_id": { "$in": ids } }, None,)
+            .await
+            .map_err(|e| {
+                IngestorError::DatabaseError(format!(
+                    "Failed to check which MongoDB documents were inserted: {}",
+                    e
+                ),)
+            },)?;
+        Ok(batch
+            .iter()
+            .filter(|d| d.get("_id",).is_none_or(|id| !written.contains(id,),),)
+            .cloned()
+            .collect(),)
+    }
+
+    /// Inserts each collection's documents batch by batch inside one transaction, aborting it if
+    /// any batch fails so none of the file's documents are left behind.
+    async fn insert_in_transaction(
+        &self,
+        groups: &[(String, Vec<Document,>,)],
+        options: &InsertManyOptions,
+    ) -> Result<(),> {
+        let transaction_error =
+            |e: mongodb::error::Error| write_error("MongoDB transaction failed", e,);
+        let client = self.client();
+        let mut session = client.start_session(None,).await.map_err(transaction_error,)?;
+        session.start_transaction(None,).await.map_err(transaction_error,)?;
+
+        for (collection_name, documents,) in groups {
+            let collection = client.database("scm_db",).collection::<Document>(collection_name,);
+            for batch in documents.chunks(self.config.batch.size(),) {
+                let inserted = self
+                    .config
+                    .batch
+                    .run(async {
+                        collection
+                            .insert_many_with_session(
+                                batch.to_vec(),
+                                options.clone(),
+                                &mut session,
+                            )
+                            .await
+                            .map(|_| (),)
+                            .map_err(|e| {
+                                write_error("Failed to insert records into MongoDB", e,)
+                            },)
+                    },)
+                    .await;
+                if let Err(e,) = inserted {
+                    let _ = session.abort_transaction().await;
+                    return Err(e,);
+                }
+            }
+        }
+
+        session.commit_transaction().await.map_err(transaction_error,)
+    }
+}
+
+/// Turns a field value into a collection name: anything other than ASCII letters, digits, `_`,
+/// `-` and `.` becomes `_`, and names MongoDB reserves (`system.*`) get a `_` prefix. `None`
+/// when nothing usable is left.
+pub fn sanitize_collection_name(value: &str,) -> Option<String,> {
+    let mut name: String = value
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.') { c } else { '_' },)
+        .collect();
+    if name.trim_matches(|c| c == '_' || c == '.',).is_empty() {
+        return None;
+    }
+    if name.starts_with("system.",) || name.starts_with('.',) {
+        name.insert(0, '_',);
+    }
+    Some(name,)
+}
+
+/// Adds the configured CA and client certificate files to the TLS settings, enabling TLS if the
+/// URI didn't. Other TLS options from the URI are kept.
+fn apply_tls_files(client_options: &mut ClientOptions, options: &MongoOptions,) -> Result<(),> {
+    let files = [&options.tls_ca_file, &options.tls_cert_key_file,];
+    if files.iter().all(|file| file.is_none(),) {
+        return Ok((),);
+    }
+    // The driver only reads these at the first handshake, which would surface as a vague
+    // server selection timeout
+    if let Some(missing,) = files.into_iter().flatten().find(|file| !file.is_file(),) {
+        return Err(IngestorError::ConfigurationError(format!(
+            "MongoDB TLS file not found: {}",
+            missing.display()
+        ),),);
+    }
+
+    let mut tls = match client_options.tls.take() {
+        Some(Tls::Enabled(tls,),) => tls,
+        _ => TlsOptions::default(),
+    };
+    if let Some(ca_file,) = &options.tls_ca_file {
+        tls.ca_file_path = Some(ca_file.clone(),);
+    }
+    if let Some(cert_key_file,) = &options.tls_cert_key_file {
+        tls.cert_key_file_path = Some(cert_key_file.clone(),);
+    }
+    client_options.tls = Some(Tls::Enabled(tls,),);
+    Ok((),)
+}
+
+/// Transactions only work against replica sets and sharded clusters; a standalone server
+/// would only reject them at the first commit, so check up front.
+async fn ensure_transactions_supported(client: &Client,) -> Result<(),> {
+    let hello = client
+        .database("admin",)
+        .run_command(doc! {"hello": 1}, None,)
+        .await
+        .map_err(|e| {
+            IngestorError::ConnectionError(format!("Failed to query MongoDB topology: {}", e),)
+                .with_source(e,)
+        },)?;
+
+    let replica_set = hello.contains_key("setName",);
+    let sharded = hello.get_str("msg",).is_ok_and(|msg| msg == "isdbgrid",);
+    if replica_set || sharded {
+        Ok((),)
+    } else {
+        Err(IngestorError::ConfigurationError(
+            "--atomic-file needs MongoDB transactions, which require a replica set or sharded \
+             cluster; this server is standalone"
+                .to_string(),
+        ),)
+    }
+}
+
+/// `document` with a fresh `ObjectId` as `_id` unless it already has one.
+fn with_object_id(mut document: Document,) -> Document {
+    if !document.contains_key("_id",) {
+        document.insert("_id", mongodb::bson::oid::ObjectId::new(),);
+    }
+    document
+}
+
+/// Maps a failed write: IO and server selection failures, and errors the server labels as
+/// retryable, are `ConnectionError`s, which are retried and count towards `reconnect_after`;
+/// anything else (e.g. a duplicate key) is an `IngestionError`.
+pub fn write_error(context: &str, e: mongodb::error::Error,) -> IngestorError {
+    let connection = matches!(*e.kind, ErrorKind::Io(_,) | ErrorKind::ServerSelection { .. })
+        || e.contains_label(RETRYABLE_WRITE_ERROR,)
+        || e.contains_label(TRANSIENT_TRANSACTION_ERROR,);
+    if connection {
+        IngestorError::ConnectionError(format!("{}: {}", context, e),)
+    } else {
+        IngestorError::IngestionError(format!("{}: {}", context, e),)
+    }
 }
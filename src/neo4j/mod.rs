@@ -2,49 +2,94 @@
 // Neo4j specific ingestion logic.
 
 use std::collections::HashMap;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use async_trait::async_trait;
 use nc_reader::nc_reader_result::DataReaderResult;
 use neo4rs::{BoltType, Graph, query};
-use tracing::info;
+use tracing::{info, warn};
 
-use crate::error::{IngestorError, Result};
-use crate::ingestor::{Ingestor, IngestorConfig};
-use crate::retry::{execute_with_retry, wrap_error};
+use crate::error::{IngestorError, Result, redact_message, redact_uri};
+use crate::ingestor::{
+    Ingestor, IngestorConfig, RawFile, RelationshipConfig, RelationshipDirection, blob_value,
+    content_hash, raw_target_name,
+};
+use crate::retry::{execute_with_connection_retry, execute_with_reconnect, wrap_error};
+use crate::transform::round_floats;
 
 pub struct Neo4jIngestor {
-    config: IngestorConfig,
-    graph:  Graph,
+    config:           IngestorConfig,
+    /// Replaced by [`Neo4jIngestor::reconnect`]; clone it with [`Neo4jIngestor::graph`]
+    graph:            RwLock<Graph,>,
+    synthetic_warned: AtomicBool,
 }
 
-#[async_trait]
-impl Ingestor for Neo4jIngestor {
-    async fn new(config: IngestorConfig,) -> Result<Self,> {
-        let uri = &config.database_url;
+/// Default Bolt port when the URI doesn't name one.
+const DEFAULT_BOLT_PORT: u16 = 7687;
+
+/// `host:port` that `Graph::new` connects to. IPv6 hosts keep their brackets (`[::1]:7687`) so
+/// the port stays separable from the address.
+pub fn bolt_address(uri: &url::Url,) -> String {
+    let port = uri.port().unwrap_or(DEFAULT_BOLT_PORT,);
+    match uri.host() {
+        Some(url::Host::Ipv6(addr,),) => format!("[{}]:{}", addr, port),
+        Some(host,) => format!("{}:{}", host, port),
+        None => format!("localhost:{}", port),
+    }
+}
 
-        let parsed_uri = url::Url::parse(uri,)
-            .map_err(|e| IngestorError::ConfigurationError(format!("Invalid Neo4j URI: {}", e),),)?;
+/// `MERGE` clause(s) linking the record's node `a` to the target node `b` in `rel`'s direction.
+pub fn relationship_merge(rel: &RelationshipConfig,) -> String {
+    let kind = &rel.relationship_type;
+    match rel.direction {
+        RelationshipDirection::Outgoing => format!("MERGE (a)-[:{}]->(b)", kind),
+        RelationshipDirection::Incoming => format!("MERGE (a)<-[:{}]-(b)", kind),
+        RelationshipDirection::Undirected => {
+            format!("MERGE (a)-[:{0}]->(b) MERGE (a)<-[:{0}]-(b)", kind)
+        },
+    }
+}
 
-        let host = parsed_uri.host_str().unwrap_or("localhost",).to_string();
-        let port = parsed_uri.port().unwrap_or(7687,);
+/// Connects to the Bolt server named by `config.database_url`, retrying up to
+/// `connection_retries` times.
+async fn connect(config: &IngestorConfig,) -> Result<Graph,> {
+    let parsed_uri = url::Url::parse(&config.database_url,)
+        .map_err(|e| {
+            IngestorError::ConfigurationError(format!(
+                "Invalid Neo4j URI {}: {}",
+                redact_uri(&config.database_url,),
+                e
+            ),)
+        },)?;
 
-        let username = parsed_uri.username();
-        let password = parsed_uri.password().unwrap_or_default();
+    let username = parsed_uri.username();
+    let password = parsed_uri.password().unwrap_or_default();
 
-        let host_port = format!("{}:{}", host, port);
-        let graph = execute_with_retry(|| async {
-            Graph::new(&host_port, username, password,)
-                .await
-                .map_err(|e| {
-                    wrap_error(IngestorError::ConnectionError(format!(
-                        "Failed to connect to Neo4j: {:?}",
-                        e
-                    ),),)
-                },)
-        },)
-        .await?;
+    let host_port = bolt_address(&parsed_uri,);
+    execute_with_connection_retry(config.connection_retries(), || async {
+        Graph::new(&host_port, username, password,)
+            .await
+            .map_err(|e| {
+                wrap_error(IngestorError::ConnectionError(format!(
+                    "Failed to connect to Neo4j: {}",
+                    redact_message(&format!("{:?}", e), &config.database_url,)
+                ),),)
+            },)
+    },)
+    .await
+}
 
-        Ok(Neo4jIngestor { config, graph, },)
+#[async_trait]
+impl Ingestor for Neo4jIngestor {
+    async fn new(config: IngestorConfig,) -> Result<Self,> {
+        let graph = connect(&config,).await?;
+
+        Ok(Neo4jIngestor {
+            config,
+            graph: RwLock::new(graph,),
+            synthetic_warned: AtomicBool::new(false,),
+        },)
     }
 
     async fn ingest(&self, data: DataReaderResult,) -> Result<(),> {
@@ -55,24 +100,32 @@ impl Ingestor for Neo4jIngestor {
             .unwrap_or("IngestedData",)
             .to_string();
 
+        // id -> content hash of the record that claimed it, to catch distinct records sharing ids
+        let mut seen_ids = HashMap::new();
+        let mut batch = Vec::with_capacity(self.config.batch.size(),);
         match data {
             DataReaderResult::Csv(csv_data, _,) => {
                 for row in csv_data.nc_rows {
-                    self.ingest_record(row, &label_name,).await?;
+                    batch.push(row,);
+                    if batch.len() == self.config.batch.size() {
+                        self.merge_batch(&mut batch, &label_name, &mut seen_ids,).await?;
+                    }
                 }
             },
             DataReaderResult::Stream(stream, _,) => {
                 for record_res in stream {
                     let record =
                         record_res.map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
-                    self.ingest_record(record, &label_name,).await?;
+                    batch.push(record,);
+                    if batch.len() == self.config.batch.size() {
+                        self.merge_batch(&mut batch, &label_name, &mut seen_ids,).await?;
+                    }
                 }
             },
-            _ => {
-                let json_val = serde_json::to_value(&data,)
-                    .map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
-                self.ingest_record(json_val, &label_name,).await?;
-            },
+            _ => batch.push(blob_value(&data, &self.config,)?,),
+        }
+        if !batch.is_empty() {
+            self.merge_batch(&mut batch, &label_name, &mut seen_ids,).await?;
         }
 
         info!(
@@ -81,108 +134,362 @@ impl Ingestor for Neo4jIngestor {
         );
         Ok((),)
     }
-}
-
-impl Neo4jIngestor {
-    async fn ingest_record(&self, record: serde_json::Value, label: &str,) -> Result<(),> {
-        let record_obj = record.as_object().ok_or_else(|| {
-            IngestorError::IngestionError("Record must be an object".to_string(),)
-        },)?;
-
-        // Find a unique ID for MERGE
-        let id_field = if record_obj.contains_key("id",) {
-            "id"
-        } else if record_obj.contains_key("ID",) {
-            "ID"
-        } else if record_obj.contains_key("uuid",) {
-            "uuid"
-        } else {
-            ""
-        };
-
-        let id_value = if !id_field.is_empty() {
-            record_obj
-                .get(id_field,)
-                .unwrap()
-                .to_string()
-                .replace("\"", "",)
-        } else {
-            // Use hash of the record as ID if no ID field found
-            use std::collections::hash_map::DefaultHasher;
-            use std::hash::{Hash, Hasher};
-            let mut hasher = DefaultHasher::new();
-            record.to_string().hash(&mut hasher,);
-            hasher.finish().to_string()
-        };
 
-        let json_data = serde_json::to_string(&record,)
-            .map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
-        let bolt_props = json_to_bolt(&record,);
-
-        // MERGE node
-        let merge_query = format!(
-            "MERGE (n:{} {{_id: $id}}) SET n += $props, n.data = $data",
-            label
+    async fn ingest_raw(&self, raw: RawFile,) -> Result<(),> {
+        let label_name = raw_target_name(
+            self.config
+                .collection_name
+                .as_deref()
+                .unwrap_or("IngestedData",),
         );
+        let merge_query = format!("MERGE (n:{} {{path: $path}}) SET n += $props", label_name);
+        let bolt_props = json_to_bolt(&raw.to_value(),);
 
-        execute_with_retry(|| async {
-            self.graph
+        self.with_retry(|| async {
+            self.graph()
                 .run(
                     query(&merge_query,)
-                        .param("id", id_value.clone(),)
-                        .param("props", bolt_props.clone(),)
-                        .param("data", json_data.clone(),),
+                        .param("path", raw.path.clone(),)
+                        .param("props", bolt_props.clone(),),
                 )
                 .await
                 .map(|_| (),)
                 .map_err(|e| {
-                    wrap_error(IngestorError::IngestionError(format!(
-                        "Failed to merge node in Neo4j: {:?}",
-                        e
-                    ),),)
+                    wrap_error(write_error("Failed to merge raw file node in Neo4j", e,),)
                 },)
         },)
-        .await?;
+        .await
+    }
 
-        // Handle relationships
-        if let Some(relationships,) = &self.config.relationships {
-            for rel in relationships {
-                if let Some(source_val,) = record_obj.get(&rel.source_field,) {
-                    if source_val.is_null() {
-                        continue;
-                    }
+    async fn target_count(&self,) -> Result<Option<u64,>,> {
+        let label_name = self
+            .config
+            .collection_name
+            .as_deref()
+            .unwrap_or("IngestedData",);
+        let count_query = format!("MATCH (n:{}) RETURN count(n) AS count", label_name);
 
-                    let target_id = source_val.to_string().replace("\"", "",);
-                    let rel_query = format!(
-                        "MATCH (a:{} {{_id: $source_id}}) 
-                         MERGE (b:{} {{_id: $target_id}}) 
-                         MERGE (a)-[:{}]->(b)",
-                        label, rel.target_label, rel.relationship_type
-                    );
-
-                    execute_with_retry(|| async {
-                        self.graph
-                            .run(
-                                query(&rel_query,)
-                                    .param("source_id", id_value.clone(),)
-                                    .param("target_id", target_id.clone(),),
-                            )
-                            .await
-                            .map(|_| (),)
-                            .map_err(|e| {
-                                wrap_error(IngestorError::IngestionError(format!(
-                                    "Failed to create relationship in Neo4j: {:?}",
-                                    e
-                                ),),)
-                            },)
-                    },)
-                    .await?;
-                }
+        let count_err = |e: neo4rs::Error| {
+            IngestorError::DatabaseError(format!("Failed to count nodes: {:?}", e),)
+        };
+        let mut result = self
+            .graph()
+            .execute(query(&count_query,),)
+            .await
+            .map_err(count_err,)?;
+        let row = result.next().await.map_err(count_err,)?;
+
+        match row {
+            Some(row,) => {
+                let count = row.get::<i64>("count",).map_err(|e| {
+                    IngestorError::DatabaseError(format!("Failed to read node count: {:?}", e),)
+                },)?;
+                Ok(Some(count as u64,),)
+            },
+            None => Ok(Some(0,),),
+        }
+    }
+
+    async fn clean(&self,) -> Result<String,> {
+        let label_name = self
+            .config
+            .collection_name
+            .as_deref()
+            .unwrap_or("IngestedData",);
+        let delete_query =
+            format!("MATCH (n:{}) DETACH DELETE n RETURN count(n) AS count", label_name);
+
+        let delete_err = |e: neo4rs::Error| {
+            IngestorError::DatabaseError(format!("Failed to delete nodes: {:?}", e),)
+        };
+        let mut result = self
+            .graph()
+            .execute(query(&delete_query,),)
+            .await
+            .map_err(delete_err,)?;
+        let count = match result.next().await.map_err(delete_err,)? {
+            Some(row,) => row.get::<i64>("count",).unwrap_or_default(),
+            None => 0,
+        };
+        Ok(format!("detach-deleted {} `{}` nodes", count, label_name),)
+    }
+}
+
+impl Neo4jIngestor {
+    fn graph(&self,) -> Graph {
+        self.graph.read().unwrap().clone()
+    }
+
+    /// Replaces the client with a freshly connected one.
+    async fn reconnect(&self,) -> Result<(),> {
+        let graph = connect(&self.config,).await?;
+        *self.graph.write().unwrap() = graph;
+        Ok((),)
+    }
+
+    /// Retries `operation` with backoff, reconnecting after `reconnect_after` consecutive
+    /// transient failures.
+    async fn with_retry<F, Fut, T,>(&self, operation: F,) -> Result<T,>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = std::result::Result<T, backoff::Error<IngestorError,>,>,>,
+    {
+        execute_with_reconnect(self.config.reconnect_after, || self.reconnect(), operation,).await
+    }
+
+    /// Merges `records` in one transaction under the batch timeout: one `UNWIND` query per node
+    /// label, in the order the labels first appear, then one per label and relationship. Every
+    /// statement is a MERGE, so a batch retried after a timeout or dropped connection doesn't
+    /// duplicate nodes. Drains `records` and reports them committed.
+    async fn merge_batch(
+        &self,
+        records: &mut Vec<serde_json::Value,>,
+        default_label: &str,
+        seen_ids: &mut HashMap<String, String,>,
+    ) -> Result<(),> {
+        let mut nodes: Vec<(String, Vec<BoltType,>,),> = Vec::new();
+        let mut edges: Vec<((String, usize,), Vec<BoltType,>,),> = Vec::new();
+        let merged = records.len();
+        let decimals = self.config.float_precision();
+        for mut record in records.drain(..,) {
+            if let Some(decimals,) = decimals {
+                round_floats(&mut record, decimals,);
+            }
+            let (label, row, links,) = self.node_row(record, default_label, seen_ids,)?;
+            for (rel, link,) in links {
+                group(&mut edges, (label.clone(), rel,),).push(link,);
             }
+            group(&mut nodes, label,).push(row,);
         }
 
+        let key_property = match &self.config.id_field {
+            Some(field,) => cypher_property(field,),
+            None => "_id".to_string(),
+        };
+        let mut queries = Vec::new();
+        for (label, rows,) in nodes {
+            let merge_query = format!(
+                "UNWIND $rows AS row MERGE (n:{} {{{}: row.key}}) \
+                 SET n += row.props, n._id = row.id, n.data = row.data",
+                label, key_property
+            );
+            queries.push(query(&merge_query,).param("rows", bolt_list(rows,),),);
+        }
+        let relationships = self.config.relationships.as_deref().unwrap_or_default();
+        for ((label, rel,), rows,) in edges {
+            let rel = &relationships[rel];
+            let rel_query = format!(
+                "UNWIND $rows AS row MATCH (a:{} {{_id: row.source}}) \
+                 MERGE (b:{} {{_id: row.target}}) {}",
+                label,
+                rel.target_label,
+                relationship_merge(rel,)
+            );
+            queries.push(query(&rel_query,).param("rows", bolt_list(rows,),),);
+        }
+
+        let merge_error = |e: neo4rs::Error| write_error("Failed to merge batch in Neo4j", e,);
+        self.with_retry(|| async {
+            self.config
+                .batch
+                .run(async {
+                    let mut txn = self.graph().start_txn().await.map_err(merge_error,)?;
+                    txn.run_queries(queries.clone(),).await.map_err(merge_error,)?;
+                    txn.commit().await.map_err(merge_error,)
+                },)
+                .await
+                .map_err(wrap_error,)
+        },)
+        .await?;
+        self.config.batch_committed(default_label, merged,);
         Ok((),)
     }
+
+    /// A record as its node label, its `UNWIND` row (`key`, `id`, `props`, `data`) and a
+    /// `source`/`target` row for each relationship it has a target for, keyed by the
+    /// relationship's index in `relationships`.
+    ///
+    /// With `id_field` the node merges on that property, typed as in the record, so
+    /// re-ingesting an overlapping file updates the existing entity; `_id` is still set for
+    /// relationship matching.
+    fn node_row(
+        &self,
+        record: serde_json::Value,
+        default_label: &str,
+        seen_ids: &mut HashMap<String, String,>,
+    ) -> Result<(String, BoltType, Vec<(usize, BoltType,),>,),> {
+        let record_obj = record.as_object().ok_or_else(|| {
+            IngestorError::IngestionError("Record must be an object".to_string(),)
+        },)?;
+
+        let content_digest = content_hash(&record,);
+        let id_value = self.resolve_id(record_obj, &content_digest,)?;
+        let label = self.resolve_label(record_obj, default_label,);
+
+        match seen_ids.get(&id_value,) {
+            Some(previous,) if previous != &content_digest => {
+                warn!(
+                    "ID collision on '{}': distinct records share this id and will be merged into \
+                     one node",
+                    id_value
+                );
+            },
+            Some(_,) => {},
+            None => {
+                seen_ids.insert(id_value.clone(), content_digest,);
+            },
+        }
+
+        let json_data = serde_json::to_string(&record,)
+            .map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
+        let key = match &self.config.id_field {
+            Some(field,) => json_to_bolt(&record_obj[field],),
+            None => BoltType::from(id_value.clone(),),
+        };
+
+        let mut links = Vec::new();
+        for (i, rel,) in self.config.relationships.iter().flatten().enumerate() {
+            if let Some(source_val,) = record_obj.get(&rel.source_field,)
+                && !source_val.is_null()
+            {
+                let target_id = source_val.to_string().replace("\"", "",);
+                links.push((
+                    i,
+                    bolt_map([
+                        ("source", BoltType::from(id_value.clone(),),),
+                        ("target", BoltType::from(target_id,),),
+                    ],),
+                ),);
+            }
+        }
+
+        let row = bolt_map([
+            ("key", key,),
+            ("id", BoltType::from(id_value,),),
+            ("props", json_to_bolt(&record,),),
+            ("data", BoltType::from(json_data,),),
+        ],);
+        Ok((label, row, links,),)
+    }
+
+    /// Picks the node label: the sanitized value of `label_field` when the record has one,
+    /// otherwise `default_label`.
+    fn resolve_label(
+        &self,
+        record: &serde_json::Map<String, serde_json::Value,>,
+        default_label: &str,
+    ) -> String {
+        self.config
+            .label_field
+            .as_ref()
+            .and_then(|field| record.get(field,),)
+            .and_then(scalar_id,)
+            .and_then(|value| sanitize_label(&value,),)
+            .unwrap_or_else(|| default_label.to_string(),)
+    }
+
+    /// Picks the node identity for MERGE: the configured `id_field`, else the first of
+    /// `id`/`ID`/`uuid`, else a stable content hash of the record.
+    fn resolve_id(
+        &self,
+        record: &serde_json::Map<String, serde_json::Value,>,
+        content_digest: &str,
+    ) -> Result<String,> {
+        if let Some(id_field,) = &self.config.id_field {
+            return record
+                .get(id_field,)
+                .and_then(scalar_id,)
+                .ok_or_else(|| {
+                    IngestorError::IngestionError(format!(
+                        "Record has no usable value for id field '{}'",
+                        id_field
+                    ),)
+                },);
+        }
+
+        if let Some(id,) = ["id", "ID", "uuid",]
+            .iter()
+            .find_map(|field| record.get(*field,).and_then(scalar_id,),)
+        {
+            return Ok(id,);
+        }
+
+        if !self.synthetic_warned.swap(true, Ordering::Relaxed,) {
+            warn!(
+                "No id field found; using content hashes as node ids. Set --id-field to merge on a \
+                 stable key"
+            );
+        }
+        Ok(content_digest.to_string(),)
+    }
+}
+
+/// The rows collected under `key`, added after the existing groups if it's new.
+fn group<K: PartialEq,>(groups: &mut Vec<(K, Vec<BoltType,>,),>, key: K,) -> &mut Vec<BoltType,> {
+    let index = match groups.iter().position(|(k, _,)| *k == key,) {
+        Some(index,) => index,
+        None => {
+            groups.push((key, Vec::new(),),);
+            groups.len() - 1
+        },
+    };
+    &mut groups[index].1
+}
+
+fn bolt_map<const N: usize,>(entries: [(&str, BoltType,); N],) -> BoltType {
+    let value = entries
+        .into_iter()
+        .map(|(k, v,)| (neo4rs::BoltString { value: k.to_string(), }, v,),)
+        .collect();
+    BoltType::Map(neo4rs::BoltMap { value, },)
+}
+
+fn bolt_list(rows: Vec<BoltType,>,) -> BoltType {
+    BoltType::List(neo4rs::BoltList { value: rows, },)
+}
+
+/// Ids must be non-empty scalars; nulls, objects and arrays can't identify a node.
+fn scalar_id(value: &serde_json::Value,) -> Option<String,> {
+    match value {
+        serde_json::Value::String(s,) if !s.is_empty() => Some(s.clone(),),
+        serde_json::Value::Number(n,) => Some(n.to_string(),),
+        serde_json::Value::Bool(b,) => Some(b.to_string(),),
+        _ => None,
+    }
+}
+
+/// A record field as a Cypher property name, backtick-quoted so any field name is safe to
+/// interpolate.
+fn cypher_property(field: &str,) -> String {
+    format!("`{}`", field.replace('`', "``",))
+}
+
+/// Maps a failed write: connection and IO failures are `ConnectionError`s, which are retried and
+/// count towards `reconnect_after`; anything else is an `IngestionError`.
+pub fn write_error(context: &str, e: neo4rs::Error,) -> IngestorError {
+    match e {
+        neo4rs::Error::IOError { .. } | neo4rs::Error::ConnectionError => {
+            IngestorError::ConnectionError(format!("{}: {:?}", context, e),)
+        },
+        _ => IngestorError::IngestionError(format!("{}: {:?}", context, e),),
+    }
+}
+
+/// Turns a field value into a label that can be interpolated into Cypher: anything other than
+/// ASCII letters, digits and `_` becomes `_`, and a leading digit gets a `_` prefix.
+pub fn sanitize_label(value: &str,) -> Option<String,> {
+    let mut label: String = value
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' },)
+        .collect();
+    if label.trim_matches('_',).is_empty() {
+        return None;
+    }
+    if label.starts_with(|c: char| c.is_ascii_digit(),) {
+        label.insert(0, '_',);
+    }
+    Some(label,)
 }
 
 fn json_to_bolt(val: &serde_json::Value,) -> BoltType {
@@ -0,0 +1,164 @@
+// nc_ingestor/src/nested.rs
+// `--normalize-nested`: nested columns of typed SQL tables moved to key-value child tables.
+
+use std::collections::HashMap;
+
+use nc_schema::DataType;
+use serde_json::{Map, Value};
+
+use crate::json_input::infer_schema;
+
+/// Column added to parent tables as their primary key, a random UUID per row.
+pub const ROW_ID_COLUMN: &str = "_row_id";
+/// Column of a child table referencing its parent row's [`ROW_ID_COLUMN`].
+pub const PARENT_ID_COLUMN: &str = "_parent_id";
+
+/// How typed SQL tables store array and object columns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize,)]
+#[serde(rename_all = "kebab-case")]
+pub enum NestedMode {
+    /// In the column itself, as JSONB (Postgres) or JSON text (SQLite)
+    #[default]
+    Json,
+    /// In a child table `<table>_<column>` per nested column, one row per element or key
+    TablePerField,
+}
+
+impl NestedMode {
+    /// Columns of the parent table for data of `schema`: in table-per-field mode the nested
+    /// fields are gone and [`ROW_ID_COLUMN`] is added.
+    pub fn table_schema(self, schema: &HashMap<String, DataType,>,) -> HashMap<String, DataType,> {
+        let mut table = schema.clone();
+        if self == NestedMode::TablePerField {
+            for field in nested_fields(schema,) {
+                table.remove(&field,);
+            }
+            table.insert(ROW_ID_COLUMN.to_string(), DataType::String,);
+        }
+        table
+    }
+}
+
+/// Rows split off one nested column, for the child table `<parent>_<column>`. Array elements
+/// become `(_parent_id, position, value)` rows and object entries `(_parent_id, key, value)`.
+#[derive(Debug, Clone, PartialEq,)]
+pub struct ChildTable {
+    /// The parent's column name, after mappings
+    pub column: String,
+    pub schema: HashMap<String, DataType,>,
+    pub rows:   Vec<Value,>,
+}
+
+pub fn child_table_name(parent: &str, column: &str,) -> String {
+    format!("{}_{}", parent, column)
+}
+
+/// Fields of `schema` holding arrays or objects (possibly nullable), sorted.
+pub fn nested_fields(schema: &HashMap<String, DataType,>,) -> Vec<String,> {
+    let mut fields: Vec<String,> = schema
+        .iter()
+        .filter(|(_, data_type,)| nested_kind(data_type,).is_some(),)
+        .map(|(field, _,)| field.clone(),)
+        .collect();
+    fields.sort();
+    fields
+}
+
+/// Moves the nested fields of `rows` into child tables. Every row gets a new
+/// [`ROW_ID_COLUMN`] the child rows point back to, and `schema` loses the nested fields and
+/// gains that column. A scalar found in a nested field becomes a single child row (position 0,
+/// or a null key); nulls and missing values add none.
+pub fn split_nested(
+    rows: &mut [Value],
+    schema: &mut HashMap<String, DataType,>,
+    mappings: Option<&HashMap<String, String,>,>,
+) -> Vec<ChildTable,> {
+    let fields: Vec<(String, NestedKind,),> = nested_fields(schema,)
+        .into_iter()
+        .filter_map(|field| nested_kind(&schema[&field],).map(|kind| (field, kind,),),)
+        .collect();
+    let mut children: Vec<Vec<Value,>,> = vec![Vec::new(); fields.len()];
+
+    for row in rows.iter_mut() {
+        let Value::Object(obj,) = row else { continue };
+        let row_id = Value::String(uuid::Uuid::new_v4().to_string(),);
+        for ((field, kind,), child_rows,) in fields.iter().zip(&mut children,) {
+            let entries: Vec<(Value, Value,),> = match (obj.remove(field,), kind,) {
+                (None | Some(Value::Null,), _,) => continue,
+                (Some(Value::Array(items,),), _,) => items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(position, item,)| (Value::from(position,), item,),)
+                    .collect(),
+                (Some(Value::Object(entries,),), _,) => {
+                    entries.into_iter().map(|(key, item,)| (Value::String(key,), item,),).collect()
+                },
+                (Some(scalar,), NestedKind::Array,) => vec![(Value::from(0,), scalar,)],
+                (Some(scalar,), NestedKind::Object,) => vec![(Value::Null, scalar,)],
+            };
+            for (key, value,) in entries {
+                let mut child = Map::new();
+                child.insert(PARENT_ID_COLUMN.to_string(), row_id.clone(),);
+                child.insert(kind.key_column().to_string(), key,);
+                child.insert("value".to_string(), value,);
+                child_rows.push(Value::Object(child,),);
+            }
+        }
+        obj.insert(ROW_ID_COLUMN.to_string(), row_id,);
+    }
+
+    *schema = NestedMode::TablePerField.table_schema(schema,);
+
+    fields
+        .into_iter()
+        .zip(children,)
+        .map(|((field, kind,), rows,)| {
+            let mut child_schema = HashMap::from([
+                (PARENT_ID_COLUMN.to_string(), DataType::String,),
+                (kind.key_column().to_string(), kind.key_type(),),
+                ("value".to_string(), DataType::String,),
+            ],);
+            // the value type comes from the elements; a column that was always empty keeps TEXT
+            if let Some(value_type,) = infer_schema(&rows,).remove("value",)
+                && value_type != DataType::Null
+            {
+                child_schema.insert("value".to_string(), value_type,);
+            }
+            let column = mappings.and_then(|m| m.get(&field,),).unwrap_or(&field,).clone();
+            ChildTable { column, schema: child_schema, rows, }
+        },)
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy,)]
+enum NestedKind {
+    Array,
+    Object,
+}
+
+impl NestedKind {
+    fn key_column(self,) -> &'static str {
+        match self {
+            NestedKind::Array => "position",
+            NestedKind::Object => "key",
+        }
+    }
+
+    fn key_type(self,) -> DataType {
+        match self {
+            NestedKind::Array => DataType::Integer,
+            NestedKind::Object => DataType::String,
+        }
+    }
+}
+
+fn nested_kind(data_type: &DataType,) -> Option<NestedKind,> {
+    match data_type {
+        DataType::Array(_,) => Some(NestedKind::Array,),
+        DataType::Object(_,) => Some(NestedKind::Object,),
+        DataType::Union(variants,) => {
+            variants.iter().find(|t| !matches!(t, DataType::Null),).and_then(nested_kind,)
+        },
+        _ => None,
+    }
+}
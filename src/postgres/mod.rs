@@ -1,95 +1,153 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use deadpool_postgres::{Manager, Pool};
+use deadpool_postgres::{Hook, HookError, Manager, Pool, PoolError};
 use futures_util::{SinkExt, pin_mut};
 use nc_reader::nc_reader_result::{DataReaderResult, RecordStream};
+use nc_schema::DataType;
+use tokio::sync::OnceCell;
+use tokio_postgres::error::SqlState;
 use tokio_postgres::{Config as TokioPgConfig, CopyInSink, NoTls};
-use tracing::info;
+use tracing::{debug, info, warn};
 
-use crate::error::{IngestorError, Result};
-use crate::ingestor::{Ingestor, IngestorConfig};
-use crate::retry::{execute_with_retry, wrap_error};
-use crate::schema_builder::{SqlDialect, SqlSchemaBuilder};
+use crate::embeddings::{Embedder, build_embedder, embed, embed_text};
+use crate::error::{IngestorError, Result, redact_uri};
+use crate::ingestor::{
+    Ingestor, IngestorConfig, RawFile, RecordSet, blob_value, raw_target_name,
+};
+use crate::nested::{
+    ChildTable, NestedMode, PARENT_ID_COLUMN, ROW_ID_COLUMN, child_table_name, split_nested,
+};
+use crate::retry::{execute_with_connection_retry, execute_with_retry, wrap_error};
+use crate::schema_builder::{
+    FULLTEXT_COLUMN, ForeignKey, SchemaDiff, SchemaDiffLog, SqlDialect, SqlSchemaBuilder,
+    VectorColumn, suffixed_table_name,
+};
+use crate::transform::{round_float_columns, round_vector};
+
+/// PostgreSQL session settings applied to every pooled connection.
+#[derive(Debug, Clone, Default, serde::Deserialize,)]
+#[serde(default, deny_unknown_fields)]
+pub struct PostgresOptions {
+    /// `statement_timeout` in milliseconds, so one stuck statement can't hold a connection forever
+    pub statement_timeout_ms: Option<u64,>,
+    /// pgvector column added to typed tables, filled from `embed_field` when embeddings are
+    /// configured
+    pub embedding_column:     Option<VectorColumn,>,
+    /// Text columns combined into a generated, GIN-indexed `tsvector` column on created tables
+    pub fulltext_fields:      Vec<String,>,
+    /// Create tables `UNLOGGED`: no WAL, so faster writes, but emptied after a crash
+    pub unlogged:             bool,
+    /// Load a typed table that doesn't exist yet in one transaction: create it, `COPY ...
+    /// FREEZE` the file's rows and only then build its indexes
+    pub optimize_bulk_load:   bool,
+}
 
 pub struct PostgresIngestor {
     #[allow(dead_code)]
     config: IngestorConfig,
 
-    pool: Pool,
+    pool:         Pool,
+    /// Fills `postgres.embedding_column`; only built when that column is configured
+    embedder:     Option<Box<dyn Embedder,>,>,
+    /// `optimize_bulk_load`: per table, set once the first file writing to it has either
+    /// bulk-loaded it or found it already existing. Later files wait for that, then COPY as usual.
+    bulk_loads:   Mutex<HashMap<String, Arc<OnceCell<(),>,>,>,>,
+    /// Tables compared by `--dry-run-schema-diff`
+    schema_diffs: SchemaDiffLog,
 }
 
 #[async_trait]
 impl Ingestor for PostgresIngestor {
     async fn new(config: IngestorConfig,) -> Result<Self,> {
         let pg_config = TokioPgConfig::from_str(&config.database_url,).map_err(|e| {
-            IngestorError::ConfigurationError(format!("Invalid PostgreSQL URI: {}", e),)
+            IngestorError::ConfigurationError(format!(
+                "Invalid PostgreSQL URI {}: {}",
+                redact_uri(&config.database_url,),
+                e
+            ),)
         },)?;
 
+        // A primary key of a partitioned table must include the partition column
+        if config.partition.is_some() && config.normalize_nested == NestedMode::TablePerField {
+            return Err(IngestorError::ConfigurationError(
+                "--normalize-nested table-per-field can't be combined with --partition-by"
+                    .to_string(),
+            ),);
+        }
+        // Postgres can't create unlogged partitioned tables, nor COPY FREEZE into them
+        if config.partition.is_some() && config.postgres.unlogged {
+            return Err(IngestorError::ConfigurationError(
+                "--unlogged can't be combined with --partition-by".to_string(),
+            ),);
+        }
+        if config.partition.is_some() && config.postgres.optimize_bulk_load {
+            return Err(IngestorError::ConfigurationError(
+                "--optimize-bulk-load can't be combined with --partition-by".to_string(),
+            ),);
+        }
+
         let manager = Manager::new(pg_config, NoTls,);
-        let pool = Pool::builder(manager,)
-            .max_size(16,) // Example max pool size
+        let mut builder = Pool::builder(manager,).max_size(16,); // Example max pool size
+        if let Some(timeout_ms,) = config.postgres.statement_timeout_ms {
+            let set_timeout = format!("SET statement_timeout = {}", timeout_ms);
+            builder = builder.post_create(Hook::async_fn(move |client, _| {
+                let set_timeout = set_timeout.clone();
+                Box::pin(async move {
+                    client.batch_execute(&set_timeout,).await.map_err(HookError::Backend,)
+                },)
+            },),);
+        }
+        let pool = builder
             .build()
             .map_err(|e| {
                 IngestorError::ConnectionError(format!("Failed to create PostgreSQL pool: {}", e),)
             },)?;
 
         // Test the connection with retry
-        execute_with_retry(|| async {
-            pool.get().await.map(|_| (),).map_err(|e| {
-                wrap_error(IngestorError::ConnectionError(format!(
-                    "Failed to get client from pool: {}",
-                    e
-                ),),)
-            },)
+        execute_with_connection_retry(config.connection_retries(), || async {
+            pool.get()
+                .await
+                .map(|_| (),)
+                .map_err(|e| wrap_error(pool_error(e,),),)
         },)
         .await?;
 
-        Ok(PostgresIngestor { config, pool, },)
+        let embedder = match config.postgres.embedding_column {
+            Some(_,) => build_embedder(&config,)?,
+            None => None,
+        };
+
+        Ok(PostgresIngestor {
+            config,
+            pool,
+            embedder,
+            bulk_loads: Mutex::default(),
+            schema_diffs: SchemaDiffLog::default(),
+        },)
     }
 
     async fn ingest(&self, data: DataReaderResult,) -> Result<(),> {
-        let table_name = self
-            .config
-            .collection_name
-            .as_deref()
-            .unwrap_or(crate::DEFAULT_SQL_TABLE_NAME,)
-            .to_string();
+        let table_name = self.config.table_name();
 
-        let mappings = self.config.mappings.clone();
+        if self.config.schema_diff {
+            let typed = match &data {
+                DataReaderResult::Csv(csv_data, _,) => csv_data
+                    .inferred_schema
+                    .as_ref()
+                    .map(|schema| (schema, Vec::new(),),),
+                _ => None,
+            };
+            return self.log_schema_diff(typed, &table_name,).await;
+        }
 
         match data {
             DataReaderResult::Csv(csv_data, _metadata,) => {
                 if let Some(schema,) = csv_data.inferred_schema {
-                    let client = self
-                        .pool
-                        .get()
-                        .await
-                        .map_err(|e| IngestorError::ConnectionError(e.to_string(),),)?;
-                    let builder = SqlSchemaBuilder::new(SqlDialect::Postgres, mappings.clone(),);
-                    let create_query = builder.build_create_table(&table_name, &schema,);
-
-                    execute_with_retry(|| async {
-                        client
-                            .execute(&create_query, &[],)
-                            .await
-                            .map(|_| (),)
-                            .map_err(|e| wrap_error(IngestorError::DatabaseError(e.to_string(),),),)
-                    },)
-                    .await?;
-
-                    let mut col_names: Vec<String,> = schema.keys().cloned().collect();
-                    col_names.sort();
-
-                    self.ingest_via_copy(
-                        csv_data.nc_rows.into_iter(),
-                        &table_name,
-                        &col_names,
-                        mappings,
-                    )
-                    .await?;
+                    self.ingest_typed(csv_data.nc_rows, &schema, Vec::new(), &table_name,).await?;
                 } else {
                     self.ingest_as_blob(DataReaderResult::Csv(csv_data, _metadata,), &table_name,)
                         .await?;
@@ -109,82 +167,489 @@ impl Ingestor for PostgresIngestor {
         );
         Ok((),)
     }
+
+    async fn ingest_records(&self, records: RecordSet,) -> Result<(),> {
+        let table_name = self.config.table_name();
+
+        if self.config.schema_diff {
+            let typed = records.schema.as_ref().map(|schema| (schema, records.columns.clone(),),);
+            return self.log_schema_diff(typed, &table_name,).await;
+        }
+
+        match records.schema {
+            Some(schema,) => {
+                self.ingest_typed(records.rows, &schema, records.columns, &table_name,)
+                    .await?
+            },
+            None => {
+                let stream: RecordStream = Box::new(records.rows.into_iter().map(Ok,),);
+                self.batch_ingest_stream(stream, &table_name,).await?;
+            },
+        }
+
+        info!(
+            "Successfully ingested data to PostgreSQL table '{}'.",
+            table_name
+        );
+        Ok((),)
+    }
+
+    async fn ingest_raw(&self, raw: RawFile,) -> Result<(),> {
+        let table_name = raw_target_name(&self.config.table_name(),);
+        let json_data = serde_json::to_string(&raw.to_value(),)
+            .map_err(|e| IngestorError::ingestion(e,),)?;
+        self.insert_blob(json_data, &table_name,).await
+    }
+
+    async fn target_count(&self,) -> Result<Option<u64,>,> {
+        let table_name = self.config.table_name();
+        let client = self.pool.get().await.map_err(pool_error,)?;
+
+        let count_query = format!("SELECT count(*) FROM \"{}\"", table_name);
+        match client.query_one(&count_query, &[],).await {
+            Ok(row,) => Ok(Some(row.get::<_, i64>(0,) as u64,),),
+            Err(e,) if e.code() == Some(&SqlState::UNDEFINED_TABLE,) => Ok(Some(0,),),
+            Err(e,) => Err(IngestorError::database(e,),),
+        }
+    }
+
+    async fn clean(&self,) -> Result<String,> {
+        let table_name = self.config.table_name();
+        let count = self.target_count().await?.unwrap_or_default();
+        let client = self.pool.get().await.map_err(pool_error,)?;
+
+        let drop_query = format!("DROP TABLE IF EXISTS \"{}\"", table_name);
+        client
+            .batch_execute(&drop_query,)
+            .await
+            .map_err(|e| IngestorError::database(e,),)?;
+        Ok(format!("dropped PostgreSQL table \"{}\" ({} rows)", table_name, count),)
+    }
+
+    /// Runs `sql` as one simple query, so several statements without their own `BEGIN` /
+    /// `COMMIT` succeed or fail together.
+    async fn execute_sql(&self, sql: &str,) -> Result<(),> {
+        let client = self.pool.get().await.map_err(pool_error,)?;
+        client.batch_execute(sql,).await.map_err(|e| IngestorError::database(e,),)
+    }
+
+    fn schema_diffs(&self,) -> Vec<SchemaDiff,> {
+        self.schema_diffs.diffs()
+    }
 }
 
 impl PostgresIngestor {
-    async fn ingest_via_copy(
+    /// Builder for the typed table, given the data's source column order.
+    fn schema_builder(&self, source_columns: Vec<String,>,) -> SqlSchemaBuilder {
+        SqlSchemaBuilder::new(SqlDialect::Postgres, self.config.mappings.clone(),)
+            .with_partition(self.config.partition.clone(),)
+            .with_vector_column(self.config.postgres.embedding_column.clone(),)
+            .with_fulltext(self.config.postgres.fulltext_fields.clone(),)
+            .with_type_overrides(self.config.type_overrides.clone(),)
+            .with_column_order(self.config.column_order.clone(), source_columns,)
+            .with_collation(self.config.collation.clone(),)
+            .with_comments(self.config.comments.clone(),)
+            .with_primary_key(self.row_id_column(),)
+            .with_unlogged(self.config.postgres.unlogged,)
+    }
+
+    /// `CREATE TABLE`, or `CREATE UNLOGGED TABLE` with `--unlogged`, for the JSON blob tables.
+    fn create_table(&self,) -> &'static str {
+        if self.config.postgres.unlogged { "CREATE UNLOGGED TABLE" } else { "CREATE TABLE" }
+    }
+
+    /// `_row_id`, the primary key of typed tables whose nested columns go to child tables.
+    fn row_id_column(&self,) -> Option<String,> {
+        (self.config.normalize_nested == NestedMode::TablePerField)
+            .then(|| ROW_ID_COLUMN.to_string(),)
+    }
+
+    /// Builder for the child tables of `parent` (`--normalize-nested table-per-field`).
+    fn child_builder(&self, parent: &str,) -> SqlSchemaBuilder {
+        SqlSchemaBuilder::new(SqlDialect::Postgres, None,)
+            .with_collation(self.config.collation.clone(),)
+            .with_unlogged(self.config.postgres.unlogged,)
+            .with_foreign_key(Some(ForeignKey {
+                column:     PARENT_ID_COLUMN.to_string(),
+                table:      parent.to_string(),
+                referenced: ROW_ID_COLUMN.to_string(),
+            },),)
+    }
+
+    /// `--dry-run-schema-diff`: logs and records how the table for `typed` (the inferred schema
+    /// and source column order) differs from `table_name` as it exists, without writing. Data
+    /// without a schema would go to a JSON blob table and is only noted.
+    async fn log_schema_diff(
         &self,
-        rows: impl Iterator<Item = serde_json::Value,>,
+        typed: Option<(&HashMap<String, DataType,>, Vec<String,>,),>,
         table_name: &str,
-        col_names: &[String],
-        mappings: Option<HashMap<String, String,>,>,
     ) -> Result<(),> {
-        let client = self
-            .pool
-            .get()
-            .await
-            .map_err(|e| IngestorError::ConnectionError(e.to_string(),),)?;
+        let Some((schema, source_columns,),) = typed else {
+            info!(
+                "No inferred schema; the data would be stored as JSON documents in \"{}\"",
+                table_name
+            );
+            return Ok((),);
+        };
+        let client = self.pool.get().await.map_err(pool_error,)?;
+        let live = table_columns(&client, table_name,).await?;
+        let schema = self.config.normalize_nested.table_schema(schema,);
+        let diff = self.schema_builder(source_columns,).diff(table_name, &schema, &live,);
+        self.schema_diffs.record(diff,);
+        Ok((),)
+    }
 
-        let mapped_cols: Vec<String,> = col_names
-            .iter()
-            .map(|c| {
-                let target = mappings
-                    .as_ref()
-                    .and_then(|m: &HashMap<String, String,>| m.get(c,),)
-                    .unwrap_or(c,);
-                format!("\"{}\"", target)
+    /// Creates the typed table for `schema` if needed and COPYs the rows into it.
+    async fn ingest_typed(
+        &self,
+        mut rows: Vec<serde_json::Value,>,
+        schema: &HashMap<String, DataType,>,
+        source_columns: Vec<String,>,
+        table_name: &str,
+    ) -> Result<(),> {
+        if let Some(decimals,) = self.config.float_precision() {
+            round_float_columns(&mut rows, schema, decimals,);
+        }
+        let mut schema = schema.clone();
+        let children = match self.config.normalize_nested {
+            NestedMode::TablePerField => {
+                split_nested(&mut rows, &mut schema, self.config.mappings.as_ref(),)
+            },
+            NestedMode::Json => Vec::new(),
+        };
+        let schema = &schema;
+        let mappings = self.config.mappings.clone();
+        let client = self.pool.get().await.map_err(pool_error,)?;
+        let vector_column = self.config.postgres.embedding_column.clone();
+        if vector_column.is_some() {
+            client
+                .batch_execute("CREATE EXTENSION IF NOT EXISTS vector",)
+                .await
+                .map_err(|e| {
+                    let detail = e
+                        .as_db_error()
+                        .map(|db| db.message().to_string(),)
+                        .unwrap_or_else(|| e.to_string(),);
+                    IngestorError::ConfigurationError(format!(
+                        "--embedding-column needs the pgvector extension, which could not be \
+                         enabled: {}",
+                        detail
+                    ),)
+                },)?;
+        }
+        let builder = self.schema_builder(source_columns,);
+        if let Some(column,) = builder.missing_partition_column(schema,) {
+            return Err(IngestorError::ConfigurationError(format!(
+                "--partition-by column '{}' is not among the columns of \"{}\"",
+                column, table_name
+            ),),);
+        }
+        let target = if self.config.rename_on_conflict {
+            let resolved = free_table_name(&client, table_name, &builder, schema,).await?;
+            if resolved != table_name {
+                info!(
+                    "Table \"{}\" has different columns; ingesting into \"{}\" instead",
+                    table_name, resolved
+                );
+            }
+            resolved
+        } else {
+            table_name.to_string()
+        };
+        let table_name = target.as_str();
+        let mut col_names = builder.ordered_fields(schema,);
+        if let (Some(vector,), Some(embedder,), Some(field,),) =
+            (&vector_column, &self.embedder, &self.config.embed_field,)
+        {
+            self.embed_rows(embedder.as_ref(), &mut rows, field, vector,)
+                .await?;
+            col_names.push(vector.name.clone(),);
+        }
+
+        let mut pending = Some((rows, children,),);
+        if self.config.postgres.optimize_bulk_load {
+            let pending = &mut pending;
+            let (client, builder, col_names,) = (&client, &builder, &col_names,);
+            self.bulk_load_cell(table_name,)
+                .get_or_try_init(|| async move {
+                    // Only a table this run creates can be loaded with FREEZE
+                    if table_exists(client, table_name,).await? {
+                        return Ok((),);
+                    }
+                    let (rows, children,) = pending.take().unwrap_or_default();
+                    self.bulk_load(builder, table_name, schema, col_names, &rows, &children,)
+                        .await
+                },)
+                .await?;
+        }
+
+        if let Some((rows, children,),) = pending {
+            let create_query = builder.build_create_table(table_name, schema,);
+
+            execute_with_retry(|| async {
+                client
+                    .execute(&create_query, &[],)
+                    .await
+                    .map(|_| (),)
+                    .map_err(|e| wrap_error(IngestorError::database(e,),),)
             },)
-            .collect();
+            .await?;
 
-        let copy_query = format!(
-            "COPY \"{}\" ({}) FROM STDIN (FORMAT CSV, HEADER FALSE)",
-            table_name,
-            mapped_cols.join(", ")
-        );
+            for partition_query in builder.build_partitions(table_name,) {
+                execute_with_retry(|| async {
+                    client
+                        .execute(&partition_query, &[],)
+                        .await
+                        .map(|_| (),)
+                        .map_err(|e| wrap_error(IngestorError::database(e,),),)
+                },)
+                .await?;
+            }
+            // Without partitions every COPY would fail with "no partition of relation found"
+            if self.config.partition.is_some() && lacks_partitions(&client, table_name,).await? {
+                return Err(IngestorError::ConfigurationError(format!(
+                    "Partitioned table \"{}\" has no partitions; create them (e.g. with \
+                     --pre-sql) or drop --no-default-partition",
+                    table_name
+                ),),);
+            }
 
-        let sink: CopyInSink<Bytes,> = client
-            .copy_in(&copy_query,)
-            .await
-            .map_err(|e| IngestorError::DatabaseError(e.to_string(),),)?;
-        pin_mut!(sink);
+            // A table created before --fulltext-fields has no tsvector column to index
+            if let Some(index_query,) = builder.build_fulltext_index(table_name, schema,) {
+                let live = table_columns(&client, table_name,).await?;
+                if !live.iter().any(|(name, _,)| name == FULLTEXT_COLUMN,) {
+                    warn!(
+                        "Table \"{}\" has no \"{}\" column; --fulltext-fields only applies to new \
+                         tables",
+                        table_name, FULLTEXT_COLUMN
+                    );
+                } else {
+                    execute_with_retry(|| async {
+                        client
+                            .execute(&index_query, &[],)
+                            .await
+                            .map(|_| (),)
+                            .map_err(|e| wrap_error(IngestorError::database(e,),),)
+                    },)
+                    .await?;
+                }
+            }
 
-        for row in rows {
-            if let serde_json::Value::Object(obj,) = row {
-                let mut line = String::new();
-                for (i, col,) in col_names.iter().enumerate() {
-                    if i > 0 {
-                        line.push(',',);
-                    }
-                    let val = obj.get(col,).unwrap_or(&serde_json::Value::Null,);
-                    line.push_str(&json_value_to_csv_field(val,),);
+            // COMMENT ON replaces any previous comment, so repeating it is harmless
+            for comment_query in builder.build_comments(table_name, schema,) {
+                execute_with_retry(|| async {
+                    client
+                        .execute(&comment_query, &[],)
+                        .await
+                        .map(|_| (),)
+                        .map_err(|e| wrap_error(IngestorError::database(e,),),)
+                },)
+                .await?;
+            }
+
+            // Child rows go into the COPY transaction of their parents' batch
+            let mut child_copies = Vec::new();
+            for child in children {
+                let child_table = child_table_name(table_name, &child.column,);
+                let child_builder = self.child_builder(table_name,);
+                let create_query = child_builder.build_create_table(&child_table, &child.schema,);
+                execute_with_retry(|| async {
+                    client
+                        .execute(&create_query, &[],)
+                        .await
+                        .map(|_| (),)
+                        .map_err(|e| wrap_error(IngestorError::database(e,),),)
+                },)
+                .await?;
+                let col_names = child_builder.ordered_fields(&child.schema,);
+                let copy = CopyTarget::new(&client, &child_table, &col_names, None,).await?;
+                child_copies.push((copy, child.rows,),);
+            }
+
+            let copy =
+                CopyTarget::new(&client, table_name, &col_names, mappings.as_ref(),).await?;
+            self.ingest_via_copy(&rows, &copy, &child_copies,).await?;
+        }
+        Ok((),)
+    }
+
+    /// The `optimize_bulk_load` cell of `table_name`, created on first use.
+    fn bulk_load_cell(&self, table_name: &str,) -> Arc<OnceCell<(),>,> {
+        let mut cells = self.bulk_loads.lock().unwrap_or_else(|e| e.into_inner(),);
+        Arc::clone(cells.entry(table_name.to_string(),).or_default(),)
+    }
+
+    /// `--optimize-bulk-load` for a table that doesn't exist yet. In one transaction it creates
+    /// the table and its `--normalize-nested` child tables, COPYs every row with `FREEZE` (so
+    /// they are written once, already frozen, and need no vacuum to become all-visible) and only
+    /// then adds the `_row_id` primary key, the child tables' foreign keys and the full-text
+    /// index, each in one pass instead of row by row. Nothing is left behind if any step fails,
+    /// and a transient failure retries the whole load.
+    async fn bulk_load(
+        &self,
+        builder: &SqlSchemaBuilder,
+        table_name: &str,
+        schema: &HashMap<String, DataType,>,
+        col_names: &[String],
+        rows: &[serde_json::Value],
+        children: &[ChildTable],
+    ) -> Result<(),> {
+        let (create_query, mut keys,) =
+            builder.build_create_table_deferring_keys(table_name, schema,);
+        let copy = CopyTarget::frozen(table_name, col_names, self.config.mappings.as_ref(),);
+        let child_builder = self.child_builder(table_name,);
+        let mut child_loads = Vec::new();
+        for child in children {
+            let child_table = child_table_name(table_name, &child.column,);
+            let (create_query, child_keys,) =
+                child_builder.build_create_table_deferring_keys(&child_table, &child.schema,);
+            let fields = child_builder.ordered_fields(&child.schema,);
+            child_loads.push((create_query, CopyTarget::frozen(&child_table, &fields, None,),),);
+            // After the parent's primary key, which they reference
+            keys.extend(child_keys,);
+        }
+        keys.extend(builder.build_fulltext_index(table_name, schema,),);
+
+        let database = |e| wrap_error(IngestorError::database(e,),);
+        execute_with_retry(|| async {
+            let mut client = self.pool.get().await.map_err(|e| wrap_error(pool_error(e,),),)?;
+            // Rolled back when dropped without `commit`
+            let tx = client.transaction().await.map_err(database,)?;
+            tx.batch_execute(&create_query,).await.map_err(database,)?;
+            for comment_query in builder.build_comments(table_name, schema,) {
+                tx.batch_execute(&comment_query,).await.map_err(database,)?;
+            }
+            copy.send(&tx, rows, 0,).await.map_err(wrap_error,)?;
+            for ((create_query, copy,), child,) in child_loads.iter().zip(children,) {
+                tx.batch_execute(create_query,).await.map_err(database,)?;
+                copy.send(&tx, &child.rows, 0,).await.map_err(wrap_error,)?;
+            }
+            for statement in &keys {
+                tx.batch_execute(statement,).await.map_err(database,)?;
+            }
+            tx.commit().await.map_err(database,)
+        },)
+        .await?;
+
+        info!("Bulk-loaded {} rows into new table \"{}\"", rows.len(), table_name);
+        self.config.batch_committed(table_name, rows.len(),);
+        Ok((),)
+    }
+
+    /// Embeds each row's `field` text and stores it under the vector column as a pgvector
+    /// literal (`[0.1,0.2,...]`). Rows without text keep a NULL vector.
+    async fn embed_rows(
+        &self,
+        embedder: &dyn Embedder,
+        rows: &mut [serde_json::Value],
+        field: &str,
+        vector: &VectorColumn,
+    ) -> Result<(),> {
+        let has_text = |row: &serde_json::Value| embed_text(row, field,).is_some();
+        for chunk in rows.chunks_mut(self.config.batch.size(),) {
+            let texts: Vec<String,> = chunk
+                .iter()
+                .filter_map(|row| embed_text(row, field,).map(String::from,),)
+                .collect();
+            if texts.is_empty() {
+                continue;
+            }
+
+            let mut embeddings = embed(embedder, &texts,).await?.into_iter();
+            for row in chunk.iter_mut().filter(|row| has_text(row,),) {
+                let Some(embedding,) = embeddings.next() else { break };
+                if embedding.len() as u64 != vector.dimensions {
+                    return Err(IngestorError::ConfigurationError(format!(
+                        "Embedding has {} dimensions but column '{}' is vector({})",
+                        embedding.len(),
+                        vector.name,
+                        vector.dimensions
+                    ),),);
+                }
+                let embedding = match self.config.float_precision() {
+                    Some(decimals,) => round_vector(embedding, decimals,),
+                    None => embedding,
+                };
+                let components: Vec<String,> = embedding.iter().map(f32::to_string,).collect();
+                if let serde_json::Value::Object(obj,) = row {
+                    obj.insert(
+                        vector.name.clone(),
+                        serde_json::Value::String(format!("[{}]", components.join(",")),),
+                    );
                 }
-                line.push('\n',);
-                sink.send(Bytes::from(line,),)
-                    .await
-                    .map_err(|e: tokio_postgres::Error| {
-                        IngestorError::IngestionError(e.to_string(),)
-                    },)?;
             }
         }
+        Ok((),)
+    }
 
-        sink.close()
-            .await
-            .map_err(|e: tokio_postgres::Error| IngestorError::IngestionError(e.to_string(),),)?;
+    /// COPYs `rows` into `copy`'s table in batches, each in one transaction with the rows of
+    /// `children` whose `_parent_id` is in the batch, so no parent is committed without its
+    /// child rows or the other way round. Child rows are grouped by parent, in parent order.
+    async fn ingest_via_copy(
+        &self,
+        rows: &[serde_json::Value],
+        copy: &CopyTarget,
+        children: &[(CopyTarget, Vec<serde_json::Value,>,)],
+    ) -> Result<(),> {
+        let mut child_starts = vec![0; children.len()];
+        let mut first_row = 0;
+        for batch in rows.chunks(self.config.batch.size(),) {
+            let row_ids: HashSet<&str,> =
+                batch.iter().filter_map(|row| row.get(ROW_ID_COLUMN,)?.as_str(),).collect();
+            let child_batches: Vec<(usize, &[serde_json::Value],),> = children
+                .iter()
+                .zip(&mut child_starts,)
+                .map(|((_, child_rows,), start,)| {
+                    let first = *start;
+                    *start += child_rows[first..]
+                        .iter()
+                        .take_while(|row| {
+                            row.get(PARENT_ID_COLUMN,)
+                                .and_then(|id| id.as_str(),)
+                                .is_some_and(|id| row_ids.contains(id,),)
+                        },)
+                        .count();
+                    (first, &child_rows[first..*start],)
+                },)
+                .collect();
+
+            // A transaction that fails or times out before `commit` is rolled back with nothing
+            // written, so the batch is retried whole.
+            execute_with_retry(|| async {
+                self.config
+                    .batch
+                    .run(async {
+                        let mut client = self.pool.get().await.map_err(pool_error,)?;
+                        let tx = client.transaction().await.map_err(IngestorError::database,)?;
+                        copy.send(&tx, batch, first_row,).await?;
+                        for ((child, _,), (first, child_rows,),) in
+                            children.iter().zip(&child_batches,)
+                        {
+                            if !child_rows.is_empty() {
+                                child.send(&tx, child_rows, *first,).await?;
+                            }
+                        }
+                        tx.commit().await.map_err(IngestorError::database,)
+                    },)
+                    .await
+                    .map_err(wrap_error,)
+            },)
+            .await?;
+            first_row += batch.len();
+            self.config.batch_committed(&copy.table, batch.len(),);
+        }
         Ok((),)
     }
 
     async fn batch_ingest_stream(&self, stream: RecordStream, table_name: &str,) -> Result<(),> {
-        let client = self
-            .pool
-            .get()
-            .await
-            .map_err(|e| IngestorError::ConnectionError(e.to_string(),),)?;
+        let client = self.pool.get().await.map_err(pool_error,)?;
 
         let create_table_query = format!(
-            "CREATE TABLE IF NOT EXISTS \"{}\" (
+            "{} IF NOT EXISTS \"{}\" (
                 id SERIAL PRIMARY KEY,
                 data JSONB NOT NULL
             )",
+            self.create_table(),
             table_name
         );
 
@@ -193,7 +658,7 @@ impl PostgresIngestor {
                 .execute(&create_table_query, &[],)
                 .await
                 .map(|_| (),)
-                .map_err(|e| wrap_error(IngestorError::DatabaseError(e.to_string(),),),)
+                .map_err(|e| wrap_error(IngestorError::database(e,),),)
         },)
         .await?;
 
@@ -201,44 +666,70 @@ impl PostgresIngestor {
             "COPY \"{}\" (data) FROM STDIN (FORMAT CSV, HEADER FALSE)",
             table_name
         );
-        let sink: CopyInSink<Bytes,> = client
-            .copy_in(&copy_query,)
-            .await
-            .map_err(|e| IngestorError::DatabaseError(e.to_string(),),)?;
-        pin_mut!(sink);
-
-        for record_res in stream {
-            let record = record_res.map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
-            let json_data = serde_json::to_string(&record,)
+        // One COPY per batch, each committed on its own and retried whole like the typed COPY
+        let mut stream = stream.peekable();
+        while stream.peek().is_some() {
+            let batch = stream
+                .by_ref()
+                .take(self.config.batch.size(),)
+                .collect::<std::result::Result<Vec<serde_json::Value,>, _,>>()
                 .map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
+            let batch_len = batch.len();
 
-            let mut line = json_value_to_csv_field(&serde_json::Value::String(json_data,),);
-            line.push('\n',);
-            sink.send(Bytes::from(line,),)
-                .await
-                .map_err(
-                    |e: tokio_postgres::Error| IngestorError::IngestionError(e.to_string(),),
-                )?;
-        }
+            execute_with_retry(|| async {
+                self.config
+                    .batch
+                    .run(async {
+                        let sink: CopyInSink<Bytes,> = client
+                            .copy_in(&copy_query,)
+                            .await
+                            .map_err(|e| IngestorError::database(e,),)?;
+                        pin_mut!(sink);
 
-        sink.close()
-            .await
-            .map_err(|e: tokio_postgres::Error| IngestorError::IngestionError(e.to_string(),),)?;
+                        for record in &batch {
+                            let json_data = serde_json::to_string(record,)
+                                .map_err(|e| IngestorError::ingestion(e,),)?;
+
+                            let mut line =
+                                json_value_to_csv_field(&serde_json::Value::String(json_data,),);
+                            line.push('\n',);
+                            sink.send(Bytes::from(line,),).await.map_err(
+                                |e: tokio_postgres::Error| IngestorError::ingestion(e,),
+                            )?;
+                        }
+
+                        sink.close()
+                            .await
+                            .map_err(|e: tokio_postgres::Error| IngestorError::ingestion(e,),)?;
+                        Ok((),)
+                    },)
+                    .await
+                    .map_err(wrap_error,)
+            },)
+            .await?;
+            self.config.batch_committed(table_name, batch_len,);
+        }
         Ok((),)
     }
 
     async fn ingest_as_blob(&self, data: DataReaderResult, table_name: &str,) -> Result<(),> {
-        let client = self
-            .pool
-            .get()
-            .await
-            .map_err(|e| IngestorError::ConnectionError(e.to_string(),),)?;
+        let json_data = serde_json::to_string(&blob_value(&data, &self.config,)?,)
+            .map_err(|e| IngestorError::ingestion(e,),)?;
+        self.insert_blob(json_data, table_name,).await?;
+        self.config.batch_committed(table_name, 1,);
+        Ok((),)
+    }
+
+    /// Inserts one JSON document into an `(id, data)` blob table, creating it if needed.
+    async fn insert_blob(&self, json_data: String, table_name: &str,) -> Result<(),> {
+        let client = self.pool.get().await.map_err(pool_error,)?;
 
         let create_table_query = format!(
-            "CREATE TABLE IF NOT EXISTS \"{}\" (
+            "{} IF NOT EXISTS \"{}\" (
                 id SERIAL PRIMARY KEY,
                 data JSONB NOT NULL
             )",
+            self.create_table(),
             table_name
         );
 
@@ -247,12 +738,10 @@ impl PostgresIngestor {
                 .execute(&create_table_query, &[],)
                 .await
                 .map(|_| (),)
-                .map_err(|e| wrap_error(IngestorError::DatabaseError(e.to_string(),),),)
+                .map_err(|e| wrap_error(IngestorError::database(e,),),)
         },)
         .await?;
 
-        let json_data = serde_json::to_string(&data,)
-            .map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
         let insert_query = format!("INSERT INTO \"{}\" (data) VALUES ($1)", table_name);
 
         execute_with_retry(|| async {
@@ -260,13 +749,300 @@ impl PostgresIngestor {
                 .execute(&insert_query, &[&json_data,],)
                 .await
                 .map(|_| (),)
-                .map_err(|e| wrap_error(IngestorError::IngestionError(e.to_string(),),),)
+                .map_err(|e| wrap_error(IngestorError::ingestion(e,),),)
         },)
         .await?;
         Ok((),)
     }
 }
 
+/// First of `base`, `base_2`, ... in the current schema that has exactly the columns of
+/// `schema`, or that doesn't exist yet, in which case it is created for `schema` right away.
+/// The plain `CREATE TABLE` fails if another file created the table in the meantime, and that
+/// table's columns are then checked like any existing table's.
+async fn free_table_name(
+    client: &tokio_postgres::Client,
+    base: &str,
+    builder: &SqlSchemaBuilder,
+    schema: &HashMap<String, DataType,>,
+) -> Result<String,> {
+    let columns = builder.column_names(schema,);
+    let mut attempt = 1;
+    loop {
+        let candidate = suffixed_table_name(base, attempt,);
+        let rows = client
+            .query(
+                "SELECT column_name::text FROM information_schema.columns \
+                 WHERE table_schema = current_schema() AND table_name = $1",
+                &[&candidate],
+            )
+            .await
+            .map_err(|e| IngestorError::database(e,),)?;
+        let existing: BTreeSet<String,> = rows.iter().map(|row| row.get(0,),).collect();
+        if existing == columns {
+            return Ok(candidate,);
+        }
+        if existing.is_empty() {
+            let create = builder.build_create_new_table(&candidate, schema,);
+            match client.batch_execute(&create,).await {
+                Ok((),) => return Ok(candidate,),
+                Err(e,) if e.code() == Some(&SqlState::DUPLICATE_TABLE,) => continue,
+                Err(e,) => return Err(IngestorError::database(e,),),
+            }
+        }
+        attempt += 1;
+    }
+}
+
+/// Whether `table_name` exists in the current schema.
+async fn table_exists(client: &tokio_postgres::Client, table_name: &str,) -> Result<bool,> {
+    let row = client
+        .query_one(
+            "SELECT to_regclass(quote_ident(current_schema()) || '.' || quote_ident($1)) \
+             IS NOT NULL",
+            &[&table_name],
+        )
+        .await
+        .map_err(IngestorError::database,)?;
+    Ok(row.get(0,),)
+}
+
+/// Whether `table_name` is a partitioned table without any partitions.
+async fn lacks_partitions(client: &tokio_postgres::Client, table_name: &str,) -> Result<bool,> {
+    let row = client
+        .query_one(
+            "SELECT c.relkind = 'p' AND NOT EXISTS \
+             (SELECT 1 FROM pg_inherits i WHERE i.inhparent = c.oid) \
+             FROM pg_class c \
+             WHERE c.oid = to_regclass(quote_ident(current_schema()) || '.' || quote_ident($1))",
+            &[&table_name],
+        )
+        .await
+        .map_err(IngestorError::database,)?;
+    Ok(row.get(0,),)
+}
+
+/// A table COPY writes into: its `(field, column)` pairs and the `COPY ... FROM STDIN`
+/// statement for them.
+struct CopyTarget {
+    table:   String,
+    columns: Vec<(String, String,),>,
+    query:   String,
+}
+
+impl CopyTarget {
+    async fn new(
+        client: &tokio_postgres::Client,
+        table_name: &str,
+        fields: &[String],
+        mappings: Option<&HashMap<String, String,>,>,
+    ) -> Result<Self,> {
+        let columns = copy_columns(client, table_name, fields, mappings,).await?;
+        Ok(Self::with_columns(table_name, columns, "",),)
+    }
+
+    /// A table created from `fields` in the COPY's own transaction: every field has its column,
+    /// and the rows can be written with `FREEZE`.
+    fn frozen(
+        table_name: &str,
+        fields: &[String],
+        mappings: Option<&HashMap<String, String,>,>,
+    ) -> Self {
+        let columns = fields
+            .iter()
+            .map(|field| {
+                let column = mappings.and_then(|m| m.get(field,),).unwrap_or(field,);
+                (field.clone(), column.clone(),)
+            },)
+            .collect();
+        Self::with_columns(table_name, columns, ", FREEZE",)
+    }
+
+    fn with_columns(table_name: &str, columns: Vec<(String, String,),>, options: &str,) -> Self {
+        let mapped_cols: Vec<String,> =
+            columns.iter().map(|(_, column,)| format!("\"{}\"", column),).collect();
+        let query = format!(
+            "COPY \"{}\" ({}) FROM STDIN (FORMAT CSV, HEADER FALSE{})",
+            table_name,
+            mapped_cols.join(", "),
+            options
+        );
+        CopyTarget { table: table_name.to_string(), columns, query, }
+    }
+
+    /// COPYs `rows` in `tx`; they start at record `first_row` of the file.
+    async fn send(
+        &self,
+        tx: &tokio_postgres::Transaction<'_,>,
+        rows: &[serde_json::Value],
+        first_row: usize,
+    ) -> Result<(),> {
+        let sink: CopyInSink<Bytes,> =
+            tx.copy_in(&self.query,).await.map_err(|e| IngestorError::database(e,),)?;
+        pin_mut!(sink);
+
+        // Lines sent so far; the server numbers COPY lines from 1
+        let mut line_no = 0;
+        for row in rows {
+            if let serde_json::Value::Object(obj,) = row {
+                line_no += 1;
+                sink.send(Bytes::from(copy_line(obj, &self.columns,),),)
+                    .await
+                    .map_err(|e| copy_error(e, first_row, Some(line_no,),),)?;
+            }
+        }
+        sink.close().await.map_err(|e| copy_error(e, first_row, None,),)?;
+        Ok((),)
+    }
+}
+
+/// One COPY CSV line of `obj`, with the values of `columns` (field, column) in order.
+fn copy_line(
+    obj: &serde_json::Map<String, serde_json::Value,>,
+    columns: &[(String, String,)],
+) -> String {
+    let mut line = String::new();
+    for (i, (field, _,),) in columns.iter().enumerate() {
+        if i > 0 {
+            line.push(',',);
+        }
+        let val = obj.get(field,).unwrap_or(&serde_json::Value::Null,);
+        line.push_str(&json_value_to_csv_field(val,),);
+    }
+    line.push('\n',);
+    line
+}
+
+/// `(column, type)` of `table_name` in the current schema, in column order; empty if the table
+/// doesn't exist. Extension types such as pgvector's report their type name.
+async fn table_columns(
+    client: &tokio_postgres::Client,
+    table_name: &str,
+) -> Result<Vec<(String, String,),>,> {
+    let rows = client
+        .query(
+            "SELECT column_name::text, \
+                    CASE WHEN data_type = 'USER-DEFINED' THEN udt_name ELSE data_type END::text \
+             FROM information_schema.columns \
+             WHERE table_schema = current_schema() AND table_name = $1 \
+             ORDER BY ordinal_position",
+            &[&table_name],
+        )
+        .await
+        .map_err(|e| IngestorError::database(e,),)?;
+    Ok(rows.iter().map(|row| (row.get(0,), row.get(1,),),).collect(),)
+}
+
+/// Record fields to COPY, paired with their table columns. Only columns the data provides are
+/// listed, so the server fills defaults for the table's other columns. Generated columns and
+/// `GENERATED ALWAYS` identities can't be written by COPY and are left to the server too. A
+/// field the table has no column for is an error.
+async fn copy_columns(
+    client: &tokio_postgres::Client,
+    table_name: &str,
+    fields: &[String],
+    mappings: Option<&HashMap<String, String,>,>,
+) -> Result<Vec<(String, String,),>,> {
+    let rows = client
+        .query(
+            "SELECT column_name::text, \
+                    is_generated = 'ALWAYS' OR identity_generation IS NOT DISTINCT FROM 'ALWAYS' \
+             FROM information_schema.columns \
+             WHERE table_schema = current_schema() AND table_name = $1",
+            &[&table_name],
+        )
+        .await
+        .map_err(|e| IngestorError::database(e,),)?;
+    let generated: HashMap<String, bool,> =
+        rows.iter().map(|row| (row.get(0,), row.get(1,),),).collect();
+
+    let mut columns = Vec::new();
+    let mut missing = Vec::new();
+    for field in fields {
+        let column = mappings.and_then(|m| m.get(field,),).unwrap_or(field,);
+        match generated.get(column,) {
+            // Not in the current schema (e.g. found elsewhere on the search path): let COPY
+            // resolve it
+            _ if generated.is_empty() => columns.push((field.clone(), column.clone(),),),
+            Some(false,) => columns.push((field.clone(), column.clone(),),),
+            Some(true,) => debug!(
+                "Leaving generated column \"{}\" of \"{}\" to the server",
+                column, table_name
+            ),
+            None => missing.push(format!("\"{}\"", column),),
+        }
+    }
+    if !missing.is_empty() {
+        return Err(IngestorError::ConfigurationError(format!(
+            "Table \"{}\" has no column for {}; add the column(s) or use --rename-on-conflict",
+            table_name,
+            missing.join(", ")
+        ),),);
+    }
+    Ok(columns,)
+}
+
+/// Maps a failed pool checkout. Rejected credentials (SQLSTATE 28P01/28000) become a permanent
+/// authentication error so a mistyped password fails fast instead of retrying for the whole
+/// backoff window.
+fn pool_error(e: PoolError,) -> IngestorError {
+    if let PoolError::Backend(pg_err,) = &e {
+        let code = pg_err.code();
+        if code == Some(&SqlState::INVALID_PASSWORD,)
+            || code == Some(&SqlState::INVALID_AUTHORIZATION_SPECIFICATION,)
+        {
+            let detail = pg_err
+                .as_db_error()
+                .map(|db| db.message().to_string(),)
+                .unwrap_or_else(|| pg_err.to_string(),);
+            return IngestorError::AuthenticationError(format!(
+                "PostgreSQL rejected the credentials: {}",
+                detail
+            ),);
+        }
+    }
+    IngestorError::ConnectionError(format!("Failed to get client from pool: {}", e),)
+        .with_source(e,)
+}
+
+/// Adds the failing row to a COPY error. Postgres reports the line (and usually the column) in
+/// the error context, e.g. `COPY events, line 3, column score: "abc"`; lines count from 1 within
+/// the COPY, which starts at record `first_row` of the file. Without that context the error is
+/// pinned to `sent_line`, the line being sent when it surfaced.
+fn copy_error(
+    e: tokio_postgres::Error,
+    first_row: usize,
+    sent_line: Option<usize,>,
+) -> IngestorError {
+    let context = e.as_db_error().and_then(|db| db.where_(),).unwrap_or_default();
+    let line = copy_context_value(context, "line ",)
+        .and_then(|n| n.parse::<usize>().ok(),)
+        .or(sent_line,);
+    let column = copy_context_value(context, "column ",);
+
+    let location = match (line, column,) {
+        (Some(line,), Some(column,),) => {
+            format!("record {} (column \"{}\")", first_row + line, column)
+        },
+        (Some(line,), None,) => format!("record {}", first_row + line),
+        (None, _,) => format!("the COPY starting at record {}", first_row + 1),
+    };
+    let detail = e
+        .as_db_error()
+        .map(|db| db.message().to_string(),)
+        .unwrap_or_else(|| e.to_string(),);
+    IngestorError::IngestionError(format!("COPY failed at {}: {}", location, detail),)
+        .with_source(e,)
+}
+
+/// Value following `key` in a COPY error context, up to the next `,` or `:`.
+fn copy_context_value<'a,>(context: &'a str, key: &str,) -> Option<&'a str,> {
+    let start = context.find(key,)? + key.len();
+    let rest = &context[start..];
+    let end = rest.find([',', ':',],).unwrap_or(rest.len(),);
+    Some(rest[..end].trim(),).filter(|value| !value.is_empty(),)
+}
+
 fn json_value_to_csv_field(val: &serde_json::Value,) -> String {
     match val {
         serde_json::Value::Null => "".to_string(),
@@ -1,101 +1,418 @@
 // nc_ingestor/src/qdrant/mod.rs
 // Qdrant specific ingestion logic.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use nc_reader::nc_reader_result::DataReaderResult;
 use qdrant_client::Qdrant;
 use qdrant_client::qdrant::{
-    CollectionStatus, CreateCollection, Distance, PointStruct, UpsertPoints, VectorParams,
+    BinaryQuantization, CollectionInfo, CollectionStatus, CompressionRatio, CountPoints,
+    CreateAlias, CreateCollection, CreateFieldIndexCollection, Distance, FieldType, GetPoints,
+    PayloadIncludeSelector, PointStruct, ProductQuantization, QuantizationConfig, QuantizationType,
+    QueryPoints, RetrievedPoint, ScalarQuantization, StrictModeConfig, UpsertPoints, VectorParams,
+    WithPayloadSelector, WriteOrdering, WriteOrderingType, quantization_config,
+    with_payload_selector,
 };
 use qdrant_client::qdrant::{PointId, point_id::PointIdOptions}; /* Ensure PointIdOptions is
                                                                   * imported */
-use tracing::info;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
-use crate::embeddings::{Embedder, OpenAIEmbedder};
-use crate::error::{IngestorError, Result};
-use crate::ingestor::{Ingestor, IngestorConfig};
-use crate::retry::{execute_with_retry, wrap_error};
+use crate::embeddings::{Embedder, build_embedder, embed, embed_text};
+use crate::error::{IngestorError, Result, redact_message};
+use crate::ingestor::{
+    CommitTracker, IngestStats, Ingestor, IngestorConfig, RawFile, blob_value, raw_target_name,
+};
+use crate::retry::{execute_with_connection_retry, execute_with_retry, wrap_error};
+use crate::transform::{round_floats, round_vector};
+
+/// Qdrant-only options: blue-green ingestion through a collection alias and creation-time
+/// payload checks.
+#[derive(Debug, Clone, Default, serde::Deserialize,)]
+#[serde(default, deny_unknown_fields)]
+pub struct QdrantOptions {
+    /// Ingest into a fresh `<alias>_<unix seconds>` collection and point this alias at it once
+    /// every file succeeded. `collection_name` is ignored when set.
+    pub alias:              Option<String,>,
+    /// Delete the collection the alias previously pointed to after switching it.
+    pub delete_old:         bool,
+    /// Create collections in Qdrant strict mode and reject records that don't fit
+    /// `payload_schema`, reporting each one instead of writing it.
+    pub strict:             bool,
+    /// Payload field -> index type (`keyword`, `integer`, `float`, `bool`, `geo`, `text`,
+    /// `datetime` or `uuid`). Each field is indexed when the collection is created.
+    pub payload_schema:     HashMap<String, String,>,
+    /// Skip records whose embedding has at least this cosine similarity to a point already in
+    /// the collection. Costs one search per record; requires an embedder.
+    pub dedup_threshold:    Option<f32,>,
+    /// What to do with records whose `embed_field` is missing, null or blank.
+    pub on_empty_embedding: EmptyEmbedding,
+    /// Vector quantization set when the collection is created.
+    pub quantization:       Option<Quantization,>,
+    /// Scalar quantization quantile (0.5 to 1.0) bounding the int8 range; Qdrant's default
+    /// when unset.
+    pub quantile:           Option<f32,>,
+    /// Keep quantized vectors in RAM even when the original vectors are on disk.
+    pub always_ram:         bool,
+    /// Send upserts with `wait=false`, so Qdrant acknowledges them before applying them, and
+    /// wait for all of them once in [`Ingestor::flush`], which also checks every point sent was
+    /// applied. Files only count as ingested after that.
+    pub no_wait:            bool,
+    /// Record field identifying a document across runs. Points get an ID derived from it and
+    /// the hash of their embed text, and records whose stored hash is unchanged are neither
+    /// re-embedded nor upserted. Requires an embedder, and can't be combined with `alias`, whose
+    /// fresh collection never holds a stored hash.
+    pub change_detect:      Option<String,>,
+}
+
+/// Qdrant vector quantization, trading some search accuracy for memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize,)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantization {
+    /// int8 components (4x smaller)
+    Scalar,
+    /// Product quantization at 16x compression
+    Product,
+    /// One bit per component (32x smaller; best with high-dimensional embeddings)
+    Binary,
+}
+
+/// Handling of records with nothing to embed, when an embedder and `embed_field` are configured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize,)]
+#[serde(rename_all = "lowercase")]
+pub enum EmptyEmbedding {
+    /// Store the point with a constant placeholder vector
+    #[default]
+    Placeholder,
+    /// Leave the record out and log how many were skipped
+    Skip,
+    /// Store the point with an all-zero vector
+    Zero,
+    /// Fail the file at the first such record
+    Error,
+}
 
 pub struct QdrantIngestor {
-    config:   IngestorConfig,
-    client:   Qdrant,
-    embedder: Option<Box<dyn Embedder,>,>,
+    config:          IngestorConfig,
+    client:          Qdrant,
+    embedder:        Option<Box<dyn Embedder,>,>,
+    /// Collection written to; a timestamped one when ingesting behind an alias
+    collection_name: String,
+    /// `qdrant.payload_schema` with its index types parsed
+    payload_schema:  HashMap<String, FieldType,>,
+    /// IDs of the points sent under `no_wait` that haven't been confirmed yet, by collection
+    unconfirmed:     Mutex<BTreeMap<String, BTreeSet<String,>,>,>,
 }
 
 #[async_trait]
 impl Ingestor for QdrantIngestor {
     async fn new(config: IngestorConfig,) -> Result<Self,> {
+        if let Some(threshold,) = config.qdrant.dedup_threshold {
+            if !(0.0..=1.0).contains(&threshold,) {
+                return Err(IngestorError::ConfigurationError(format!(
+                    "Dedup threshold must be a cosine similarity between 0 and 1, got {}",
+                    threshold
+                ),),);
+            }
+            if config.embedding_providers()?.is_empty() || config.embed_field.is_none() {
+                return Err(IngestorError::ConfigurationError(
+                    "Deduplicating on embeddings needs --embed-field and an embedding provider"
+                        .to_string(),
+                ),);
+            }
+        }
+
+        if config.qdrant.change_detect.is_some()
+            && (config.embedding_providers()?.is_empty() || config.embed_field.is_none())
+        {
+            return Err(IngestorError::ConfigurationError(
+                "Change detection needs --embed-field and an embedding provider".to_string(),
+            ),);
+        }
+        if config.qdrant.change_detect.is_some() && config.qdrant.alias.is_some() {
+            return Err(IngestorError::ConfigurationError(
+                "Change detection can't be combined with --qdrant-alias: every run writes a new \
+                 collection, so no record would ever be unchanged"
+                    .to_string(),
+            ),);
+        }
+
+        if let Some(quantile,) = config.qdrant.quantile {
+            if config.qdrant.quantization != Some(Quantization::Scalar,) {
+                return Err(IngestorError::ConfigurationError(
+                    "A quantization quantile only applies to scalar quantization".to_string(),
+                ),);
+            }
+            if !(0.5..=1.0).contains(&quantile,) {
+                return Err(IngestorError::ConfigurationError(format!(
+                    "Quantization quantile must be between 0.5 and 1, got {}",
+                    quantile
+                ),),);
+            }
+        }
+
         let client = Qdrant::from_url(&config.database_url,)
             .build()
             .map_err(|e| {
-                IngestorError::ConnectionError(format!("Failed to create Qdrant client: {}", e),)
+                IngestorError::ConnectionError(format!(
+                    "Failed to create Qdrant client: {}",
+                    redact_message(&e.to_string(), &config.database_url,)
+                ),)
             },)?;
 
         // Basic check: list collections with retry
-        execute_with_retry(|| async {
+        execute_with_connection_retry(config.connection_retries(), || async {
             client.list_collections().await.map(|_| (),).map_err(|e| {
                 wrap_error(IngestorError::ConnectionError(format!(
                     "Failed to connect to Qdrant: {}",
-                    e
+                    redact_message(&e.to_string(), &config.database_url,)
                 ),),)
             },)
         },)
         .await?;
 
-        let embedder: Option<Box<dyn Embedder,>,> = config
-            .openai_api_key
-            .as_ref()
-            .map(|key| Box::new(OpenAIEmbedder::new(key.clone(), None,),) as Box<dyn Embedder,>,);
+        let embedder = build_embedder(&config,)?;
+
+        let payload_schema = config
+            .qdrant
+            .payload_schema
+            .iter()
+            .map(|(field, name,)| {
+                parse_field_type(name,).map(|t| (field.clone(), t,),).ok_or_else(|| {
+                    IngestorError::ConfigurationError(format!(
+                        "Unknown Qdrant payload index type '{}' for field '{}'",
+                        name, field
+                    ),)
+                },)
+            },)
+            .collect::<Result<HashMap<_, _,>,>>()?;
+
+        let collection_name = match &config.qdrant.alias {
+            Some(alias,) => {
+                let secs = SystemTime::now()
+                    .duration_since(UNIX_EPOCH,)
+                    .map(|d| d.as_secs(),)
+                    .unwrap_or_default();
+                format!("{}_{}", alias, secs)
+            },
+            None => config.collection_name(),
+        };
 
         Ok(QdrantIngestor {
             config,
             client,
             embedder,
+            collection_name,
+            payload_schema,
+            unconfirmed: Mutex::new(BTreeMap::new(),),
         },)
     }
 
     async fn ingest(&self, data: DataReaderResult,) -> Result<(),> {
-        let collection_name = self
-            .config
-            .collection_name
-            .as_deref()
-            .unwrap_or(crate::DEFAULT_COLLECTION_NAME,);
-        let vector_size = self
-            .config
-            .vector_size
-            .unwrap_or(crate::DEFAULT_VECTOR_SIZE,);
+        let collection_name = self.collection_name.as_str();
+        let vector_size = self.config.vector_size();
 
         self.ensure_collection(collection_name, vector_size,)
             .await?;
 
         match data {
             DataReaderResult::Csv(csv_data, _,) => {
-                for row in csv_data.nc_rows {
-                    self.ingest_record(row, collection_name, vector_size,)
-                        .await?;
-                }
+                self.upsert_in_batches(
+                    csv_data.nc_rows.into_iter().map(Ok,),
+                    collection_name,
+                    vector_size,
+                )
+                .await?;
             },
             DataReaderResult::Stream(stream, _,) => {
-                for record_res in stream {
-                    let record =
-                        record_res.map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
-                    self.ingest_record(record, collection_name, vector_size,)
-                        .await?;
-                }
+                let records = stream.map(|record_res| {
+                    record_res.map_err(|e| IngestorError::IngestionError(e.to_string(),),)
+                },);
+                self.upsert_in_batches(records, collection_name, vector_size,)
+                    .await?;
             },
             _ => {
-                let json_val = serde_json::to_value(&data,)
-                    .map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
-                self.ingest_record(json_val, collection_name, vector_size,)
+                let json_val = blob_value(&data, &self.config,)?;
+                let records = std::iter::once(Ok(json_val,),);
+                self.upsert_in_batches(records, collection_name, vector_size,)
                     .await?;
             },
         }
 
         Ok((),)
     }
+
+    fn streams_csv(&self,) -> bool {
+        // Points carry no schema, so large CSVs go through upsert_in_batches without being
+        // read into memory first
+        true
+    }
+
+    async fn ingest_raw(&self, raw: RawFile,) -> Result<(),> {
+        // Payload-only: raw points carry a placeholder vector and aren't meant for search
+        let collection_name = raw_target_name(&self.collection_name,);
+        let vector_size = self.config.vector_size();
+
+        self.ensure_collection(&collection_name, vector_size,)
+            .await?;
+        let payload = match raw.to_value() {
+            serde_json::Value::Object(obj,) => obj
+                .iter()
+                .map(|(key, value,)| (key.clone(), serde_json_value_to_qdrant_value(value,),),)
+                .collect(),
+            _ => HashMap::new(),
+        };
+        let point = new_point(payload, vec![0.1; vector_size as usize],);
+        self.upsert_points(vec![point], &collection_name,).await
+    }
+
+    async fn target_count(&self,) -> Result<Option<u64,>,> {
+        let collection_name = self.collection_name.as_str();
+
+        let exists = self
+            .client
+            .collection_exists(collection_name,)
+            .await
+            .map_err(|e| IngestorError::database(e,),)?;
+        if !exists {
+            return Ok(Some(0,),);
+        }
+
+        let count_req = CountPoints {
+            collection_name: collection_name.to_string(),
+            exact: Some(true,),
+            ..Default::default()
+        };
+        let response = execute_with_retry(|| async {
+            self.client.count(count_req.clone(),).await.map_err(|e| {
+                wrap_error(IngestorError::DatabaseError(format!(
+                    "Failed to count Qdrant points: {}",
+                    e
+                ),),)
+            },)
+        },)
+        .await?;
+        Ok(Some(response.result.map(|r| r.count,).unwrap_or(0,),),)
+    }
+
+    async fn clean(&self,) -> Result<String,> {
+        let collection_name = self.collection_name.as_str();
+        let exists = self
+            .client
+            .collection_exists(collection_name,)
+            .await
+            .map_err(|e| IngestorError::database(e,),)?;
+        if !exists {
+            return Ok(format!("Qdrant collection `{}` does not exist", collection_name),);
+        }
+        let count = self.target_count().await?.unwrap_or_default();
+
+        self.client.delete_collection(collection_name,).await.map_err(|e| {
+            IngestorError::DatabaseError(format!("Failed to delete Qdrant collection: {}", e),)
+        },)?;
+        Ok(format!("deleted Qdrant collection `{}` ({} points)", collection_name, count),)
+    }
+
+    fn defers_writes(&self,) -> bool {
+        self.config.qdrant.no_wait
+    }
+
+    async fn flush(&self,) -> Result<Vec<IngestStats,>,> {
+        let collections = std::mem::take(&mut *self.unconfirmed.lock().unwrap(),);
+        for (collection_name, ids,) in collections {
+            // Updates to a collection are applied in order, so once an empty upsert sent with
+            // wait=true is applied, every unconfirmed upsert before it is as well. An upsert
+            // that failed while being applied leaves no trace but its missing points.
+            self.send_upsert(Vec::new(), &collection_name, true,).await?;
+            let missing = self.missing_points(&collection_name, ids,).await?;
+            if missing > 0 {
+                return Err(IngestorError::IngestionError(format!(
+                    "{} point(s) sent to '{}' without waiting were never applied",
+                    missing, collection_name
+                ),),);
+            }
+            debug!("Confirmed unwaited upserts into '{}'", collection_name);
+        }
+        Ok(Vec::new(),)
+    }
+
+    async fn finalize(&self,) -> Result<(),> {
+        let Some(alias,) = &self.config.qdrant.alias else {
+            return Ok((),);
+        };
+
+        let exists = self
+            .client
+            .collection_exists(&self.collection_name,)
+            .await
+            .map_err(|e| IngestorError::database(e,),)?;
+        if !exists {
+            warn!(
+                "Nothing was ingested into '{}'; leaving alias '{}' unchanged",
+                self.collection_name, alias
+            );
+            return Ok((),);
+        }
+
+        let previous = execute_with_retry(|| async {
+            self.client.list_aliases().await.map_err(|e| {
+                wrap_error(IngestorError::DatabaseError(format!(
+                    "Failed to list Qdrant aliases: {}",
+                    e
+                ),),)
+            },)
+        },)
+        .await?
+        .aliases
+        .into_iter()
+        .find(|a| &a.alias_name == alias,)
+        .map(|a| a.collection_name,);
+
+        // CreateAlias replaces an existing mapping in one operation, so the alias never dangles
+        let create_alias = CreateAlias {
+            collection_name: self.collection_name.clone(),
+            alias_name:      alias.clone(),
+        };
+        execute_with_retry(|| async {
+            self.client
+                .create_alias(create_alias.clone(),)
+                .await
+                .map(|_| (),)
+                .map_err(|e| {
+                    wrap_error(IngestorError::DatabaseError(format!(
+                        "Failed to switch Qdrant alias: {}",
+                        e
+                    ),),)
+                },)
+        },)
+        .await?;
+        info!("Alias '{}' now points to '{}'", alias, self.collection_name);
+
+        if self.config.qdrant.delete_old
+            && let Some(previous,) = previous
+            && previous != self.collection_name
+        {
+            execute_with_retry(|| async {
+                self.client
+                    .delete_collection(previous.as_str(),)
+                    .await
+                    .map(|_| (),)
+                    .map_err(|e| {
+                        wrap_error(IngestorError::DatabaseError(format!(
+                            "Failed to delete previous Qdrant collection: {}",
+                            e
+                        ),),)
+                    },)
+            },)
+            .await?;
+            info!("Deleted previous collection '{}'", previous);
+        }
+        Ok((),)
+    }
 }
 
 impl QdrantIngestor {
@@ -113,22 +430,21 @@ impl QdrantIngestor {
         },)
         .await?;
 
+        if let Some(info,) = &collection_info.result
+            && let Some(existing,) = configured_vector_size(info,)
+            && existing != vector_size
+        {
+            return Err(IngestorError::ConfigurationError(format!(
+                "Qdrant collection '{}' stores {}-dimensional vectors but --vector-size is {}",
+                collection_name, existing, vector_size
+            ),),);
+        }
+
         if collection_info.result.is_none()
             || collection_info.result.unwrap().status != CollectionStatus::Green as i32
         {
-            let create_collection_req = CreateCollection {
-                collection_name: collection_name.to_string(),
-                vectors_config: Some(qdrant_client::qdrant::VectorsConfig {
-                    config: Some(qdrant_client::qdrant::vectors_config::Config::Params(
-                        VectorParams {
-                            size: vector_size,
-                            distance: Distance::Cosine as i32,
-                            ..Default::default()
-                        },
-                    ),),
-                },),
-                ..Default::default()
-            };
+            let create_collection_req =
+                create_collection_request(&self.config, collection_name, vector_size,);
 
             execute_with_retry(|| async {
                 self.client
@@ -144,68 +460,419 @@ impl QdrantIngestor {
             },)
             .await?;
             info!("Created Qdrant collection: {}", collection_name);
+
+            for (field, field_type,) in &self.payload_schema {
+                self.create_payload_index(collection_name, field, *field_type,)
+                    .await?;
+            }
         }
         Ok((),)
     }
 
-    async fn ingest_record(
+    async fn create_payload_index(
         &self,
-        record: serde_json::Value,
         collection_name: &str,
-        vector_size: u64,
+        field: &str,
+        field_type: FieldType,
     ) -> Result<(),> {
-        let mut qdrant_payload = HashMap::new();
-        let mut text_to_embed = None;
+        let index_req = CreateFieldIndexCollection {
+            collection_name: collection_name.to_string(),
+            wait: Some(true,),
+            field_name: field.to_string(),
+            field_type: Some(field_type as i32,),
+            ..Default::default()
+        };
+
+        execute_with_retry(|| async {
+            self.client
+                .create_field_index(index_req.clone(),)
+                .await
+                .map(|_| (),)
+                .map_err(|e| {
+                    wrap_error(IngestorError::DatabaseError(format!(
+                        "Failed to index Qdrant payload field '{}': {}",
+                        field, e
+                    ),),)
+                },)
+        },)
+        .await
+    }
 
-        if let Some(obj,) = record.as_object() {
-            for (key, value,) in obj {
-                qdrant_payload.insert(key.clone(), serde_json_value_to_qdrant_value(value,),);
+    /// Embeds records and upserts them `batch.size` points at a time. In strict mode records
+    /// that fail the payload checks (ours or Qdrant's) are skipped and listed in the returned
+    /// error once the rest of the file is written. With `dedup_threshold`, records whose
+    /// embedding is that similar to an existing or pending point are skipped.
+    async fn upsert_in_batches(
+        &self,
+        records: impl Iterator<Item = Result<serde_json::Value,>,> + Send,
+        collection_name: &str,
+        vector_size: u64,
+    ) -> Result<(),> {
+        let batch_size = self.config.batch.size();
+        let mut batch = Vec::with_capacity(batch_size,);
+        let mut rejected = Vec::new();
+        // Vectors of `batch`, which near-duplicate searches can't see yet
+        let mut pending = Vec::new();
+        let mut deduped = 0;
+        let mut skipped_empty = 0;
+        let mut unchanged = 0;
+        let mut records = records.enumerate().peekable();
+        while records.peek().is_some() {
+            // Read a batch ahead, so its stored hashes are fetched in one request
+            let mut chunk = Vec::with_capacity(batch_size,);
+            for (index, record,) in records.by_ref().take(batch_size,) {
+                let mut record = record?;
+                if let Some(decimals,) = self.config.float_precision() {
+                    round_floats(&mut record, decimals,);
+                }
+                if self.config.qdrant.strict
+                    && let Err(reason,) = self.check_payload(&record,)
+                {
+                    rejected.push(format!("record {}: {}", index + 1, reason),);
+                    continue;
+                }
+                let fields = (&self.config.qdrant.change_detect, &self.config.embed_field,);
+                let change_key = match fields {
+                    (Some(key_field,), Some(embed_field,),) => {
+                        Some(change_key(&record, key_field, embed_field, index,)?,)
+                    },
+                    _ => None,
+                };
+                chunk.push((index, record, change_key,),);
+            }
+            let ids = chunk.iter().filter_map(|(_, _, key,)| key.as_ref(),).map(|(id, _,)| id,);
+            let stored = self.stored_hashes(collection_name, ids,).await?;
 
-                if let Some(embed_f,) = &self.config.embed_field
-                    && key == embed_f
+            for (index, record, change_key,) in chunk {
+                if let Some((id, hash,),) = &change_key
+                    && stored.get(id,) == Some(hash,)
                 {
-                    text_to_embed = value.as_str().map(|s| s.to_string(),);
+                    debug!("Record {} is unchanged", index + 1);
+                    unchanged += 1;
+                    continue;
+                }
+                let Some(vector,) = self.record_vector(&record, index, vector_size,).await? else {
+                    debug!("Skipped record {} with nothing to embed", index + 1);
+                    skipped_empty += 1;
+                    continue;
+                };
+                if let Some(threshold,) = self.config.qdrant.dedup_threshold {
+                    if self
+                        .is_near_duplicate(&vector, &pending, collection_name, threshold,)
+                        .await?
+                    {
+                        debug!("Deduped record {}", index + 1);
+                        deduped += 1;
+                        continue;
+                    }
+                    pending.push(vector.clone(),);
+                }
+                let mut point = self.build_point(record, vector,);
+                if let Some((id, hash,),) = change_key {
+                    point.id = Some(PointId::from(id,),);
+                    point.payload.insert(
+                        CONTENT_HASH_FIELD.to_string(),
+                        serde_json_value_to_qdrant_value(&serde_json::Value::String(hash,),),
+                    );
+                }
+                batch.push((index, point,),);
+                if batch.len() >= batch_size {
+                    self.upsert_batch(std::mem::take(&mut batch,), collection_name, &mut rejected,)
+                        .await?;
+                    pending.clear();
                 }
             }
         }
+        if !batch.is_empty() {
+            self.upsert_batch(batch, collection_name, &mut rejected,)
+                .await?;
+        }
+        if deduped > 0 {
+            info!("Deduped {} near-duplicate record(s) in {}", deduped, collection_name);
+        }
+        if skipped_empty > 0 {
+            info!(
+                "Skipped {} record(s) with an empty embed field in {}",
+                skipped_empty, collection_name
+            );
+            if let Some(tracker,) = CommitTracker::current() {
+                tracker.skip(skipped_empty,);
+            }
+        }
+        if unchanged > 0 {
+            info!("Skipped {} unchanged record(s) in {}", unchanged, collection_name);
+        }
 
-        let vector_data = if let (Some(embedder,), Some(text,),) = (&self.embedder, text_to_embed,)
-        {
-            let embeddings = embedder.generate_embeddings(&[text,],).await?;
-            if !embeddings.is_empty() {
-                embeddings[0].clone()
-            } else {
-                vec![0.0; vector_size as usize]
+        if rejected.is_empty() {
+            return Ok((),);
+        }
+        let shown = rejected.iter().take(MAX_REPORTED_REJECTIONS,).cloned();
+        let mut details = shown.collect::<Vec<_,>>().join("; ",);
+        if rejected.len() > MAX_REPORTED_REJECTIONS {
+            details.push_str("; ...",);
+        }
+        Err(IngestorError::IngestionError(format!(
+            "{} record(s) rejected by strict payload checks: {}",
+            rejected.len(),
+            details
+        ),),)
+    }
+
+    /// Upserts numbered points. When strict mode makes Qdrant refuse a whole batch, the points are
+    /// retried one by one so the offending records can be named.
+    async fn upsert_batch(
+        &self,
+        batch: Vec<(usize, PointStruct,),>,
+        collection_name: &str,
+        rejected: &mut Vec<String,>,
+    ) -> Result<(),> {
+        let points = batch.iter().map(|(_, point,)| point.clone(),).collect();
+        match self.upsert_points(points, collection_name,).await {
+            Ok((),) => {
+                self.config.batch_committed(collection_name, batch.len(),);
+                Ok((),)
+            },
+            Err(e,) if self.config.qdrant.strict && !e.is_transient() && batch.len() > 1 => {
+                let mut upserted = 0;
+                for (index, point,) in batch {
+                    match self.upsert_points(vec![point], collection_name,).await {
+                        Ok((),) => upserted += 1,
+                        Err(e,) => rejected.push(format!("record {}: {}", index + 1, e),),
+                    }
+                }
+                if upserted > 0 {
+                    self.config.batch_committed(collection_name, upserted,);
+                }
+                Ok((),)
+            },
+            Err(e,) if self.config.qdrant.strict && !e.is_transient() => {
+                let index = batch.first().map_or(0, |(index, _,)| *index,);
+                rejected.push(format!("record {}: {}", index + 1, e),);
+                Ok((),)
+            },
+            other => other,
+        }
+    }
+
+    /// Checks a record against `payload_schema`: every field must be declared and hold values of
+    /// its index type. Nulls are always accepted. No-op without a schema.
+    fn check_payload(&self, record: &serde_json::Value,) -> std::result::Result<(), String,> {
+        if self.payload_schema.is_empty() {
+            return Ok((),);
+        }
+        let Some(obj,) = record.as_object() else {
+            return Err("record is not an object".to_string(),);
+        };
+        for (field, value,) in obj {
+            match self.payload_schema.get(field,) {
+                None => return Err(format!("unexpected field '{}'", field),),
+                Some(field_type,) if !matches_field_type(value, *field_type,) => {
+                    return Err(format!(
+                        "field '{}' doesn't match its '{}' index",
+                        field, self.config.qdrant.payload_schema[field]
+                    ),);
+                },
+                Some(_,) => {},
             }
+        }
+        Ok((),)
+    }
+
+    /// The record's embedding, or a placeholder vector when nothing is embedded. Records whose
+    /// embed field is missing, null or blank follow `on_empty_embedding`; `None` skips them.
+    async fn record_vector(
+        &self,
+        record: &serde_json::Value,
+        index: usize,
+        vector_size: u64,
+    ) -> Result<Option<Vec<f32,>,>,> {
+        let (Some(embedder,), Some(field,),) = (&self.embedder, &self.config.embed_field,) else {
+            return Ok(Some(vec![0.1; vector_size as usize],),);
+        };
+        let text_to_embed = embed_text(record, field,).filter(|text| !text.trim().is_empty(),);
+
+        let Some(text,) = text_to_embed else {
+            return match self.config.qdrant.on_empty_embedding {
+                EmptyEmbedding::Placeholder => Ok(Some(vec![0.1; vector_size as usize],),),
+                EmptyEmbedding::Zero => Ok(Some(vec![0.0; vector_size as usize],),),
+                EmptyEmbedding::Skip => Ok(None,),
+                EmptyEmbedding::Error => Err(IngestorError::IngestionError(format!(
+                    "record {}: embed field '{}' is empty",
+                    index + 1,
+                    field
+                ),),),
+            };
+        };
+        let embeddings = embed(embedder.as_ref(), &[text.to_string(),],).await?;
+        Ok(Some(match (embeddings.into_iter().next(), self.config.float_precision(),) {
+            (Some(embedding,), Some(decimals,),) => round_vector(embedding, decimals,),
+            (Some(embedding,), None,) => embedding,
+            (None, _,) => vec![0.0; vector_size as usize],
+        },),)
+    }
+
+    /// Content hashes stored on the points `ids`, by point ID, fetched in one request. Points
+    /// that don't exist yet or have no hash are left out.
+    async fn stored_hashes(
+        &self,
+        collection_name: &str,
+        ids: impl Iterator<Item = &String,>,
+    ) -> Result<HashMap<String, String,>,> {
+        let ids: Vec<PointId,> = ids.map(|id| PointId::from(id.clone(),),).collect();
+        if ids.is_empty() {
+            return Ok(HashMap::new(),);
+        }
+        let get_req = GetPoints {
+            collection_name: collection_name.to_string(),
+            ids,
+            with_payload: Some(WithPayloadSelector {
+                selector_options: Some(with_payload_selector::SelectorOptions::Include(
+                    PayloadIncludeSelector { fields: vec![CONTENT_HASH_FIELD.to_string()], },
+                ),),
+            },),
+            with_vectors: Some(false.into(),),
+            ..Default::default()
+        };
+        let response = execute_with_retry(|| async {
+            self.client.get_points(get_req.clone(),).await.map_err(|e| {
+                wrap_error(IngestorError::DatabaseError(format!(
+                    "Failed to retrieve Qdrant points: {}",
+                    e
+                ),),)
+            },)
+        },)
+        .await?;
+        Ok(content_hashes(&response.result,),)
+    }
+
+    fn build_point(&self, record: serde_json::Value, vector: Vec<f32,>,) -> PointStruct {
+        let qdrant_payload = record
+            .as_object()
+            .map(|obj| {
+                obj.iter()
+                    .map(|(key, value,)| (key.clone(), serde_json_value_to_qdrant_value(value,),),)
+                    .collect()
+            },)
+            .unwrap_or_default();
+        new_point(qdrant_payload, vector,)
+    }
+
+    /// Whether `vector` is at least `threshold` cosine-similar to a point already in the
+    /// collection or to one still waiting in the current batch.
+    async fn is_near_duplicate(
+        &self,
+        vector: &[f32],
+        pending: &[Vec<f32,>],
+        collection_name: &str,
+        threshold: f32,
+    ) -> Result<bool,> {
+        if pending.iter().any(|other| cosine_similarity(vector, other,) >= threshold,) {
+            return Ok(true,);
+        }
+
+        let query_req = QueryPoints {
+            collection_name: collection_name.to_string(),
+            query: Some(vector.to_vec().into(),),
+            score_threshold: Some(threshold,),
+            limit: Some(1,),
+            ..Default::default()
+        };
+        let response = execute_with_retry(|| async {
+            self.client.query(query_req.clone(),).await.map_err(|e| {
+                wrap_error(IngestorError::DatabaseError(format!(
+                    "Failed to search Qdrant for near duplicates: {}",
+                    e
+                ),),)
+            },)
+        },)
+        .await?;
+        Ok(!response.result.is_empty(),)
+    }
+
+    async fn upsert_points(
+        &self,
+        points: Vec<PointStruct,>,
+        collection_name: &str,
+    ) -> Result<(),> {
+        let wait = !self.config.qdrant.no_wait;
+        let ids: Vec<String,> = if wait {
+            Vec::new()
         } else {
-            vec![0.1; vector_size as usize]
+            points.iter().filter_map(|point| point_uuid(point.id.as_ref()?,),).collect()
         };
+        self.send_upsert(points, collection_name, wait,).await?;
+        if !wait {
+            let mut unconfirmed = self.unconfirmed.lock().unwrap();
+            unconfirmed.entry(collection_name.to_string(),).or_default().extend(ids,);
+        }
+        Ok((),)
+    }
 
-        let point_id = Uuid::new_v4().to_string();
+    /// How many of the points `ids` the collection doesn't hold, looked up `batch.size` at a
+    /// time.
+    async fn missing_points(
+        &self,
+        collection_name: &str,
+        ids: BTreeSet<String,>,
+    ) -> Result<usize,> {
+        let count = ids.len();
+        let mut ids = ids.into_iter().map(PointId::from,).peekable();
+        let mut found = 0;
+        while ids.peek().is_some() {
+            let get_req = GetPoints {
+                collection_name: collection_name.to_string(),
+                ids: ids.by_ref().take(self.config.batch.size(),).collect(),
+                with_payload: Some(false.into(),),
+                with_vectors: Some(false.into(),),
+                ..Default::default()
+            };
+            let response = execute_with_retry(|| async {
+                self.client.get_points(get_req.clone(),).await.map_err(|e| {
+                    wrap_error(IngestorError::DatabaseError(format!(
+                        "Failed to retrieve Qdrant points: {}",
+                        e
+                    ),),)
+                },)
+            },)
+            .await?;
+            found += response.result.len();
+        }
+        Ok(count.saturating_sub(found,),)
+    }
+
+    async fn send_upsert(
+        &self,
+        points: Vec<PointStruct,>,
+        collection_name: &str,
+        wait: bool,
+    ) -> Result<(),> {
         let upsert_req = UpsertPoints {
             collection_name: collection_name.to_string(),
-            wait: Some(true,),
-            points: vec![PointStruct {
-                id:      Some(PointId {
-                    point_id_options: Some(PointIdOptions::Uuid(point_id.clone(),),),
-                },),
-                payload: qdrant_payload,
-                vectors: Some(vector_data.into(),),
-            }],
+            wait: Some(wait,),
+            points,
+            ordering: self.config.batch.preserve_order.then_some(WriteOrdering {
+                r#type: WriteOrderingType::Strong as i32,
+            },),
             ..Default::default()
         };
 
         execute_with_retry(|| async {
-            self.client
-                .upsert_points(upsert_req.clone(),)
-                .await
-                .map(|_| (),)
-                .map_err(|e| {
-                    wrap_error(IngestorError::IngestionError(format!(
-                        "Failed to upsert point to Qdrant: {}",
-                        e
-                    ),),)
+            self.config
+                .batch
+                .run(async {
+                    self.client
+                        .upsert_points(upsert_req.clone(),)
+                        .await
+                        .map(|_| (),)
+                        .map_err(|e| {
+                            IngestorError::IngestionError(format!(
+                                "Failed to upsert points to Qdrant: {}",
+                                e
+                            ),)
+                        },)
                 },)
+                .await
+                .map_err(wrap_error,)
         },)
         .await?;
 
@@ -213,6 +880,177 @@ impl QdrantIngestor {
     }
 }
 
+/// Cosine similarity of two vectors, as `--dedup-threshold` compares it; 0 when either is all
+/// zeros.
+pub fn cosine_similarity(a: &[f32], b: &[f32],) -> f32 {
+    let dot: f32 = a.iter().zip(b,).map(|(x, y,)| x * y,).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x,).sum::<f32>().sqrt();
+    let norms = norm(a,) * norm(b,);
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+/// Most rejected records named in a strict-mode error; the count covers the rest.
+const MAX_REPORTED_REJECTIONS: usize = 10;
+
+/// Payload field holding the embed text hash of points written with `change_detect`.
+pub const CONTENT_HASH_FIELD: &str = "_content_hash";
+
+/// The request creating `collection_name` with `vector_size` cosine vectors, with the strict mode
+/// and quantization `config.qdrant` asks for.
+pub fn create_collection_request(
+    config: &IngestorConfig,
+    collection_name: &str,
+    vector_size: u64,
+) -> CreateCollection {
+    CreateCollection {
+        collection_name: collection_name.to_string(),
+        vectors_config: Some(qdrant_client::qdrant::VectorsConfig {
+            config: Some(qdrant_client::qdrant::vectors_config::Config::Params(VectorParams {
+                size: vector_size,
+                distance: Distance::Cosine as i32,
+                ..Default::default()
+            },),),
+        },),
+        strict_mode_config: config.qdrant.strict.then(|| StrictModeConfig {
+            enabled: Some(true,),
+            unindexed_filtering_retrieve: Some(false,),
+            unindexed_filtering_update: Some(false,),
+            upsert_max_batchsize: Some(config.batch.size() as u64,),
+            ..Default::default()
+        },),
+        quantization_config: collection_quantization(&config.qdrant,),
+        ..Default::default()
+    }
+}
+
+/// `options.quantization` as the collection's quantization config.
+fn collection_quantization(options: &QdrantOptions,) -> Option<QuantizationConfig,> {
+    let always_ram = options.always_ram.then_some(true,);
+    let quantization = match options.quantization? {
+        Quantization::Scalar => quantization_config::Quantization::Scalar(ScalarQuantization {
+            r#type: QuantizationType::Int8 as i32,
+            quantile: options.quantile,
+            always_ram,
+        },),
+        Quantization::Product => quantization_config::Quantization::Product(ProductQuantization {
+            compression: CompressionRatio::X16 as i32,
+            always_ram,
+        },),
+        Quantization::Binary => quantization_config::Quantization::Binary(BinaryQuantization {
+            always_ram,
+            ..Default::default()
+        },),
+    };
+    Some(QuantizationConfig { quantization: Some(quantization,), },)
+}
+
+/// With `change_detect`, a record's stable point ID (a UUID v5 of its `key_field` value) and the
+/// SHA-256 of its embed text. `index` numbers the record in errors.
+pub fn change_key(
+    record: &serde_json::Value,
+    key_field: &str,
+    embed_field: &str,
+    index: usize,
+) -> Result<(String, String,),> {
+    let key = match record.get(key_field,) {
+        Some(serde_json::Value::String(key,),) => key.clone(),
+        Some(key,) if !key.is_null() => key.to_string(),
+        _ => {
+            return Err(IngestorError::IngestionError(format!(
+                "record {}: change detection field '{}' is missing",
+                index + 1,
+                key_field
+            ),),);
+        },
+    };
+    let id = Uuid::new_v5(&Uuid::NAMESPACE_OID, key.as_bytes(),);
+    let text = embed_text(record, embed_field,).unwrap_or_default();
+    let hash = format!("{:x}", Sha256::digest(text.as_bytes()));
+    Ok((id.to_string(), hash,),)
+}
+
+/// The UUID of a point ID; every point this ingestor writes has one.
+fn point_uuid(id: &PointId,) -> Option<String,> {
+    match &id.point_id_options {
+        Some(PointIdOptions::Uuid(uuid,),) => Some(uuid.clone(),),
+        _ => None,
+    }
+}
+
+/// The [`CONTENT_HASH_FIELD`] of each of `points` that has one, by point UUID.
+pub fn content_hashes(points: &[RetrievedPoint],) -> HashMap<String, String,> {
+    points
+        .iter()
+        .filter_map(|point| {
+            let id = point_uuid(point.id.as_ref()?,)?;
+            match point.payload.get(CONTENT_HASH_FIELD,)?.kind.as_ref()? {
+                qdrant_client::qdrant::value::Kind::StringValue(hash,) => {
+                    Some((id, hash.clone(),),)
+                },
+                _ => None,
+            }
+        },)
+        .collect()
+}
+
+fn parse_field_type(name: &str,) -> Option<FieldType,> {
+    match name.to_ascii_lowercase().as_str() {
+        "keyword" => Some(FieldType::Keyword,),
+        "integer" => Some(FieldType::Integer,),
+        "float" => Some(FieldType::Float,),
+        "bool" => Some(FieldType::Bool,),
+        "geo" => Some(FieldType::Geo,),
+        "text" => Some(FieldType::Text,),
+        "datetime" => Some(FieldType::Datetime,),
+        "uuid" => Some(FieldType::Uuid,),
+        _ => None,
+    }
+}
+
+/// Whether `value` can be stored under a payload index of `field_type`. Arrays match when every
+/// element does, like Qdrant's multi-value payloads.
+fn matches_field_type(value: &serde_json::Value, field_type: FieldType,) -> bool {
+    match value {
+        serde_json::Value::Null => true,
+        serde_json::Value::Array(items,) => {
+            items.iter().all(|item| matches_field_type(item, field_type,),)
+        },
+        _ => match field_type {
+            FieldType::Keyword | FieldType::Text | FieldType::Datetime | FieldType::Uuid => {
+                value.is_string()
+            },
+            FieldType::Integer => value.is_i64() || value.is_u64(),
+            FieldType::Float => value.is_number(),
+            FieldType::Bool => value.is_boolean(),
+            FieldType::Geo => ["lat", "lon",]
+                .iter()
+                .all(|key| value.get(key,).is_some_and(serde_json::Value::is_number,),),
+        },
+    }
+}
+
+/// Dimension of a collection's unnamed vector, or `None` for named vectors or missing config.
+fn configured_vector_size(info: &CollectionInfo,) -> Option<u64,> {
+    let vectors = info.config.as_ref()?.params.as_ref()?.vectors_config.as_ref()?;
+    match vectors.config.as_ref()? {
+        qdrant_client::qdrant::vectors_config::Config::Params(params,) => Some(params.size,),
+        qdrant_client::qdrant::vectors_config::Config::ParamsMap(_,) => None,
+    }
+}
+
+fn new_point(
+    payload: HashMap<String, qdrant_client::qdrant::Value,>,
+    vector_data: Vec<f32,>,
+) -> PointStruct {
+    PointStruct {
+        id: Some(PointId {
+            point_id_options: Some(PointIdOptions::Uuid(Uuid::new_v4().to_string(),),),
+        },),
+        payload,
+        vectors: Some(vector_data.into(),),
+    }
+}
+
 // Helper function to convert serde_json::Value to qdrant_client::qdrant::Value
 fn serde_json_value_to_qdrant_value(json_val: &serde_json::Value,) -> qdrant_client::qdrant::Value {
     match json_val {
@@ -0,0 +1,138 @@
+// nc_ingestor/src/remote_input.rs
+// Lists `s3://` and `gs://` inputs and fetches each object when its file is processed.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use futures_util::StreamExt;
+use object_store::aws::AmazonS3Builder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectMeta, ObjectStore};
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use crate::error::{IngestorError, Result};
+
+/// Whether `path` is an object storage URL rather than a local path.
+pub fn is_remote(path: &Path,) -> bool {
+    path.to_str()
+        .is_some_and(|p| p.starts_with("s3://",) || p.starts_with("gs://",),)
+}
+
+/// An object found under a `--path` prefix, named by its full URL.
+#[derive(Debug, Clone, PartialEq, Eq,)]
+pub struct RemoteObject {
+    pub url:  String,
+    pub size: u64,
+}
+
+/// The bucket of an `s3://` or `gs://` URL. Credentials and region come from the standard
+/// `AWS_*` / `GOOGLE_*` environment variables.
+pub struct RemoteStore {
+    store:  Arc<dyn ObjectStore,>,
+    /// `scheme://bucket`, which object keys are appended to
+    bucket: String,
+}
+
+impl RemoteStore {
+    pub fn open(url: &str,) -> Result<Self,> {
+        let parsed = parse(url,)?;
+        let store: Arc<dyn ObjectStore,> = match parsed.scheme() {
+            "s3" => Arc::new(
+                AmazonS3Builder::from_env()
+                    .with_url(url,)
+                    .build()
+                    .map_err(|e| store_error(url, e,),)?,
+            ),
+            "gs" => Arc::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_url(url,)
+                    .build()
+                    .map_err(|e| store_error(url, e,),)?,
+            ),
+            scheme => {
+                return Err(IngestorError::ConfigurationError(format!(
+                    "Unsupported object storage scheme '{}'",
+                    scheme
+                ),),);
+            },
+        };
+        Self::with_store(url, store,)
+    }
+
+    /// Serves the bucket of `url` from `store`, e.g. an in-memory store in tests.
+    pub fn with_store(url: &str, store: Arc<dyn ObjectStore,>,) -> Result<Self,> {
+        let parsed = parse(url,)?;
+        let bucket = format!("{}://{}", parsed.scheme(), parsed.host_str().unwrap_or_default());
+        Ok(RemoteStore { store, bucket, },)
+    }
+
+    /// Every object under the prefix `url`, sorted by URL. A URL naming a single object lists
+    /// just that object. Only metadata is read.
+    pub async fn list(&self, url: &str,) -> Result<Vec<RemoteObject,>,> {
+        let key = object_key(url,)?;
+        let prefix = (!key.is_empty()).then(|| ObjectPath::from(key.as_str(),),);
+
+        let mut objects = Vec::new();
+        let mut listing = self.store.list(prefix.as_ref(),);
+        while let Some(meta,) = listing.next().await {
+            let meta = meta.map_err(|e| store_error(url, e,),)?;
+            objects.push(self.object(&meta,),);
+        }
+        // Listing only matches whole path segments, so a URL naming one object lists nothing
+        if objects.is_empty()
+            && let Some(prefix,) = &prefix
+            && let Ok(meta,) = self.store.head(prefix,).await
+        {
+            objects.push(self.object(&meta,),);
+        }
+        objects.sort_by(|a, b| a.url.cmp(&b.url,),);
+        Ok(objects,)
+    }
+
+    /// Streams the object at `url` chunk by chunk into a temporary file that keeps the key's
+    /// extensions, so the file is read by the same reader as a local copy. Only one chunk is
+    /// held in memory, and the file is removed when the returned path drops.
+    pub async fn fetch(&self, url: &str,) -> Result<tempfile::TempPath,> {
+        let key = object_key(url,)?;
+        let name = key.rsplit('/',).next().unwrap_or_default();
+        let suffix = name.find('.',).map_or("", |dot| &name[dot..],);
+        let (file, path,) = tempfile::Builder::new().suffix(suffix,).tempfile()?.into_parts();
+
+        let mut chunks = self
+            .store
+            .get(&ObjectPath::from(key.as_str(),),)
+            .await
+            .map_err(|e| store_error(url, e,),)?
+            .into_stream();
+        let mut out = tokio::fs::File::from_std(file,);
+        while let Some(chunk,) = chunks.next().await {
+            out.write_all(&chunk.map_err(|e| store_error(url, e,),)?,).await?;
+        }
+        out.flush().await?;
+        Ok(path,)
+    }
+
+    fn object(&self, meta: &ObjectMeta,) -> RemoteObject {
+        RemoteObject {
+            url:  format!("{}/{}", self.bucket, meta.location),
+            size: meta.size,
+        }
+    }
+}
+
+fn parse(url: &str,) -> Result<Url,> {
+    Url::parse(url,).map_err(|e| {
+        IngestorError::ConfigurationError(format!("Invalid object storage URL {}: {}", url, e),)
+    },)
+}
+
+/// Object key (or prefix) of `url`, without surrounding slashes.
+fn object_key(url: &str,) -> Result<String,> {
+    Ok(parse(url,)?.path().trim_matches('/',).to_string(),)
+}
+
+fn store_error(url: &str, e: object_store::Error,) -> IngestorError {
+    IngestorError::ConnectionError(format!("Failed to read {}: {}", url, e),)
+}
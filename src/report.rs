@@ -0,0 +1,333 @@
+// nc_ingestor/src/report.rs
+// The `--report` written at the end of a run, `--progress-file` snapshots and the
+// `--dead-letter` file.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::Serialize;
+
+use crate::error::{IngestorError, Result};
+use crate::schema_builder::SchemaDiff;
+
+#[derive(Serialize,)]
+pub struct ProcessingError {
+    pub path:   String,
+    pub error:  String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String,>,
+}
+
+#[derive(Serialize, Default,)]
+pub struct TargetReport {
+    pub success_count: usize,
+    pub failure_count: usize,
+}
+
+#[derive(Serialize,)]
+pub struct SkippedFile {
+    pub path:   String,
+    pub reason: String,
+}
+
+/// A record skipped or truncated by `--max-record-size`.
+#[derive(Serialize,)]
+pub struct OversizedEntry {
+    pub path:   String,
+    pub record: usize,
+    pub size:   usize,
+    pub action: &'static str,
+}
+
+/// `--retry-budget` and how much of it the run used.
+#[derive(Serialize,)]
+pub struct RetryBudgetUsage {
+    pub budget:    u64,
+    pub used:      u64,
+    /// A transient error was failed because no retry was left
+    pub exhausted: bool,
+}
+
+/// `ingestion_report.json`. Per-file lists are kept in completion order until
+/// [`Report::sort_by_path`].
+#[derive(Serialize, Default,)]
+pub struct Report {
+    pub total_files:      usize,
+    pub success_count:    usize,
+    pub failure_count:    usize,
+    pub skipped_count:    usize,
+    /// Set when `--timeout-secs` cut the run short
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub timed_out:        bool,
+    pub errors:           Vec<ProcessingError,>,
+    pub skipped:          Vec<SkippedFile,>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub oversized:        Vec<OversizedEntry,>,
+    /// Path -> records dropped by `--dedupe-within-file`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub duplicates:       BTreeMap<String, usize,>,
+    /// Path -> columns dropped by `--drop-null-columns`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub null_columns:     BTreeMap<String, Vec<String,>,>,
+    /// Path -> records left out by `--on-empty-embedding skip`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub empty_embeddings: BTreeMap<String, u64,>,
+    /// Per-file durations
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files:            Vec<FileTiming,>,
+    /// Per-target outcomes for `pipeline` runs
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub targets:          BTreeMap<String, TargetReport,>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_budget:     Option<RetryBudgetUsage,>,
+    /// Error of `--post-sql`, which doesn't fail the run unless `--post-sql-strict` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_sql_error:   Option<String,>,
+    /// Provider -> batches it embedded, when `--embedder` lists more than one
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub embedders:        BTreeMap<String, u64,>,
+    /// Tables compared by `--dry-run-schema-diff`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub schema_diffs:     Vec<SchemaDiff,>,
+}
+
+impl Report {
+    /// Orders the per-file lists by path (then target or record), so reports of the same input
+    /// don't depend on the order in which concurrent files finished. Sorts are stable.
+    pub fn sort_by_path(&mut self,) {
+        self.errors.sort_by(|a, b| (&a.path, &a.target,).cmp(&(&b.path, &b.target,),),);
+        self.skipped.sort_by(|a, b| a.path.cmp(&b.path,),);
+        self.oversized.sort_by(|a, b| (&a.path, a.record,).cmp(&(&b.path, b.record,),),);
+        self.files.sort_by(|a, b| a.path.cmp(&b.path,),);
+    }
+
+    /// Writes the report as JSON, gzipped with `compress`, through a temporary file and a rename,
+    /// so readers never see a partially written report.
+    pub fn write(&self, path: &Path, compress: bool,) -> Result<(),> {
+        let json = serde_json::to_string_pretty(self,).map_err(|e| {
+            IngestorError::Other(format!("Failed to serialize error report: {}", e),)
+        },)?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp",);
+        let bytes = if compress { gzip(json.as_bytes(),) } else { Ok(json.into_bytes(),) };
+        bytes
+            .and_then(|bytes| std::fs::write(&tmp, bytes,),)
+            .and_then(|_| std::fs::rename(&tmp, path,),)
+            .map_err(|e| {
+                IngestorError::Other(format!("Failed to write {}: {}", path.display(), e),)
+            },)
+    }
+}
+
+/// When to write intermediate report snapshots (`--report-every`, `--report-interval-secs`), so
+/// a killed run still leaves a recent report.
+#[derive(Debug, Clone, Copy,)]
+pub struct FlushPolicy {
+    pub every_files: Option<usize,>,
+    pub interval:    Option<Duration,>,
+}
+
+impl FlushPolicy {
+    /// Whether a snapshot is due with `processed` files done, given the file count and time of
+    /// the last one.
+    pub fn is_due(&self, processed: usize, last: (usize, Instant,),) -> bool {
+        self.every_files.is_some_and(|n| processed >= last.0 + n,)
+            || self.interval.is_some_and(|d| last.1.elapsed() >= d,)
+    }
+}
+
+/// `--progress-file` snapshot: where the run stands, for dashboards polling during the run.
+#[derive(Serialize,)]
+pub struct Progress {
+    pub updated_at:      String,
+    /// Files found for this run, once listing finished
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files_total:     Option<usize,>,
+    pub files_done:      usize,
+    pub success_count:   usize,
+    pub failure_count:   usize,
+    pub skipped_count:   usize,
+    /// Records written by finished files; for pipeline runs, the most any target wrote per file
+    pub records:         u64,
+    pub elapsed_secs:    f64,
+    /// Records per second since the previous snapshot
+    pub records_per_sec: f64,
+    pub files_per_sec:   f64,
+    pub timed_out:       bool,
+    pub finished:        bool,
+}
+
+impl Progress {
+    /// Snapshot of `report` with `records` written `elapsed` into the run. `previous` is the last
+    /// snapshot's record count and time, for the rate.
+    pub fn new(
+        report: &Report,
+        files_total: Option<usize,>,
+        records: u64,
+        elapsed: Duration,
+        previous: (u64, Instant,),
+        finished: bool,
+    ) -> Self {
+        let elapsed = elapsed.as_secs_f64();
+        let interval = previous.1.elapsed().as_secs_f64();
+        Self {
+            updated_at: chrono::Utc::now().to_rfc3339(),
+            files_total,
+            files_done: report.total_files,
+            success_count: report.success_count,
+            failure_count: report.failure_count,
+            skipped_count: report.skipped_count,
+            records,
+            elapsed_secs: elapsed,
+            records_per_sec: if interval > 0.0 {
+                records.saturating_sub(previous.0,) as f64 / interval
+            } else {
+                0.0
+            },
+            files_per_sec: if elapsed > 0.0 { report.total_files as f64 / elapsed } else { 0.0 },
+            timed_out: report.timed_out,
+            finished,
+        }
+    }
+
+    /// Writes the snapshot through a temporary file and a rename, so a polling reader never sees
+    /// a partial file.
+    pub fn write(&self, path: &Path,) -> Result<(),> {
+        let json = serde_json::to_string_pretty(self,).map_err(|e| {
+            IngestorError::Other(format!("Failed to serialize progress snapshot: {}", e),)
+        },)?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp",);
+        std::fs::write(&tmp, json,).and_then(|_| std::fs::rename(&tmp, path,),).map_err(|e| {
+            IngestorError::Other(format!("Failed to write {}: {}", path.display(), e),)
+        },)
+    }
+}
+
+/// How long one file took, from when it was picked up until its records were written.
+#[derive(Debug, Serialize,)]
+pub struct FileTiming {
+    pub path:        String,
+    pub started_at:  String,
+    pub finished_at: String,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub records:     Option<u64,>,
+    /// Took at least `--slow-file-threshold-secs`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub slow:        bool,
+}
+
+/// Start of a file's processing, for [`FileTiming`].
+pub struct FileClock {
+    started_at: chrono::DateTime<chrono::Utc,>,
+    started:    Instant,
+}
+
+impl FileClock {
+    pub fn start() -> Self {
+        Self { started_at: chrono::Utc::now(), started: Instant::now(), }
+    }
+
+    /// Stops the clock for `path`. `records` is the number written, if known; the file is slow
+    /// when it took at least `slow_threshold`.
+    pub fn finish(
+        self,
+        path: &str,
+        records: Option<u64,>,
+        slow_threshold: Option<Duration,>,
+    ) -> FileTiming {
+        let elapsed = self.started.elapsed();
+        FileTiming {
+            path: path.to_string(),
+            started_at: self.started_at.to_rfc3339(),
+            finished_at: chrono::Utc::now().to_rfc3339(),
+            duration_ms: elapsed.as_millis() as u64,
+            records,
+            slow: slow_threshold.is_some_and(|threshold| elapsed >= threshold,),
+        }
+    }
+}
+
+/// `bytes` as a gzip stream, for `--compress-output`.
+pub fn gzip(bytes: &[u8],) -> std::io::Result<Vec<u8,>,> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default(),);
+    encoder.write_all(bytes,)?;
+    encoder.finish()
+}
+
+/// `--dead-letter`: records left out of the run, one JSON object per line with the file, the
+/// record number and the record. With `--compress-output` the lines are gzipped as they are
+/// written and `.gz` is appended to the file name. Share one across all files of the run and
+/// [`DeadLetter::finish`] it once they are done.
+pub struct DeadLetter {
+    path: PathBuf,
+    out:  Mutex<DeadLetterOut,>,
+}
+
+enum DeadLetterOut {
+    Plain(BufWriter<File,>,),
+    Gzip(GzEncoder<BufWriter<File,>,>,),
+}
+
+impl DeadLetter {
+    /// Creates (or truncates) the file at `path`, or at `path.gz` when `compress` is set.
+    pub fn create(path: &Path, compress: bool,) -> Result<Self,> {
+        let path = if compress {
+            let mut name = path.as_os_str().to_owned();
+            name.push(".gz",);
+            PathBuf::from(name,)
+        } else {
+            path.to_path_buf()
+        };
+        let file = File::create(&path,).map(BufWriter::new,).map_err(|e| {
+            IngestorError::Other(format!("Failed to create {}: {}", path.display(), e),)
+        },)?;
+        let out = if compress {
+            DeadLetterOut::Gzip(GzEncoder::new(file, Compression::default(),),)
+        } else {
+            DeadLetterOut::Plain(file,)
+        };
+        Ok(Self { path, out: Mutex::new(out,), },)
+    }
+
+    /// Where the records go.
+    pub fn path(&self,) -> &Path {
+        &self.path
+    }
+
+    /// Appends the `record`-th record of `file`.
+    pub fn write(&self, file: &str, record: usize, data: &serde_json::Value,) -> Result<(),> {
+        let mut line =
+            serde_json::json!({ "path": file, "record": record, "data": data }).to_string();
+        line.push('\n',);
+        let mut out = self.out.lock().unwrap();
+        let written = match &mut *out {
+            DeadLetterOut::Plain(file,) => file.write_all(line.as_bytes(),),
+            DeadLetterOut::Gzip(encoder,) => encoder.write_all(line.as_bytes(),),
+        };
+        written.map_err(|e| self.write_error(e,),)
+    }
+
+    /// Flushes the records written so far and, when compressed, ends the gzip stream.
+    pub fn finish(&self,) -> Result<(),> {
+        let mut out = self.out.lock().unwrap();
+        let finished = match &mut *out {
+            DeadLetterOut::Plain(file,) => file.flush(),
+            DeadLetterOut::Gzip(encoder,) => {
+                encoder.try_finish().and_then(|_| encoder.get_mut().flush(),)
+            },
+        };
+        finished.map_err(|e| self.write_error(e,),)
+    }
+
+    fn write_error(&self, e: std::io::Error,) -> IngestorError {
+        IngestorError::Other(format!("Failed to write {}: {}", self.path.display(), e),)
+    }
+}
@@ -1,25 +1,198 @@
 use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
 
 use backoff::ExponentialBackoff;
-use backoff::future::retry;
+use backoff::backoff::Backoff;
+use backoff::future::retry_notify;
 use tracing::warn;
 
 use crate::error::{IngestorError, Result};
+use crate::telemetry;
+
+/// Retries shared by every operation of a run, across files (`--retry-budget`). Once they are
+/// used up, further transient errors fail like permanent ones.
+#[derive(Debug,)]
+pub struct RetryBudget {
+    /// `u64::MAX` is unlimited
+    limit:     AtomicU64,
+    used:      AtomicU64,
+    /// Set once a transient error was refused a retry
+    exhausted: AtomicBool,
+}
+
+/// The budget every retried operation draws from.
+static RUN_BUDGET: RetryBudget = RetryBudget::new(None,);
+
+/// The run's budget, unlimited unless `--retry-budget` set it.
+pub fn run_budget() -> &'static RetryBudget {
+    &RUN_BUDGET
+}
+
+impl RetryBudget {
+    pub const fn new(limit: Option<u64,>,) -> Self {
+        let limit = match limit {
+            Some(limit,) => limit,
+            None => u64::MAX,
+        };
+        RetryBudget {
+            limit:     AtomicU64::new(limit,),
+            used:      AtomicU64::new(0,),
+            exhausted: AtomicBool::new(false,),
+        }
+    }
+
+    /// Replaces the limit and starts counting from zero. `None` lifts the cap.
+    pub fn set_limit(&self, limit: Option<u64,>,) {
+        self.limit.store(limit.unwrap_or(u64::MAX,), Ordering::Relaxed,);
+        self.used.store(0, Ordering::Relaxed,);
+        self.exhausted.store(false, Ordering::Relaxed,);
+    }
+
+    pub fn limit(&self,) -> Option<u64,> {
+        Some(self.limit.load(Ordering::Relaxed,),).filter(|&limit| limit != u64::MAX,)
+    }
+
+    /// Retries taken so far.
+    pub fn used(&self,) -> u64 {
+        self.used.load(Ordering::Relaxed,)
+    }
+
+    /// Whether a transient error has gone unretried because the budget ran out.
+    pub fn is_exhausted(&self,) -> bool {
+        self.exhausted.load(Ordering::Relaxed,)
+    }
+
+    /// Takes one retry, or returns false when none is left.
+    pub fn take(&self,) -> bool {
+        let limit = self.limit.load(Ordering::Relaxed,);
+        let taken = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                (used < limit).then_some(used + 1,)
+            },)
+            .is_ok();
+        if !taken {
+            self.exhausted.store(true, Ordering::Relaxed,);
+        }
+        taken
+    }
+
+    /// Wraps `backoff` so each retry it schedules is taken from this budget. Nothing is charged
+    /// when `backoff` itself gives up, and once the budget is used up no retry is scheduled.
+    pub fn backoff<B: Backoff,>(&self, backoff: B,) -> BudgetedBackoff<'_, B,> {
+        BudgetedBackoff { budget: self, backoff, }
+    }
+}
+
+/// A [`Backoff`] drawing its retries from a [`RetryBudget`].
+pub struct BudgetedBackoff<'a, B,> {
+    budget:  &'a RetryBudget,
+    backoff: B,
+}
+
+impl<B: Backoff,> Backoff for BudgetedBackoff<'_, B,> {
+    fn reset(&mut self,) {
+        self.backoff.reset();
+    }
+
+    fn next_backoff(&mut self,) -> Option<Duration,> {
+        let delay = self.backoff.next_backoff()?;
+        if !self.budget.take() {
+            warn!("Retry budget exhausted, not retrying");
+            return None;
+        }
+        telemetry::record_retry();
+        Some(delay,)
+    }
+}
+
+/// Retries `operation` with the default backoff, charging the run's budget per retry.
+async fn retry<F, Fut, T,>(operation: F,) -> Result<T,>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<T, backoff::Error<IngestorError,>,>,>,
+{
+    let backoff = RUN_BUDGET.backoff(ExponentialBackoff::default(),);
+    retry_notify(backoff, operation, |err: IngestorError, _: Duration| {
+        warn!("Transient error encountered, retrying: {}", err);
+    },)
+    .await
+}
 
 pub async fn execute_with_retry<F, Fut, T,>(operation: F,) -> Result<T,>
 where
     F: Fn() -> Fut,
     Fut: Future<Output = std::result::Result<T, backoff::Error<IngestorError,>,>,>,
 {
-    let backoff = ExponentialBackoff::default();
+    retry(operation,).await
+}
+
+/// Like [`execute_with_retry`], but gives up after `retries` retries. Used while establishing
+/// connections, so a misconfigured URI fails within seconds instead of retrying for the whole
+/// backoff window. The final attempt's error is returned as is, without charging the budget.
+pub async fn execute_with_connection_retry<F, Fut, T,>(retries: u32, operation: F,) -> Result<T,>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = std::result::Result<T, backoff::Error<IngestorError,>,>,>,
+{
+    let attempts = AtomicU32::new(0,);
+
+    retry(|| {
+        let attempt = attempts.fetch_add(1, Ordering::Relaxed,);
+        let result = operation();
+        async move {
+            result.await.map_err(|e| match e {
+                backoff::Error::Transient { err, .. } if attempt >= retries => {
+                    backoff::Error::permanent(err,)
+                },
+                other => other,
+            },)
+        }
+    },)
+    .await
+}
+
+/// Like [`execute_with_retry`], but after `reconnect_after` consecutive transient failures of
+/// `operation` runs `reconnect` to rebuild the backend's client before the next attempt, for
+/// connections that went stale in a way retrying on them never recovers from (e.g. a failover).
+/// A failed reconnect is retried like the operation. `None` or 0 never reconnects.
+pub async fn execute_with_reconnect<F, Fut, T, R, RFut,>(
+    reconnect_after: Option<u32,>,
+    reconnect: R,
+    operation: F,
+) -> Result<T,>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = std::result::Result<T, backoff::Error<IngestorError,>,>,>,
+    R: Fn() -> RFut,
+    RFut: Future<Output = Result<(),>,>,
+{
+    let Some(threshold,) = reconnect_after.filter(|&k| k > 0,) else {
+        return execute_with_retry(operation,).await;
+    };
+    let failures = AtomicU32::new(0,);
+    let (failures, operation, reconnect,) = (&failures, &operation, &reconnect,);
 
-    retry(backoff, operation,).await
+    execute_with_retry(move || async move {
+        if failures.load(Ordering::Relaxed,) >= threshold {
+            warn!("{} consecutive transient failures, rebuilding the client", threshold);
+            reconnect().await.map_err(transient_error,)?;
+            failures.store(0, Ordering::Relaxed,);
+        }
+        let result = operation().await;
+        if matches!(result, Err(backoff::Error::Transient { .. })) {
+            failures.fetch_add(1, Ordering::Relaxed,);
+        }
+        result
+    },)
+    .await
 }
 
 /// Helper to wrap an IngestorError into a backoff::Error.
 /// Decisions on what is transient vs permanent should be made here or at call site.
+/// The run's `--retry-budget` is only charged once a retry is actually scheduled.
 pub fn transient_error(err: IngestorError,) -> backoff::Error<IngestorError,> {
-    warn!("Transient error encountered, retrying: {}", err);
     backoff::Error::transient(err,)
 }
 
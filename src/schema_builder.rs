@@ -1,15 +1,297 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
 
 use nc_schema::DataType;
+use tracing::{info, warn};
 
 pub enum SqlDialect {
     Postgres,
     Sqlite,
 }
 
+/// Postgres declarative partitioning strategy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize,)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionStrategy {
+    #[default]
+    Range,
+    List,
+    Hash,
+}
+
+impl PartitionStrategy {
+    fn as_sql(&self,) -> &'static str {
+        match self {
+            PartitionStrategy::Range => "RANGE",
+            PartitionStrategy::List => "LIST",
+            PartitionStrategy::Hash => "HASH",
+        }
+    }
+}
+
+/// Default number of partitions created for hash partitioning.
+pub const DEFAULT_HASH_PARTITIONS: u32 = 4;
+
+/// Creates the target table as a partitioned parent so Postgres routes rows on insert.
+#[derive(Debug, Clone, serde::Deserialize,)]
+#[serde(deny_unknown_fields)]
+pub struct PartitionConfig {
+    pub column:          String,
+    #[serde(default)]
+    pub strategy:        PartitionStrategy,
+    /// Create a `<table>_default` catch-all partition so COPY into the parent always succeeds.
+    /// Not available for hash partitioning.
+    pub create_default:  bool,
+    /// Partitions `<table>_p0` .. `<table>_p{N-1}` created for hash partitioning, which covers
+    /// every value without a default partition
+    #[serde(default = "default_hash_partitions")]
+    pub hash_partitions: u32,
+}
+
+fn default_hash_partitions() -> u32 {
+    DEFAULT_HASH_PARTITIONS
+}
+
+/// A pgvector `vector(dimensions)` column added to the generated Postgres DDL, independent of
+/// the inferred schema.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize,)]
+#[serde(deny_unknown_fields)]
+pub struct VectorColumn {
+    pub name:       String,
+    pub dimensions: u64,
+}
+
+/// SQL column type forced on a field by `--type-override`, instead of the one inferred from its
+/// values. Parsed case-insensitively from `TEXT`, `BIGINT`, `DOUBLE`, `BOOLEAN` or `JSONB` (or
+/// the aliases `STRING`, `INTEGER`, `FLOAT`, `BOOL`, `JSON`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize,)]
+#[serde(try_from = "String")]
+pub enum ColumnType {
+    Text,
+    Bigint,
+    Double,
+    Boolean,
+    Jsonb,
+}
+
+impl ColumnType {
+    /// The inferred type each dialect maps back to this column type.
+    pub fn data_type(self,) -> DataType {
+        match self {
+            ColumnType::Text => DataType::String,
+            ColumnType::Bigint => DataType::Integer,
+            ColumnType::Double => DataType::Float,
+            ColumnType::Boolean => DataType::Boolean,
+            ColumnType::Jsonb => DataType::Object(Default::default(),),
+        }
+    }
+}
+
+impl std::fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_,>,) -> std::fmt::Result {
+        f.write_str(match self {
+            ColumnType::Text => "TEXT",
+            ColumnType::Bigint => "BIGINT",
+            ColumnType::Double => "DOUBLE",
+            ColumnType::Boolean => "BOOLEAN",
+            ColumnType::Jsonb => "JSONB",
+        },)
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = String;
+
+    fn from_str(s: &str,) -> Result<Self, Self::Err,> {
+        match s.trim().to_ascii_uppercase().as_str() {
+            "TEXT" | "STRING" => Ok(ColumnType::Text,),
+            "BIGINT" | "INTEGER" => Ok(ColumnType::Bigint,),
+            "DOUBLE" | "FLOAT" => Ok(ColumnType::Double,),
+            "BOOLEAN" | "BOOL" => Ok(ColumnType::Boolean,),
+            "JSONB" | "JSON" => Ok(ColumnType::Jsonb,),
+            _ => Err(format!(
+                "invalid column type `{}`: expected TEXT, BIGINT, DOUBLE, BOOLEAN or JSONB",
+                s
+            ),),
+        }
+    }
+}
+
+impl TryFrom<String,> for ColumnType {
+    type Error = String;
+
+    fn try_from(s: String,) -> Result<Self, Self::Error,> {
+        s.parse()
+    }
+}
+
+/// Name of the generated `tsvector` column added by [`SqlSchemaBuilder::with_fulltext`].
+pub const FULLTEXT_COLUMN: &str = "fulltext";
+
+/// Name of the FTS5 table [`SqlSchemaBuilder::build_fts5`] creates for `table_name`.
+pub fn fts5_table_name(table_name: &str,) -> String {
+    format!("{}_fts", table_name)
+}
+
+/// `COMMENT ON` text for created tables, for data catalogs. Column comments are keyed by column
+/// name, or by source field name before `--map`. Only honoured for the Postgres dialect.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize,)]
+#[serde(default, deny_unknown_fields)]
+pub struct TableComments {
+    pub table:   Option<String,>,
+    pub columns: HashMap<String, String,>,
+}
+
+/// How the table `SqlSchemaBuilder` would create differs from an existing one
+/// (`--dry-run-schema-diff`). Columns are compared by name after mappings, and types by their
+/// base name, ignoring case and modifiers such as `(384)`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize,)]
+pub struct SchemaDiff {
+    pub table:      String,
+    /// Whether the table exists. A missing table would simply be created.
+    pub exists:     bool,
+    /// Inferred columns the table lacks, with the type they would get
+    pub missing:    Vec<(String, String,),>,
+    /// Columns on both sides with different types, as `(column, table type, inferred type)`
+    pub mismatched: Vec<(String, String, String,),>,
+    /// Table columns the data doesn't provide, left to their defaults
+    pub extra:      Vec<(String, String,),>,
+}
+
+impl SchemaDiff {
+    /// Whether the data fits the table as it is.
+    pub fn is_empty(&self,) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// The distinct [`SchemaDiff`]s an ingestor found, for the run report. Files that share a table
+/// and schema yield the same diff, which is kept once.
+#[derive(Debug, Default,)]
+pub struct SchemaDiffLog {
+    diffs: std::sync::Mutex<Vec<SchemaDiff,>,>,
+}
+
+impl SchemaDiffLog {
+    /// Logs `diff`, as a warning when an existing table differs, and keeps it if it's new.
+    pub fn record(&self, diff: SchemaDiff,) {
+        if diff.exists && !diff.is_empty() {
+            warn!("{}", diff);
+        } else {
+            info!("{}", diff);
+        }
+        let mut diffs = self.diffs.lock().unwrap();
+        if !diffs.contains(&diff,) {
+            diffs.push(diff,);
+        }
+    }
+
+    /// The recorded diffs, ordered by table.
+    pub fn diffs(&self,) -> Vec<SchemaDiff,> {
+        let mut diffs = self.diffs.lock().unwrap().clone();
+        diffs.sort_by(|a, b| a.table.cmp(&b.table,),);
+        diffs
+    }
+}
+
+impl std::fmt::Display for SchemaDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_,>,) -> std::fmt::Result {
+        if !self.exists {
+            return write!(
+                f,
+                "Table \"{}\" does not exist and would be created with {} column(s)",
+                self.table,
+                self.missing.len()
+            );
+        }
+        if self.is_empty() {
+            return write!(f, "Table \"{}\" matches the inferred schema", self.table);
+        }
+        write!(f, "Table \"{}\" differs from the inferred schema:", self.table)?;
+        for (column, sql_type,) in &self.missing {
+            write!(f, "\n  + \"{}\" {} (missing from the table)", column, sql_type)?;
+        }
+        for (column, live, inferred,) in &self.mismatched {
+            write!(f, "\n  ~ \"{}\" is {} in the table, inferred {}", column, live, inferred)?;
+        }
+        for (column, sql_type,) in &self.extra {
+            write!(f, "\n  - \"{}\" {} (not in the data)", column, sql_type)?;
+        }
+        Ok((),)
+    }
+}
+
+impl TableComments {
+    pub fn is_empty(&self,) -> bool {
+        self.table.is_none() && self.columns.is_empty()
+    }
+}
+
+/// Order of the columns in generated tables and in the matching INSERT/COPY column lists.
+///
+/// Parsed from `alpha`, `source` or `custom:a,b,c`. Source order is [`RecordSet::columns`]: the
+/// CSV or Excel header, or an Avro writer schema; custom lists name fields (or their `--map`
+/// targets) and any columns they leave out follow alphabetically.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize,)]
+#[serde(try_from = "String")]
+pub enum ColumnOrder {
+    #[default]
+    Alpha,
+    Source,
+    Custom(Vec<String,>,),
+}
+
+impl FromStr for ColumnOrder {
+    type Err = String;
+
+    fn from_str(s: &str,) -> Result<Self, Self::Err,> {
+        match s {
+            "alpha" => Ok(ColumnOrder::Alpha,),
+            "source" => Ok(ColumnOrder::Source,),
+            _ => match s.strip_prefix("custom:",) {
+                Some(list,) => Ok(ColumnOrder::Custom(
+                    list.split(',',).map(|c| c.trim().to_string(),).collect(),
+                ),),
+                None => Err(format!(
+                    "invalid column order `{}`: expected alpha, source or custom:a,b,c",
+                    s
+                ),),
+            },
+        }
+    }
+}
+
+impl TryFrom<String,> for ColumnOrder {
+    type Error = String;
+
+    fn try_from(s: String,) -> Result<Self, Self::Error,> {
+        s.parse()
+    }
+}
+
+/// A column of a child table pointing at its parent row, declared
+/// `NOT NULL REFERENCES <table> (<referenced>) ON DELETE CASCADE`.
+#[derive(Debug, Clone, PartialEq, Eq,)]
+pub struct ForeignKey {
+    pub column:     String,
+    pub table:      String,
+    pub referenced: String,
+}
+
 pub struct SqlSchemaBuilder {
-    dialect:  SqlDialect,
-    mappings: HashMap<String, String,>,
+    dialect:        SqlDialect,
+    mappings:       HashMap<String, String,>,
+    partition:      Option<PartitionConfig,>,
+    vector_column:  Option<VectorColumn,>,
+    fulltext:       Vec<String,>,
+    type_overrides: HashMap<String, ColumnType,>,
+    column_order:   ColumnOrder,
+    source_columns: Vec<String,>,
+    collation:      Option<String,>,
+    comments:       TableComments,
+    primary_key:    Option<String,>,
+    foreign_key:    Option<ForeignKey,>,
+    unlogged:       bool,
 }
 
 impl SqlSchemaBuilder {
@@ -17,9 +299,87 @@ impl SqlSchemaBuilder {
         Self {
             dialect,
             mappings: mappings.unwrap_or_default(),
+            partition: None,
+            vector_column: None,
+            fulltext: Vec::new(),
+            type_overrides: HashMap::new(),
+            column_order: ColumnOrder::default(),
+            source_columns: Vec::new(),
+            collation: None,
+            comments: TableComments::default(),
+            primary_key: None,
+            foreign_key: None,
+            unlogged: false,
         }
     }
 
+    /// Orders columns by `order`; `source_columns` is the file's own field order, used by
+    /// [`ColumnOrder::Source`].
+    pub fn with_column_order(mut self, order: ColumnOrder, source_columns: Vec<String,>,) -> Self {
+        self.column_order = order;
+        self.source_columns = source_columns;
+        self
+    }
+
+    /// Declares string columns with `COLLATE <collation>` on table creation, e.g. `"C"` or
+    /// `"und-x-icu"` in Postgres and `NOCASE` in SQLite. JSON stored as text keeps the default.
+    pub fn with_collation(mut self, collation: Option<String,>,) -> Self {
+        self.collation = collation;
+        self
+    }
+
+    /// Adds a pgvector column on table creation. Only honoured for the Postgres dialect.
+    pub fn with_vector_column(mut self, vector_column: Option<VectorColumn,>,) -> Self {
+        self.vector_column = vector_column;
+        self
+    }
+
+    /// Makes the text of `fields` (source field or column names) searchable. Postgres tables get
+    /// a generated `tsvector` column on creation, indexed by
+    /// [`SqlSchemaBuilder::build_fulltext_index`]; SQLite tables get the FTS5 table of
+    /// [`SqlSchemaBuilder::build_fts5`].
+    pub fn with_fulltext(mut self, fields: Vec<String,>,) -> Self {
+        self.fulltext = fields;
+        self
+    }
+
+    /// Declares the listed fields (source field or column names) with the given type instead of
+    /// the inferred one.
+    pub fn with_type_overrides(mut self, overrides: HashMap<String, ColumnType,>,) -> Self {
+        self.type_overrides = overrides;
+        self
+    }
+
+    /// Comments emitted by [`SqlSchemaBuilder::build_comments`].
+    pub fn with_comments(mut self, comments: TableComments,) -> Self {
+        self.comments = comments;
+        self
+    }
+
+    /// Declares `column` (a column name, after mappings) the table's primary key on creation.
+    pub fn with_primary_key(mut self, column: Option<String,>,) -> Self {
+        self.primary_key = column;
+        self
+    }
+
+    /// Declares a column referencing a parent table on creation.
+    pub fn with_foreign_key(mut self, foreign_key: Option<ForeignKey,>,) -> Self {
+        self.foreign_key = foreign_key;
+        self
+    }
+
+    /// Creates `UNLOGGED` tables, which skip the WAL. Only honoured for the Postgres dialect.
+    pub fn with_unlogged(mut self, unlogged: bool,) -> Self {
+        self.unlogged = unlogged;
+        self
+    }
+
+    /// Emits `PARTITION BY` on table creation. Only honoured for the Postgres dialect.
+    pub fn with_partition(mut self, partition: Option<PartitionConfig,>,) -> Self {
+        self.partition = partition;
+        self
+    }
+
     pub fn map_type(&self, nc_type: &DataType,) -> String {
         match (nc_type, &self.dialect,) {
             (DataType::String, SqlDialect::Postgres,) => "TEXT".to_string(),
@@ -60,23 +420,164 @@ impl SqlSchemaBuilder {
         }
     }
 
+    /// Schema fields in table column order, before mappings. Fields not placed by the chosen
+    /// order follow alphabetically.
+    pub fn ordered_fields(&self, schema: &HashMap<String, DataType,>,) -> Vec<String,> {
+        let mut rest: Vec<&String,> = schema.keys().collect();
+        rest.sort();
+
+        let leading: Vec<&String,> = match &self.column_order {
+            ColumnOrder::Alpha => Vec::new(),
+            ColumnOrder::Source => self
+                .source_columns
+                .iter()
+                .filter_map(|c| schema.get_key_value(c,).map(|(key, _,)| key,),)
+                .collect(),
+            ColumnOrder::Custom(list,) => list
+                .iter()
+                .filter_map(|c| {
+                    rest.iter()
+                        .find(|key| **key == c || self.mappings.get(**key,) == Some(c,),)
+                        .copied()
+                },)
+                .collect(),
+        };
+        rest.retain(|key| !leading.contains(key,),);
+
+        let mut fields: Vec<String,> = Vec::new();
+        for key in leading.into_iter().chain(rest,) {
+            if !fields.contains(key,) {
+                fields.push(key.clone(),);
+            }
+        }
+        fields
+    }
+
+    /// Column names `build_create_table` would create for `schema`, after mappings.
+    pub fn column_names(&self, schema: &HashMap<String, DataType,>,) -> BTreeSet<String,> {
+        let mut columns: BTreeSet<String,> = schema
+            .keys()
+            .map(|key| self.mappings.get(key,).unwrap_or(key,).clone(),)
+            .collect();
+        if let (Some(vector,), SqlDialect::Postgres,) = (&self.vector_column, &self.dialect,) {
+            columns.insert(vector.name.clone(),);
+        }
+        if self.fulltext_expression(schema,).is_some() {
+            columns.insert(FULLTEXT_COLUMN.to_string(),);
+        }
+        columns
+    }
+
+    /// `(column, SQL type)` of each column `build_create_table` would create for `schema`, in
+    /// table order and after mappings.
+    pub fn column_types(&self, schema: &HashMap<String, DataType,>,) -> Vec<(String, String,),> {
+        let mut columns: Vec<(String, String,),> = self
+            .ordered_fields(schema,)
+            .into_iter()
+            .map(|key| {
+                let sql_type = self.map_type(&self.field_type(&key, &schema[&key],),);
+                (self.mappings.get(&key,).unwrap_or(&key,).clone(), sql_type,)
+            },)
+            .collect();
+        if let (Some(vector,), SqlDialect::Postgres,) = (&self.vector_column, &self.dialect,) {
+            columns.push((vector.name.clone(), format!("vector({})", vector.dimensions),),);
+        }
+        if self.fulltext_expression(schema,).is_some() {
+            columns.push((FULLTEXT_COLUMN.to_string(), "tsvector".to_string(),),);
+        }
+        columns
+    }
+
+    /// Compares the table `build_create_table` would create for `schema` with the existing
+    /// `live` columns of `table_name`, as `(column, declared type)`. An empty `live` means the
+    /// table doesn't exist.
+    pub fn diff(
+        &self,
+        table_name: &str,
+        schema: &HashMap<String, DataType,>,
+        live: &[(String, String,)],
+    ) -> SchemaDiff {
+        let expected = self.column_types(schema,);
+        let mut diff = SchemaDiff {
+            table: table_name.to_string(),
+            exists: !live.is_empty(),
+            ..SchemaDiff::default()
+        };
+        for (column, sql_type,) in &expected {
+            match live.iter().find(|(name, _,)| name == column,) {
+                None => diff.missing.push((column.clone(), sql_type.clone(),),),
+                Some((_, live_type,),) if base_type(live_type,) != base_type(sql_type,) => {
+                    diff.mismatched.push((column.clone(), live_type.clone(), sql_type.clone(),),)
+                },
+                Some(_,) => {},
+            }
+        }
+        diff.extra = live
+            .iter()
+            .filter(|(name, _,)| !expected.iter().any(|(column, _,)| column == name,),)
+            .cloned()
+            .collect();
+        diff
+    }
+
     pub fn build_create_table(
         &self,
         table_name: &str,
         schema: &HashMap<String, DataType,>,
     ) -> String {
-        let mut columns = Vec::new();
+        self.create_table(table_name, schema, true, true,)
+    }
 
-        // Sort keys for deterministic SQL generation
-        let mut keys: Vec<&String,> = schema.keys().collect();
-        keys.sort();
+    /// [`build_create_table`](Self::build_create_table) without `IF NOT EXISTS`, so it fails
+    /// with `duplicate_table` when the table exists. Claims a table name between concurrent
+    /// writers.
+    pub fn build_create_new_table(
+        &self,
+        table_name: &str,
+        schema: &HashMap<String, DataType,>,
+    ) -> String {
+        self.create_table(table_name, schema, true, false,)
+    }
+
+    /// [`build_create_table`](Self::build_create_table) without the primary and foreign keys,
+    /// and the `ALTER TABLE` statements adding them, for bulk loads that build the keys over the
+    /// loaded rows in one pass.
+    pub fn build_create_table_deferring_keys(
+        &self,
+        table_name: &str,
+        schema: &HashMap<String, DataType,>,
+    ) -> (String, Vec<String,>,) {
+        let columns = self.column_names(schema,);
+        let mut keys = Vec::new();
+        if let Some(column,) = self.primary_key.as_ref().filter(|c| columns.contains(*c,),) {
+            keys.push(format!("ALTER TABLE \"{}\" ADD PRIMARY KEY (\"{}\")", table_name, column),);
+        }
+        let foreign_key = self.foreign_key.as_ref().filter(|k| columns.contains(&k.column,),);
+        if let Some(foreign_key,) = foreign_key {
+            keys.push(format!(
+                "ALTER TABLE \"{0}\" ALTER COLUMN \"{1}\" SET NOT NULL, ADD FOREIGN KEY (\"{1}\") \
+                 REFERENCES \"{2}\" (\"{3}\") ON DELETE CASCADE",
+                table_name, foreign_key.column, foreign_key.table, foreign_key.referenced
+            ),);
+        }
+        (self.create_table(table_name, schema, false, true,), keys,)
+    }
 
-        for key in keys {
-            let nc_type = schema.get(key,).unwrap();
+    fn create_table(
+        &self,
+        table_name: &str,
+        schema: &HashMap<String, DataType,>,
+        keys: bool,
+        if_not_exists: bool,
+    ) -> String {
+        let mut columns = Vec::new();
+
+        for key in self.ordered_fields(schema,) {
+            let nc_type = &self.field_type(&key, &schema[&key],);
             let sql_type = self.map_type(nc_type,);
 
             // Apply renaming mapping if exists
-            let column_name = self.mappings.get(key,).unwrap_or(key,);
+            let column_name = self.mappings.get(&key,).unwrap_or(&key,);
 
             // Quote column name to handle reserved words
             let quoted_name = match self.dialect {
@@ -84,13 +585,267 @@ impl SqlSchemaBuilder {
                 SqlDialect::Sqlite => format!("`{}`", column_name),
             };
 
-            columns.push(format!("{} {}", quoted_name, sql_type),);
+            let mut column = format!("{} {}", quoted_name, sql_type);
+            if let Some(collation,) = &self.collation
+                && is_string_type(nc_type,)
+            {
+                let clause = match self.dialect {
+                    SqlDialect::Postgres => format!(" COLLATE \"{}\"", collation),
+                    SqlDialect::Sqlite => format!(" COLLATE {}", collation),
+                };
+                column.push_str(&clause,);
+            }
+            if keys && self.primary_key.as_ref() == Some(column_name,) {
+                column.push_str(" PRIMARY KEY",);
+            }
+            if keys
+                && let Some(foreign_key,) = &self.foreign_key
+                && foreign_key.column == *column_name
+            {
+                column.push_str(&format!(
+                    " NOT NULL REFERENCES \"{}\" (\"{}\") ON DELETE CASCADE",
+                    foreign_key.table, foreign_key.referenced
+                ),);
+            }
+            columns.push(column,);
         }
 
-        format!(
-            "CREATE TABLE IF NOT EXISTS \"{}\" ({})",
+        if let (Some(vector,), SqlDialect::Postgres,) = (&self.vector_column, &self.dialect,) {
+            columns.push(format!("\"{}\" vector({})", vector.name, vector.dimensions),);
+        }
+
+        if let Some(expression,) = self.fulltext_expression(schema,) {
+            columns.push(format!(
+                "\"{}\" tsvector GENERATED ALWAYS AS ({}) STORED",
+                FULLTEXT_COLUMN, expression
+            ),);
+        }
+
+        let unlogged = self.unlogged && matches!(self.dialect, SqlDialect::Postgres);
+        let mut ddl = format!(
+            "CREATE {}TABLE {}\"{}\" ({})",
+            if unlogged { "UNLOGGED " } else { "" },
+            if if_not_exists { "IF NOT EXISTS " } else { "" },
             table_name,
             columns.join(", ")
-        )
+        );
+
+        if let (Some(partition,), SqlDialect::Postgres,) = (&self.partition, &self.dialect,) {
+            let column_name = self.mappings.get(&partition.column,).unwrap_or(&partition.column,);
+            ddl.push_str(&format!(
+                " PARTITION BY {} (\"{}\")",
+                partition.strategy.as_sql(),
+                column_name
+            ),);
+        }
+
+        ddl
+    }
+
+    /// `COMMENT ON TABLE/COLUMN` statements for the table created from `schema`. Comments for
+    /// columns the schema doesn't have are left out. Empty for SQLite, which has no comments.
+    pub fn build_comments(
+        &self,
+        table_name: &str,
+        schema: &HashMap<String, DataType,>,
+    ) -> Vec<String,> {
+        if !matches!(self.dialect, SqlDialect::Postgres) {
+            return Vec::new();
+        }
+        let mut statements = Vec::new();
+        if let Some(comment,) = &self.comments.table {
+            statements.push(format!(
+                "COMMENT ON TABLE \"{}\" IS {}",
+                table_name,
+                quote_literal(comment,)
+            ),);
+        }
+        for key in self.ordered_fields(schema,) {
+            let column_name = self.mappings.get(&key,).unwrap_or(&key,);
+            let comment = self.comments.columns.get(column_name,);
+            if let Some(comment,) = comment.or_else(|| self.comments.columns.get(&key,),) {
+                statements.push(format!(
+                    "COMMENT ON COLUMN \"{}\".\"{}\" IS {}",
+                    table_name,
+                    column_name,
+                    quote_literal(comment,)
+                ),);
+            }
+        }
+        statements
+    }
+
+    /// `inferred`, unless `--type-override` forces a type on the field `key`. Overrides name
+    /// fields as in the records, before `--map`, like everywhere else they are applied.
+    fn field_type(&self, key: &str, inferred: &DataType,) -> DataType {
+        self.type_overrides
+            .get(key,)
+            .map_or_else(|| inferred.clone(), |column_type| column_type.data_type(),)
+    }
+
+    /// Table columns of the full-text fields present in `schema`, in the order they were given.
+    fn fulltext_columns(&self, schema: &HashMap<String, DataType,>,) -> Vec<String,> {
+        self.fulltext
+            .iter()
+            .filter_map(|field| {
+                schema
+                    .keys()
+                    .find(|key| *key == field || self.mappings.get(*key,) == Some(field,),)
+            },)
+            .map(|key| self.mappings.get(key,).unwrap_or(key,).clone(),)
+            .collect()
+    }
+
+    /// `to_tsvector` over the `--fulltext-fields` columns present in `schema`, each cast to text
+    /// and NULL-safe so one missing value doesn't blank the whole vector. `None` when no such
+    /// column exists or for SQLite.
+    fn fulltext_expression(&self, schema: &HashMap<String, DataType,>,) -> Option<String,> {
+        if !matches!(self.dialect, SqlDialect::Postgres) {
+            return None;
+        }
+        let parts: Vec<String,> = self
+            .fulltext_columns(schema,)
+            .iter()
+            .map(|column_name| format!("coalesce(\"{}\"::text, '')", column_name),)
+            .collect();
+        if parts.is_empty() {
+            return None;
+        }
+        Some(format!("to_tsvector('english', {})", parts.join(" || ' ' || ")),)
+    }
+
+    /// DDL for the GIN index over the generated `tsvector` column, if the table has one.
+    pub fn build_fulltext_index(
+        &self,
+        table_name: &str,
+        schema: &HashMap<String, DataType,>,
+    ) -> Option<String,> {
+        self.fulltext_expression(schema,)?;
+        Some(format!(
+            "CREATE INDEX IF NOT EXISTS \"{}_{}_idx\" ON \"{}\" USING GIN (\"{}\")",
+            table_name, FULLTEXT_COLUMN, table_name, FULLTEXT_COLUMN
+        ),)
+    }
+
+    /// DDL for an external-content FTS5 table `<table>_fts` over the full-text columns present
+    /// in `schema`, and the triggers that keep it in step with inserts, updates and deletes, so
+    /// `SELECT ... FROM <table>_fts WHERE <table>_fts MATCH 'term'` finds ingested rows. Empty
+    /// for Postgres or when none of the columns exist.
+    pub fn build_fts5(
+        &self,
+        table_name: &str,
+        schema: &HashMap<String, DataType,>,
+    ) -> Vec<String,> {
+        let columns = self.fulltext_columns(schema,);
+        if !matches!(self.dialect, SqlDialect::Sqlite) || columns.is_empty() {
+            return Vec::new();
+        }
+        let fts = fts5_table_name(table_name,);
+        let quoted: Vec<String,> = columns.iter().map(|c| format!("`{}`", c),).collect();
+        let values = |row: &str| {
+            columns.iter().map(|c| format!("{}.`{}`", row, c),).collect::<Vec<_,>>().join(", ",)
+        };
+        let insert = format!(
+            "INSERT INTO `{}`(rowid, {}) VALUES (new.rowid, {});",
+            fts,
+            quoted.join(", "),
+            values("new")
+        );
+        let delete = format!(
+            "INSERT INTO `{0}`(`{0}`, rowid, {1}) VALUES ('delete', old.rowid, {2});",
+            fts,
+            quoted.join(", "),
+            values("old")
+        );
+        vec![
+            format!(
+                "CREATE VIRTUAL TABLE IF NOT EXISTS `{}` USING fts5({}, content='{}', \
+                 content_rowid='rowid')",
+                fts,
+                quoted.join(", "),
+                table_name
+            ),
+            format!(
+                "CREATE TRIGGER IF NOT EXISTS `{0}_ai` AFTER INSERT ON `{1}` BEGIN {2} END",
+                fts, table_name, insert
+            ),
+            format!(
+                "CREATE TRIGGER IF NOT EXISTS `{0}_ad` AFTER DELETE ON `{1}` BEGIN {2} END",
+                fts, table_name, delete
+            ),
+            format!(
+                "CREATE TRIGGER IF NOT EXISTS `{0}_au` AFTER UPDATE ON `{1}` BEGIN {2} {3} END",
+                fts, table_name, delete, insert
+            ),
+        ]
+    }
+
+    /// DDL for the partitions created with a partitioned table: one per remainder for hash
+    /// partitioning, otherwise the catch-all partition if one should be created. Without either
+    /// the table has no partitions to route rows to until they are created by other means.
+    pub fn build_partitions(&self, table_name: &str,) -> Vec<String,> {
+        match (&self.partition, &self.dialect,) {
+            (Some(partition,), SqlDialect::Postgres,)
+                if partition.strategy == PartitionStrategy::Hash =>
+            {
+                let modulus = partition.hash_partitions.max(1,);
+                (0..modulus)
+                    .map(|remainder| {
+                        format!(
+                            "CREATE TABLE IF NOT EXISTS \"{0}_p{1}\" PARTITION OF \"{0}\" \
+                             FOR VALUES WITH (MODULUS {2}, REMAINDER {1})",
+                            table_name, remainder, modulus
+                        )
+                    },)
+                    .collect()
+            },
+            (Some(partition,), SqlDialect::Postgres,) if partition.create_default => {
+                vec![format!(
+                    "CREATE TABLE IF NOT EXISTS \"{}_default\" PARTITION OF \"{}\" DEFAULT",
+                    table_name, table_name
+                )]
+            },
+            _ => Vec::new(),
+        }
     }
+
+    /// The partition column (after mappings) when the table built from `schema` wouldn't have
+    /// it, so `PARTITION BY` would fail.
+    pub fn missing_partition_column(
+        &self,
+        schema: &HashMap<String, DataType,>,
+    ) -> Option<String,> {
+        let partition = self.partition.as_ref()?;
+        let column = self.mappings.get(&partition.column,).unwrap_or(&partition.column,);
+        (!self.column_names(schema,).contains(column,)).then(|| column.clone(),)
+    }
+}
+
+/// SQL string literal for `text`, with embedded quotes doubled.
+fn quote_literal(text: &str,) -> String {
+    format!("'{}'", text.replace('\'', "''",))
+}
+
+/// Lowercased type name without modifiers, so `vector(384)` matches `vector` and `TEXT` matches
+/// `text`.
+fn base_type(sql_type: &str,) -> String {
+    sql_type.split('(',).next().unwrap_or_default().trim().to_lowercase()
+}
+
+/// Whether `data_type` maps to a plain string column, nullable or not.
+fn is_string_type(data_type: &DataType,) -> bool {
+    match data_type {
+        DataType::String => true,
+        DataType::Union(variants,) => variants
+            .iter()
+            .find(|t| !matches!(t, DataType::Null),)
+            .is_some_and(is_string_type,),
+        _ => false,
+    }
+}
+
+/// Candidate name for the `attempt`-th table when `--rename-on-conflict` skips tables whose
+/// columns don't match: `base`, then `base_2`, `base_3`, ...
+pub fn suffixed_table_name(base: &str, attempt: usize,) -> String {
+    if attempt <= 1 { base.to_string() } else { format!("{}_{}", base, attempt) }
 }
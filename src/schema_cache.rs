@@ -0,0 +1,69 @@
+// nc_ingestor/src/schema_cache.rs
+// Reuses inferred schemas across CSV files that share the same header set.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use csv::StringRecord;
+use nc_reader::nc_reader_result::DataReaderResult;
+use nc_schema::DataType;
+
+use crate::csv_input::{self, CsvLayout, DuplicateColumns};
+use crate::error::Result;
+use crate::ingestor::RecordSet;
+
+/// Inferred schemas keyed by the sorted header set they were inferred from.
+#[derive(Default,)]
+pub struct SchemaCache {
+    schemas: Mutex<HashMap<Vec<String,>, HashMap<String, DataType,>,>,>,
+}
+
+impl SchemaCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cache key for a CSV file, read from its header row only. Column order doesn't matter.
+    pub fn key_for(path: &Path, layout: CsvLayout,) -> Result<Vec<String,>,> {
+        Self::key_of(&csv_input::read_header_row(path, layout.trim,)?, layout.on_duplicate,)
+    }
+
+    /// [`SchemaCache::key_for`] a header row that was already read.
+    pub fn key_of(headers: &StringRecord, policy: DuplicateColumns,) -> Result<Vec<String,>,> {
+        let mut headers = csv_input::header_names(headers, policy,)?;
+        headers.sort();
+        Ok(headers,)
+    }
+
+    pub fn get(&self, key: &[String],) -> Option<HashMap<String, DataType,>,> {
+        self.schemas.lock().unwrap().get(key,).cloned()
+    }
+
+    /// Stores the schema for `key` unless another file already did.
+    pub fn insert(&self, key: Vec<String,>, schema: HashMap<String, DataType,>,) {
+        self.schemas.lock().unwrap().entry(key,).or_insert(schema,);
+    }
+
+    /// Caches the schema nc_reader inferred for a CSV file that missed the cache and hands its
+    /// rows on as a [`RecordSet`], the shape files that hit the cache are read as, so backends
+    /// that store reader results whole (MongoDB) still write one document per row. Results
+    /// without an inferred schema are handed back unchanged.
+    pub fn fill(
+        &self,
+        key: Vec<String,>,
+        data: DataReaderResult,
+    ) -> std::result::Result<RecordSet, DataReaderResult,> {
+        match data {
+            DataReaderResult::Csv(csv_data, metadata,) => match csv_data.inferred_schema.clone() {
+                Some(schema,) => {
+                    self.insert(key, schema.clone(),);
+                    let rows = csv_data.nc_rows;
+                    Ok(RecordSet { rows, schema: Some(schema,), columns: Vec::new(), },)
+                },
+                None => Err(DataReaderResult::Csv(csv_data, metadata,),),
+            },
+            other => Err(other,),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+// nc_ingestor/src/script.rs
+// User-provided Rhai scripts run against every record before it reaches an ingestor.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use nc_reader::error::DataReaderError;
+use nc_reader::nc_reader_result::DataReaderResult;
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{AST, Dynamic, Engine, Scope};
+use serde_json::Value;
+
+use crate::error::{IngestorError, Result};
+use crate::ingestor::RecordSet;
+use crate::json_input::infer_schema;
+
+/// Operations one record may take before the script is aborted, so a runaway loop fails the file
+/// instead of hanging the run.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// A compiled `--transform-script`. The script sees the record as a mutable map named `record`,
+/// and its value becomes the new record; evaluating to `()` (e.g. a bare `return;`) drops it.
+/// Rhai has no filesystem or network access, and `import` is disabled. Clones share the
+/// compiled script.
+#[derive(Clone,)]
+pub struct TransformScript {
+    engine: Arc<Engine,>,
+    ast:    Arc<AST,>,
+}
+
+impl TransformScript {
+    pub fn load(path: &Path,) -> Result<Self,> {
+        let source = std::fs::read_to_string(path,)?;
+        Self::compile(&source,).map_err(|e| {
+            IngestorError::ConfigurationError(format!("{}: {}", path.display(), e),)
+        },)
+    }
+
+    pub fn compile(source: &str,) -> Result<Self,> {
+        let mut engine = Engine::new();
+        engine.set_module_resolver(DummyModuleResolver::new(),);
+        engine.set_max_operations(MAX_OPERATIONS,);
+        let ast = engine.compile(source,).map_err(|e| {
+            IngestorError::ConfigurationError(format!("Invalid transform script: {}", e),)
+        },)?;
+        Ok(TransformScript { engine: Arc::new(engine,), ast: Arc::new(ast,), },)
+    }
+
+    /// Runs the script on one record. `None` means the script dropped it.
+    pub fn transform(&self, record: Value,) -> Result<Option<Value,>,> {
+        let mut scope = Scope::new();
+        scope.push("record", rhai::serde::to_dynamic(&record,).map_err(script_error,)?,);
+        let result: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast,)
+            .map_err(script_error,)?;
+        if result.is_unit() {
+            return Ok(None,);
+        }
+        rhai::serde::from_dynamic(&result,).map(Some,).map_err(script_error,)
+    }
+
+    /// Applies the script to structured reader output. Scripts may add, drop or retype fields, so
+    /// an inferred schema is re-inferred from the transformed rows. Blob fallbacks pass through.
+    pub fn apply(&self, data: DataReaderResult,) -> Result<DataReaderResult,> {
+        match data {
+            DataReaderResult::Csv(mut csv_data, metadata,) => {
+                csv_data.nc_rows = self.transform_rows(csv_data.nc_rows,)?;
+                if let Some(schema,) = csv_data.inferred_schema.as_mut()
+                    && !csv_data.nc_rows.is_empty()
+                {
+                    *schema = infer_schema(&csv_data.nc_rows,);
+                }
+                Ok(DataReaderResult::Csv(csv_data, metadata,),)
+            },
+            DataReaderResult::Stream(stream, metadata,) => {
+                let script = self.clone();
+                let stream = stream.filter_map(move |record| match record {
+                    Ok(record,) => match script.transform(record,) {
+                        Ok(transformed,) => transformed.map(Ok,),
+                        Err(e,) => Some(Err(DataReaderError::Other(e.to_string(),),),),
+                    },
+                    Err(e,) => Some(Err(e,),),
+                },);
+                Ok(DataReaderResult::Stream(Box::new(stream,), metadata,),)
+            },
+            other => Ok(other,),
+        }
+    }
+
+    /// [`TransformScript::apply`] for pre-structured records.
+    pub fn apply_records(&self, mut records: RecordSet,) -> Result<RecordSet,> {
+        records.rows = self.transform_rows(records.rows,)?;
+        if let Some(schema,) = records.schema.as_mut()
+            && !records.rows.is_empty()
+        {
+            *schema = infer_schema(&records.rows,);
+        }
+        Ok(records,)
+    }
+
+    fn transform_rows(&self, rows: Vec<Value,>,) -> Result<Vec<Value,>,> {
+        let mut transformed = Vec::with_capacity(rows.len(),);
+        for row in rows {
+            if let Some(row,) = self.transform(row,)? {
+                transformed.push(row,);
+            }
+        }
+        Ok(transformed,)
+    }
+}
+
+fn script_error(e: impl std::fmt::Display,) -> IngestorError {
+    IngestorError::IngestionError(format!("Transform script failed: {}", e),)
+}
@@ -1,156 +1,116 @@
 // nc_ingestor/src/sqlite/mod.rs
 // SQLite specific ingestion logic.
 
-use std::sync::{Arc, Mutex};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::Duration;
 
 use async_trait::async_trait;
 use nc_reader::nc_reader_result::{DataReaderResult, RecordStream};
-use rusqlite::{Connection, params};
+use nc_schema::DataType;
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, Transaction, TransactionBehavior, params};
 use tokio::task;
-use tracing::info;
+use tracing::{debug, info, warn};
 
 use crate::error::{IngestorError, Result};
-use crate::ingestor::{Ingestor, IngestorConfig};
-use crate::schema_builder::{SqlDialect, SqlSchemaBuilder};
+use crate::ingestor::{
+    CommitTracker, Ingestor, IngestorConfig, RawFile, RecordSet, blob_value, raw_target_name,
+};
+use crate::nested::{
+    ChildTable, NestedMode, PARENT_ID_COLUMN, ROW_ID_COLUMN, child_table_name, split_nested,
+};
+use crate::schema_builder::{
+    ForeignKey, SchemaDiff, SchemaDiffLog, SqlDialect, SqlSchemaBuilder, fts5_table_name,
+    suffixed_table_name,
+};
+use crate::transform::round_float_columns;
 
+/// Connections opened when `pool_size` is unset.
+const DEFAULT_POOL_SIZE: u32 = 4;
+
+/// How long a connection waits for another connection's write transaction before failing with
+/// `SQLITE_BUSY`. Concurrent files take turns committing batches, so this covers the longest
+/// batch another file may be writing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30,);
+
+type SqlitePool = r2d2::Pool<SqliteConnectionManager,>;
+
+/// SQLite-specific table options.
+#[derive(Debug, Clone, Default, serde::Deserialize,)]
+#[serde(default, deny_unknown_fields)]
+pub struct SqliteOptions {
+    /// Text columns indexed by an FTS5 table `<table>_fts` created next to typed tables
+    pub fts_fields: Vec<String,>,
+}
+
+/// Writes through a connection pool in WAL mode, so files ingested concurrently each get their
+/// own connection. SQLite still admits one writer at a time; the others wait on the busy timeout
+/// rather than on a process-wide lock.
 pub struct SqliteIngestor {
-    #[allow(dead_code)]
-    config: IngestorConfig,
-    conn:   Arc<Mutex<Connection,>,>,
+    config:       IngestorConfig,
+    pool:         SqlitePool,
+    /// Tables compared by `--dry-run-schema-diff`
+    schema_diffs: SchemaDiffLog,
 }
 
 #[async_trait]
 impl Ingestor for SqliteIngestor {
     async fn new(config: IngestorConfig,) -> Result<Self,> {
-        let database_url_owned = config.database_url.clone();
-        let conn_path = database_url_owned.trim_start_matches("sqlite://",);
-        let conn_path_owned = conn_path.to_string();
-        let conn = task::spawn_blocking(move || Connection::open(conn_path_owned,),)
-            .await
-            .map_err(|e| {
-                IngestorError::Other(format!(
-                    "Failed to spawn blocking task for SQLite connection: {}",
-                    e
-                ),)
-            },)?
-            .map_err(|e| {
-                IngestorError::ConnectionError(format!("Failed to connect to SQLite: {}", e),)
-            },)?;
-
-        let conn_arc = Arc::new(Mutex::new(conn,),);
-
-        Ok(SqliteIngestor {
-            config,
-            conn: conn_arc,
+        if !config.comments.is_empty() {
+            warn!("SQLite has no table or column comments; ignoring the configured comments");
+        }
+        let conn_path = config.database_url.trim_start_matches("sqlite://",).to_string();
+        // Every pooled connection to `:memory:` would open a database of its own
+        let pool_size = if conn_path.is_empty() || conn_path == ":memory:" {
+            1
+        } else {
+            config.pool_size.unwrap_or(DEFAULT_POOL_SIZE,).max(1,)
+        };
+        let manager = SqliteConnectionManager::file(conn_path,).with_init(|conn| {
+            conn.busy_timeout(BUSY_TIMEOUT,)?;
+            conn.execute_batch("PRAGMA journal_mode = WAL;",)
+        },);
+        let pool = task::spawn_blocking(move || {
+            r2d2::Pool::builder().max_size(pool_size,).build(manager,)
         },)
+        .await
+        .map_err(|e| {
+            IngestorError::Other(format!(
+                "Failed to spawn blocking task for SQLite connection: {}",
+                e
+            ),)
+        },)?
+        .map_err(|e| {
+            IngestorError::ConnectionError(format!("Failed to connect to SQLite: {}", e),)
+                .with_source(e,)
+        },)?;
+
+        Ok(SqliteIngestor { config, pool, schema_diffs: SchemaDiffLog::default(), },)
     }
 
     async fn ingest(&self, data: DataReaderResult,) -> Result<(),> {
-        let table_name = self
-            .config
-            .collection_name
-            .as_deref()
-            .unwrap_or(crate::DEFAULT_SQL_TABLE_NAME,)
-            .to_string();
-
-        let conn_clone = Arc::clone(&self.conn,);
+        let table_name = self.config.table_name();
+
         let table_name_clone = table_name.clone();
-        let mappings = self.config.mappings.clone();
+
+        if self.config.schema_diff {
+            let typed = match &data {
+                DataReaderResult::Csv(csv_data, _,) => csv_data
+                    .inferred_schema
+                    .as_ref()
+                    .map(|schema| (schema, Vec::new(),),),
+                _ => None,
+            };
+            return self.log_schema_diff(typed, &table_name,).await;
+        }
 
         match data {
             DataReaderResult::Csv(csv_data, _metadata,) => {
                 if let Some(schema,) = csv_data.inferred_schema {
                     // Structured Ingestion
-                    let builder = SqlSchemaBuilder::new(SqlDialect::Sqlite, mappings.clone(),);
-                    let create_query = builder.build_create_table(&table_name_clone, &schema,);
-
-                    task::spawn_blocking(move || {
-                        let conn = conn_clone.lock().unwrap();
-                        conn.execute(&create_query, [],)
-                    },)
-                    .await
-                    .map_err(|e| IngestorError::Other(e.to_string(),),)?
-                    .map_err(|e| IngestorError::DatabaseError(e.to_string(),),)?;
-
-                    // INSERT rows
-                    let conn_clone = Arc::clone(&self.conn,);
-                    let table_name_for_insert = table_name_clone.clone();
-
-                    // Build Insert Query
-                    let mut col_names: Vec<String,> = schema.keys().cloned().collect();
-                    col_names.sort();
-
-                    let mapped_cols: Vec<String,> = col_names
-                        .iter()
-                        .map(|c| {
-                            let target = mappings.as_ref().and_then(|m| m.get(c,),).unwrap_or(c,);
-                            format!("`{}`", target)
-                        },)
-                        .collect();
-
-                    let placeholders: Vec<String,> =
-                        (1..=col_names.len()).map(|i| format!("?{}", i),).collect();
-                    let insert_sql = format!(
-                        "INSERT INTO `{}` ({}) VALUES ({})",
-                        table_name_for_insert,
-                        mapped_cols.join(", "),
-                        placeholders.join(", ")
-                    );
-
-                    let nc_rows = csv_data.nc_rows;
-                    task::spawn_blocking(move || {
-                        let mut conn = conn_clone.lock().unwrap();
-                        let tx = conn
-                            .transaction()
-                            .map_err(|e| IngestorError::DatabaseError(e.to_string(),),)?;
-
-                        {
-                            let mut stmt = tx
-                                .prepare(&insert_sql,)
-                                .map_err(|e| IngestorError::DatabaseError(e.to_string(),),)?;
-                            for row in nc_rows {
-                                if let serde_json::Value::Object(obj,) = row {
-                                    let mut params = Vec::new();
-                                    for col in &col_names {
-                                        let val =
-                                            obj.get(col,).unwrap_or(&serde_json::Value::Null,);
-                                        // Convert serde_json::Value to rusqlite::types::Value
-                                        // (simplified)
-                                        let sql_val = match val {
-                                            serde_json::Value::Number(n,) => {
-                                                if let Some(i,) = n.as_i64() {
-                                                    rusqlite::types::Value::Integer(i,)
-                                                } else {
-                                                    rusqlite::types::Value::Real(
-                                                        n.as_f64().unwrap_or(0.0,),
-                                                    )
-                                                }
-                                            },
-                                            serde_json::Value::String(s,) => {
-                                                rusqlite::types::Value::Text(s.clone(),)
-                                            },
-                                            serde_json::Value::Bool(b,) => {
-                                                rusqlite::types::Value::Integer(if *b {
-                                                    1
-                                                } else {
-                                                    0
-                                                },)
-                                            },
-                                            _ => rusqlite::types::Value::Null,
-                                        };
-                                        params.push(sql_val,);
-                                    }
-                                    stmt.execute(rusqlite::params_from_iter(params,),).map_err(
-                                        |e| IngestorError::IngestionError(e.to_string(),),
-                                    )?;
-                                }
-                            }
-                        }
-                        tx.commit()
-                            .map_err(|e| IngestorError::DatabaseError(e.to_string(),),)
-                    },)
-                    .await
-                    .map_err(|e| IngestorError::Other(e.to_string(),),)??;
+                    self.ingest_typed(csv_data.nc_rows, &schema, Vec::new(), &table_name_clone,)
+                        .await?;
                 } else {
                     // Fallback to Blob if no schema
                     self.ingest_as_blob(
@@ -175,16 +135,321 @@ impl Ingestor for SqliteIngestor {
         );
         Ok((),)
     }
+
+    async fn ingest_records(&self, records: RecordSet,) -> Result<(),> {
+        let table_name = self.config.table_name();
+
+        if self.config.schema_diff {
+            let typed = records.schema.as_ref().map(|schema| (schema, records.columns.clone(),),);
+            return self.log_schema_diff(typed, &table_name,).await;
+        }
+
+        match records.schema {
+            Some(schema,) => {
+                self.ingest_typed(records.rows, &schema, records.columns, &table_name,)
+                    .await?
+            },
+            None => {
+                let stream: RecordStream = Box::new(records.rows.into_iter().map(Ok,),);
+                self.batch_ingest_stream(stream, &table_name,).await?;
+            },
+        }
+
+        info!(
+            "Successfully ingested data to SQLite table '{}'.",
+            table_name
+        );
+        Ok((),)
+    }
+
+    async fn ingest_raw(&self, raw: RawFile,) -> Result<(),> {
+        let table_name = raw_target_name(&self.config.table_name(),);
+        let json_data = serde_json::to_string(&raw.to_value(),)
+            .map_err(|e| IngestorError::ingestion(e,),)?;
+        self.insert_blob(json_data, &table_name,).await
+    }
+
+    async fn target_count(&self,) -> Result<Option<u64,>,> {
+        let table_name = self.config.table_name();
+        let conn = self.connection().await?;
+
+        task::spawn_blocking(move || {
+            let exists: i64 = conn.query_row(
+                "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                params![table_name],
+                |row| row.get(0,),
+            )?;
+            if exists == 0 {
+                return Ok(0,);
+            }
+            let count_query = format!("SELECT count(*) FROM `{}`", table_name);
+            conn.query_row(&count_query, [], |row| row.get::<_, i64>(0,),)
+        },)
+        .await
+        .map_err(|e| IngestorError::Other(e.to_string(),),)?
+        .map(|count| Some(count as u64,),)
+        .map_err(|e| IngestorError::database(e,),)
+    }
+
+    async fn clean(&self,) -> Result<String,> {
+        let table_name = self.config.table_name();
+        let count = self.target_count().await?.unwrap_or_default();
+        let conn = self.connection().await?;
+        let drop_query = format!("DROP TABLE IF EXISTS `{}`", table_name);
+
+        task::spawn_blocking(move || conn.execute(&drop_query, [],),)
+            .await
+            .map_err(|e| IngestorError::Other(e.to_string(),),)?
+            .map_err(|e| IngestorError::database(e,),)?;
+        Ok(format!("dropped SQLite table `{}` ({} rows)", table_name, count),)
+    }
+
+    async fn execute_sql(&self, sql: &str,) -> Result<(),> {
+        let conn = self.connection().await?;
+        let sql = sql.to_string();
+        task::spawn_blocking(move || conn.execute_batch(&sql,),)
+            .await
+            .map_err(|e| IngestorError::Other(e.to_string(),),)?
+            .map_err(|e| IngestorError::database(e,),)
+    }
+
+    fn schema_diffs(&self,) -> Vec<SchemaDiff,> {
+        self.schema_diffs.diffs()
+    }
 }
 
 impl SqliteIngestor {
+    /// Builder for the typed table, given the data's source column order.
+    fn schema_builder(&self, source_columns: Vec<String,>,) -> SqlSchemaBuilder {
+        SqlSchemaBuilder::new(SqlDialect::Sqlite, self.config.mappings.clone(),)
+            .with_column_order(self.config.column_order.clone(), source_columns,)
+            .with_collation(self.config.collation.clone(),)
+            .with_type_overrides(self.config.type_overrides.clone(),)
+            .with_fulltext(self.config.sqlite.fts_fields.clone(),)
+            .with_primary_key(self.row_id_column(),)
+    }
+
+    /// `_row_id`, the primary key of typed tables whose nested columns go to child tables.
+    fn row_id_column(&self,) -> Option<String,> {
+        (self.config.normalize_nested == NestedMode::TablePerField)
+            .then(|| ROW_ID_COLUMN.to_string(),)
+    }
+
+    /// Builder for the child tables of `parent` (`--normalize-nested table-per-field`).
+    fn child_builder(&self, parent: &str,) -> SqlSchemaBuilder {
+        SqlSchemaBuilder::new(SqlDialect::Sqlite, None,)
+            .with_collation(self.config.collation.clone(),)
+            .with_foreign_key(Some(ForeignKey {
+                column:     PARENT_ID_COLUMN.to_string(),
+                table:      parent.to_string(),
+                referenced: ROW_ID_COLUMN.to_string(),
+            },),)
+    }
+
+    /// `--dry-run-schema-diff`: logs and records how the table for `typed` (the inferred schema
+    /// and source column order) differs from `table_name` as it exists, without writing. Data
+    /// without a schema would go to a JSON blob table and is only noted.
+    async fn log_schema_diff(
+        &self,
+        typed: Option<(&HashMap<String, DataType,>, Vec<String,>,),>,
+        table_name: &str,
+    ) -> Result<(),> {
+        let Some((schema, source_columns,),) = typed else {
+            info!(
+                "No inferred schema; the data would be stored as JSON documents in `{}`",
+                table_name
+            );
+            return Ok((),);
+        };
+        let conn = self.connection().await?;
+        let table = table_name.to_string();
+        let live = task::spawn_blocking(move || table_columns(&conn, &table,),)
+            .await
+            .map_err(|e| IngestorError::Other(e.to_string(),),)?
+            .map_err(|e| IngestorError::database(e,),)?;
+        let schema = self.config.normalize_nested.table_schema(schema,);
+        let diff = self.schema_builder(source_columns,).diff(table_name, &schema, &live,);
+        self.schema_diffs.record(diff,);
+        Ok((),)
+    }
+
+    /// Creates the typed table for `schema` if needed and inserts the rows, committing every
+    /// `batch.size` rows so large files never hold one huge transaction.
+    async fn ingest_typed(
+        &self,
+        mut rows: Vec<serde_json::Value,>,
+        schema: &HashMap<String, DataType,>,
+        source_columns: Vec<String,>,
+        table_name: &str,
+    ) -> Result<(),> {
+        if let Some(decimals,) = self.config.float_precision() {
+            round_float_columns(&mut rows, schema, decimals,);
+        }
+        let mut schema = schema.clone();
+        let children = match self.config.normalize_nested {
+            NestedMode::TablePerField => {
+                split_nested(&mut rows, &mut schema, self.config.mappings.as_ref(),)
+            },
+            NestedMode::Json => Vec::new(),
+        };
+        let schema = &schema;
+        let mut conn = self.connection().await?;
+        let mappings = self.config.mappings.clone();
+        let builder = self.schema_builder(source_columns,);
+        let col_names = builder.ordered_fields(schema,);
+        let rename_on_conflict = self.config.rename_on_conflict;
+        let base_table = table_name.to_string();
+        let create_schema = schema.clone();
+
+        // Resolving and creating in one write transaction keeps concurrent files from racing for
+        // a name
+        let table_name_for_insert = task::spawn_blocking(move || {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate,)?;
+            let table_name = if rename_on_conflict {
+                free_table_name(&tx, &base_table, &builder.column_names(&create_schema,),)?
+            } else {
+                base_table
+            };
+            tx.execute(&builder.build_create_table(&table_name, &create_schema,), [],)?;
+            let fts = builder.build_fts5(&table_name, &create_schema,);
+            if !fts.is_empty() {
+                let fts_table = fts5_table_name(&table_name,);
+                let existed: bool = tx.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = ?1)",
+                    [&fts_table],
+                    |row| row.get(0,),
+                )?;
+                for statement in &fts {
+                    tx.execute(statement, [],)?;
+                }
+                // Rows written before the index existed are only indexed by a rebuild
+                if !existed {
+                    tx.execute(
+                        &format!("INSERT INTO `{0}`(`{0}`) VALUES ('rebuild')", fts_table),
+                        [],
+                    )?;
+                }
+            }
+            tx.commit()?;
+            Ok::<_, rusqlite::Error>(table_name,)
+        },)
+        .await
+        .map_err(|e| IngestorError::Other(e.to_string(),),)?
+        .map_err(|e| IngestorError::database(e,),)?;
+        if table_name_for_insert != table_name {
+            info!(
+                "Table `{}` has different columns; ingesting into `{}` instead",
+                table_name, table_name_for_insert
+            );
+        }
+
+        // INSERT rows
+        let mut conn = self.connection().await?;
+
+        // Build Insert Query
+        let mapped_cols: Vec<String,> = col_names
+            .iter()
+            .map(|c| {
+                let target = mappings.as_ref().and_then(|m| m.get(c,),).unwrap_or(c,);
+                format!("`{}`", target)
+            },)
+            .collect();
+
+        let placeholders: Vec<String,> =
+            (1..=col_names.len()).map(|i| format!("?{}", i),).collect();
+        let insert_sql = format!(
+            "INSERT INTO `{}` ({}) VALUES ({})",
+            table_name_for_insert,
+            mapped_cols.join(", "),
+            placeholders.join(", ")
+        );
+
+        let batch = self.config.batch.clone();
+        let table_name = table_name_for_insert.clone();
+        let on_batch_committed = self.config.on_batch_committed.clone();
+        let tracker = CommitTracker::current();
+        let child_builder = self.child_builder(&table_name,);
+        let mut children: Vec<ChildInsert,> = children
+            .into_iter()
+            .map(|child| ChildInsert::new(&table_name, &child_builder, child,),)
+            .collect();
+
+        task::spawn_blocking(move || {
+            let mut inserted = 0;
+
+            if !children.is_empty() {
+                let tx = conn
+                    .transaction_with_behavior(TransactionBehavior::Immediate,)
+                    .map_err(|e| IngestorError::database(e,),)?;
+                for child in &children {
+                    tx.execute(&child.create_sql, [],).map_err(|e| IngestorError::database(e,),)?;
+                }
+                tx.commit().map_err(|e| IngestorError::database(e,),)?;
+            }
+
+            // One transaction and prepared statement per batch. A batch that runs past the
+            // timeout is rolled back when `tx` drops.
+            for chunk in rows.chunks(batch.size(),) {
+                let deadline = batch.deadline();
+                let tx = conn
+                    .transaction_with_behavior(TransactionBehavior::Immediate,)
+                    .map_err(|e| IngestorError::database(e,),)?;
+                {
+                    let mut stmt = tx
+                        .prepare(&insert_sql,)
+                        .map_err(|e| IngestorError::database(e,),)?;
+                    for row in chunk {
+                        if let serde_json::Value::Object(obj,) = row {
+                            let mut params = Vec::new();
+                            for col in &col_names {
+                                let val = obj.get(col,).unwrap_or(&serde_json::Value::Null,);
+                                params.push(json_to_sql_value(val,),);
+                            }
+                            stmt.execute(rusqlite::params_from_iter(params,),)
+                                .map_err(|e| IngestorError::ingestion(e,),)?;
+                        }
+                        batch.check_deadline(deadline,)?;
+                    }
+                }
+                // Child rows are committed with their parents
+                let row_ids: HashSet<&str,> = chunk
+                    .iter()
+                    .filter_map(|row| row.get(ROW_ID_COLUMN,)?.as_str(),)
+                    .collect();
+                for child in &mut children {
+                    child.insert_batch(&tx, &row_ids,).map_err(|e| IngestorError::ingestion(e,),)?;
+                    batch.check_deadline(deadline,)?;
+                }
+                tx.commit()
+                    .map_err(|e| IngestorError::database(e,),)?;
+                inserted += chunk.len();
+                debug!("Committed {} of {} rows into {}", inserted, rows.len(), table_name);
+                if let Some(callback,) = &on_batch_committed {
+                    callback.call(&table_name, chunk.len(),);
+                }
+                if let Some(tracker,) = &tracker {
+                    tracker.add(chunk.len(),);
+                }
+            }
+            Ok::<_, IngestorError>((),)
+        },)
+        .await
+        .map_err(|e| IngestorError::Other(e.to_string(),),)??;
+
+        Ok((),)
+    }
+
+    /// Writes records as JSON into a blob table. With `id_field` set, each row also gets a
+    /// unique `record_key` from that field and is upserted, so re-ingesting replaces rows
+    /// instead of appending them; records without the field are still appended.
     async fn batch_ingest_stream(&self, stream: RecordStream, table_name: &str,) -> Result<(),> {
-        let conn_clone = Arc::clone(&self.conn,);
+        let conn = self.connection().await?;
         let table_name_for_create = table_name.to_string();
+        let keyed = self.config.id_field.is_some();
 
-        // Ensure table exists (blob mode for generic stream)
-        task::spawn_blocking(move || {
-            let conn = conn_clone.lock().unwrap();
+        // Ensure table exists (blob mode for generic stream). The pooled connection is handed
+        // back so the inserts below reuse it.
+        let mut conn = task::spawn_blocking(move || {
             let create_table_query = format!(
                 "CREATE TABLE IF NOT EXISTS `{}` (
                     id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -192,47 +457,72 @@ impl SqliteIngestor {
                 )",
                 table_name_for_create
             );
-            conn.execute(&create_table_query, [],)
+            conn.execute(&create_table_query, [],)?;
+            if keyed {
+                ensure_record_key(&conn, &table_name_for_create,)?;
+            }
+            Ok(conn,)
         },)
         .await
         .map_err(|e| IngestorError::Other(e.to_string(),),)?
-        .map_err(|e| IngestorError::DatabaseError(e.to_string(),),)?;
+        .map_err(|e: rusqlite::Error| IngestorError::database(e,),)?;
 
-        let conn_clone = Arc::clone(&self.conn,);
         let table_name_clone = table_name.to_string();
-        let insert_query = format!("INSERT INTO `{}` (data) VALUES (?1)", table_name_clone);
+        let insert_query = if keyed {
+            format!(
+                "INSERT OR REPLACE INTO `{}` (record_key, data) VALUES (?1, ?2)",
+                table_name_clone
+            )
+        } else {
+            format!("INSERT INTO `{}` (data) VALUES (?1)", table_name_clone)
+        };
+        let id_field = self.config.id_field.clone();
+
+        let batch = self.config.batch.clone();
+        let on_batch_committed = self.config.on_batch_committed.clone();
+        let tracker = CommitTracker::current();
 
         task::spawn_blocking(move || {
-            let mut conn = conn_clone.lock().unwrap();
-            let tx = conn
-                .transaction()
-                .map_err(|e| IngestorError::DatabaseError(e.to_string(),),)?;
-
-            let mut count = 0;
-            {
-                let mut stmt = tx
-                    .prepare(&insert_query,)
-                    .map_err(|e| IngestorError::DatabaseError(e.to_string(),),)?;
-                for record_res in stream {
-                    let record = record_res.map_err(|e: nc_reader::error::DataReaderError| {
-                        IngestorError::IngestionError(e.to_string(),)
-                    },)?;
-                    let json_data = serde_json::to_string(&record,)
-                        .map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
-                    stmt.execute(params![json_data],)
-                        .map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
-
-                    count += 1;
-                    if count >= 1000 {
-                        // We can't easily commit and continue inside this closure because stmt
-                        // holds a borrow of tx. For simplicity, we'll do
-                        // one big transaction for now, or we could refactor to chunk the stream
-                        // outside.
+            let mut stream = stream.peekable();
+
+            // One transaction per batch, rolled back if it runs past the timeout
+            while stream.peek().is_some() {
+                let deadline = batch.deadline();
+                let tx = conn
+                    .transaction_with_behavior(TransactionBehavior::Immediate,)
+                    .map_err(|e| IngestorError::database(e,),)?;
+                let mut batch_len = 0;
+                {
+                    let mut stmt = tx
+                        .prepare(&insert_query,)
+                        .map_err(|e| IngestorError::database(e,),)?;
+                    for record_res in stream.by_ref().take(batch.size(),) {
+                        let record = record_res.map_err(|e: nc_reader::error::DataReaderError| {
+                            IngestorError::IngestionError(e.to_string(),)
+                        },)?;
+                        let json_data = serde_json::to_string(&record,)
+                            .map_err(|e| IngestorError::ingestion(e,),)?;
+                        let inserted = match &id_field {
+                            Some(field,) => {
+                                stmt.execute(params![record_key(&record, field), json_data],)
+                            },
+                            None => stmt.execute(params![json_data],),
+                        };
+                        inserted.map_err(|e| IngestorError::ingestion(e,),)?;
+                        batch_len += 1;
+                        batch.check_deadline(deadline,)?;
                     }
                 }
+                tx.commit()
+                    .map_err(|e| IngestorError::database(e,),)?;
+                if let Some(callback,) = &on_batch_committed {
+                    callback.call(&table_name_clone, batch_len,);
+                }
+                if let Some(tracker,) = &tracker {
+                    tracker.add(batch_len,);
+                }
             }
-            tx.commit()
-                .map_err(|e| IngestorError::DatabaseError(e.to_string(),),)
+            Ok::<_, IngestorError>((),)
         },)
         .await
         .map_err(|e| IngestorError::Other(e.to_string(),),)?
@@ -242,10 +532,18 @@ impl SqliteIngestor {
     }
 
     async fn ingest_as_blob(&self, data: DataReaderResult, table_name: &str,) -> Result<(),> {
-        let conn_clone = Arc::clone(&self.conn,);
+        let json_data = serde_json::to_string(&blob_value(&data, &self.config,)?,)
+            .map_err(|e| IngestorError::ingestion(e,),)?;
+        self.insert_blob(json_data, table_name,).await?;
+        self.config.batch_committed(table_name, 1,);
+        Ok((),)
+    }
+
+    /// Inserts one JSON document into a `(id, data)` blob table, creating it if needed.
+    async fn insert_blob(&self, json_data: String, table_name: &str,) -> Result<(),> {
+        let conn = self.connection().await?;
         let table_name_for_create = table_name.to_string();
         task::spawn_blocking(move || {
-            let conn = conn_clone.lock().unwrap();
             let create_table_query = format!(
                 "CREATE TABLE IF NOT EXISTS `{}` (
                     id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -253,25 +551,160 @@ impl SqliteIngestor {
                 )",
                 table_name_for_create
             );
-            conn.execute(&create_table_query, [],)
+            conn.execute(&create_table_query, [],)?;
+            let insert_query =
+                format!("INSERT INTO `{}` (data) VALUES (?1)", table_name_for_create);
+            conn.execute(&insert_query, params![json_data],)
         },)
         .await
         .map_err(|e| IngestorError::Other(e.to_string(),),)?
-        .map_err(|e| IngestorError::DatabaseError(e.to_string(),),)?;
+        .map_err(|e| IngestorError::ingestion(e,),)?;
+        Ok((),)
+    }
 
-        let json_data = serde_json::to_string(&data,)
-            .map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
+    /// Checks a connection out of the pool, waiting off the runtime when all are in use.
+    async fn connection(&self,) -> Result<PooledConnection<SqliteConnectionManager,>,> {
+        let pool = self.pool.clone();
+        task::spawn_blocking(move || pool.get(),)
+            .await
+            .map_err(|e| IngestorError::Other(e.to_string(),),)?
+            .map_err(|e| {
+                IngestorError::ConnectionError(format!("Failed to get SQLite connection: {}", e),)
+                    .with_source(e,)
+            },)
+    }
+}
 
-        let conn_clone = Arc::clone(&self.conn,);
-        let table_name_clone = table_name.to_string();
-        task::spawn_blocking(move || {
-            let conn = conn_clone.lock().unwrap();
-            let insert_query = format!("INSERT INTO `{}` (data) VALUES (?1)", table_name_clone);
-            conn.execute(&insert_query, params![json_data],)
-        },)
-        .await
-        .map_err(|e| IngestorError::Other(e.to_string(),),)?
-        .map_err(|e| IngestorError::IngestionError(e.to_string(),),)?;
+/// Adds the `record_key` column and its unique index to a blob table created before keys were
+/// configured. Both steps are no-ops once done.
+fn ensure_record_key(conn: &Connection, table_name: &str,) -> rusqlite::Result<(),> {
+    let column_query = format!(
+        "SELECT count(*) FROM pragma_table_info('{}') WHERE name = 'record_key'",
+        table_name
+    );
+    let has_column: i64 = conn.query_row(&column_query, [], |row| row.get(0,),)?;
+    if has_column == 0 {
+        conn.execute(&format!("ALTER TABLE `{}` ADD COLUMN record_key TEXT", table_name), [],)?;
+    }
+    conn.execute(
+        &format!(
+            "CREATE UNIQUE INDEX IF NOT EXISTS `{0}_record_key` ON `{0}` (record_key)",
+            table_name
+        ),
+        [],
+    )?;
+    Ok((),)
+}
+
+/// `(column, declared type)` of `table_name` from `pragma table_info`, in column order; empty if
+/// the table doesn't exist.
+fn table_columns(
+    conn: &Connection,
+    table_name: &str,
+) -> rusqlite::Result<Vec<(String, String,),>,> {
+    let mut stmt = conn.prepare("SELECT name, type FROM pragma_table_info(?1) ORDER BY cid",)?;
+    stmt.query_map(params![table_name], |row| Ok((row.get(0,)?, row.get(1,)?,),),)?
+        .collect()
+}
+
+/// First of `base`, `base_2`, ... that either doesn't exist yet or has exactly `columns`.
+fn free_table_name(
+    conn: &Connection,
+    base: &str,
+    columns: &BTreeSet<String,>,
+) -> rusqlite::Result<String,> {
+    for attempt in 1.. {
+        let candidate = suffixed_table_name(base, attempt,);
+        let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1)",)?;
+        let existing = stmt
+            .query_map(params![candidate], |row| row.get::<_, String,>(0,),)?
+            .collect::<rusqlite::Result<BTreeSet<String,>,>>()?;
+        if existing.is_empty() || existing == *columns {
+            return Ok(candidate,);
+        }
+    }
+    unreachable!("table name attempts are unbounded")
+}
+
+/// Natural key of a blob record: the scalar at `field`, or `None` to append the record.
+fn record_key(record: &serde_json::Value, field: &str,) -> Option<String,> {
+    match record.get(field,)? {
+        serde_json::Value::String(s,) => Some(s.clone(),),
+        value @ (serde_json::Value::Number(_,) | serde_json::Value::Bool(_,)) => {
+            Some(value.to_string(),)
+        },
+        _ => None,
+    }
+}
+
+/// A `--normalize-nested` child table, filled batch by batch in the transactions of its parent
+/// rows.
+struct ChildInsert {
+    create_sql: String,
+    insert_sql: String,
+    columns:    Vec<String,>,
+    rows:       Vec<serde_json::Value,>,
+    /// Rows before this one are written
+    next:       usize,
+}
+
+impl ChildInsert {
+    fn new(parent: &str, builder: &SqlSchemaBuilder, child: ChildTable,) -> Self {
+        let table = child_table_name(parent, &child.column,);
+        let columns = builder.ordered_fields(&child.schema,);
+        let quoted: Vec<String,> = columns.iter().map(|c| format!("`{}`", c),).collect();
+        let placeholders: Vec<String,> = (1..=columns.len()).map(|i| format!("?{}", i),).collect();
+        let insert_sql = format!(
+            "INSERT INTO `{}` ({}) VALUES ({})",
+            table,
+            quoted.join(", "),
+            placeholders.join(", ")
+        );
+        ChildInsert {
+            create_sql: builder.build_create_table(&table, &child.schema,),
+            insert_sql,
+            columns,
+            rows: child.rows,
+            next: 0,
+        }
+    }
+
+    /// Inserts the next rows while their parent is among `row_ids`. Child rows are grouped by
+    /// parent in parent order, so a batch's rows follow those of the batch before.
+    fn insert_batch(
+        &mut self,
+        tx: &Transaction,
+        row_ids: &HashSet<&str,>,
+    ) -> rusqlite::Result<(),> {
+        let mut stmt = tx.prepare_cached(&self.insert_sql,)?;
+        while let Some(row,) = self.rows.get(self.next,)
+            && row
+                .get(PARENT_ID_COLUMN,)
+                .and_then(|id| id.as_str(),)
+                .is_some_and(|id| row_ids.contains(id,),)
+        {
+            let params = self.columns.iter().map(|column| {
+                json_to_sql_value(row.get(column,).unwrap_or(&serde_json::Value::Null,),)
+            },);
+            stmt.execute(rusqlite::params_from_iter(params,),)?;
+            self.next += 1;
+        }
         Ok((),)
     }
 }
+
+// Convert serde_json::Value to rusqlite::types::Value (simplified)
+fn json_to_sql_value(val: &serde_json::Value,) -> rusqlite::types::Value {
+    match val {
+        serde_json::Value::Number(n,) => {
+            if let Some(i,) = n.as_i64() {
+                rusqlite::types::Value::Integer(i,)
+            } else {
+                rusqlite::types::Value::Real(n.as_f64().unwrap_or(0.0,),)
+            }
+        },
+        serde_json::Value::String(s,) => rusqlite::types::Value::Text(s.clone(),),
+        serde_json::Value::Bool(b,) => rusqlite::types::Value::Integer(if *b { 1 } else { 0 },),
+        _ => rusqlite::types::Value::Null,
+    }
+}
@@ -0,0 +1,159 @@
+// nc_ingestor/src/telemetry.rs
+// Optional OpenTelemetry export of ingestion spans and counters. The exporters are only built
+// with the `otel` feature; without it `--otel-endpoint` is rejected and the counters do nothing.
+
+#[cfg(feature = "otel")]
+use std::sync::OnceLock;
+
+#[cfg(feature = "otel")]
+use opentelemetry::metrics::Counter;
+#[cfg(feature = "otel")]
+use opentelemetry::trace::TracerProvider as _;
+#[cfg(feature = "otel")]
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::Resource;
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+#[cfg(feature = "otel")]
+use tracing::warn;
+
+use crate::error::{IngestorError, Result};
+
+#[cfg(feature = "otel")]
+const SERVICE_NAME: &str = "nc-ingestor";
+
+/// OTLP/HTTP exporters set up by `--otel-endpoint`. Call [`Telemetry::shutdown`] before exiting
+/// so the last batch of spans and metrics is flushed.
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    tracer_provider: SdkTracerProvider,
+    #[cfg(feature = "otel")]
+    meter_provider:  SdkMeterProvider,
+}
+
+#[cfg(feature = "otel")]
+impl Telemetry {
+    /// Exports to the OTLP/HTTP collector at `endpoint` (e.g. `http://localhost:4318`) and
+    /// installs the global meter provider the ingestion counters report to.
+    pub fn init(endpoint: &str,) -> Result<Self,> {
+        let base = endpoint.trim_end_matches('/',);
+        let resource = Resource::builder().with_service_name(SERVICE_NAME,).build();
+
+        let span_exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", base),)
+            .build()
+            .map_err(otel_error,)?;
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_batch_exporter(span_exporter,)
+            .with_resource(resource.clone(),)
+            .build();
+
+        let metric_exporter = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/metrics", base),)
+            .build()
+            .map_err(otel_error,)?;
+        let meter_provider = SdkMeterProvider::builder()
+            .with_periodic_exporter(metric_exporter,)
+            .with_resource(resource,)
+            .build();
+        opentelemetry::global::set_meter_provider(meter_provider.clone(),);
+
+        Ok(Self { tracer_provider, meter_provider, },)
+    }
+
+    /// Tracer for the `tracing-opentelemetry` layer.
+    pub fn tracer(&self,) -> Tracer {
+        self.tracer_provider.tracer(SERVICE_NAME,)
+    }
+
+    /// `tracing` layer exporting spans through [`Telemetry::tracer`].
+    pub fn layer<S,>(&self,) -> tracing_opentelemetry::OpenTelemetryLayer<S, Tracer,>
+    where
+        S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span,>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.tracer(),)
+    }
+
+    /// Flushes and stops both exporters. Blocks until the export finishes.
+    pub fn shutdown(&self,) {
+        if let Err(e,) = self.tracer_provider.shutdown() {
+            warn!("Failed to flush OpenTelemetry spans: {}", e);
+        }
+        if let Err(e,) = self.meter_provider.shutdown() {
+            warn!("Failed to flush OpenTelemetry metrics: {}", e);
+        }
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+impl Telemetry {
+    /// Always fails: this build has no exporters.
+    pub fn init(_endpoint: &str,) -> Result<Self,> {
+        Err(IngestorError::ConfigurationError(
+            "--otel-endpoint needs nc-ingestor built with the `otel` feature".to_string(),
+        ),)
+    }
+
+    pub fn layer(&self,) -> tracing_subscriber::layer::Identity {
+        tracing_subscriber::layer::Identity::new()
+    }
+
+    pub fn shutdown(&self,) {}
+}
+
+#[cfg(feature = "otel")]
+struct Counters {
+    records:  Counter<u64,>,
+    failures: Counter<u64,>,
+    retries:  Counter<u64,>,
+}
+
+/// Counters are created on first use from the global meter provider, which is a no-op unless
+/// [`Telemetry::init`] ran first.
+#[cfg(feature = "otel")]
+fn counters() -> &'static Counters {
+    static COUNTERS: OnceLock<Counters,> = OnceLock::new();
+    COUNTERS.get_or_init(|| {
+        let meter = opentelemetry::global::meter(SERVICE_NAME,);
+        Counters {
+            records:  meter
+                .u64_counter("nc_ingestor.records_ingested",)
+                .with_description("Records written to a target",)
+                .build(),
+            failures: meter
+                .u64_counter("nc_ingestor.failures",)
+                .with_description("Files (or pipeline targets) that failed to ingest",)
+                .build(),
+            retries:  meter
+                .u64_counter("nc_ingestor.retries",)
+                .with_description("Transient errors that were retried",)
+                .build(),
+        }
+    },)
+}
+
+#[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+pub fn record_ingested(count: u64,) {
+    #[cfg(feature = "otel")]
+    counters().records.add(count, &[],);
+}
+
+pub fn record_failure() {
+    #[cfg(feature = "otel")]
+    counters().failures.add(1, &[],);
+}
+
+pub fn record_retry() {
+    #[cfg(feature = "otel")]
+    counters().retries.add(1, &[],);
+}
+
+#[cfg(feature = "otel")]
+fn otel_error(e: opentelemetry_otlp::ExporterBuildError,) -> IngestorError {
+    IngestorError::ConfigurationError(format!("Failed to set up OpenTelemetry export: {}", e),)
+}
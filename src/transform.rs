@@ -0,0 +1,1274 @@
+// nc_ingestor/src/transform.rs
+// Record-level transforms applied to reader output before it reaches an ingestor.
+
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use nc_reader::error::DataReaderError;
+use nc_reader::nc_reader_result::DataReaderResult;
+use nc_schema::DataType;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::csv_input::{DuplicateColumns, typed_cell, unique_names};
+use crate::error::{IngestorError, Result};
+use crate::ingestor::{RecordSet, content_hash};
+use crate::json_input::infer_schema;
+use crate::schema_builder::ColumnType;
+
+/// Include/exclude lists applied to the top-level keys of every structured record. The inferred
+/// schema is narrowed to the kept columns, so `--map` and `--embed-field` refer to post-filter
+/// names. Blob fallbacks (text, whole JSON documents) are filtered by
+/// [`blob_value`](crate::ingestor::blob_value) instead, through `IngestorConfig::field_filter`.
+#[derive(Debug, Clone, Default, serde::Deserialize,)]
+#[serde(default)]
+pub struct FieldFilter {
+    /// Keep only these fields. `None` keeps every field not excluded.
+    pub include_fields: Option<Vec<String,>,>,
+    pub exclude_fields: Vec<String,>,
+}
+
+impl FieldFilter {
+    pub fn is_empty(&self,) -> bool {
+        self.include_fields.is_none() && self.exclude_fields.is_empty()
+    }
+
+    pub fn keeps(&self, field: &str,) -> bool {
+        let included = self
+            .include_fields
+            .as_ref()
+            .is_none_or(|include| include.iter().any(|f| f == field,),);
+        included && !self.exclude_fields.iter().any(|f| f == field,)
+    }
+
+    pub fn filter_record(&self, record: Value,) -> Value {
+        match record {
+            Value::Object(obj,) => {
+                Value::Object(obj.into_iter().filter(|(key, _,)| self.keeps(key,),).collect(),)
+            },
+            other => other,
+        }
+    }
+
+    pub fn apply(&self, data: DataReaderResult,) -> DataReaderResult {
+        if self.is_empty() {
+            return data;
+        }
+
+        match data {
+            DataReaderResult::Csv(mut csv_data, metadata,) => {
+                csv_data.nc_rows = csv_data
+                    .nc_rows
+                    .into_iter()
+                    .map(|row| self.filter_record(row,),)
+                    .collect();
+                if let Some(schema,) = csv_data.inferred_schema.as_mut() {
+                    schema.retain(|key, _| self.keeps(key,),);
+                }
+                DataReaderResult::Csv(csv_data, metadata,)
+            },
+            DataReaderResult::Stream(stream, metadata,) => {
+                let filter = self.clone();
+                let stream = stream.map(move |record| record.map(|r| filter.filter_record(r,),),);
+                DataReaderResult::Stream(Box::new(stream,), metadata,)
+            },
+            other => other,
+        }
+    }
+
+    pub fn apply_records(&self, mut records: RecordSet,) -> RecordSet {
+        if self.is_empty() {
+            return records;
+        }
+
+        records.rows = records
+            .rows
+            .into_iter()
+            .map(|row| self.filter_record(row,),)
+            .collect();
+        if let Some(schema,) = records.schema.as_mut() {
+            schema.retain(|key, _| self.keeps(key,),);
+        }
+        records
+    }
+}
+
+/// `--type-override`: converts the values of the listed top-level fields to their forced type and
+/// sets that type in the inferred schema. Fields are named as in the file, before any renaming.
+/// Strings are parsed like CSV cells and other values are rendered as text for `TEXT`; a value
+/// that doesn't convert fails the file (see [`coerce_value`]). CSVs are already typed from their
+/// raw cells when read, so this matters for the other formats.
+#[derive(Debug, Clone, Default,)]
+pub struct TypeOverrides {
+    pub types: HashMap<String, ColumnType,>,
+}
+
+impl TypeOverrides {
+    pub fn retype_record(&self, record: Value,) -> Result<Value,> {
+        let Value::Object(mut obj,) = record else { return Ok(record,) };
+        for (field, column_type,) in &self.types {
+            if let Some(value,) = obj.get_mut(field,) {
+                *value = coerce_value(field, value.take(), *column_type,)?;
+            }
+        }
+        Ok(Value::Object(obj,),)
+    }
+
+    fn retype_schema(&self, schema: &mut HashMap<String, DataType,>,) {
+        for (field, column_type,) in &self.types {
+            if let Some(data_type,) = schema.get_mut(field,) {
+                *data_type = column_type.data_type();
+            }
+        }
+    }
+
+    pub fn apply(&self, data: DataReaderResult,) -> Result<DataReaderResult,> {
+        if self.types.is_empty() {
+            return Ok(data,);
+        }
+
+        match data {
+            DataReaderResult::Csv(mut csv_data, metadata,) => {
+                csv_data.nc_rows = csv_data
+                    .nc_rows
+                    .into_iter()
+                    .map(|row| self.retype_record(row,),)
+                    .collect::<Result<_,>>()?;
+                if let Some(schema,) = csv_data.inferred_schema.as_mut() {
+                    self.retype_schema(schema,);
+                }
+                Ok(DataReaderResult::Csv(csv_data, metadata,),)
+            },
+            DataReaderResult::Stream(stream, metadata,) => {
+                let overrides = self.clone();
+                let stream = stream.map(move |record| {
+                    overrides
+                        .retype_record(record?,)
+                        .map_err(|e| DataReaderError::Other(e.to_string(),),)
+                },);
+                Ok(DataReaderResult::Stream(Box::new(stream,), metadata,),)
+            },
+            other => Ok(other,),
+        }
+    }
+
+    pub fn apply_records(&self, mut records: RecordSet,) -> Result<RecordSet,> {
+        if self.types.is_empty() {
+            return Ok(records,);
+        }
+
+        records.rows = records
+            .rows
+            .into_iter()
+            .map(|row| self.retype_record(row,),)
+            .collect::<Result<_,>>()?;
+        if let Some(schema,) = records.schema.as_mut() {
+            self.retype_schema(schema,);
+        }
+        Ok(records,)
+    }
+}
+
+/// `--normalize-field-names`: how top-level field names are rewritten before anything refers to
+/// them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize,)]
+#[serde(rename_all = "lowercase")]
+pub enum NameStyle {
+    /// Keep names as they are in the file
+    #[default]
+    None,
+    /// Lowercase names
+    Lower,
+    /// `snake_case`: lowercase, words split at case changes and runs of other characters
+    /// replaced by one underscore (`Order ID` and `orderId` both become `order_id`)
+    Snake,
+}
+
+/// Rewrites `name` in `style`. A snake-cased name with no letters or digits left becomes
+/// `field`.
+pub fn normalize_name(name: &str, style: NameStyle,) -> String {
+    match style {
+        NameStyle::None => name.to_string(),
+        NameStyle::Lower => name.to_lowercase(),
+        NameStyle::Snake => {
+            let mut snake = String::with_capacity(name.len(),);
+            let mut prev: Option<char,> = None;
+            for c in name.chars() {
+                if c.is_alphanumeric() {
+                    let boundary = c.is_uppercase()
+                        && prev.is_some_and(|p| p.is_lowercase() || p.is_numeric(),);
+                    if boundary && !snake.ends_with('_',) {
+                        snake.push('_',);
+                    }
+                    snake.extend(c.to_lowercase(),);
+                } else if !snake.is_empty() && !snake.ends_with('_',) {
+                    snake.push('_',);
+                }
+                prev = Some(c,);
+            }
+            let snake = snake.trim_end_matches('_',);
+            if snake.is_empty() { "field".to_string() } else { snake.to_string() }
+        },
+    }
+}
+
+/// Applies a [`NameStyle`] to the top-level keys of every structured record and to the inferred
+/// schema. Names that collide once normalized are resolved by the `--on-duplicate-column`
+/// policy, in the order the names first appear. Blob fallbacks pass through.
+#[derive(Debug, Clone, Copy, Default,)]
+pub struct FieldNames {
+    pub style:        NameStyle,
+    pub on_duplicate: DuplicateColumns,
+}
+
+impl FieldNames {
+    pub fn apply(&self, data: DataReaderResult,) -> Result<DataReaderResult,> {
+        if self.style == NameStyle::None {
+            return Ok(data,);
+        }
+
+        match data {
+            DataReaderResult::Csv(mut csv_data, metadata,) => {
+                let keys = record_keys(&csv_data.nc_rows, csv_data.inferred_schema.as_ref(),);
+                let renames = self.renames(keys,)?;
+                csv_data.nc_rows = rename_rows(csv_data.nc_rows, &renames,);
+                if let Some(schema,) = csv_data.inferred_schema.as_mut() {
+                    rename_schema(schema, &renames,);
+                }
+                Ok(DataReaderResult::Csv(csv_data, metadata,),)
+            },
+            DataReaderResult::Stream(stream, metadata,) => {
+                // names are resolved as they are first seen; a key new to a later record
+                // re-resolves the whole list, like a header holding every key so far
+                let names = *self;
+                let mut keys: Vec<String,> = Vec::new();
+                let mut renames = HashMap::new();
+                let stream = stream.map(move |record| {
+                    let record = record?;
+                    if let Some(obj,) = record.as_object()
+                        && obj.keys().any(|key| !renames.contains_key(key,),)
+                    {
+                        for key in obj.keys() {
+                            if !keys.contains(key,) {
+                                keys.push(key.clone(),);
+                            }
+                        }
+                        renames = names
+                            .renames(keys.clone(),)
+                            .map_err(|e| DataReaderError::Other(e.to_string(),),)?;
+                    }
+                    Ok(rename_record(record, &renames,),)
+                },);
+                Ok(DataReaderResult::Stream(Box::new(stream,), metadata,),)
+            },
+            other => Ok(other,),
+        }
+    }
+
+    pub fn apply_records(&self, mut records: RecordSet,) -> Result<RecordSet,> {
+        if self.style == NameStyle::None {
+            return Ok(records,);
+        }
+
+        let renames = self.renames(record_keys(&records.rows, records.schema.as_ref(),),)?;
+        records.rows = rename_rows(records.rows, &renames,);
+        if let Some(schema,) = records.schema.as_mut() {
+            rename_schema(schema, &renames,);
+        }
+        records.columns = records
+            .columns
+            .into_iter()
+            .filter_map(|column| match renames.get(&column,) {
+                Some(renamed,) => renamed.clone(),
+                None => Some(column,),
+            },)
+            .collect();
+        Ok(records,)
+    }
+
+    /// New name of each of `keys`, `None` for keys dropped as repeats.
+    fn renames(&self, keys: Vec<String,>,) -> Result<HashMap<String, Option<String,>,>,> {
+        let normalized: Vec<String,> =
+            keys.iter().map(|key| normalize_name(key, self.style,),).collect();
+        let unique = unique_names(normalized.iter().map(String::as_str,), self.on_duplicate,)
+            .map_err(|name| {
+                IngestorError::IngestionError(format!(
+                    "More than one field normalizes to the name '{}'",
+                    name
+                ),)
+            },)?;
+        Ok(keys.into_iter().zip(unique,).collect(),)
+    }
+}
+
+/// Top-level keys of `rows` in the order they first appear, then schema columns no row has
+/// (sorted, since the schema is unordered).
+fn record_keys(rows: &[Value], schema: Option<&HashMap<String, DataType,>,>,) -> Vec<String,> {
+    let mut seen = HashSet::new();
+    let mut keys = Vec::new();
+    for key in rows.iter().filter_map(Value::as_object,).flat_map(|obj| obj.keys(),) {
+        if seen.insert(key.as_str(),) {
+            keys.push(key.clone(),);
+        }
+    }
+    let mut extra: Vec<String,> = schema
+        .into_iter()
+        .flat_map(|schema| schema.keys(),)
+        .filter(|key| !seen.contains(key.as_str(),),)
+        .cloned()
+        .collect();
+    extra.sort();
+    keys.extend(extra,);
+    keys
+}
+
+fn rename_rows(rows: Vec<Value,>, renames: &HashMap<String, Option<String,>,>,) -> Vec<Value,> {
+    rows.into_iter().map(|row| rename_record(row, renames,),).collect()
+}
+
+/// Renames the keys of `record`. Keys missing from `renames` are kept as they are.
+fn rename_record(record: Value, renames: &HashMap<String, Option<String,>,>,) -> Value {
+    let Value::Object(obj,) = record else { return record };
+    Value::Object(
+        obj.into_iter()
+            .filter_map(|(key, value,)| match renames.get(&key,) {
+                Some(Some(name,),) => Some((name.clone(), value,),),
+                Some(None,) => None,
+                None => Some((key, value,),),
+            },)
+            .collect(),
+    )
+}
+
+fn rename_schema(
+    schema: &mut HashMap<String, DataType,>,
+    renames: &HashMap<String, Option<String,>,>,
+) {
+    *schema = std::mem::take(schema,)
+        .into_iter()
+        .filter_map(|(key, data_type,)| match renames.get(&key,) {
+            Some(Some(name,),) => Some((name.clone(), data_type,),),
+            Some(None,) => None,
+            None => Some((key, data_type,),),
+        },)
+        .collect();
+}
+
+/// Converts the value of `field` to `column_type` (`--type-override`). Nulls and empty strings
+/// become null, and strings are parsed like CSV cells. A value with no such representation,
+/// such as `"abc"` for `BIGINT` or `2.5` for `BOOLEAN`, is an error naming the field.
+pub fn coerce_value(field: &str, value: Value, column_type: ColumnType,) -> Result<Value,> {
+    let converted = match (column_type, &value,) {
+        (_, Value::Null,) => Some(Value::Null,),
+        (ColumnType::Text, Value::String(_,),) => Some(value.clone(),),
+        (ColumnType::Text, other,) => Some(Value::String(other.to_string(),),),
+        (ColumnType::Jsonb, Value::String(s,),) => {
+            Some(serde_json::from_str(s,).unwrap_or_else(|_| value.clone(),),)
+        },
+        (ColumnType::Jsonb, _,) => Some(value.clone(),),
+        (_, Value::String(s,),) => {
+            Some(typed_cell(s, &column_type.data_type(),),).filter(|v| !v.is_string(),)
+        },
+        (ColumnType::Bigint, Value::Number(n,),) if n.is_i64() || n.is_u64() => {
+            Some(value.clone(),)
+        },
+        (ColumnType::Bigint, Value::Number(n,),) => n
+            .as_f64()
+            .filter(|f| f.fract() == 0.0 && f.abs() < i64::MAX as f64,)
+            .map(|f| Value::from(f as i64,),),
+        (ColumnType::Double, Value::Number(n,),) => {
+            n.as_f64().and_then(serde_json::Number::from_f64,).map(Value::Number,)
+        },
+        (ColumnType::Boolean, Value::Bool(_,),) => Some(value.clone(),),
+        (ColumnType::Boolean, Value::Number(n,),) => match n.as_i64() {
+            Some(0,) => Some(Value::Bool(false,),),
+            Some(1,) => Some(Value::Bool(true,),),
+            _ => None,
+        },
+        _ => None,
+    };
+    converted.ok_or_else(|| {
+        IngestorError::IngestionError(format!(
+            "Field '{}' value {} is not a valid {} (--type-override)",
+            field, value, column_type
+        ),)
+    },)
+}
+
+/// Splits every record whose `field` holds an array into one record per element, each a copy of
+/// the original with the array replaced by that element (like SQL `UNNEST`). Empty arrays keep
+/// the record with a null `field` so nothing is dropped; records without an array pass through.
+pub fn explode_record(record: Value, field: &str,) -> Vec<Value,> {
+    let Value::Object(mut obj,) = record else { return vec![record] };
+    let items = match obj.remove(field,) {
+        Some(Value::Array(items,),) if items.is_empty() => vec![Value::Null],
+        Some(Value::Array(items,),) => items,
+        Some(other,) => {
+            obj.insert(field.to_string(), other,);
+            return vec![Value::Object(obj,)];
+        },
+        None => return vec![Value::Object(obj,)],
+    };
+
+    items
+        .into_iter()
+        .map(|item| {
+            let mut row = obj.clone();
+            row.insert(field.to_string(), item,);
+            Value::Object(row,)
+        },)
+        .collect()
+}
+
+/// Applies [`explode_record`] to structured reader output. The inferred type of `field` is
+/// re-inferred from the exploded rows, so SQL targets get the element type.
+pub fn explode(data: DataReaderResult, field: &str,) -> DataReaderResult {
+    match data {
+        DataReaderResult::Csv(mut csv_data, metadata,) => {
+            csv_data.nc_rows = explode_rows(csv_data.nc_rows, field,);
+            if let Some(schema,) = csv_data.inferred_schema.as_mut() {
+                reinfer_column(schema, &csv_data.nc_rows, field,);
+            }
+            DataReaderResult::Csv(csv_data, metadata,)
+        },
+        DataReaderResult::Stream(stream, metadata,) => {
+            let field = field.to_string();
+            let stream = stream.flat_map(move |record| match record {
+                Ok(record,) => explode_record(record, &field,).into_iter().map(Ok,).collect(),
+                Err(e,) => vec![Err(e,)],
+            },);
+            DataReaderResult::Stream(Box::new(stream,), metadata,)
+        },
+        other => other,
+    }
+}
+
+/// [`explode`] for pre-structured records.
+pub fn explode_records(mut records: RecordSet, field: &str,) -> RecordSet {
+    records.rows = explode_rows(records.rows, field,);
+    if let Some(schema,) = records.schema.as_mut() {
+        reinfer_column(schema, &records.rows, field,);
+    }
+    records
+}
+
+fn explode_rows(rows: Vec<Value,>, field: &str,) -> Vec<Value,> {
+    rows.into_iter().flat_map(|row| explode_record(row, field,),).collect()
+}
+
+fn reinfer_column(schema: &mut HashMap<String, DataType,>, rows: &[Value], field: &str,) {
+    if let Some(data_type,) = infer_schema(rows,).remove(field,) {
+        schema.insert(field.to_string(), data_type,);
+    }
+}
+
+/// What happens to a record whose serialized JSON is larger than `--max-record-size`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize,)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizePolicy {
+    /// Leave the record out
+    #[default]
+    Skip,
+    /// Shorten its longest top-level strings until it fits, else leave it out
+    Truncate,
+    /// Fail the file
+    Fail,
+}
+
+/// A record over the size limit, numbered from 1 within its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq,)]
+pub struct OversizedRecord {
+    pub record:    usize,
+    /// Serialized size in bytes before truncation
+    pub size:      usize,
+    pub truncated: bool,
+}
+
+/// Callback told about every oversized record that was skipped or truncated, along with the
+/// record itself when it was left out.
+pub type OversizeHandler = Arc<dyn Fn(OversizedRecord, Option<&Value,>,) + Send + Sync,>;
+
+/// `--max-record-size` check run on every structured record, so one pathological record (a giant
+/// JSON blob in a cell) can't exceed backend document limits or abort the whole file.
+#[derive(Debug, Clone, Copy,)]
+pub struct RecordSizeGuard {
+    pub max_bytes: usize,
+    pub policy:    OversizePolicy,
+}
+
+impl RecordSizeGuard {
+    /// Checks the `record`-th record of a file. `None` means it was skipped.
+    pub fn check(
+        &self,
+        value: Value,
+        record: usize,
+        on_oversize: &OversizeHandler,
+    ) -> Result<Option<Value,>,> {
+        let size = value.to_string().len();
+        if size <= self.max_bytes {
+            return Ok(Some(value,),);
+        }
+        let kept = match self.policy {
+            OversizePolicy::Fail => {
+                return Err(IngestorError::IngestionError(format!(
+                    "Record {} is {} bytes, over the {}-byte --max-record-size",
+                    record, size, self.max_bytes
+                ),),);
+            },
+            OversizePolicy::Skip => None,
+            OversizePolicy::Truncate => truncate_to_fit(value.clone(), self.max_bytes,),
+        };
+        let left_out = kept.is_none().then_some(&value,);
+        on_oversize(OversizedRecord { record, size, truncated: kept.is_some(), }, left_out,);
+        Ok(kept,)
+    }
+
+    /// Applies the guard to structured reader output. Blob fallbacks pass through.
+    pub fn apply(
+        &self,
+        data: DataReaderResult,
+        on_oversize: OversizeHandler,
+    ) -> Result<DataReaderResult,> {
+        match data {
+            DataReaderResult::Csv(mut csv_data, metadata,) => {
+                csv_data.nc_rows = self.check_rows(csv_data.nc_rows, &on_oversize,)?;
+                Ok(DataReaderResult::Csv(csv_data, metadata,),)
+            },
+            DataReaderResult::Stream(stream, metadata,) => {
+                let guard = *self;
+                let stream = stream.enumerate().filter_map(move |(index, record,)| match record {
+                    Ok(record,) => match guard.check(record, index + 1, &on_oversize,) {
+                        Ok(kept,) => kept.map(Ok,),
+                        Err(e,) => Some(Err(DataReaderError::Other(e.to_string(),),),),
+                    },
+                    Err(e,) => Some(Err(e,),),
+                },);
+                Ok(DataReaderResult::Stream(Box::new(stream,), metadata,),)
+            },
+            other => Ok(other,),
+        }
+    }
+
+    /// [`RecordSizeGuard::apply`] for pre-structured records.
+    pub fn apply_records(
+        &self,
+        mut records: RecordSet,
+        on_oversize: OversizeHandler,
+    ) -> Result<RecordSet,> {
+        records.rows = self.check_rows(records.rows, &on_oversize,)?;
+        Ok(records,)
+    }
+
+    fn check_rows(
+        &self,
+        rows: Vec<Value,>,
+        on_oversize: &OversizeHandler,
+    ) -> Result<Vec<Value,>,> {
+        let mut kept = Vec::with_capacity(rows.len(),);
+        for (index, row,) in rows.into_iter().enumerate() {
+            if let Some(row,) = self.check(row, index + 1, on_oversize,)? {
+                kept.push(row,);
+            }
+        }
+        Ok(kept,)
+    }
+}
+
+/// `--dedupe-within-file`: drops records identical to an earlier record of the same file. Records
+/// are compared by their [`content_hash`], so key order doesn't matter and only one digest per
+/// distinct record is kept.
+/// Use one filter per file; clones share the count of dropped duplicates, which for streams is
+/// only final once the stream has been consumed.
+#[derive(Debug, Clone, Default,)]
+pub struct DuplicateFilter {
+    dropped: Arc<AtomicUsize,>,
+}
+
+impl DuplicateFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Duplicates dropped so far.
+    pub fn dropped(&self,) -> usize {
+        self.dropped.load(Ordering::Relaxed,)
+    }
+
+    /// Filters structured reader output. Blob fallbacks pass through.
+    pub fn apply(&self, data: DataReaderResult,) -> DataReaderResult {
+        match data {
+            DataReaderResult::Csv(mut csv_data, metadata,) => {
+                csv_data.nc_rows = self.dedupe_rows(csv_data.nc_rows,);
+                DataReaderResult::Csv(csv_data, metadata,)
+            },
+            DataReaderResult::Stream(stream, metadata,) => {
+                let filter = self.clone();
+                let mut seen = HashSet::new();
+                let stream = stream.filter(move |record| match record {
+                    Ok(record,) => filter.is_first(&mut seen, record,),
+                    Err(_,) => true,
+                },);
+                DataReaderResult::Stream(Box::new(stream,), metadata,)
+            },
+            other => other,
+        }
+    }
+
+    /// [`DuplicateFilter::apply`] for pre-structured records.
+    pub fn apply_records(&self, mut records: RecordSet,) -> RecordSet {
+        records.rows = self.dedupe_rows(records.rows,);
+        records
+    }
+
+    fn dedupe_rows(&self, mut rows: Vec<Value,>,) -> Vec<Value,> {
+        let mut seen = HashSet::new();
+        rows.retain(|row| self.is_first(&mut seen, row,),);
+        rows
+    }
+
+    fn is_first(&self, seen: &mut HashSet<String,>, record: &Value,) -> bool {
+        let first = seen.insert(content_hash(record,),);
+        if !first {
+            self.dropped.fetch_add(1, Ordering::Relaxed,);
+        }
+        first
+    }
+}
+
+/// `--drop-null-columns`: drops the columns whose share of empty values (null, missing or blank
+/// strings) over the first `sample` records is above `threshold`, from every record and from the
+/// inferred schema. The columns are decided once, on the first file with records, and every later
+/// file drops the same ones, so all files of a target keep the same columns. Streams are measured
+/// on their first `sample` records, which are then put back in front of the rest. Share one
+/// filter across all files going to the same target.
+#[derive(Debug,)]
+pub struct NullColumnFilter {
+    pub threshold: f64,
+    pub sample:    usize,
+    /// The columns decided on, once a file had records
+    dropped:       Mutex<Option<Vec<String,>,>,>,
+}
+
+impl NullColumnFilter {
+    pub fn new(threshold: f64, sample: usize,) -> Self {
+        NullColumnFilter { threshold, sample, dropped: Mutex::new(None,), }
+    }
+
+    /// Filters structured reader output and returns the dropped columns, sorted. Blob fallbacks
+    /// pass through.
+    pub fn apply(&self, data: DataReaderResult,) -> (DataReaderResult, Vec<String,>,) {
+        match data {
+            DataReaderResult::Csv(mut csv_data, metadata,) => {
+                let dropped = self.decide(|| {
+                    let sample = csv_data.nc_rows.iter().take(self.sample,);
+                    self.sparse_columns(sample, csv_data.inferred_schema.as_ref(),)
+                },);
+                if !dropped.is_empty() {
+                    csv_data.nc_rows = csv_data
+                        .nc_rows
+                        .into_iter()
+                        .map(|row| without_columns(row, &dropped,),)
+                        .collect();
+                    if let Some(schema,) = csv_data.inferred_schema.as_mut() {
+                        schema.retain(|key, _| !dropped.contains(key,),);
+                    }
+                }
+                (DataReaderResult::Csv(csv_data, metadata,), dropped,)
+            },
+            DataReaderResult::Stream(mut stream, metadata,) => {
+                let head: Vec<_,> = stream.by_ref().take(self.sample,).collect();
+                let dropped = self.decide(|| {
+                    self.sparse_columns(head.iter().filter_map(|r| r.as_ref().ok(),), None,)
+                },);
+                let columns = dropped.clone();
+                let stream = head
+                    .into_iter()
+                    .chain(stream,)
+                    .map(move |record| record.map(|r| without_columns(r, &columns,),),);
+                (DataReaderResult::Stream(Box::new(stream,), metadata,), dropped,)
+            },
+            other => (other, Vec::new(),),
+        }
+    }
+
+    /// [`NullColumnFilter::apply`] for pre-structured records.
+    pub fn apply_records(&self, mut records: RecordSet,) -> (RecordSet, Vec<String,>,) {
+        let dropped = self.decide(|| {
+            let sample = records.rows.iter().take(self.sample,);
+            self.sparse_columns(sample, records.schema.as_ref(),)
+        },);
+        if !dropped.is_empty() {
+            records.rows = records
+                .rows
+                .into_iter()
+                .map(|row| without_columns(row, &dropped,),)
+                .collect();
+            if let Some(schema,) = records.schema.as_mut() {
+                schema.retain(|key, _| !dropped.contains(key,),);
+            }
+        }
+        (records, dropped,)
+    }
+
+    /// The columns decided on by an earlier file, or else `measure`d on this one. A file with
+    /// nothing sampled drops nothing and leaves the decision to the next file.
+    fn decide(&self, measure: impl FnOnce() -> Option<Vec<String,>,>,) -> Vec<String,> {
+        // Held while measuring, so concurrent first files agree on one decision
+        let mut dropped = self.dropped.lock().unwrap();
+        if let Some(dropped,) = &*dropped {
+            return dropped.clone();
+        }
+        let measured = measure();
+        *dropped = measured.clone();
+        measured.unwrap_or_default()
+    }
+
+    /// Columns of `records` (and of `schema`, which may name columns no sampled record has)
+    /// whose empty share is above the threshold, sorted. `None` without any record.
+    pub fn sparse_columns<'a,>(
+        &self,
+        records: impl IntoIterator<Item = &'a Value,>,
+        schema: Option<&HashMap<String, DataType,>,>,
+    ) -> Option<Vec<String,>,> {
+        let mut filled: BTreeMap<String, usize,> = schema
+            .into_iter()
+            .flat_map(|schema| schema.keys(),)
+            .map(|key| (key.clone(), 0,),)
+            .collect();
+        let mut sampled = 0;
+        for record in records {
+            sampled += 1;
+            for (key, value,) in record.as_object().into_iter().flatten() {
+                let count = filled.entry(key.clone(),).or_default();
+                if !is_empty_value(value,) {
+                    *count += 1;
+                }
+            }
+        }
+        if sampled == 0 {
+            return None;
+        }
+        let sparse = filled
+            .into_iter()
+            .filter(|(_, count,)| (sampled - count) as f64 / sampled as f64 > self.threshold,)
+            .map(|(key, _,)| key,)
+            .collect();
+        Some(sparse,)
+    }
+}
+
+/// Null, or a string with nothing but whitespace.
+fn is_empty_value(value: &Value,) -> bool {
+    match value {
+        Value::Null => true,
+        Value::String(text,) => text.trim().is_empty(),
+        _ => false,
+    }
+}
+
+fn without_columns(record: Value, columns: &[String],) -> Value {
+    match record {
+        Value::Object(mut obj,) => {
+            obj.retain(|key, _| !columns.contains(key,),);
+            Value::Object(obj,)
+        },
+        other => other,
+    }
+}
+
+/// `--strict-schema`: the first file whose columns are known fixes the column set for the run;
+/// any later file with a different set is rejected before it is written. Columns come from the
+/// inferred schema, or from the first record when there is none (streams, JSON). Blob fallbacks
+/// have no columns and pass. Share one guard across all files of a run.
+#[derive(Debug, Default,)]
+pub struct SchemaGuard {
+    /// The adopted column set and the file it came from
+    expected: Mutex<Option<(String, BTreeSet<String,>,),>,>,
+}
+
+impl SchemaGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks structured reader output of `path`. Streams are checked on their first record,
+    /// which is put back in front of the rest.
+    pub fn apply(&self, data: DataReaderResult, path: &str,) -> Result<DataReaderResult,> {
+        match data {
+            DataReaderResult::Csv(csv_data, metadata,) => {
+                let columns = match &csv_data.inferred_schema {
+                    Some(schema,) => schema.keys().cloned().collect(),
+                    None => record_columns(csv_data.nc_rows.first(),),
+                };
+                self.check(path, columns,)?;
+                Ok(DataReaderResult::Csv(csv_data, metadata,),)
+            },
+            DataReaderResult::Stream(mut stream, metadata,) => {
+                let head = stream.next();
+                if let Some(Ok(record,),) = &head {
+                    self.check(path, record_columns(Some(record,),),)?;
+                }
+                let stream = Box::new(head.into_iter().chain(stream,),);
+                Ok(DataReaderResult::Stream(stream, metadata,),)
+            },
+            other => Ok(other,),
+        }
+    }
+
+    /// [`SchemaGuard::apply`] for pre-structured records.
+    pub fn apply_records(&self, records: RecordSet, path: &str,) -> Result<RecordSet,> {
+        let columns = match &records.schema {
+            Some(schema,) => schema.keys().cloned().collect(),
+            None => record_columns(records.rows.first(),),
+        };
+        self.check(path, columns,)?;
+        Ok(records,)
+    }
+
+    /// Adopts `columns` if no file has set the column set yet, otherwise fails when they differ.
+    /// An empty set (no records) is never adopted nor rejected.
+    pub fn check(&self, path: &str, columns: BTreeSet<String,>,) -> Result<(),> {
+        if columns.is_empty() {
+            return Ok((),);
+        }
+        let mut adopted = self.expected.lock().unwrap();
+        let Some((first, expected,),) = adopted.as_ref() else {
+            *adopted = Some((path.to_string(), columns,),);
+            return Ok((),);
+        };
+        if columns == *expected {
+            return Ok((),);
+        }
+        let missing: Vec<_,> = expected.difference(&columns,).map(String::as_str,).collect();
+        let extra: Vec<_,> = columns.difference(expected,).map(String::as_str,).collect();
+        Err(IngestorError::IngestionError(format!(
+            "--strict-schema: columns differ from {} (missing: [{}], unexpected: [{}])",
+            first,
+            missing.join(", "),
+            extra.join(", ")
+        ),),)
+    }
+}
+
+/// Top-level keys of `record`, if it is an object.
+fn record_columns(record: Option<&Value,>,) -> BTreeSet<String,> {
+    record.and_then(Value::as_object,).map(|obj| obj.keys().cloned().collect(),).unwrap_or_default()
+}
+
+/// `--count-distinct`: how many distinct values a key field takes over the first `sample`
+/// records of a file, to catch keying mistakes (e.g. a constant field) before records collide.
+#[derive(Debug, Clone,)]
+pub struct KeyCheck {
+    pub field:  String,
+    pub sample: usize,
+}
+
+/// Result of a [`KeyCheck`]. Records without the field (or with null) count as `missing`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq,)]
+pub struct KeyCardinality {
+    pub sampled:  usize,
+    pub distinct: usize,
+    pub missing:  usize,
+}
+
+impl KeyCardinality {
+    /// Every keyed record in the sample has the same key (and there was more than one).
+    pub fn is_constant(&self,) -> bool {
+        self.distinct == 1 && self.sampled - self.missing > 1
+    }
+}
+
+impl KeyCheck {
+    /// Counts over structured reader output. Streams are checked on their first `sample`
+    /// records, which are then put back in front of the rest. Blob fallbacks return `None`.
+    pub fn apply(&self, data: DataReaderResult,) -> (DataReaderResult, Option<KeyCardinality,>,) {
+        match data {
+            DataReaderResult::Csv(csv_data, metadata,) => {
+                let cardinality = self.count(csv_data.nc_rows.iter().take(self.sample,),);
+                (DataReaderResult::Csv(csv_data, metadata,), Some(cardinality,),)
+            },
+            DataReaderResult::Stream(mut stream, metadata,) => {
+                let head: Vec<_,> = stream.by_ref().take(self.sample,).collect();
+                let cardinality = self.count(head.iter().filter_map(|r| r.as_ref().ok(),),);
+                let stream = Box::new(head.into_iter().chain(stream,),);
+                (DataReaderResult::Stream(stream, metadata,), Some(cardinality,),)
+            },
+            other => (other, None,),
+        }
+    }
+
+    /// [`KeyCheck::apply`] for pre-structured records.
+    pub fn apply_records(&self, records: &RecordSet,) -> KeyCardinality {
+        self.count(records.rows.iter().take(self.sample,),)
+    }
+
+    pub fn count<'a,>(&self, records: impl IntoIterator<Item = &'a Value,>,) -> KeyCardinality {
+        let mut keys = HashSet::new();
+        let mut cardinality = KeyCardinality { sampled: 0, distinct: 0, missing: 0, };
+        for record in records {
+            cardinality.sampled += 1;
+            match record.get(&self.field,) {
+                Some(key,) if !key.is_null() => {
+                    keys.insert(key.to_string(),);
+                },
+                _ => cardinality.missing += 1,
+            }
+        }
+        cardinality.distinct = keys.len();
+        cardinality
+    }
+}
+
+/// `--sample-per-file`: a uniform random sample of `size` records per file (reservoir sampling), in
+/// source order. With a seed the same input always yields the same sample.
+#[derive(Debug, Clone, Copy,)]
+pub struct ReservoirSample {
+    pub size: usize,
+    pub seed: Option<u64,>,
+}
+
+/// Seed for the records of `path`: the first 8 bytes of the SHA-256 of `seed` and the path, so
+/// files of the same length aren't sampled at the same positions.
+pub fn file_seed(seed: u64, path: &str,) -> u64 {
+    let digest = Sha256::new().chain_update(seed.to_le_bytes(),).chain_update(path,).finalize();
+    u64::from_le_bytes(digest[..8].try_into().unwrap(),)
+}
+
+impl ReservoirSample {
+    /// The sample for the file at `path`, seeded with [`file_seed`].
+    pub fn for_file(&self, path: &str,) -> Self {
+        Self { seed: self.seed.map(|seed| file_seed(seed, path,),), ..*self }
+    }
+
+    /// Samples structured reader output. Streams are read to the end, since every record must be
+    /// seen before the sample is known, and only the sample is held in memory. Blob fallbacks
+    /// pass through.
+    pub fn apply(&self, data: DataReaderResult,) -> Result<DataReaderResult,> {
+        match data {
+            DataReaderResult::Csv(mut csv_data, metadata,) => {
+                csv_data.nc_rows = self.sample(csv_data.nc_rows,);
+                Ok(DataReaderResult::Csv(csv_data, metadata,),)
+            },
+            DataReaderResult::Stream(stream, metadata,) => {
+                let mut first_error = None;
+                let records = stream.filter_map(|record| match record {
+                    Ok(record,) => Some(record,),
+                    Err(e,) => {
+                        first_error.get_or_insert(e,);
+                        None
+                    },
+                },);
+                let sampled = self.sample(records,);
+                if let Some(e,) = first_error {
+                    return Err(IngestorError::IngestionError(e.to_string(),),);
+                }
+                Ok(DataReaderResult::Stream(Box::new(sampled.into_iter().map(Ok,),), metadata,),)
+            },
+            other => Ok(other,),
+        }
+    }
+
+    /// [`ReservoirSample::apply`] for pre-structured records.
+    pub fn apply_records(&self, mut records: RecordSet,) -> Result<RecordSet,> {
+        records.rows = self.sample(records.rows,);
+        Ok(records,)
+    }
+
+    /// Algorithm R: the first `size` items fill the reservoir, then item `i` replaces a random
+    /// slot with probability `size / (i + 1)`.
+    pub fn sample<T,>(&self, items: impl IntoIterator<Item = T,>,) -> Vec<T,> {
+        let mut rng = match self.seed {
+            Some(seed,) => StdRng::seed_from_u64(seed,),
+            None => StdRng::from_entropy(),
+        };
+        let mut reservoir: Vec<(usize, T,),> = Vec::with_capacity(self.size,);
+        for (index, item,) in items.into_iter().enumerate() {
+            if reservoir.len() < self.size {
+                reservoir.push((index, item,),);
+            } else {
+                let slot = rng.gen_range(0..=index,);
+                if slot < self.size {
+                    reservoir[slot] = (index, item,);
+                }
+            }
+        }
+        reservoir.sort_by_key(|(index, _,)| *index,);
+        reservoir.into_iter().map(|(_, item,)| item,).collect()
+    }
+}
+
+/// `--sample-size`: one uniform random sample of `size` records across every file offered to it.
+/// Each record draws a random priority from a generator seeded with [`file_seed`] for its file,
+/// and the `size` records with the lowest priorities are kept (bottom-k sampling), so with a seed
+/// the sample doesn't depend on the order in which concurrent files are read. Only the kept
+/// records are held.
+pub struct RunSample {
+    size: usize,
+    seed: u64,
+    pool: Mutex<Pool,>,
+}
+
+/// What [`RunSample`] kept so far.
+#[derive(Default,)]
+struct Pool {
+    kept:    BinaryHeap<Pooled,>,
+    files:   Vec<String,>,
+    schema:  Option<HashMap<String, DataType,>,>,
+    /// Some file came with an inferred schema
+    typed:   bool,
+    columns: Vec<String,>,
+}
+
+/// A record in a [`RunSample`], ordered by priority, then by file and position.
+struct Pooled {
+    priority: u64,
+    path:     Arc<str,>,
+    index:    usize,
+    record:   Value,
+}
+
+impl Pooled {
+    fn key(&self,) -> (u64, &str, usize,) {
+        (self.priority, &*self.path, self.index,)
+    }
+}
+
+impl PartialEq for Pooled {
+    fn eq(&self, other: &Self,) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Pooled {}
+
+impl PartialOrd for Pooled {
+    fn partial_cmp(&self, other: &Self,) -> Option<std::cmp::Ordering,> {
+        Some(self.cmp(other,),)
+    }
+}
+
+impl Ord for Pooled {
+    fn cmp(&self, other: &Self,) -> std::cmp::Ordering {
+        self.key().cmp(&other.key(),)
+    }
+}
+
+/// Adds `item` to `heap` if it is among the `size` lowest so far.
+fn keep_lowest(heap: &mut BinaryHeap<Pooled,>, size: usize, item: Pooled,) {
+    if heap.len() < size {
+        heap.push(item,);
+    } else if heap.peek().is_some_and(|top| item < *top,) {
+        heap.pop();
+        heap.push(item,);
+    }
+}
+
+impl RunSample {
+    /// Without a seed, the run draws one at random.
+    pub fn new(size: usize, seed: Option<u64,>,) -> Self {
+        Self { size, seed: seed.unwrap_or_else(rand::random,), pool: Mutex::default(), }
+    }
+
+    /// Offers the records of structured reader output of `path`, reading streams to the end, and
+    /// returns how many there were. Blob fallbacks aren't records and are handed back.
+    pub fn apply(
+        &self,
+        data: DataReaderResult,
+        path: &str,
+    ) -> Result<std::result::Result<usize, DataReaderResult,>,> {
+        let offered = match data {
+            DataReaderResult::Csv(csv_data, _,) => {
+                let rows = csv_data.nc_rows.into_iter().map(Ok,);
+                self.offer(path, csv_data.inferred_schema, Vec::new(), rows,)?
+            },
+            DataReaderResult::Stream(stream, _,) => {
+                let rows = stream.map(|record| {
+                    record.map_err(|e| IngestorError::IngestionError(e.to_string(),),)
+                },);
+                self.offer(path, None, Vec::new(), rows,)?
+            },
+            other => return Ok(Err(other,),),
+        };
+        Ok(Ok(offered,),)
+    }
+
+    /// [`RunSample::apply`] for pre-structured records.
+    pub fn apply_records(&self, records: RecordSet, path: &str,) -> Result<usize,> {
+        let rows = records.rows.into_iter().map(Ok,);
+        self.offer(path, records.schema, records.columns, rows,)
+    }
+
+    fn offer(
+        &self,
+        path: &str,
+        schema: Option<HashMap<String, DataType,>,>,
+        columns: Vec<String,>,
+        rows: impl Iterator<Item = Result<Value,>,>,
+    ) -> Result<usize,> {
+        // The file's lowest priorities are picked before taking the lock, so files are still read
+        // in parallel
+        let mut rng = StdRng::seed_from_u64(file_seed(self.seed, path,),);
+        let shared: Arc<str,> = Arc::from(path,);
+        let mut kept = BinaryHeap::new();
+        let mut offered = 0;
+        for (index, record,) in rows.enumerate() {
+            let priority = rng.gen_range(0..=u64::MAX,);
+            let item = Pooled { priority, path: Arc::clone(&shared,), index, record: record?, };
+            keep_lowest(&mut kept, self.size, item,);
+            offered += 1;
+        }
+
+        let mut pool = self.pool.lock().unwrap();
+        pool.typed |= schema.is_some();
+        if pool.files.is_empty() {
+            pool.schema = schema;
+            pool.columns = columns;
+        } else if pool.columns != columns {
+            pool.columns.clear();
+        }
+        pool.files.push(path.to_string(),);
+        for item in kept {
+            keep_lowest(&mut pool.kept, self.size, item,);
+        }
+        Ok(offered,)
+    }
+
+    /// Takes the sample, ordered by file and position, and the files it was drawn from. Records
+    /// of several files that came typed are typed again over the sample.
+    pub fn take(&self,) -> (RecordSet, Vec<String,>,) {
+        let pool = std::mem::take(&mut *self.pool.lock().unwrap(),);
+        let mut kept = pool.kept.into_vec();
+        kept.sort_by(|a, b| (&a.path, a.index,).cmp(&(&b.path, b.index,),),);
+        let rows: Vec<Value,> = kept.into_iter().map(|pooled| pooled.record,).collect();
+        let schema = match pool.files.len() {
+            1 => pool.schema,
+            _ if pool.typed => Some(infer_schema(&rows,),),
+            _ => None,
+        };
+        (RecordSet { rows, schema, columns: pool.columns, }, pool.files,)
+    }
+}
+
+/// Batch-level checkpoints: skips the first `records` records of a file, which an interrupted
+/// run already committed, so only the tail is ingested again. Must run after every stage that
+/// changes which records a file yields. Blob fallbacks pass through.
+#[derive(Debug, Clone, Copy,)]
+pub struct ResumeOffset {
+    pub records: usize,
+}
+
+impl ResumeOffset {
+    pub fn apply(&self, data: DataReaderResult,) -> DataReaderResult {
+        match data {
+            DataReaderResult::Csv(mut csv_data, metadata,) => {
+                let skipped = self.records.min(csv_data.nc_rows.len(),);
+                csv_data.nc_rows.drain(..skipped,);
+                DataReaderResult::Csv(csv_data, metadata,)
+            },
+            DataReaderResult::Stream(stream, metadata,) => {
+                DataReaderResult::Stream(Box::new(stream.skip(self.records,),), metadata,)
+            },
+            other => other,
+        }
+    }
+
+    /// [`ResumeOffset::apply`] for pre-structured records.
+    pub fn apply_records(&self, mut records: RecordSet,) -> RecordSet {
+        let skipped = self.records.min(records.rows.len(),);
+        records.rows.drain(..skipped,);
+        records
+    }
+}
+
+/// Cuts the longest top-level string of `record` until its JSON fits in `max_bytes`. `None` when
+/// emptying every string still isn't enough.
+fn truncate_to_fit(mut record: Value, max_bytes: usize,) -> Option<Value,> {
+    loop {
+        let size = record.to_string().len();
+        if size <= max_bytes {
+            return Some(record,);
+        }
+        let Value::Object(obj,) = &mut record else { return None };
+        let longest = obj
+            .values_mut()
+            .filter_map(|value| match value {
+                Value::String(s,) if !s.is_empty() => Some(s,),
+                _ => None,
+            },)
+            .max_by_key(|s| s.len(),)?;
+        let keep = longest.len().saturating_sub(size - max_bytes,);
+        let boundary = (0..=keep).rev().find(|&i| longest.is_char_boundary(i,),).unwrap_or(0,);
+        longest.truncate(boundary,);
+    }
+}
+
+/// Rounds `value` to `decimals` decimal places.
+pub fn round_float(value: f64, decimals: u32,) -> f64 {
+    let scale = 10f64.powi(decimals as i32,);
+    (value * scale).round() / scale
+}
+
+/// Rounds every component of an embedding vector to `decimals` places.
+pub fn round_vector(vector: Vec<f32,>, decimals: u32,) -> Vec<f32,> {
+    vector
+        .into_iter()
+        .map(|c| round_float(c as f64, decimals,) as f32,)
+        .collect()
+}
+
+/// Rounds the values of `Float`/`Number` columns (nullable ones included) to `decimals` places.
+/// Integers, non-numeric values and other columns are left untouched.
+pub fn round_float_columns(
+    rows: &mut [Value],
+    schema: &HashMap<String, DataType,>,
+    decimals: u32,
+) {
+    let columns: Vec<&String,> = schema
+        .iter()
+        .filter(|(_, data_type,)| is_float_type(data_type,),)
+        .map(|(column, _,)| column,)
+        .collect();
+    if columns.is_empty() {
+        return;
+    }
+
+    for row in rows {
+        let Value::Object(obj,) = row else { continue };
+        for column in &columns {
+            if let Some(value,) = obj.get_mut(*column,) {
+                round_value(value, decimals,);
+            }
+        }
+    }
+}
+
+/// Rounds the top-level float values of a record to `decimals` places, for backends that store
+/// records without a schema (MongoDB, Neo4j, Qdrant and Weaviate payloads). A float value means
+/// a `Float`/`Number` column, so this matches [`round_float_columns`]. Integers and nested
+/// values are left untouched.
+pub fn round_floats(record: &mut Value, decimals: u32,) {
+    if let Value::Object(obj,) = record {
+        obj.values_mut().for_each(|value| round_value(value, decimals,),);
+    }
+}
+
+fn round_value(value: &mut Value, decimals: u32,) {
+    if let Some(rounded,) = value
+        .as_f64()
+        .filter(|_| value.is_f64(),)
+        .and_then(|n| serde_json::Number::from_f64(round_float(n, decimals,),),)
+    {
+        *value = Value::Number(rounded,);
+    }
+}
+
+fn is_float_type(data_type: &DataType,) -> bool {
+    match data_type {
+        DataType::Float | DataType::Number => true,
+        DataType::Union(variants,) => variants.iter().any(is_float_type,),
+        _ => false,
+    }
+}
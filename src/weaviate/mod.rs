@@ -0,0 +1,447 @@
+// nc_ingestor/src/weaviate/mod.rs
+// Weaviate specific ingestion logic, over the REST schema and batch APIs.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use nc_reader::nc_reader_result::DataReaderResult;
+use nc_schema::DataType;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde_json::{Value, json};
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::embeddings::{Embedder, build_embedder, embed, embed_text};
+use crate::error::{IngestorError, Result, redact_message};
+use crate::ingestor::{Ingestor, IngestorConfig, RawFile, RecordSet, blob_value, raw_target_name};
+use crate::json_input::infer_schema;
+use crate::retry::{execute_with_connection_retry, execute_with_retry, wrap_error};
+use crate::transform::{round_floats, round_vector};
+
+/// Weaviate-only options.
+#[derive(Debug, Clone, Default, serde::Deserialize,)]
+#[serde(default, deny_unknown_fields)]
+pub struct WeaviateOptions {
+    /// Sent as a bearer token, for Weaviate Cloud and other instances with API key auth.
+    pub api_key: Option<String,>,
+}
+
+/// Writes records as objects of a Weaviate class named after `collection_name`. Classes are
+/// created with `vectorizer: none` and properties typed from the schema, named after `mappings`
+/// and [`property_name`]; vectors come from `embed_field` through the same [`Embedder`] as
+/// Qdrant, and objects without one are stored unvectorized. With `id_field` object IDs are
+/// derived from that field, so re-ingesting a record replaces its object.
+pub struct WeaviateIngestor {
+    config:     IngestorConfig,
+    client:     Client,
+    base_url:   String,
+    embedder:   Option<Box<dyn Embedder,>,>,
+    class_name: String,
+}
+
+#[async_trait]
+impl Ingestor for WeaviateIngestor {
+    async fn new(config: IngestorConfig,) -> Result<Self,> {
+        let base_url = config.database_url.trim_end_matches('/',).to_string();
+        let ingestor = WeaviateIngestor {
+            client: Client::new(),
+            embedder: build_embedder(&config,)?,
+            class_name: class_name(&config.collection_name(),),
+            base_url,
+            config,
+        };
+
+        execute_with_connection_retry(ingestor.config.connection_retries(), || async {
+            let response = ingestor
+                .send(ingestor.client.get(ingestor.url("/v1/.well-known/ready",),),)
+                .await
+                .map_err(wrap_error,)?;
+            if response.status().is_success() {
+                Ok((),)
+            } else {
+                Err(wrap_error(IngestorError::ConnectionError(format!(
+                    "Weaviate is not ready: {}",
+                    response.status()
+                ),),),)
+            }
+        },)
+        .await?;
+
+        Ok(ingestor,)
+    }
+
+    async fn ingest(&self, data: DataReaderResult,) -> Result<(),> {
+        let class = self.class_name.as_str();
+        match data {
+            DataReaderResult::Csv(csv_data, _,) => {
+                let records = csv_data.nc_rows.into_iter().map(Ok,);
+                self.insert_in_batches(records, class, csv_data.inferred_schema,)
+                    .await?;
+            },
+            DataReaderResult::Stream(stream, _,) => {
+                let records = stream.map(|record_res| {
+                    record_res.map_err(|e| IngestorError::IngestionError(e.to_string(),),)
+                },);
+                self.insert_in_batches(records, class, None,).await?;
+            },
+            _ => {
+                let json_val = blob_value(&data, &self.config,)?;
+                self.insert_in_batches(std::iter::once(Ok(json_val,),), class, None,)
+                    .await?;
+            },
+        }
+
+        info!("Successfully ingested data to Weaviate class '{}'.", class);
+        Ok((),)
+    }
+
+    async fn ingest_records(&self, records: RecordSet,) -> Result<(),> {
+        let class = self.class_name.as_str();
+        self.insert_in_batches(records.rows.into_iter().map(Ok,), class, records.schema,)
+            .await?;
+        info!("Successfully ingested data to Weaviate class '{}'.", class);
+        Ok((),)
+    }
+
+    async fn ingest_raw(&self, raw: RawFile,) -> Result<(),> {
+        let class = class_name(&raw_target_name(&self.class_name,),);
+        self.insert_in_batches(std::iter::once(Ok(raw.to_value(),),), &class, None,)
+            .await
+    }
+
+    async fn target_count(&self,) -> Result<Option<u64,>,> {
+        let class = self.class_name.as_str();
+        if !self.class_exists(class,).await? {
+            return Ok(Some(0,),);
+        }
+
+        let query = format!("{{ Aggregate {{ {} {{ meta {{ count }} }} }} }}", class);
+        let request = self.client.post(self.url("/v1/graphql",),).json(&json!({ "query": query }),);
+        let response = json_body(self.send(request,).await?, "count Weaviate objects",).await?;
+        let count = response
+            .pointer(&format!("/data/Aggregate/{}/0/meta/count", class),)
+            .and_then(Value::as_u64,)
+            .unwrap_or(0,);
+        Ok(Some(count,),)
+    }
+
+    async fn clean(&self,) -> Result<String,> {
+        let class = self.class_name.as_str();
+        if !self.class_exists(class,).await? {
+            return Ok(format!("Weaviate class `{}` does not exist", class),);
+        }
+        let count = self.target_count().await?.unwrap_or_default();
+
+        let request = self.client.delete(self.url(&format!("/v1/schema/{}", class),),);
+        json_body(self.send(request,).await?, "delete Weaviate class",).await?;
+        Ok(format!("deleted Weaviate class `{}` ({} objects)", class, count),)
+    }
+}
+
+impl WeaviateIngestor {
+    fn url(&self, path: &str,) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Sends a request with the API key attached.
+    async fn send(&self, mut request: RequestBuilder,) -> Result<Response,> {
+        if let Some(api_key,) = &self.config.weaviate.api_key {
+            request = request.bearer_auth(api_key,);
+        }
+        request.send().await.map_err(|e| {
+            IngestorError::ConnectionError(format!(
+                "Failed to reach Weaviate: {}",
+                redact_message(&e.to_string(), &self.config.database_url,)
+            ),)
+            .with_source(e.without_url(),)
+        },)
+    }
+
+    async fn class_exists(&self, class: &str,) -> Result<bool,> {
+        let request = self.client.get(self.url(&format!("/v1/schema/{}", class),),);
+        let response = self.send(request,).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false,),
+            status if status.is_success() => Ok(true,),
+            _ => json_body(response, "read Weaviate schema",).await.map(|_| true,),
+        }
+    }
+
+    /// Creates `class` with a property per schema field unless it already exists.
+    async fn ensure_class(&self, class: &str, schema: &HashMap<String, DataType,>,) -> Result<(),> {
+        if self.class_exists(class,).await? {
+            return Ok((),);
+        }
+
+        let mut fields: Vec<&String,> = schema.keys().collect();
+        fields.sort();
+        let properties: Vec<Value,> = fields
+            .into_iter()
+            .map(|field| {
+                let name = self.property_name(field,);
+                json!({ "name": name, "dataType": [weaviate_type(&schema[field])] })
+            },)
+            .collect();
+        let body = json!({ "class": class, "vectorizer": "none", "properties": properties });
+
+        let response = self.send(self.client.post(self.url("/v1/schema",),).json(&body,),).await?;
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            // Another file may have created it in the meantime
+            if text.contains("already exists",) {
+                return Ok((),);
+            }
+            return Err(IngestorError::DatabaseError(format!(
+                "Failed to create Weaviate class: {} - {}",
+                status, text
+            ),),);
+        }
+        info!("Created Weaviate class: {}", class);
+        Ok((),)
+    }
+
+    /// Writes records in `batch.size` batches. Without a schema, the class is created from the
+    /// types of the first batch.
+    async fn insert_in_batches(
+        &self,
+        records: impl Iterator<Item = Result<Value,>,> + Send,
+        class: &str,
+        mut schema: Option<HashMap<String, DataType,>,>,
+    ) -> Result<(),> {
+        let batch_size = self.config.batch.size();
+        let mut records = records.peekable();
+        let mut class_ready = false;
+        while records.peek().is_some() {
+            let mut batch = records.by_ref().take(batch_size,).collect::<Result<Vec<_,>,>>()?;
+            if let Some(decimals,) = self.config.float_precision() {
+                batch.iter_mut().for_each(|record| round_floats(record, decimals,),);
+            }
+            let schema = schema.get_or_insert_with(|| infer_schema(&batch,),);
+            if !class_ready {
+                self.ensure_class(class, schema,).await?;
+                class_ready = true;
+            }
+            self.write_batch(batch, class, schema,).await?;
+        }
+        Ok((),)
+    }
+
+    async fn write_batch(
+        &self,
+        batch: Vec<Value,>,
+        class: &str,
+        schema: &HashMap<String, DataType,>,
+    ) -> Result<(),> {
+        let count = batch.len();
+        let mut vectors = self.embed(&batch,).await?;
+        let objects: Vec<Value,> = batch
+            .into_iter()
+            .zip(vectors.iter_mut(),)
+            .enumerate()
+            .map(|(index, (record, vector,),)| {
+                let mut object = json!({
+                    "class": class,
+                    "id": self.object_id(&record, index)?.to_string(),
+                    "properties": self.properties(record, schema),
+                });
+                if let Some(vector,) = vector.take() {
+                    object["vector"] = json!(vector);
+                }
+                Ok(object,)
+            },)
+            .collect::<Result<_,>>()?;
+        let body = json!({ "objects": objects });
+
+        let results = execute_with_retry(|| async {
+            self.config
+                .batch
+                .run(async {
+                    let request = self.client.post(self.url("/v1/batch/objects",),).json(&body,);
+                    json_body(self.send(request,).await?, "write batch to Weaviate",).await
+                },)
+                .await
+                .map_err(wrap_error,)
+        },)
+        .await?;
+
+        // The batch endpoint answers 200 and reports failures per object
+        let failures: Vec<(usize, &str,),> = results
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .filter_map(|(index, result,)| {
+                result
+                    .pointer("/result/errors/error/0/message",)
+                    .and_then(Value::as_str,)
+                    .map(|message| (index, message,),)
+            },)
+            .collect();
+        if let Some((index, message,),) = failures.first() {
+            return Err(IngestorError::IngestionError(format!(
+                "Weaviate rejected {} of {} objects (first, batch item {}: {})",
+                failures.len(),
+                count,
+                index + 1,
+                message
+            ),),);
+        }
+
+        debug!("Wrote {} objects to {}", count, class);
+        self.config.batch_committed(class, count,);
+        Ok((),)
+    }
+
+    /// The object ID of `record`: a UUID v5 of its `id_field` value, so the same key always
+    /// names the same object, or a random one without `id_field`.
+    fn object_id(&self, record: &Value, index: usize,) -> Result<Uuid,> {
+        let Some(id_field,) = &self.config.id_field else {
+            return Ok(Uuid::new_v4(),);
+        };
+        let key = match record.get(id_field,) {
+            Some(Value::String(key,),) => key.clone(),
+            Some(key,) if !key.is_null() => key.to_string(),
+            _ => {
+                return Err(IngestorError::IngestionError(format!(
+                    "record {}: id field '{}' is missing",
+                    index + 1,
+                    id_field
+                ),),);
+            },
+        };
+        Ok(Uuid::new_v5(&Uuid::NAMESPACE_OID, key.as_bytes(),),)
+    }
+
+    /// Property name of `field`, after `mappings`.
+    fn property_name(&self, field: &str,) -> String {
+        let mapped = self.config.mappings.as_ref().and_then(|m| m.get(field,),);
+        property_name(mapped.map_or(field, String::as_str,),)
+    }
+
+    /// The record's fields as object properties. Nulls are left out, and values of text
+    /// properties that aren't strings are stored as their JSON.
+    fn properties(&self, record: Value, schema: &HashMap<String, DataType,>,) -> Value {
+        let Value::Object(obj,) = record else {
+            return json!({ "value": record.to_string() });
+        };
+        let properties: serde_json::Map<String, Value,> = obj
+            .into_iter()
+            .filter(|(_, value,)| !value.is_null(),)
+            .map(|(key, value,)| {
+                let text = schema.get(&key,).is_none_or(|t| weaviate_type(t,) == "text",);
+                let name = self.property_name(&key,);
+                match value {
+                    Value::String(_,) => (name, value,),
+                    other if text => (name, Value::String(other.to_string(),),),
+                    other => (name, other,),
+                }
+            },)
+            .collect();
+        Value::Object(properties,)
+    }
+
+    /// Embeddings of the batch's non-blank `embed_field` values in one request. Records without
+    /// text, and every record when no embedder is configured, get `None`.
+    async fn embed(&self, batch: &[Value],) -> Result<Vec<Option<Vec<f32,>,>,>,> {
+        let mut vectors = vec![None; batch.len()];
+        let (Some(embedder,), Some(field,),) = (&self.embedder, &self.config.embed_field,) else {
+            return Ok(vectors,);
+        };
+        let (indices, texts,): (Vec<usize,>, Vec<String,>,) = batch
+            .iter()
+            .enumerate()
+            .filter_map(|(index, record,)| {
+                let text = embed_text(record, field,)?;
+                (!text.trim().is_empty()).then(|| (index, text.to_string(),),)
+            },)
+            .unzip();
+
+        let embeddings = embed(embedder.as_ref(), &texts,).await?;
+        for (index, embedding,) in indices.into_iter().zip(embeddings,) {
+            vectors[index] = Some(match self.config.float_precision() {
+                Some(decimals,) => round_vector(embedding, decimals,),
+                None => embedding,
+            },);
+        }
+        Ok(vectors,)
+    }
+}
+
+/// Weaviate class names start with an uppercase letter and hold only letters, digits and
+/// underscores, so `events-2024` becomes `Events_2024` and `2024` becomes `C2024`.
+pub fn class_name(collection: &str,) -> String {
+    let sanitized: String = collection
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' },)
+        .collect();
+    let mut chars = sanitized.chars();
+    match chars.next() {
+        Some(first,) if first.is_ascii_alphabetic() => {
+            format!("{}{}", first.to_ascii_uppercase(), chars.as_str())
+        },
+        _ => format!("C{}", sanitized),
+    }
+}
+
+/// Weaviate `dataType` of a column. Nested objects and mixed types are stored as JSON text.
+fn weaviate_type(data_type: &DataType,) -> &'static str {
+    match data_type {
+        DataType::Integer => "int",
+        DataType::Float | DataType::Number => "number",
+        DataType::Boolean => "boolean",
+        DataType::Array(item,) => match weaviate_type(item,) {
+            "int" => "int[]",
+            "number" => "number[]",
+            "boolean" => "boolean[]",
+            _ => "text[]",
+        },
+        DataType::Union(variants,) => {
+            let mut non_null = variants.iter().filter(|t| !matches!(t, DataType::Null),);
+            match (non_null.next(), non_null.next(),) {
+                (Some(only,), None,) => weaviate_type(only,),
+                _ => "text",
+            }
+        },
+        _ => "text",
+    }
+}
+
+/// Weaviate property names hold only letters, digits and underscores and don't start with a
+/// digit, so `order-id` becomes `order_id` and `2024` becomes `_2024`. `id`, `_id` and
+/// `_additional` are reserved and get a trailing underscore.
+pub fn property_name(field: &str,) -> String {
+    let mut name: String = field
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' },)
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_',) {
+        name.insert(0, '_',);
+    }
+    if matches!(name.as_str(), "id" | "_id" | "_additional") {
+        name.push('_',);
+    }
+    name
+}
+
+/// Parses a JSON response, turning error statuses into errors that name the failed `action`.
+async fn json_body(response: Response, action: &str,) -> Result<Value,> {
+    let status = response.status();
+    if !status.is_success() {
+        let text = response.text().await.unwrap_or_default();
+        let message = format!("Failed to {}: {} - {}", action, status, text);
+        // Every server error is worth retrying, not only the 503/504 the transient patterns match
+        return Err(if status.is_server_error() {
+            IngestorError::ConnectionError(message,)
+        } else {
+            IngestorError::IngestionError(message,)
+        },);
+    }
+    if status == StatusCode::NO_CONTENT {
+        return Ok(Value::Null,);
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| IngestorError::IngestionError(format!("Failed to {}: {}", action, e),),)
+}